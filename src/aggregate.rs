@@ -0,0 +1,234 @@
+use std::panic::Location;
+
+use leptos::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+use crate::{ClientInputSignal, ServerSignal};
+
+#[cfg(feature = "server")]
+pub use server::AggregateSignal;
+
+#[cfg(not(feature = "server"))]
+pub use client::AggregateSignal;
+
+#[cfg(feature = "server")]
+mod server {
+    use super::*;
+    use crate::connection::ConnectionId;
+
+    /// A value computed by the server from one contribution per connected client, e.g. the tally
+    /// of a poll or the average of everyone's slider.
+    ///
+    /// Every client contributes at most one `In`, replacing its previous one. The server folds
+    /// all current contributions into an `Out`, starting from `initial`, whenever one is added,
+    /// replaced or withdrawn, including when its client disconnects. Clients only read the
+    /// result, never the contributions of others.
+    ///
+    /// Contributions are folded in the order their connections were opened, by a task of the
+    /// registry like [`ServerSignal::derive`], also after the request which created the signal
+    /// was rendered.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::AggregateSignal;
+    /// # use serde::{Deserialize, Serialize};
+    /// # use std::collections::HashMap;
+    /// # #[derive(Clone, Default, Serialize, Deserialize)]
+    /// # struct Tally(HashMap<String, usize>);
+    /// # impl Tally {
+    /// #     fn add(&mut self, vote: &String) {
+    /// #         *self.0.entry(vote.clone()).or_default() += 1;
+    /// #     }
+    /// # }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// let votes = AggregateSignal::new("votes".to_string(), Tally::default(), |mut tally, vote| {
+    ///     tally.add(vote);
+    ///     tally
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[derive(Clone)]
+    pub struct AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + DeserializeOwned,
+        Out: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<In>>,
+        result: ServerSignal<Out>,
+    }
+
+    impl<In, Out> AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        pub fn new(
+            name: String,
+            initial: Out,
+            fold: impl Fn(Out, &In) -> Out + Send + Sync + 'static,
+        ) -> Result<Self, Error> {
+            let input = ClientInputSignal::new(name.clone(), None)?;
+            let values = input.values();
+            let result = ServerSignal::derive(name, move || {
+                values.with(|values| {
+                    let mut contributions = values
+                        .iter()
+                        .filter_map(|(connection, value)| Some((*connection, value.as_ref()?)))
+                        .collect::<Vec<_>>();
+                    contributions.sort_unstable_by_key(|(connection, _)| *connection);
+                    contributions
+                        .into_iter()
+                        .fold(initial.clone(), |result, (_, value)| fold(result, value))
+                })
+            })?;
+            Ok(Self { input, result })
+        }
+
+        /// The contribution of a single client, if it is connected and has one.
+        pub fn contribution_for(&self, connection: ConnectionId) -> Option<In> {
+            self.input.value_for(connection).flatten()
+        }
+
+        /// Does nothing, only clients contribute.
+        pub fn contribute(&self, _value: In) {}
+
+        /// Does nothing, only clients contribute.
+        pub fn withdraw(&self) {}
+
+        /// Always `None`, only clients contribute.
+        pub fn mine(&self) -> Option<In> {
+            None
+        }
+
+        /// Always empty, only clients receive the result.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = Out> + Send + 'static {
+            self.result.to_stream()
+        }
+    }
+
+    impl<In, Out> DefinedAt for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.result.defined_at()
+        }
+    }
+
+    impl<In, Out> Track for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.result.track()
+        }
+    }
+
+    impl<In, Out> ReadUntracked for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<Out> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.result.try_read_untracked()
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use super::*;
+
+    /// A value computed by the server from one contribution per connected client, e.g. the tally
+    /// of a poll or the average of everyone's slider.
+    ///
+    /// This client contributes at most one `In` with [`AggregateSignal::contribute`] and reads
+    /// the `Out` the server folded from the contributions of all clients, starting with
+    /// `initial` until it arrives.
+    #[derive(Clone)]
+    pub struct AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + DeserializeOwned,
+        Out: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<In>>,
+        result: ServerSignal<Out>,
+    }
+
+    impl<In, Out> AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        /// `fold` only runs on the server.
+        pub fn new(
+            name: String,
+            initial: Out,
+            fold: impl Fn(Out, &In) -> Out + Send + Sync + 'static,
+        ) -> Result<Self, Error> {
+            let _ = fold;
+            let input = ClientInputSignal::new(name.clone(), None)?;
+            let result = ServerSignal::new(name, initial)?;
+            Ok(Self { input, result })
+        }
+
+        /// Replaces the contribution of this client.
+        pub fn contribute(&self, value: In) {
+            self.input.set(Some(value));
+        }
+
+        /// Removes the contribution of this client, e.g. when a vote is taken back.
+        pub fn withdraw(&self) {
+            self.input.set(None);
+        }
+
+        /// The contribution of this client as last set, without waiting for the server.
+        pub fn mine(&self) -> Option<In> {
+            self.input.get_untracked()
+        }
+
+        /// The result every time it changes from now on.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = Out> + Send + 'static {
+            self.result.to_stream()
+        }
+    }
+
+    impl<In, Out> DefinedAt for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.result.defined_at()
+        }
+    }
+
+    impl<In, Out> Track for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.result.track()
+        }
+    }
+
+    impl<In, Out> ReadUntracked for AggregateSignal<In, Out>
+    where
+        In: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        Out: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<Out> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.result.try_read_untracked()
+        }
+    }
+}
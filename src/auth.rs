@@ -0,0 +1,91 @@
+//! Authenticating connections with a token, e.g. a JWT, which can be refreshed while the
+//! connection stays open.
+//!
+//! The client gets its token from [`WsConfig::auth_token`](crate::config::WsConfig) before it
+//! connects, and sends it in every `Hello`. Refreshed tokens are sent with a
+//! [`Messages::Authenticate`](crate::messages::Messages::Authenticate), so the signals stay
+//! established. The server checks every token with
+//! [`ConnectionConfig::authenticate`](crate::config::ConnectionConfig), and answers a rejected
+//! one with [`Messages::AuthRejected`](crate::messages::Messages::AuthRejected) and closes the
+//! connection.
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! # use leptos_ws::config::WsConfig;
+//! # async fn fetch_token() -> Result<String, ()> { Ok(String::new()) }
+//! # fn verify_jwt(_token: &str) -> Result<(), String> { Ok(()) }
+//! // Client
+//! let config = WsConfig::default()
+//!     .auth_token(|| async { fetch_token().await.ok() })
+//!     .auth_refresh(Duration::from_secs(240));
+//! leptos_ws::provide_websocket_with_config("/ws", config);
+//!
+//! // Server
+//! # #[cfg(feature = "server")]
+//! let config = leptos_ws::config::ConnectionConfig::default().authenticate(|token| verify_jwt(token));
+//! ```
+//!
+//! Tokens which never change can also be sent as query parameter with
+//! [`WsConfig::query_param`](crate::config::WsConfig::query_param), then the server reads them
+//! from the upgrade request instead.
+
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type TokenFuture = Pin<Box<dyn Future<Output = Option<String>>>>;
+
+/// Fetches the token of the client, see [`WsConfig::auth_token`](crate::config::WsConfig).
+#[derive(Clone)]
+pub struct TokenProvider(Arc<dyn Fn() -> TokenFuture + Send + Sync>);
+
+impl Debug for TokenProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenProvider")
+    }
+}
+
+impl TokenProvider {
+    pub(crate) fn new<F, Fut>(provider: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<String>> + 'static,
+    {
+        Self(Arc::new(move || Box::pin(provider())))
+    }
+
+    /// The current token, `None` if there is none, e.g. while logged out.
+    #[cfg_attr(feature = "server", allow(dead_code))]
+    pub(crate) fn token(&self) -> TokenFuture {
+        (self.0)()
+    }
+}
+
+/// Checks the tokens of clients, see
+/// [`ConnectionConfig::authenticate`](crate::config::ConnectionConfig).
+#[cfg(feature = "server")]
+#[derive(Clone)]
+pub struct Verifier(Arc<Verify>);
+
+#[cfg(feature = "server")]
+type Verify = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+
+#[cfg(feature = "server")]
+impl Debug for Verifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Verifier")
+    }
+}
+
+#[cfg(feature = "server")]
+impl Verifier {
+    pub(crate) fn new(verify: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(verify))
+    }
+
+    /// Checks `token`, returns why it was rejected otherwise.
+    pub(crate) fn verify(&self, token: &str) -> Result<(), String> {
+        (self.0)(token)
+    }
+}
@@ -1,40 +1,52 @@
 use crate::{
-    messages::{Messages, ServerSignalMessage, ServerSignalUpdate},
+    config::{AffinityConfig, ConnectionConfig, WsConfig},
     server_signals::ServerSignals,
+    transport::{serve, Frame},
+};
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use axum::Router;
+use futures::{
+    future::{ready, BoxFuture},
+    SinkExt, StreamExt, TryStreamExt,
 };
-use axum::extract::ws::Message;
-use futures::{future::BoxFuture, stream::SplitSink, SinkExt, StreamExt};
 use leptos::logging::error;
-use std::sync::Arc;
-use tokio::{
-    spawn,
-    sync::{broadcast::Receiver, RwLock},
+use serde_json::{json, Value};
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
 };
 
-async fn handle_broadcasts(
-    mut receiver: Receiver<ServerSignalUpdate>,
-    sink: Arc<RwLock<SplitSink<axum::extract::ws::WebSocket, axum::extract::ws::Message>>>,
-) {
-    while let Ok(message) = receiver.recv().await {
-        if sink
-            .write()
-            .await
-            .send(Message::Text(
-                serde_json::to_string(&Messages::ServerSignal(ServerSignalMessage::Update(
-                    message,
-                )))
-                .unwrap(),
-            ))
-            .await
-            .is_err()
-        {
-            break;
-        };
+use axum::extract::WebSocketUpgrade;
+use axum::response::Response;
+
+/// Reads a value of a client from the headers of the upgrade request.
+type HeaderFn = dyn Fn(&HeaderMap) -> Option<String> + Send + Sync;
+
+/// Extracts the identity of a client from the upgrade request, see
+/// [`ConnectionConfig::identify`].
+#[derive(Clone)]
+pub struct Identify(pub(crate) Arc<HeaderFn>);
+
+impl Debug for Identify {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Identify")
+    }
+}
+
+/// Extracts the tenant of a client from the upgrade request, see
+/// [`ConnectionConfig::tenant`].
+#[derive(Clone)]
+pub struct TenantKey(pub(crate) Arc<HeaderFn>);
+
+impl Debug for TenantKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TenantKey")
     }
 }
 
-use axum::extract::WebSocketUpgrade;
-use axum::response::Response;
 /// Creates a WebSocket handler function for upgrading HTTP connections to WebSocket connections.
 ///
 /// This function returns a closure that can be used as a route handler in an Axum web server to handle
@@ -58,9 +70,16 @@ use axum::response::Response;
 ///
 /// # Example
 ///
-/// ```
+/// ```rust,no_run
 /// use axum::Router;
 /// use axum::routing::{get, post};
+/// # use leptos_ws::server_signals::ServerSignals;
+/// # #[derive(Clone)]
+/// # struct AppState {
+/// #     server_signals: ServerSignals,
+/// # }
+/// # async fn server_fn_handler() {}
+/// # fn app(state: AppState) -> Router {
 ///
 /// let app = Router::new()
 ///     .route("/api/*fn_name", post(server_fn_handler))
@@ -68,9 +87,10 @@ use axum::response::Response;
 ///         "/ws",
 ///         get(leptos_ws::axum::websocket(state.server_signals.clone())),
 ///     )
-///     .leptos_routes_with_handler(routes, get(leptos_routes_handler))
-///     .fallback(file_and_error_handler)
+///     // .leptos_routes_with_handler(routes, get(leptos_routes_handler))
 ///     .with_state(state);
+/// # app
+/// # }
 /// ```
 ///
 /// In this example, the `websocket` function is used to create a WebSocket handler for the "/ws" route
@@ -78,70 +98,402 @@ use axum::response::Response;
 pub fn websocket(
     server_signals: ServerSignals,
 ) -> impl Fn(WebSocketUpgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static {
-    move |ws: WebSocketUpgrade| {
-        let value = server_signals.clone();
-        Box::pin(async move { ws.on_upgrade(move |socket| handle_socket(socket, value)) })
+    // The default config neither identifies clients nor sets an affinity cookie, so the headers
+    // are never read
+    let handler = websocket_with_config(server_signals, ConnectionConfig::default());
+    move |ws: WebSocketUpgrade| handler(HeaderMap::new(), ws)
+}
+
+/// Like [`websocket`], but with custom [`ConnectionConfig`] for every connection, e.g. to tune
+/// the heartbeat.
+///
+/// The handler also takes the request headers, for [`ConnectionConfig::identify`],
+/// [`ConnectionConfig::tenant`] and the affinity cookie.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use leptos_ws::config::{ConnectionConfig, HeartbeatConfig};
+/// # struct AppState {
+/// #     server_signals: leptos_ws::server_signals::ServerSignals,
+/// # }
+/// # let state = AppState { server_signals: leptos_ws::server_signals::ServerSignals::new() };
+///
+/// let config = ConnectionConfig::default().heartbeat(HeartbeatConfig::new(
+///     Duration::from_secs(10),
+///     Duration::from_secs(30),
+/// ));
+/// let handler = leptos_ws::axum::websocket_with_config(state.server_signals.clone(), config);
+/// ```
+pub fn websocket_with_config(
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+) -> impl Fn(HeaderMap, WebSocketUpgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static {
+    move |headers: HeaderMap, ws: WebSocketUpgrade| {
+        let lease = config
+            .tenant
+            .as_ref()
+            .and_then(|tenant| (tenant.0)(&headers))
+            .map(|tenant| server_signals.lease_tenant(&tenant));
+        let value = match &lease {
+            Some(lease) => lease.signals.clone(),
+            None => server_signals.clone(),
+        };
+        let config = config.clone();
+        Box::pin(async move {
+            let affinity = config.affinity.clone();
+            let previous_node = affinity
+                .as_ref()
+                .and_then(|affinity| cookie(&headers, &affinity.cookie));
+            let identity = config
+                .identify
+                .as_ref()
+                .and_then(|identify| (identify.0)(&headers));
+            let ws = match config.protocols.is_empty() {
+                true => ws,
+                false => ws.protocols(config.protocols.clone()),
+            };
+            let mut response = ws.on_upgrade(move |socket| async move {
+                handle_socket(socket, value, config, previous_node, identity).await;
+                // Also dropped if the upgrade fails, the tenant is evicted after its last
+                // connection
+                drop(lease);
+            });
+            if let Some(affinity) = affinity {
+                set_affinity_cookie(&mut response, &affinity);
+            }
+            response
+        })
     }
 }
 
-async fn handle_socket(socket: axum::extract::ws::WebSocket, server_signals: ServerSignals) {
-    let (send, mut recv) = socket.split();
-    let send = Arc::new(RwLock::new(send));
-    let _ = spawn(async move {
-        while let Some(message) = recv.next().await {
-            if let Ok(msg) = message {
-                match msg {
-                    Message::Text(text) => {
-                        if let Ok(message) = serde_json::from_str::<Messages>(&text) {
-                            match message {
-                                Messages::ServerSignal(server_msg) => match server_msg {
-                                    ServerSignalMessage::Establish(name) => {
-                                        let recv = server_signals
-                                            .add_observer(name.clone())
-                                            .await
-                                            .unwrap();
-                                        send.clone()
-                                            .write()
-                                            .await
-                                            .send(Message::Text(
-                                                serde_json::to_string(&Messages::ServerSignal(
-                                                    ServerSignalMessage::EstablishResponse((
-                                                        name.clone(),
-                                                        server_signals
-                                                            .json(name.clone())
-                                                            .await
-                                                            .unwrap()
-                                                            .unwrap(),
-                                                    )),
-                                                ))
-                                                .unwrap(),
-                                            ))
-                                            .await
-                                            .unwrap();
-                                        spawn(handle_broadcasts(recv, send.clone()));
-                                    }
-                                    _ => error!("Unexpected server signal message from client"),
-                                },
-                            }
-                        } else {
-                            leptos::logging::error!("Error transmitting message")
-                        }
-                    }
-                    Message::Binary(_) => todo!(),
-                    Message::Ping(_) => send
-                        .clone()
-                        .write()
-                        .await
-                        .send(Message::Pong(vec![1, 2, 3]))
-                        .await
-                        .unwrap(),
-                    Message::Pong(_) => todo!(),
-                    Message::Close(_) => {}
+/// A router serving the socket under [`WsConfig::path`] (`/ws` if it is empty) and accepting
+/// its [`WsConfig::protocols`], so client and server are configured in one place.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum::Router;
+/// # use leptos_ws::config::{ConnectionConfig, WsConfig};
+/// # #[derive(Clone)]
+/// # struct AppState {
+/// #     server_signals: leptos_ws::server_signals::ServerSignals,
+/// # }
+/// # fn app(state: AppState) -> Router {
+/// // shared between the client and the server
+/// fn ws_config() -> WsConfig {
+///     WsConfig::default().path("/api/realtime")
+/// }
+///
+/// let app = Router::new()
+///     .merge(leptos_ws::axum::router(&ws_config(), state.server_signals.clone(), ConnectionConfig::default()))
+///     // .leptos_routes_with_handler(routes, get(leptos_routes_handler))
+///     .with_state(state);
+/// # app
+/// # }
+/// ```
+pub fn router<S>(
+    ws_config: &WsConfig,
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let path = match ws_config.path.as_str() {
+        "" => "/ws".to_string(),
+        path if path.starts_with('/') => path.to_string(),
+        path => format!("/{path}"),
+    };
+    let mut config = config;
+    if config.protocols.is_empty() {
+        config.protocols = ws_config.protocols.clone();
+    }
+    Router::new().route(&path, get(websocket_with_config(server_signals, config)))
+}
+
+/// Routes showing the state of `server_signals` to debug sync problems in production: every
+/// signal with its current value and subscribers, the open connections, and the message rates
+/// per identity (see [`ConnectionConfig::identify`]).
+///
+/// `/` renders a minimal HTML page, `/json` returns the same report as JSON. Requests for which
+/// `authorize` returns `false` are answered with `401 Unauthorized`, since values may be
+/// confidential.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum::{http::HeaderMap, Router};
+/// # struct AppState {
+/// #     server_signals: leptos_ws::server_signals::ServerSignals,
+/// # }
+/// # let state = AppState { server_signals: leptos_ws::server_signals::ServerSignals::new() };
+/// # fn is_admin(_headers: &HeaderMap) -> bool { false }
+/// let app: Router = Router::new().nest(
+///     "/debug/ws",
+///     leptos_ws::axum::debug_routes(state.server_signals.clone(), |headers| {
+///         is_admin(headers)
+///     }),
+/// );
+/// ```
+pub fn debug_routes<S>(
+    server_signals: ServerSignals,
+    authorize: impl Fn(&HeaderMap) -> bool + Clone + Send + Sync + 'static,
+) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let json_signals = server_signals.clone();
+    let json_authorize = authorize.clone();
+    Router::new()
+        .route(
+            "/",
+            get(move |headers: HeaderMap| async move {
+                if !authorize(&headers) {
+                    return StatusCode::UNAUTHORIZED.into_response();
                 }
-            } else {
-                break;
+                Html(debug_html(&debug_report(&server_signals).await)).into_response()
+            }),
+        )
+        .route(
+            "/json",
+            get(move |headers: HeaderMap| async move {
+                if !json_authorize(&headers) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                let report = debug_report(&json_signals).await;
+                (
+                    [(header::CONTENT_TYPE, "application/json")],
+                    report.to_string(),
+                )
+                    .into_response()
+            }),
+        )
+}
+
+/// Collects the report of [`debug_routes`]. Durations are in milliseconds since the event.
+async fn debug_report(server_signals: &ServerSignals) -> Value {
+    let values = server_signals.export_snapshot().await.unwrap_or_default();
+    let signals: Vec<Value> = server_signals
+        .iter_signals()
+        .await
+        .into_iter()
+        .map(|signal| {
+            json!({
+                "name": signal.name,
+                "type": signal.type_name,
+                "subscribers": signal.subscribers,
+                "last_update_ms": signal.last_update.map(|at| at.elapsed().as_millis() as u64),
+                "json_bytes": signal.json_bytes,
+                "value": values.get(&signal.name),
+            })
+        })
+        .collect();
+    let connections: Vec<Value> = server_signals
+        .connections()
+        .await
+        .into_iter()
+        .map(|connection| {
+            let capabilities = connection.capabilities();
+            json!({
+                "id": connection.id().to_string(),
+                "identity": connection.identity(),
+                "previous_node": connection.previous_node(),
+                "last_seen_ms": connection.last_seen().elapsed().as_millis() as u64,
+                "protocol_version": capabilities.protocol_version,
+                "features": capabilities.features,
+            })
+        })
+        .collect();
+    let throughput: serde_json::Map<String, Value> = server_signals
+        .identity_stats()
+        .into_iter()
+        .map(|(identity, stats)| {
+            let stats = json!({
+                "connections": stats.connections,
+                "received_per_second": stats.received_per_second,
+                "sent_per_second": stats.sent_per_second,
+            });
+            (identity, stats)
+        })
+        .collect();
+    let latency = server_signals.latency_histogram();
+    let buckets: Vec<Value> = latency
+        .buckets()
+        .map(|(bound, count)| {
+            json!({
+                "le_ms": bound.map(|bound| bound.as_millis() as u64),
+                "count": count,
+            })
+        })
+        .collect();
+    let percentile = |percentile| {
+        latency
+            .percentile(percentile)
+            .map(|bound| bound.as_millis() as u64)
+    };
+    json!({
+        "signals": signals,
+        "connections": connections,
+        "throughput": throughput,
+        "tenants": server_signals.tenants(),
+        "latency": {
+            "count": latency.count(),
+            "p50_ms": percentile(0.5),
+            "p99_ms": percentile(0.99),
+            "buckets": buckets,
+        },
+    })
+}
+
+/// Renders the report of [`debug_routes`] as tables.
+fn debug_html(report: &Value) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><title>leptos_ws</title></head><body><h1>leptos_ws</h1>",
+    );
+    for (section, columns) in [
+        (
+            "signals",
+            &[
+                "name",
+                "type",
+                "subscribers",
+                "last_update_ms",
+                "json_bytes",
+                "value",
+            ][..],
+        ),
+        (
+            "connections",
+            &["id", "identity", "last_seen_ms", "protocol_version"][..],
+        ),
+    ] {
+        html.push_str(&format!("<h2>{section}</h2><table border=\"1\"><tr>"));
+        for column in columns {
+            html.push_str(&format!("<th>{column}</th>"));
+        }
+        html.push_str("</tr>");
+        for row in report[section].as_array().into_iter().flatten() {
+            html.push_str("<tr>");
+            for column in columns {
+                let cell = match &row[*column] {
+                    Value::String(text) => text.clone(),
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+                html.push_str(&format!("<td><code>{}</code></td>", escape(&cell)));
             }
+            html.push_str("</tr>");
         }
-    })
+        html.push_str("</table>");
+    }
+    html.push_str("<h2>throughput</h2><table border=\"1\">");
+    html.push_str(
+        "<tr><th>identity</th><th>connections</th><th>received/s</th><th>sent/s</th></tr>",
+    );
+    for (identity, stats) in report["throughput"].as_object().into_iter().flatten() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(identity),
+            stats["connections"],
+            stats["received_per_second"],
+            stats["sent_per_second"],
+        ));
+    }
+    html.push_str("</table>");
+    // Counts of updates up to each bound, as measured by clients with `measure_latency`
+    html.push_str("<h2>latency</h2><table border=\"1\"><tr><th>up to</th><th>updates</th></tr>");
+    for bucket in report["latency"]["buckets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let bound = match bucket["le_ms"].as_u64() {
+            Some(bound) => format!("{bound}ms"),
+            None => "slower".to_string(),
+        };
+        html.push_str(&format!(
+            "<tr><td>{bound}</td><td>{}</td></tr>",
+            bucket["count"]
+        ));
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Reads the value of the cookie `name` from the request headers.
+fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+}
+
+fn set_affinity_cookie(response: &mut Response, affinity: &AffinityConfig) {
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax",
+        affinity.cookie, affinity.node
+    );
+    match HeaderValue::from_str(&cookie) {
+        Ok(cookie) => {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+        Err(_) => error!("Invalid affinity cookie {cookie}"),
+    }
+}
+
+/// Runs the protocol over an axum socket, see [`serve`].
+async fn handle_socket(
+    socket: WebSocket,
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+    previous_node: Option<String>,
+    identity: Option<String>,
+) {
+    let (sink, stream) = socket.split();
+    serve(
+        stream.map_ok(Frame::from),
+        sink.with(|frame: Frame| ready(Ok::<_, axum::Error>(Message::from(frame)))),
+        server_signals,
+        config,
+        previous_node,
+        identity,
+    )
     .await;
 }
+
+impl From<Message> for Frame {
+    fn from(message: Message) -> Self {
+        match message {
+            Message::Text(text) => Frame::Text(text),
+            Message::Binary(data) => Frame::Binary(data),
+            Message::Ping(payload) => Frame::Ping(payload),
+            Message::Pong(payload) => Frame::Pong(payload),
+            Message::Close(_) => Frame::Close,
+        }
+    }
+}
+
+impl From<Frame> for Message {
+    fn from(frame: Frame) -> Self {
+        match frame {
+            Frame::Text(text) => Message::Text(text),
+            Frame::Binary(data) => Message::Binary(data),
+            Frame::Ping(payload) => Message::Ping(payload),
+            Frame::Pong(payload) => Message::Pong(payload),
+            Frame::Close => Message::Close(None),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::error::Error;
+use crate::messages::ServerSignalUpdate;
+
+/// Fans signal updates out to the other nodes of a cluster.
+///
+/// Every update of a [`ServerSignal`](crate::ServerSignal) is published once the local
+/// observers were notified. Updates received from other nodes are applied to the signal of the
+/// same name and forwarded to the local observers, but not published again.
+///
+/// Install a backend with [`ServerSignals::with_backend`](crate::server_signals::ServerSignals::with_backend).
+#[async_trait]
+pub trait Backend: Send + Sync {
+    async fn publish(&self, update: &ServerSignalUpdate) -> Result<(), Error>;
+    /// Returns every update published by other nodes. Updates published by this node must not
+    /// be returned again, otherwise they are applied twice.
+    async fn subscribe(&self) -> Result<BoxStream<'static, ServerSignalUpdate>, Error>;
+}
@@ -0,0 +1,350 @@
+//! Opens many clients speaking the wire protocol against a running server, to size a
+//! deployment before real users do.
+//!
+//! ```text
+//! leptos_ws-bench ws://localhost:3000/ws --signal count --signal stats \
+//!     --clients 1000 --duration 60 --ramp-up 10 --timestamp /sent_at
+//! ```
+//!
+//! Every client establishes the signals and counts the updates it receives. Updates it never
+//! received are detected by their sequence numbers and reported as dropped, e.g. because the
+//! server dropped slow clients' updates.
+//!
+//! Latency is only measured with `--timestamp`, a JSON pointer into the values of the signals
+//! to a number of milliseconds since the Unix epoch, which the server sets with every update.
+//! It is the time from that timestamp until the update was applied, so the clocks of the server
+//! and the machine running the benchmark have to agree, ideally by running both on the same one.
+
+use std::collections::HashMap;
+use std::process::exit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{SinkExt, StreamExt};
+use leptos_ws::messages::{
+    ClientCapabilities, HeartbeatMessage, Messages, ServerSignalMessage, ServerSignalUpdate,
+};
+use serde_json::Value;
+use tokio::time::{sleep_until, timeout_at, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const USAGE: &str = "Usage: leptos_ws-bench <url> --signal <name>... [--clients <n>] \
+[--duration <seconds>] [--ramp-up <seconds>] [--timestamp <json pointer>]";
+
+struct Options {
+    url: String,
+    signals: Vec<String>,
+    clients: usize,
+    duration: Duration,
+    ramp_up: Duration,
+    timestamp: Option<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut url = None;
+        let mut options = Options {
+            url: String::new(),
+            signals: Vec::new(),
+            clients: 100,
+            duration: Duration::from_secs(30),
+            ramp_up: Duration::ZERO,
+            timestamp: None,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or(format!("{flag} needs a value"));
+            match arg.as_str() {
+                "--signal" => options.signals.push(value("--signal")?),
+                "--clients" => options.clients = number(&value("--clients")?)?,
+                "--duration" => {
+                    options.duration = Duration::from_secs(number(&value("--duration")?)?)
+                }
+                "--ramp-up" => options.ramp_up = Duration::from_secs(number(&value("--ramp-up")?)?),
+                "--timestamp" => options.timestamp = Some(value("--timestamp")?),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+                _ if url.is_none() => url = Some(arg),
+                _ => return Err(format!("Unexpected argument {arg}")),
+            }
+        }
+        options.url = url.ok_or("Missing the url of the server")?;
+        if options.signals.is_empty() {
+            return Err("Pass at least one --signal".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{value} is not a number"))
+}
+
+/// What a single client observed.
+#[derive(Default)]
+struct ClientReport {
+    connected: bool,
+    connect_time: Duration,
+    updates: u64,
+    /// Updates skipped in the sequence numbers.
+    dropped: u64,
+    /// Complete values received again after the first one, e.g. after the server resent them.
+    resyncs: u64,
+    /// In milliseconds.
+    latencies: Vec<f64>,
+    error: Option<String>,
+}
+
+/// The state of an established signal.
+#[derive(Default)]
+struct SignalState {
+    value: Value,
+    established: bool,
+    last_seq: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            exit(2);
+        }
+    };
+    println!(
+        "{} clients on {}, {} signals for {}s",
+        options.clients,
+        options.url,
+        options.signals.len(),
+        options.duration.as_secs()
+    );
+    let options = std::sync::Arc::new(options);
+    let started = Instant::now();
+    let end = started + options.ramp_up + options.duration;
+    let clients = (0..options.clients)
+        .map(|index| {
+            let options = options.clone();
+            // Spread evenly over the ramp up
+            let start = started
+                + options
+                    .ramp_up
+                    .mul_f64(index as f64 / options.clients as f64);
+            tokio::spawn(async move {
+                sleep_until(start).await;
+                run_client(&options, end).await
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut reports = Vec::with_capacity(clients.len());
+    for client in clients {
+        reports.push(client.await.unwrap_or_else(|err| ClientReport {
+            error: Some(err.to_string()),
+            ..ClientReport::default()
+        }));
+    }
+    print_report(&reports, options.duration);
+}
+
+async fn run_client(options: &Options, end: Instant) -> ClientReport {
+    let mut report = ClientReport::default();
+    let connecting = Instant::now();
+    let socket = match timeout_at(end, connect_async(&options.url)).await {
+        Ok(Ok((socket, _))) => socket,
+        Ok(Err(err)) => {
+            report.error = Some(err.to_string());
+            return report;
+        }
+        Err(_) => {
+            report.error = Some("Timed out connecting".to_string());
+            return report;
+        }
+    };
+    report.connected = true;
+    report.connect_time = connecting.elapsed();
+    let (mut sink, mut stream) = socket.split();
+    // Compressed and encrypted messages aren't unpacked
+    let hello = Messages::Hello(ClientCapabilities {
+        compression: false,
+        ..ClientCapabilities::current()
+    });
+    let mut outgoing = vec![hello];
+    outgoing.extend(
+        options
+            .signals
+            .iter()
+            .map(|name| Messages::ServerSignal(ServerSignalMessage::Establish(name.clone()))),
+    );
+    for message in outgoing {
+        if let Err(err) = sink.send(text(&message)).await {
+            report.error = Some(err.to_string());
+            return report;
+        }
+    }
+    let mut signals: HashMap<String, SignalState> = HashMap::new();
+    loop {
+        let message = match timeout_at(end, stream.next()).await {
+            Err(_) => break,
+            Ok(None) => {
+                report.error = Some("The server closed the connection".to_string());
+                break;
+            }
+            Ok(Some(Err(err))) => {
+                report.error = Some(err.to_string());
+                break;
+            }
+            Ok(Some(Ok(Message::Text(text)))) => text,
+            // Pings are answered by tungstenite
+            Ok(Some(Ok(_))) => continue,
+        };
+        let Ok(message) = serde_json::from_str::<Messages>(&message) else {
+            continue;
+        };
+        let messages = match message {
+            Messages::Batch(messages) => messages,
+            message => vec![message],
+        };
+        for message in messages {
+            let reply = apply(message, &mut signals, &mut report, options);
+            if let Some(reply) = reply {
+                let _ = sink.send(text(&reply)).await;
+            }
+        }
+    }
+    let _ = sink.close().await;
+    report
+}
+
+/// Applies a message to the signals, returning the reply a real client would send.
+fn apply(
+    message: Messages,
+    signals: &mut HashMap<String, SignalState>,
+    report: &mut ClientReport,
+    options: &Options,
+) -> Option<Messages> {
+    match message {
+        Messages::ServerSignal(ServerSignalMessage::EstablishResponse((name, value))) => {
+            let signal = signals.entry(name).or_default();
+            if signal.established {
+                report.resyncs += 1;
+            }
+            signal.established = true;
+            signal.value = value;
+            signal.last_seq = None;
+        }
+        Messages::ServerSignal(ServerSignalMessage::Version(version)) => {
+            if let Some(signal) = signals.get_mut(&version.name) {
+                signal.last_seq = Some(version.seq);
+            }
+        }
+        Messages::ServerSignal(ServerSignalMessage::Update(update)) => {
+            let signal = signals.get_mut(update.name())?;
+            count_update(signal, &update, report);
+            if update.apply(&mut signal.value).is_err() {
+                report.error = Some(format!("Could not apply an update of {}", update.name()));
+                return None;
+            }
+            if let Some(sent_at) = options
+                .timestamp
+                .as_ref()
+                .and_then(|pointer| signal.value.pointer(pointer))
+                .and_then(Value::as_f64)
+            {
+                report.latencies.push(now_millis() - sent_at);
+            }
+        }
+        Messages::Heartbeat(HeartbeatMessage::Ping(id)) => {
+            return Some(Messages::Heartbeat(HeartbeatMessage::Pong(id)));
+        }
+        _ => {}
+    }
+    None
+}
+
+fn count_update(signal: &mut SignalState, update: &ServerSignalUpdate, report: &mut ClientReport) {
+    report.updates += 1;
+    // Updates for part of the connections aren't numbered
+    let Some(seq) = update.seq() else {
+        return;
+    };
+    if let Some(last) = signal.last_seq {
+        if seq <= last {
+            return;
+        }
+        report.dropped += seq - last - 1;
+    }
+    signal.last_seq = Some(seq);
+}
+
+fn text(message: &Messages) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap())
+}
+
+fn now_millis() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64() * 1000.0)
+}
+
+fn print_report(reports: &[ClientReport], duration: Duration) {
+    let connected = reports.iter().filter(|report| report.connected).count();
+    let failed = reports
+        .iter()
+        .filter(|report| report.error.is_some())
+        .count();
+    let updates = reports.iter().map(|report| report.updates).sum::<u64>();
+    let dropped = reports.iter().map(|report| report.dropped).sum::<u64>();
+    let resyncs = reports.iter().map(|report| report.resyncs).sum::<u64>();
+    let mut connect_times = reports
+        .iter()
+        .filter(|report| report.connected)
+        .map(|report| report.connect_time.as_secs_f64() * 1000.0)
+        .collect::<Vec<_>>();
+    let mut latencies = reports
+        .iter()
+        .flat_map(|report| report.latencies.iter().copied())
+        .collect::<Vec<_>>();
+
+    println!();
+    println!("Clients     {connected} connected, {failed} with errors");
+    println!("Connect     {}", percentiles(&mut connect_times));
+    println!(
+        "Updates     {updates} received, {:.1}/s",
+        updates as f64 / duration.as_secs_f64().max(1.0)
+    );
+    println!(
+        "Dropped     {dropped} ({:.2}%), {resyncs} complete values resent",
+        dropped as f64 * 100.0 / (updates + dropped).max(1) as f64
+    );
+    if latencies.is_empty() {
+        println!("Latency     not measured, pass --timestamp");
+    } else {
+        println!("Latency     {}", percentiles(&mut latencies));
+    }
+    let mut errors: HashMap<&str, usize> = HashMap::new();
+    for error in reports.iter().filter_map(|report| report.error.as_deref()) {
+        *errors.entry(error).or_default() += 1;
+    }
+    for (error, count) in errors {
+        println!("Error       {count}x {error}");
+    }
+}
+
+/// The p50, p90, p99 and maximum of milliseconds.
+fn percentiles(values: &mut [f64]) -> String {
+    if values.is_empty() {
+        return "-".to_string();
+    }
+    values.sort_by(f64::total_cmp);
+    let at = |percentile: f64| {
+        let index = ((values.len() - 1) as f64 * percentile).round() as usize;
+        values[index]
+    };
+    format!(
+        "p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+        at(0.5),
+        at(0.9),
+        at(0.99),
+        values[values.len() - 1]
+    )
+}
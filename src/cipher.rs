@@ -0,0 +1,155 @@
+//! Application-level encryption of all messages, for deployments where TLS ends at a proxy which
+//! must not see the signal values.
+//!
+//! Configure the same cipher on both sides, with [`WsConfig::cipher`](crate::config::WsConfig)
+//! on the client and `ConnectionConfig::cipher` on the server. Every message is then sent as a
+//! [`Messages::Encrypted`] and unencrypted messages are dropped.
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "encryption")] {
+//! # use leptos_ws::{cipher::XChaCha20Poly1305Cipher, config::WsConfig, provide_websocket_with_config};
+//! # let key = [0; 32];
+//! let cipher = XChaCha20Poly1305Cipher::new(key);
+//! // Client
+//! provide_websocket_with_config("/ws", WsConfig::default().cipher(cipher.clone()));
+//! // Server
+//! # #[cfg(feature = "server")]
+//! let config = leptos_ws::config::ConnectionConfig::default().cipher(cipher);
+//! # }
+//! ```
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::messages::Messages;
+
+#[cfg(feature = "encryption")]
+pub use xchacha::XChaCha20Poly1305Cipher;
+
+/// Encrypts and decrypts serialized messages.
+pub trait Cipher: Send + Sync {
+    /// Encrypts a serialized message, the result is sent as text.
+    fn encrypt(&self, plaintext: &str) -> Result<String, Error>;
+    /// Reverses [`Cipher::encrypt`], failing if the message was not encrypted with the same key
+    /// or was tampered with.
+    fn decrypt(&self, ciphertext: &str) -> Result<String, Error>;
+}
+
+/// A [`Cipher`] shared by all messages of a connection.
+#[derive(Clone)]
+pub struct SharedCipher(Arc<dyn Cipher>);
+
+impl Debug for SharedCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedCipher")
+    }
+}
+
+impl SharedCipher {
+    pub(crate) fn new(cipher: impl Cipher + 'static) -> Self {
+        Self(Arc::new(cipher))
+    }
+
+    /// Wraps a serialized message in a [`Messages::Encrypted`].
+    pub(crate) fn seal(&self, json: &str) -> Result<Messages, Error> {
+        Ok(Messages::Encrypted(self.0.encrypt(json)?))
+    }
+
+    /// Unpacks the payload of a [`Messages::Encrypted`].
+    pub(crate) fn open(&self, data: &str) -> Result<Messages, Error> {
+        Ok(serde_json::from_str(&self.0.decrypt(data)?)?)
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod xchacha {
+    use std::fmt::{Debug, Formatter};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chacha20poly1305::{
+        aead::{Aead, AeadCore, KeyInit, OsRng},
+        XChaCha20Poly1305, XNonce,
+    };
+
+    use super::Cipher;
+    use crate::error::Error;
+
+    const NONCE_LEN: usize = 24;
+
+    /// XChaCha20-Poly1305 with a key shared by the server and all clients.
+    ///
+    /// Every message gets a random nonce, which is sent in front of the ciphertext.
+    #[derive(Clone)]
+    pub struct XChaCha20Poly1305Cipher(XChaCha20Poly1305);
+
+    impl XChaCha20Poly1305Cipher {
+        pub fn new(key: [u8; 32]) -> Self {
+            Self(XChaCha20Poly1305::new(&key.into()))
+        }
+    }
+
+    impl Debug for XChaCha20Poly1305Cipher {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("XChaCha20Poly1305Cipher")
+        }
+    }
+
+    impl Cipher for XChaCha20Poly1305Cipher {
+        fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = self
+                .0
+                .encrypt(&nonce, plaintext.as_bytes())
+                .map_err(|_| Error::EncryptionFailed)?;
+            let mut sealed = nonce.to_vec();
+            sealed.extend(ciphertext);
+            Ok(STANDARD.encode(sealed))
+        }
+
+        fn decrypt(&self, ciphertext: &str) -> Result<String, Error> {
+            let sealed = STANDARD
+                .decode(ciphertext)
+                .map_err(|_| Error::EncryptionFailed)?;
+            if sealed.len() < NONCE_LEN {
+                return Err(Error::EncryptionFailed);
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let plaintext = self
+                .0
+                .decrypt(XNonce::from_slice(nonce), ciphertext)
+                .map_err(|_| Error::EncryptionFailed)?;
+            String::from_utf8(plaintext).map_err(|_| Error::EncryptionFailed)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_only_open_with_the_same_key() {
+        let cipher = XChaCha20Poly1305Cipher::new([7; 32]);
+        let sealed = cipher.encrypt("{\"Establish\":\"count\"}").unwrap();
+        assert_ne!(cipher.encrypt("{\"Establish\":\"count\"}").unwrap(), sealed);
+        assert_eq!(
+            cipher.decrypt(&sealed).unwrap(),
+            "{\"Establish\":\"count\"}"
+        );
+
+        let other = XChaCha20Poly1305Cipher::new([8; 32]);
+        assert!(matches!(
+            other.decrypt(&sealed),
+            Err(Error::EncryptionFailed)
+        ));
+        let mut tampered = sealed.into_bytes();
+        let last = tampered.len() - 3;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(matches!(
+            cipher.decrypt(&tampered),
+            Err(Error::EncryptionFailed)
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::client_signals::ClientSignals;
+use crate::error::Error;
+use crate::hooks::{UpdateOrigin, UpdateSummary};
+use crate::messages::{ClientInputMessage, InputRejection, Messages, ServerSignalUpdate};
+use crate::scope::scoped_name;
+use crate::stream::Streams;
+use crate::ServerSignalWebSocket;
+use futures::Stream;
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    any::Any,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+/// A signal written by the client and read by the server.
+///
+/// This is the opposite of [`ServerSignal`](crate::ServerSignal): every write on the client
+/// is diffed and sent to the server as a JSON patch, but the server never sends the value back.
+/// On every (re)connect the complete current value is sent once, so the server always starts
+/// from the same state as the client.
+///
+/// Writes are applied locally right away. Every patch carries a sequence number which the server
+/// acknowledges; if the server refuses a patch, the signal rolls back to the last value the server
+/// accepted and the callback registered with [`ClientInputSignal::on_rejected`] is called.
+///
+/// ```rust,no_run
+/// # use leptos::prelude::*;
+/// let position = leptos_ws::ClientInputSignal::new("cursor".to_string(), (0, 0)).unwrap();
+/// position.set((10, 20));
+/// ```
+#[derive(Clone)]
+pub struct ClientInputSignal<T>
+where
+    T: Clone + Send + Sync + for<'de> Deserialize<'de>,
+{
+    name: String,
+    value: ArcRwSignal<T>,
+    /// The value as last sent to the server, used as the base of the next diff.
+    json_value: Arc<RwLock<Value>>,
+    ws: ServerSignalWebSocket,
+    next_seq: Arc<AtomicU64>,
+    pending: ArcRwSignal<usize>,
+    last_update: ArcRwSignal<Option<UpdateSummary>>,
+    on_rejected: Arc<RwLock<Option<RejectionHook>>>,
+    /// See [`ClientInputSignal::with_send_debounce`].
+    send_debounce: Arc<RwLock<Option<Duration>>>,
+    /// Set while a debounced send is scheduled.
+    send_scheduled: Arc<AtomicBool>,
+    streams: Arc<Streams<T>>,
+}
+
+type RejectionHook = Arc<dyn Fn(&InputRejection) + Send + Sync>;
+
+pub trait ClientInputSignalTrait {
+    fn as_any(&self) -> &dyn Any;
+    /// The name of the concrete type, reported when a signal is looked up with another type.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+    /// Builds the message which (re)establishes this signal with its complete current value.
+    fn establish(&self) -> Result<Messages, Error>;
+    fn acknowledge(&self, seq: u64);
+    /// Rolls back to the value in the rejection and returns the message to re-establish the signal.
+    fn reject(&self, rejection: &InputRejection) -> Result<Messages, Error>;
+}
+
+impl<T> ClientInputSignalTrait for ClientInputSignal<T>
+where
+    T: Clone + Send + Sync + for<'de> Deserialize<'de> + 'static + Serialize,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn establish(&self) -> Result<Messages, Error> {
+        let json = serde_json::to_value(self.value.get_untracked())?;
+        *self
+            .json_value
+            .write()
+            .map_err(|_| Error::UpdateSignalFailed)? = json.clone();
+        Ok(Messages::ClientInput(ClientInputMessage::Establish((
+            self.name.clone(),
+            json,
+        ))))
+    }
+
+    fn acknowledge(&self, _seq: u64) {
+        self.pending
+            .update(|pending| *pending = pending.saturating_sub(1));
+    }
+
+    fn reject(&self, rejection: &InputRejection) -> Result<Messages, Error> {
+        let value: T = serde_json::from_value(rejection.value.clone())?;
+        *self
+            .json_value
+            .write()
+            .map_err(|_| Error::UpdateSignalFailed)? = rejection.value.clone();
+        self.streams.send_value(&value);
+        // Write the signal directly, the rollback must not be sent as a new update
+        self.value.set(value);
+        self.last_update
+            .set(Some(UpdateSummary::now(0, UpdateOrigin::Server)));
+        // The server drops everything still in flight until the signal is established again
+        self.pending.set(0);
+        let callback = self
+            .on_rejected
+            .read()
+            .map_err(|_| Error::UpdateSignalFailed)?
+            .clone();
+        if let Some(callback) = callback {
+            callback(rejection);
+        }
+        self.establish()
+    }
+}
+
+impl<T> ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    pub fn new(name: String, value: T) -> Result<Self, Error> {
+        let name = scoped_name(name);
+        let signals: ClientSignals =
+            use_context::<ClientSignals>().ok_or(Error::MissingServerSignals)?;
+        if let Some(signal) = signals.get_input::<ClientInputSignal<T>>(&name) {
+            return signal;
+        }
+        let ws = signals.ws()?;
+        let new_signal = Self {
+            name: name.clone(),
+            json_value: Arc::new(RwLock::new(serde_json::to_value(&value)?)),
+            value: ArcRwSignal::new(value),
+            ws,
+            next_seq: Arc::default(),
+            pending: ArcRwSignal::new(0),
+            last_update: ArcRwSignal::new(None),
+            on_rejected: Arc::default(),
+            send_debounce: Arc::default(),
+            send_scheduled: Arc::default(),
+            streams: Arc::default(),
+        };
+        signals.get_or_create_input(name, new_signal)
+    }
+
+    /// Registers a callback which is called after the server refused an update and the signal
+    /// was rolled back to the last value the server accepted.
+    ///
+    /// Replaces any previously registered callback.
+    pub fn on_rejected(&self, callback: impl Fn(&InputRejection) + Send + Sync + 'static) {
+        if let Ok(mut on_rejected) = self.on_rejected.write() {
+            *on_rejected = Some(Arc::new(callback));
+        }
+    }
+
+    /// Validators only run on the server. Rejected values are reported through
+    /// [`ClientInputSignal::on_rejected`].
+    pub fn with_validator(
+        self,
+        _validator: impl Fn(&T) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self
+    }
+
+    /// Sends at most one update per `interval`, with the net change of all writes in between,
+    /// e.g. for a slider which is written on every mouse move. Writes are still applied locally
+    /// right away.
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use leptos_ws::ClientInputSignal;
+    /// let volume = ClientInputSignal::new("volume".to_string(), 50u8)
+    ///     .unwrap()
+    ///     .with_send_debounce(Duration::from_millis(100));
+    /// ```
+    pub fn with_send_debounce(self, interval: Duration) -> Self {
+        if let Ok(mut send_debounce) = self.send_debounce.write() {
+            *send_debounce = Some(interval);
+        }
+        self
+    }
+
+    /// The number of updates which were sent but not yet acknowledged by the server.
+    pub fn pending(&self) -> Signal<usize> {
+        self.pending.clone().into()
+    }
+
+    /// When and how the value last changed, `None` until it is first written.
+    pub fn update_summary(&self) -> Signal<Option<UpdateSummary>> {
+        self.last_update.clone().into()
+    }
+
+    /// Every value from now on, written locally or rolled back by the server. Dropping the stream
+    /// unsubscribes it.
+    pub fn to_stream(&self) -> impl Stream<Item = T> + Send + 'static {
+        self.streams.values()
+    }
+
+    /// Every patch sent to the server from now on. With a send debounce, that is one patch per
+    /// interval with the net change.
+    pub fn patch_stream(&self) -> impl Stream<Item = ServerSignalUpdate> + Send + 'static {
+        self.streams.patches()
+    }
+
+    /// Sends the change since the last update, or schedules it with a send debounce.
+    fn written(&self) {
+        let debounce = self
+            .send_debounce
+            .read()
+            .ok()
+            .and_then(|debounce| *debounce);
+        match debounce {
+            Some(interval) => {
+                if !self.send_scheduled.swap(true, Ordering::SeqCst) {
+                    let signal = self.clone();
+                    set_timeout(
+                        move || {
+                            signal.send_scheduled.store(false, Ordering::SeqCst);
+                            signal.send();
+                        },
+                        interval,
+                    );
+                }
+            }
+            None => self.send(),
+        }
+    }
+
+    fn send(&self) {
+        match self.send_update() {
+            Ok(operations) => self.last_update.set(Some(UpdateSummary::now(
+                operations,
+                UpdateOrigin::ThisClient,
+            ))),
+            Err(err) => leptos::logging::error!("Could not send client input: {err}"),
+        }
+    }
+
+    /// Returns the number of patch operations sent.
+    fn send_update(&self) -> Result<usize, Error> {
+        // While disconnected nothing is sent, the next establish carries the complete value
+        if !self.ws.is_open() {
+            return Ok(0);
+        }
+        let new_json = serde_json::to_value(self.value.get_untracked())?;
+        let mut json = self
+            .json_value
+            .write()
+            .map_err(|_| Error::UpdateSignalFailed)?;
+        let update = ServerSignalUpdate::new_from_json(self.name.clone(), &json, &new_json);
+        let operations = update.patch.0.len();
+        if operations == 0 {
+            return Ok(0);
+        }
+        *json = new_json;
+        drop(json);
+        self.streams.send_patch(&update);
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.ws
+            .send(&Messages::ClientInput(ClientInputMessage::Update((
+                seq, update,
+            ))))?;
+        self.pending.update(|pending| *pending += 1);
+        Ok(operations)
+    }
+}
+
+impl<T> Update for ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    type Value = T;
+
+    fn try_maybe_update<U>(&self, fun: impl FnOnce(&mut Self::Value) -> (bool, U)) -> Option<U> {
+        let mut lock = self.value.try_write()?;
+        let (did_update, val) = fun(&mut *lock);
+        if !did_update {
+            lock.untrack();
+        }
+        drop(lock);
+        if did_update {
+            if let Some(value) = self.value.try_get_untracked() {
+                self.streams.send_value(&value);
+            }
+            self.written();
+        }
+        Some(val)
+    }
+}
+
+impl<T> IsDisposed for ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    fn is_disposed(&self) -> bool {
+        self.value.is_disposed()
+    }
+}
+
+impl<T> DefinedAt for ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    fn defined_at(&self) -> Option<&'static Location<'static>> {
+        self.value.defined_at()
+    }
+}
+
+impl<T> Track for ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    #[track_caller]
+    fn track(&self) {
+        self.value.track()
+    }
+}
+
+impl<T> ReadUntracked for ClientInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    type Value = <ArcRwSignal<T> as ReadUntracked>::Value;
+
+    fn try_read_untracked(&self) -> Option<Self::Value> {
+        self.value.try_read_untracked()
+    }
+}
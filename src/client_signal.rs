@@ -1,33 +1,114 @@
+use crate::config::InitialValue;
 use crate::error::Error;
+use crate::hooks::{UpdateOrigin, UpdateSummary};
+use crate::hydration;
+use crate::local_storage;
+use crate::messages::{Messages, ServerSignalMessage};
+use crate::scope::scoped_name;
+use crate::stream::Streams;
+use crate::ServerSignalWebSocket;
 use crate::{client_signals::ClientSignals, messages::ServerSignalUpdate};
-use async_trait::async_trait;
+use futures::Stream;
 use leptos::prelude::*;
+use leptos::reactive::graph::Observer;
+use leptos_use::core::ConnectionReadyState;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::mpsc::{channel, Receiver, Sender};
 use std::{
     any::Any,
+    future::Future,
     ops::{Deref, DerefMut},
-    sync::{Arc, RwLock},
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
 };
-use thiserror::Error;
 
 #[derive(Clone, Debug)]
 pub struct ClientSignal<T>
 where
     T: Clone + Send + Sync + for<'de> Deserialize<'de>,
 {
+    name: String,
     value: ArcRwSignal<T>,
     json_value: Arc<RwLock<Value>>,
+    subscription: Arc<Subscription>,
+    /// Set once the first value was received from the server.
+    synced: ArcRwSignal<bool>,
+    /// Set while the server doesn't broadcast updates, see `ServerSignals::disable`.
+    paused: ArcRwSignal<bool>,
+    /// Set when the server refused to establish the signal, see `ServerSignal::new_with_acl`.
+    denied: ArcRwSignal<bool>,
+    last_update: ArcRwSignal<Option<UpdateSummary>>,
+    /// The latency of the last update, see [`ClientSignal::latency`].
+    latency: ArcRwSignal<Duration>,
+    /// Whether [`ClientSignal::ready`] waits for the value of the server.
+    await_server: bool,
+    /// Whether every value is cached in `localStorage`, see [`ClientSignal::new_persistent`].
+    persistent: bool,
+    streams: Arc<Streams<T>>,
+    ws: ServerSignalWebSocket,
 }
 
-#[async_trait]
+/// Tracks whether the server currently sends updates for a signal.
+#[derive(Debug)]
+struct Subscription {
+    /// Lazy signals are only established while they are read in a reactive context.
+    lazy: bool,
+    established: AtomicBool,
+    readers: AtomicUsize,
+    /// The reactive owners which created or looked up the signal and weren't disposed yet.
+    owners: AtomicUsize,
+}
+
+/// The client half of a signal kind, as stored in [`ClientSignals`].
+///
+/// See [`extension`](crate::extension) for how to implement custom signal kinds.
 pub trait ClientSignalTrait {
     fn as_any(&self) -> &dyn Any;
+    /// The name of the concrete type, reported when a signal is looked up with another type.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+    /// The name of the value type, compared with the one of the server signal to detect
+    /// signals declared with different types on both sides. `None` skips the check.
+    fn schema(&self) -> Option<&'static str> {
+        None
+    }
+    /// Applies a patch broadcast by the server.
     fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error>;
     fn json(&self) -> Result<Value, Error>;
+    /// Replaces the value with the complete value sent by the server when the signal is established.
     fn set_json(&self, new_value: Value) -> Result<(), Error>;
+    /// Called when the server paused updates of the signal, until the next
+    /// [`ClientSignalTrait::set_json`].
+    fn pause(&self) {}
+    /// Called when the server refused to establish the signal.
+    fn deny(&self) {}
     fn track(&self);
+    /// Called with the latency of every update before it is applied, if
+    /// [`WsConfig::measure_latency`](crate::config::WsConfig) is enabled.
+    fn measured(&self, latency: Duration) {
+        let _ = latency;
+    }
+    /// Whether the server should send updates for this signal, i.e. it has to be
+    /// established on (re)connect.
+    fn is_subscribed(&self) -> bool;
+    /// Metadata sent along with every establish message, passed to
+    /// [`ServerSignalTrait::establish`](crate::extension) on the server.
+    fn establish_payload(&self) -> Option<Value> {
+        None
+    }
+    fn establish_message(&self, name: String) -> Messages {
+        match self.establish_payload() {
+            Some(payload) => {
+                Messages::ServerSignal(ServerSignalMessage::EstablishWithPayload((name, payload)))
+            }
+            None => Messages::ServerSignal(ServerSignalMessage::Establish(name)),
+        }
+    }
 }
 impl<T> ClientSignalTrait for ClientSignal<T>
 where
@@ -37,23 +118,40 @@ where
         self
     }
 
+    fn schema(&self) -> Option<&'static str> {
+        Some(std::any::type_name::<T>())
+    }
+
     #[track_caller]
     fn track(&self) {
         self.value.track()
     }
 
+    fn is_subscribed(&self) -> bool {
+        self.subscription.established.load(Ordering::SeqCst)
+    }
+
+    fn measured(&self, latency: Duration) {
+        self.latency.set(latency);
+    }
+
     fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
         let mut writer = self
             .json_value
             .write()
             .map_err(|_| Error::UpdateSignalFailed)?;
-        if json_patch::patch(writer.deref_mut(), &patch.patch).is_ok() {
-            *self.value.write() = serde_json::from_value(writer.clone())
-                .map_err(|err| Error::SerializationFailed(err))?;
-            Ok(())
-        } else {
-            Err(Error::UpdateSignalFailed)
-        }
+        patch.apply(writer.deref_mut())?;
+        let value: T =
+            serde_json::from_value(writer.clone()).map_err(Error::SerializationFailed)?;
+        self.streams.send_value(&value);
+        self.streams.send_patch(&patch);
+        *self.value.write() = value;
+        self.last_update.set(Some(UpdateSummary::now(
+            patch.patch.0.len(),
+            UpdateOrigin::Server,
+        )));
+        self.cache(&writer);
+        Ok(())
     }
     fn json(&self) -> Result<Value, Error> {
         Ok(serde_json::to_value(self.value.get())?)
@@ -64,10 +162,32 @@ where
             .write()
             .map_err(|_| Error::UpdateSignalFailed)?;
         *writer = new_value;
-        *self.value.write() = serde_json::from_value(writer.clone())
-            .map_err(|err| Error::SerializationFailed(err))?;
+        let value: T =
+            serde_json::from_value(writer.clone()).map_err(Error::SerializationFailed)?;
+        self.streams.send_value(&value);
+        *self.value.write() = value;
+        self.last_update
+            .set(Some(UpdateSummary::now(0, UpdateOrigin::Server)));
+        self.cache(&writer);
+        if !self.synced.get_untracked() {
+            self.synced.set(true);
+        }
+        if self.paused.get_untracked() {
+            self.paused.set(false);
+        }
+        if self.denied.get_untracked() {
+            self.denied.set(false);
+        }
         Ok(())
     }
+
+    fn pause(&self) {
+        self.paused.set(true);
+    }
+
+    fn deny(&self) {
+        self.denied.set(true);
+    }
 }
 
 impl<T> ClientSignal<T>
@@ -75,20 +195,343 @@ where
     T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
 {
     pub fn new(name: String, value: T) -> Result<Self, Error> {
+        Self::new_with_mode(name, value, false, default_initial_value(), false)
+    }
+
+    /// Like [`ClientSignal::new`], with a policy for what to show before the value of the server
+    /// arrived.
+    pub fn new_with_initial(
+        name: String,
+        value: T,
+        initial_value: InitialValue,
+    ) -> Result<Self, Error> {
+        Self::new_with_mode(name, value, false, initial_value, false)
+    }
+
+    /// Same as [`ClientSignal::new`]. Updates are throttled by the server.
+    pub fn throttled(name: String, value: T, _interval: Duration) -> Result<Self, Error> {
+        Self::new(name, value)
+    }
+
+    /// Same as [`ClientSignal::new`]. The ACL is only checked by the server.
+    pub fn new_with_acl(
+        name: String,
+        value: T,
+        _acl: impl Fn(Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        Self::new(name, value)
+    }
+
+    /// Like [`ClientSignal::new`], but the signal is only established with the server once it is
+    /// first read in a reactive context (e.g. by `get()` inside a view or effect).
+    ///
+    /// When the last reactive reader is disposed, the server is told to stop sending updates.
+    /// Reading the signal again re-establishes it and fetches the current value.
+    pub fn new_lazy(name: String, value: T) -> Result<Self, Error> {
+        Self::new_with_mode(name, value, true, default_initial_value(), false)
+    }
+
+    /// Like [`ClientSignal::new`], but every value received from the server is cached in
+    /// `localStorage`. A returning user starts with the cached value instead of `value` while the
+    /// socket connects, and then receives the current one.
+    ///
+    /// A value embedded into the SSR payload is newer than the cache and takes precedence.
+    pub fn new_persistent(name: String, value: T) -> Result<Self, Error> {
+        Self::new_with_mode(name, value, false, default_initial_value(), true)
+    }
+
+    /// Same as [`ClientSignal::new`], the fields are skipped by the server. They never arrive, so
+    /// they have to be `#[serde(default)]`.
+    pub fn new_skipping(name: String, value: T, skip: &[&str]) -> Result<Self, Error> {
+        let _ = skip;
+        Self::new(name, value)
+    }
+
+    fn new_with_mode(
+        name: String,
+        value: T,
+        lazy: bool,
+        initial_value: InitialValue,
+        persistent: bool,
+    ) -> Result<Self, Error> {
+        let name = scoped_name(name);
+        // Taken for every signal, so the slots stay aligned with the server
+        let rendered = hydration::take();
         let mut signals: ClientSignals =
             use_context::<ClientSignals>().ok_or(Error::MissingServerSignals)?;
-        if signals.contains(&name) {
-            return Ok(signals.get_signal::<ClientSignal<T>>(&name).unwrap());
+        if let Some(signal) = signals.get_signal::<ClientSignal<T>>(&name) {
+            return signal.inspect(|signal| signal.hold(&signals));
         }
+        let value = match rendered.filter(|_| initial_value == InitialValue::UseSsrSerializedValue)
+        {
+            Some(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                leptos::logging::error!("Ignoring the SSR value of {name}: {err}");
+                value
+            }),
+            None => match local_storage::load(&name).filter(|_| persistent) {
+                // A cache of an older version of `T` is just ignored
+                Some(json) => serde_json::from_str(&json).unwrap_or(value),
+                None => value,
+            },
+        };
         let new_signal = Self {
+            name: name.clone(),
             value: ArcRwSignal::new(value.clone()),
             json_value: Arc::new(RwLock::new(
-                serde_json::to_value(value).map_err(|err| Error::SerializationFailed(err))?,
+                serde_json::to_value(value).map_err(Error::SerializationFailed)?,
             )),
+            subscription: Arc::new(Subscription {
+                lazy,
+                established: AtomicBool::new(!lazy),
+                readers: AtomicUsize::new(0),
+                owners: AtomicUsize::new(0),
+            }),
+            synced: ArcRwSignal::new(false),
+            paused: ArcRwSignal::new(false),
+            denied: ArcRwSignal::new(false),
+            last_update: ArcRwSignal::new(None),
+            latency: ArcRwSignal::new(Duration::ZERO),
+            await_server: initial_value == InitialValue::AwaitServer,
+            persistent,
+            streams: Arc::default(),
+            ws: signals.ws()?,
         };
-        let signal = new_signal.clone();
-        signals.create_signal(name, new_signal).unwrap();
-        Ok(signal)
+        signals
+            .get_or_create_signal(name, new_signal)
+            .inspect(|signal| signal.hold(&signals))
+    }
+
+    /// Keeps the signal registered while the current reactive owner lives. Once every owner
+    /// which created or looked up the signal is disposed, e.g. when navigating away from a page,
+    /// the server is told to stop sending updates and the signal is removed from the registry.
+    ///
+    /// Signals created outside of a reactive owner stay registered.
+    fn hold(&self, signals: &ClientSignals) {
+        if Owner::current().is_none() {
+            return;
+        }
+        self.subscription.owners.fetch_add(1, Ordering::SeqCst);
+        let ws = self.ws.clone();
+        let name = self.name.clone();
+        let subscription = Arc::clone(&self.subscription);
+        let signals = signals.clone();
+        on_cleanup(move || {
+            if subscription.owners.fetch_sub(1, Ordering::SeqCst) != 1 {
+                return;
+            }
+            // The page navigated to is mounted right after, and may use the signal as well
+            set_timeout(
+                move || {
+                    if subscription.owners.load(Ordering::SeqCst) != 0 {
+                        return;
+                    }
+                    if subscription.established.swap(false, Ordering::SeqCst) {
+                        let _ = ws.send(&Messages::ServerSignal(ServerSignalMessage::Unsubscribe(
+                            name.clone(),
+                        )));
+                    }
+                    signals.remove_signal(&name);
+                },
+                Duration::ZERO,
+            );
+        });
+    }
+
+    fn cache(&self, json: &Value) {
+        if self.persistent {
+            local_storage::store(&self.name, &json.to_string());
+        }
+    }
+
+    /// When and how the value last changed, `None` until the first value was received from the
+    /// server. Useful to render "updated 3s ago" or to highlight changed parts of the UI.
+    pub fn update_summary(&self) -> Signal<Option<UpdateSummary>> {
+        self.last_update.clone().into()
+    }
+
+    /// How long the last update took from the server until it was received, zero until the
+    /// first one was measured. Only measured with
+    /// [`WsConfig::measure_latency`](crate::config::WsConfig), see [`latency`](crate::latency).
+    pub fn latency(&self) -> Signal<Duration> {
+        self.latency.clone().into()
+    }
+
+    /// Every value received from the server from now on, e.g. to feed a chart or an audio node
+    /// without going through an effect. Dropping the stream unsubscribes it.
+    ///
+    /// ```rust,no_run
+    /// # use futures::StreamExt;
+    /// # use leptos::task::spawn_local;
+    /// # struct Chart;
+    /// # impl Chart {
+    /// #     fn push(&mut self, _price: f64) {}
+    /// # }
+    /// # let price = leptos_ws::ServerSignal::new("price".to_string(), 0.0).unwrap();
+    /// # let mut chart = Chart;
+    /// let mut prices = price.to_stream();
+    /// spawn_local(async move {
+    ///     while let Some(price) = prices.next().await {
+    ///         chart.push(price);
+    ///     }
+    /// });
+    /// ```
+    pub fn to_stream(&self) -> impl Stream<Item = T> + Send + 'static {
+        self.streams.values()
+    }
+
+    /// Every patch received from the server from now on. Complete values, e.g. after a
+    /// reconnect, are only sent on [`ClientSignal::to_stream`].
+    pub fn patch_stream(&self) -> impl Stream<Item = ServerSignalUpdate> + Send + 'static {
+        self.streams.patches()
+    }
+
+    /// The value as an async derived signal, for use in a `Suspense`. With
+    /// [`InitialValue::AwaitServer`] it is pending until the value of the server arrived,
+    /// otherwise it is ready right away.
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::{config::InitialValue, ServerSignal};
+    /// # fn view() -> Result<impl IntoView, leptos_ws::error::Error> {
+    /// let count = ServerSignal::new_with_initial("count".to_string(), 0, InitialValue::AwaitServer)?;
+    /// let ready = count.ready();
+    /// # Ok(
+    /// view! {
+    ///     <Suspense fallback=|| "Loading...">
+    ///         {move || {
+    ///             let ready = ready.clone();
+    ///             Suspend::new(async move { ready.await })
+    ///         }}
+    ///     </Suspense>
+    /// }
+    /// # )
+    /// # }
+    /// ```
+    pub fn ready(&self) -> ArcAsyncDerived<T> {
+        self.derived(self.await_server)
+    }
+
+    /// Resolves with the value once the first value of the server arrived, regardless of the
+    /// [`InitialValue`] policy, e.g. inside a resource:
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::ServerSignal;
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// let count = ServerSignal::new("count".to_string(), 0)?;
+    /// let doubled = Resource::new(|| (), move |_| {
+    ///     let count = count.clone();
+    ///     async move { count.await_established().await * 2 }
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn await_established(&self) -> impl Future<Output = T> {
+        let derived = self.derived(true);
+        // The derived signal has to outlive the future it is awaited with
+        async move { derived.clone().await }
+    }
+
+    fn derived(&self, await_server: bool) -> ArcAsyncDerived<T> {
+        let synced = self.synced.clone();
+        let value = self.value.clone();
+        ArcAsyncDerived::new(move || {
+            let ready = !await_server || synced.get();
+            let value = value.get();
+            async move {
+                if !ready {
+                    // Dropped once `synced` changes and the derived signal reruns
+                    std::future::pending::<()>().await;
+                }
+                value
+            }
+        })
+    }
+
+    /// Whether a value was received from the server yet.
+    pub(crate) fn synced(&self) -> ArcReadSignal<bool> {
+        self.synced.read_only()
+    }
+
+    /// Whether the server refused to establish this signal.
+    pub(crate) fn denied(&self) -> ArcReadSignal<bool> {
+        self.denied.read_only()
+    }
+
+    /// Whether the server currently doesn't broadcast updates of this signal.
+    pub(crate) fn paused(&self) -> ArcReadSignal<bool> {
+        self.paused.read_only()
+    }
+
+    /// The state of the connection this signal is synced over.
+    pub(crate) fn ready_state(&self) -> Signal<ConnectionReadyState> {
+        self.ws.ready_state
+    }
+
+    /// Tells the server to stop sending updates, e.g. for a signal which is no longer displayed.
+    /// The signal keeps its last value and isn't established again on reconnect, until
+    /// [`ClientSignal::resubscribe`] is called.
+    ///
+    /// A lazy signal is established again by its next reactive read.
+    pub fn unsubscribe(&self) {
+        if self.subscription.established.swap(false, Ordering::SeqCst) {
+            if let Err(err) =
+                self.ws
+                    .send(&Messages::ServerSignal(ServerSignalMessage::Unsubscribe(
+                        self.name.clone(),
+                    )))
+            {
+                leptos::logging::error!("Could not unsubscribe signal {}: {err}", self.name);
+            }
+        }
+    }
+
+    /// Establishes the signal again after [`ClientSignal::unsubscribe`], the server answers with
+    /// the current value.
+    pub fn resubscribe(&self) {
+        if !self.subscription.established.swap(true, Ordering::SeqCst) {
+            if let Err(err) = self.ws.send(&self.establish_message(self.name.clone())) {
+                leptos::logging::error!("Could not establish signal {}: {err}", self.name);
+            }
+        }
+    }
+
+    /// Whether the server currently sends updates of this signal.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscription.established.load(Ordering::SeqCst)
+    }
+
+    fn add_reader(&self) {
+        let ws = self.ws.clone();
+        let subscription = &self.subscription;
+        if subscription.readers.fetch_add(1, Ordering::SeqCst) == 0
+            && !subscription.established.swap(true, Ordering::SeqCst)
+        {
+            if let Err(err) = ws.send(&self.establish_message(self.name.clone())) {
+                leptos::logging::error!("Could not establish signal {}: {err}", self.name);
+            }
+        }
+        let name = self.name.clone();
+        let subscription = Arc::clone(&self.subscription);
+        on_cleanup(move || {
+            if subscription.readers.fetch_sub(1, Ordering::SeqCst) != 1 {
+                return;
+            }
+            // Readers are disposed right before an effect runs again, so only unsubscribe if
+            // nobody picked the signal up again in the meantime
+            set_timeout(
+                move || {
+                    if subscription.readers.load(Ordering::SeqCst) == 0
+                        && subscription.established.swap(false, Ordering::SeqCst)
+                    {
+                        let _ = ws.send(&Messages::ServerSignal(ServerSignalMessage::Unsubscribe(
+                            name,
+                        )));
+                    }
+                },
+                Duration::ZERO,
+            );
+        });
     }
 }
 
@@ -103,6 +546,45 @@ where
     }
 }
 
+impl<T> DefinedAt for ClientSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    fn defined_at(&self) -> Option<&'static Location<'static>> {
+        self.value.defined_at()
+    }
+}
+
+impl<T> Track for ClientSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    #[track_caller]
+    fn track(&self) {
+        if self.subscription.lazy && Observer::get().is_some() {
+            self.add_reader();
+        }
+        self.value.track()
+    }
+}
+
+/// The policy of the registry in context, see [`ClientSignals::initial_value`].
+fn default_initial_value() -> InitialValue {
+    use_context::<ClientSignals>()
+        .map_or_else(InitialValue::default, |signals| signals.initial_value())
+}
+
+impl<T> ReadUntracked for ClientSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    type Value = <ArcRwSignal<T> as ReadUntracked>::Value;
+
+    fn try_read_untracked(&self) -> Option<Self::Value> {
+        self.value.try_read_untracked()
+    }
+}
+
 impl<T> Deref for ClientSignal<T>
 where
     T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
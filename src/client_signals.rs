@@ -1,90 +1,438 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt::{Debug, Formatter},
+    sync::{Arc, OnceLock, RwLock},
 };
 
+use crate::auth::TokenProvider;
+use crate::client_input_signal::ClientInputSignalTrait;
 use crate::client_signal::ClientSignalTrait;
-use crate::messages::Messages;
-use crate::ServerSignalMessage;
+use crate::config::InitialValue;
+use crate::messages::{
+    ClientCapabilities, ClientId, InputRejection, Messages, ServerSignalMessage,
+    SignalAnnouncement, SignalVersion, FEATURE_TIMESTAMPS,
+};
 use crate::ServerSignalWebSocket;
-use crate::{error::Error, messages::ServerSignalUpdate};
+use crate::{
+    error::{downcast, Error, ErrorEvent, ErrorHooks, PatchFailure},
+    messages::ServerSignalUpdate,
+};
 use leptos::prelude::*;
 use serde_json::Value;
 
+/// The signals of a single connection.
 #[derive(Clone)]
 pub struct ClientSignals {
-    signals: Arc<RwLock<HashMap<String, Arc<Box<dyn ClientSignalTrait + Send + Sync>>>>>,
+    signals: Arc<RwLock<SignalMap>>,
+    inputs: Arc<RwLock<InputMap>>,
+    ws: Arc<OnceLock<ServerSignalWebSocket>>,
+    on_patch_failed: Arc<RwLock<Vec<PatchFailedCallback>>>,
+    sequences: Arc<RwLock<HashMap<String, Sequence>>>,
+    on_error: ErrorHooks,
+    announced: ArcRwSignal<Vec<SignalAnnouncement>>,
+    /// Signals whose type differs from the one on the server, their values are ignored.
+    mismatched: Arc<RwLock<HashSet<String>>>,
+    client_id: Arc<OnceLock<ClientId>>,
+    initial_value: Arc<OnceLock<InitialValue>>,
+    token_provider: Arc<OnceLock<TokenProvider>>,
+    auth_token: Arc<RwLock<Option<String>>>,
+}
+
+type SignalMap = HashMap<String, Arc<Box<dyn ClientSignalTrait + Send + Sync>>>;
+
+type InputMap = HashMap<String, Arc<Box<dyn ClientInputSignalTrait + Send + Sync>>>;
+
+type PatchFailedCallback = Arc<dyn Fn(&PatchFailure) + Send + Sync>;
+
+impl Debug for ClientSignals {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientSignals").finish_non_exhaustive()
+    }
 }
 
 impl ClientSignals {
     pub fn new() -> Self {
         let signals = Arc::new(RwLock::new(HashMap::new()));
-        let me = Self { signals };
+        let inputs = Arc::new(RwLock::new(HashMap::new()));
+        let me = Self {
+            signals,
+            inputs,
+            ws: Arc::default(),
+            on_patch_failed: Arc::default(),
+            sequences: Arc::default(),
+            on_error: ErrorHooks::default(),
+            announced: ArcRwSignal::new(Vec::new()),
+            mismatched: Arc::default(),
+            client_id: Arc::default(),
+            initial_value: Arc::default(),
+            token_provider: Arc::default(),
+            auth_token: Arc::default(),
+        };
         me
     }
 
-    pub fn create_signal<T: Clone + Send + Sync + 'static>(
-        &mut self,
-        name: String,
-        value: T,
-    ) -> Result<(), Error>
+    /// Records a signal announced by the server. The server announces all signals again after a
+    /// reconnect, so known ones are skipped.
+    pub(crate) fn announce(&self, announcement: &SignalAnnouncement) {
+        if !self
+            .announced
+            .read_untracked()
+            .iter()
+            .any(|existing| existing.name == announcement.name)
+        {
+            self.announced
+                .update(|announced| announced.push(announcement.clone()));
+        }
+    }
+
+    /// The signals announced by the server, in the order they were announced.
+    pub fn announced(&self) -> ArcReadSignal<Vec<SignalAnnouncement>> {
+        self.announced.read_only()
+    }
+
+    /// Binds the registry to its connection, which is created after the registry since it
+    /// dispatches received messages to it.
+    pub(crate) fn bind(&self, ws: ServerSignalWebSocket) {
+        let _ = self.ws.set(ws);
+    }
+
+    /// Sets the identity sent in the `Hello`, only the first call has an effect.
+    pub(crate) fn set_client_id(&self, id: ClientId) {
+        let _ = self.client_id.set(id);
+    }
+
+    /// The identity of this client, see [`ClientId`].
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id.get().cloned()
+    }
+
+    /// Sets where the auth token comes from, only the first call has an effect.
+    pub(crate) fn set_token_provider(&self, provider: TokenProvider) {
+        let _ = self.token_provider.set(provider);
+    }
+
+    /// Replaces the auth token, sent in every `Hello` and right away if the connection is open,
+    /// see [`auth`](crate::auth).
+    pub fn set_auth_token(&self, token: Option<String>) {
+        *self.auth_token.write().unwrap() = token.clone();
+        if let (Some(token), Ok(ws)) = (token, self.ws()) {
+            if ws.is_open() {
+                ws.send(&Messages::Authenticate(token)).ok();
+            }
+        }
+    }
+
+    /// Fetches the auth token again from the configured provider, and sends it if it changed.
+    pub(crate) async fn refresh_auth(&self) {
+        let Some(provider) = self.token_provider.get() else {
+            return;
+        };
+        let token = provider.token().await;
+        if token != *self.auth_token.read().unwrap() {
+            self.set_auth_token(token);
+        }
+    }
+
+    /// Sets the policy of signals created without one, only the first call has an effect.
+    pub(crate) fn set_initial_value(&self, initial_value: InitialValue) {
+        let _ = self.initial_value.set(initial_value);
+    }
+
+    /// The policy of signals created without one, see
+    /// [`WsConfig::initial_value`](crate::config::WsConfig::initial_value).
+    pub fn initial_value(&self) -> InitialValue {
+        self.initial_value.get().copied().unwrap_or_default()
+    }
+
+    /// The connection all signals of this registry are synced over.
+    pub(crate) fn ws(&self) -> Result<ServerSignalWebSocket, Error> {
+        self.ws.get().cloned().ok_or(Error::MissingServerSignals)
+    }
+
+    pub fn create_signal<T>(&mut self, name: String, value: T) -> Result<(), Error>
     where
-        T: ClientSignalTrait,
+        T: ClientSignalTrait + Clone + Send + Sync + 'static,
     {
-        let ws = use_context::<ServerSignalWebSocket>().ok_or(Error::MissingServerSignals)?;
-        if self
-            .signals
-            .write()
-            .unwrap()
-            .insert(name.clone(), Arc::new(Box::new(value)))
-            .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
-            .is_none()
+        let ws = self.ws()?;
+        // Lazy signals are established on their first read instead
+        let subscribed = value.is_subscribed();
+        let establish = value.establish_message(name.clone());
         {
-            // Wrap the Establish message in ServerSignalMessage and Messages
-            ws.send(&Messages::ServerSignal(ServerSignalMessage::Establish(
-                name.clone(),
-            )))?;
-            Ok(())
-        } else {
-            Err(Error::AddingSignalFailed)
+            let mut signals = self.signals.write().unwrap();
+            if let Some(existing) = signals.get(&name) {
+                downcast::<T>(&name, existing.as_any(), existing.type_name())?;
+                return Err(Error::AddingSignalFailed);
+            }
+            signals.insert(name, Arc::new(Box::new(value)));
         }
+        if subscribed {
+            ws.send(&establish)?;
+        }
+        Ok(())
+    }
+
+    /// Registers `value` as the signal `name` and returns it, or returns the signal already
+    /// registered under that name. Only a newly registered signal is established.
+    ///
+    /// Fails with [`Error::TypeMismatch`] if the registered signal isn't a `T`.
+    pub fn get_or_create_signal<T>(&self, name: String, value: T) -> Result<T, Error>
+    where
+        T: ClientSignalTrait + Clone + Send + Sync + 'static,
+    {
+        let ws = self.ws()?;
+        let establish = value.establish_message(name.clone());
+        match self.signals.write().unwrap().entry(name) {
+            Entry::Occupied(existing) => {
+                return downcast(
+                    existing.key(),
+                    existing.get().as_any(),
+                    existing.get().type_name(),
+                )
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(Arc::new(Box::new(value.clone())));
+            }
+        }
+        // Lazy signals are established on their first read instead
+        if value.is_subscribed() {
+            ws.send(&establish)?;
+        }
+        Ok(value)
+    }
+
+    /// Advertises the capabilities of this client, has to be the first message on a connection.
+    pub fn hello(&self) -> Result<(), Error> {
+        let ws = self.ws()?;
+        let mut capabilities = ClientCapabilities {
+            client_id: self.client_id(),
+            auth_token: self.auth_token.read().unwrap().clone(),
+            ..ClientCapabilities::current()
+        };
+        if ws.clock.is_some() {
+            capabilities.features.push(FEATURE_TIMESTAMPS.to_string());
+        }
+        ws.send(&Messages::Hello(capabilities))?;
+        Ok(())
     }
 
     pub fn reconnect(&self) -> Result<(), Error> {
-        let ws = use_context::<ServerSignalWebSocket>().ok_or(Error::MissingServerSignals)?;
+        let ws = self.ws()?;
 
-        // Get all signal names from the signals HashMap
-        let signal_names: Vec<String> = self.signals.read().unwrap().keys().cloned().collect();
+        // Build the establish messages of all signals the server should send updates for, resuming
+        // from the last version where it is known
+        let mut sequences = self.sequences.write().unwrap();
+        let messages: Vec<Messages> = self
+            .signals
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, signal)| signal.is_subscribed())
+            .map(
+                |(name, signal)| match sequences.get_mut(name).and_then(Sequence::resume) {
+                    Some((epoch, seq)) => {
+                        let version = SignalVersion {
+                            name: name.clone(),
+                            epoch,
+                            seq,
+                        };
+                        let payload = signal.establish_payload().unwrap_or_default();
+                        Messages::ServerSignal(ServerSignalMessage::Resume((version, payload)))
+                    }
+                    None => signal.establish_message(name.clone()),
+                },
+            )
+            .collect();
+        drop(sequences);
 
         // Resend establish message for each signal
-        for name in signal_names {
-            ws.send(&Messages::ServerSignal(ServerSignalMessage::Establish(
-                name,
-            )))?;
+        for message in messages {
+            ws.send(&message)?;
         }
 
+        self.establish_inputs()
+    }
+
+    /// Sends the complete current value of every client input signal.
+    pub fn establish_inputs(&self) -> Result<(), Error> {
+        let ws = self.ws()?;
+        let inputs: Vec<_> = self.inputs.read().unwrap().values().cloned().collect();
+        for input in inputs {
+            ws.send(&input.establish()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn create_input<T>(&mut self, name: String, value: T) -> Result<(), Error>
+    where
+        T: ClientInputSignalTrait + Clone + Send + Sync + 'static,
+    {
+        let ws = self.ws()?;
+        let establish = value.establish()?;
+        {
+            let mut inputs = self.inputs.write().unwrap();
+            if let Some(existing) = inputs.get(&name) {
+                downcast::<T>(&name, existing.as_any(), existing.type_name())?;
+                return Err(Error::AddingSignalFailed);
+            }
+            inputs.insert(name, Arc::new(Box::new(value)));
+        }
+        // Otherwise the input is established as soon as the connection opens
+        if ws.is_open() {
+            ws.send(&establish)?;
+        }
         Ok(())
     }
 
-    pub fn get_signal<T: Clone + 'static>(&mut self, name: &str) -> Option<T> {
+    /// Like [`ClientSignals::get_or_create_signal`], for client input signals.
+    pub fn get_or_create_input<T>(&self, name: String, value: T) -> Result<T, Error>
+    where
+        T: ClientInputSignalTrait + Clone + Send + Sync + 'static,
+    {
+        let ws = self.ws()?;
+        let establish = value.establish()?;
+        match self.inputs.write().unwrap().entry(name) {
+            Entry::Occupied(existing) => {
+                return downcast(
+                    existing.key(),
+                    existing.get().as_any(),
+                    existing.get().type_name(),
+                )
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(Arc::new(Box::new(value.clone())));
+            }
+        }
+        // Otherwise the input is established as soon as the connection opens
+        if ws.is_open() {
+            ws.send(&establish)?;
+        }
+        Ok(value)
+    }
+
+    /// Returns the client input signal `name`, or [`Error::TypeMismatch`] if it isn't a `T`.
+    pub fn get_input<T: Clone + 'static>(&self, name: &str) -> Option<Result<T, Error>> {
+        self.inputs
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|value| downcast(name, value.as_any(), value.type_name()))
+    }
+
+    pub fn acknowledge_input(&self, name: &str, seq: u64) {
+        if let Some(input) = self.inputs.read().unwrap().get(name) {
+            input.acknowledge(seq);
+        }
+    }
+
+    pub fn reject_input(&self, rejection: &InputRejection) -> Result<(), Error> {
+        let ws = self.ws()?;
+        let input = self.inputs.read().unwrap().get(&rejection.name).cloned();
+        if let Some(input) = input {
+            ws.send(&input.reject(rejection)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn contains_input(&self, name: &str) -> bool {
+        self.inputs.read().unwrap().contains_key(name)
+    }
+
+    /// Returns the signal `name`, or [`Error::TypeMismatch`] if it isn't a `T`.
+    pub fn get_signal<T: Clone + 'static>(&mut self, name: &str) -> Option<Result<T, Error>> {
         self.signals
-            .write()
+            .read()
             .unwrap()
-            .get_mut(name)
-            .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
+            .get(name)
+            .map(|value| downcast(name, value.as_any(), value.type_name()))
     }
 
+    /// Removes a signal, which is neither established on reconnect nor updated anymore.
+    pub(crate) fn remove_signal(&self, name: &str) {
+        self.signals.write().unwrap().remove(name);
+        self.sequences.write().unwrap().remove(name);
+        self.mismatched.write().unwrap().remove(name);
+    }
+
+    /// Applies a patch from the server. If it doesn't apply, the `on_patch_failed` callbacks are
+    /// called and the signal is established again to fetch the complete value.
+    ///
+    /// Patches are applied in the order of their sequence numbers, early ones are held back until
+    /// the missing ones arrive. If too many are missing, the signal is established again as well.
     pub fn update(&self, name: &str, patch: ServerSignalUpdate) -> Option<Result<(), Error>> {
-        match self
-            .signals
+        let signal = self.signals.read().unwrap().get(name).cloned()?;
+        if self.is_mismatched(name) {
+            return Some(Ok(()));
+        }
+        let ready = self
+            .sequences
             .write()
             .unwrap()
-            .get_mut(name)
-            .map(|value| value.update_json(patch))
-        {
-            Some(fut) => Some(fut),
-            None => None,
+            .entry(name.to_string())
+            .or_default()
+            .push(patch);
+        let Some(ready) = ready else {
+            leptos::logging::warn!("Missed updates of {name}, fetching the complete value");
+            self.resync(name, signal.as_ref().as_ref());
+            return Some(Ok(()));
+        };
+        let clock = self.ws().ok().and_then(|ws| ws.clock);
+        for patch in ready {
+            if let Some(latency) = clock
+                .as_ref()
+                .zip(patch.sent_at())
+                .and_then(|(clock, sent_at)| clock.measure(sent_at))
+            {
+                signal.measured(latency);
+            }
+            let result = signal.update_json(patch);
+            if let Err(Error::PatchFailed(failure)) = &result {
+                let callbacks = self.on_patch_failed.read().unwrap().clone();
+                for callback in callbacks {
+                    callback(failure);
+                }
+                self.resync(name, signal.as_ref().as_ref());
+            }
+            if result.is_err() {
+                return Some(result);
+            }
         }
+        Some(Ok(()))
+    }
+
+    /// Establishes the signal again, the server answers with the complete value.
+    fn resync(&self, name: &str, signal: &(dyn ClientSignalTrait + Send + Sync)) {
+        self.sequences.write().unwrap().remove(name);
+        if signal.is_subscribed() {
+            if let Err(err) = self
+                .ws()
+                .and_then(|ws| Ok(ws.send(&signal.establish_message(name.to_string()))?))
+            {
+                self.report(Some(name), err);
+            }
+        }
+    }
+
+    /// Registers a callback which is called when a patch from the server could not be applied.
+    pub fn on_patch_failed(&self, callback: impl Fn(&PatchFailure) + Send + Sync + 'static) {
+        self.on_patch_failed
+            .write()
+            .unwrap()
+            .push(Arc::new(callback));
+    }
+
+    /// Registers a callback which is called for every error handling the messages of the
+    /// server, e.g. values which don't deserialize or patches which don't apply, and for messages
+    /// which could not be sent. Errors are still logged as well.
+    pub fn on_error(&self, callback: impl Fn(&ErrorEvent) + Send + Sync + 'static) {
+        self.on_error.add(callback);
+    }
+
+    pub(crate) fn error_hooks(&self) -> ErrorHooks {
+        self.on_error.clone()
+    }
+
+    pub(crate) fn report(&self, signal: Option<&str>, error: Error) {
+        self.on_error.report(signal, error);
     }
 
     pub fn json(&self, name: &str) -> Option<Result<Value, Error>> {
@@ -100,6 +448,11 @@ impl ClientSignals {
         }
     }
     pub fn set_json(&self, name: &str, new_value: Value) -> Option<Result<(), Error>> {
+        if self.is_mismatched(name) {
+            return Some(Ok(()));
+        }
+        // The numbering of the updates after a complete value starts over
+        self.sequences.write().unwrap().remove(name);
         match self
             .signals
             .write()
@@ -112,7 +465,191 @@ impl ClientSignals {
         }
     }
 
+    /// Records the version of the value the server sent last, see
+    /// [`ServerSignalMessage::Version`].
+    pub fn set_version(&self, version: &SignalVersion) {
+        self.sequences.write().unwrap().insert(
+            version.name.clone(),
+            Sequence {
+                epoch: Some(version.epoch),
+                last: Some(version.seq),
+                pending: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Compares the value type the server declared `name` with. If it differs, the values of the
+    /// server are ignored from now on and the server is told the type of this side.
+    pub fn check_schema(&self, name: &str, remote: &str) -> Result<(), Error> {
+        let Some(local) = self
+            .signals
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|signal| signal.schema())
+        else {
+            return Ok(());
+        };
+        if local == remote {
+            return Ok(());
+        }
+        self.mismatched.write().unwrap().insert(name.to_string());
+        self.ws()?
+            .send(&Messages::ServerSignal(ServerSignalMessage::Schema((
+                name.to_string(),
+                local.to_string(),
+            ))))?;
+        Err(Error::SchemaMismatch {
+            name: name.to_string(),
+            local: local.to_string(),
+            remote: remote.to_string(),
+        })
+    }
+
+    fn is_mismatched(&self, name: &str) -> bool {
+        self.mismatched.read().unwrap().contains(name)
+    }
+
+    pub fn pause(&self, name: &str) {
+        if let Some(signal) = self.signals.read().unwrap().get(name) {
+            signal.pause();
+        }
+    }
+
+    pub fn deny(&self, name: &str) {
+        if let Some(signal) = self.signals.read().unwrap().get(name) {
+            signal.deny();
+        }
+    }
+
     pub fn contains(&self, name: &str) -> bool {
         self.signals.read().unwrap().contains_key(name)
     }
 }
+
+/// Updates of a signal which arrived ahead of their turn.
+#[derive(Default)]
+struct Sequence {
+    /// The numbering of the updates, if the server sent the version of the complete value.
+    epoch: Option<u64>,
+    /// The sequence number of the last applied update, `None` until the first one after the
+    /// complete value arrived.
+    last: Option<u64>,
+    pending: BTreeMap<u64, ServerSignalUpdate>,
+}
+
+/// Held back updates before the missing ones are given up on.
+const MAX_PENDING: usize = 16;
+
+impl Sequence {
+    /// The version to resume from after a reconnect. Held back updates are dropped, the server
+    /// sends them again.
+    fn resume(&mut self) -> Option<(u64, u64)> {
+        self.pending.clear();
+        Some((self.epoch?, self.last?))
+    }
+
+    /// Returns the updates which can be applied now, in order, or `None` if too many are missing.
+    fn push(&mut self, update: ServerSignalUpdate) -> Option<Vec<ServerSignalUpdate>> {
+        let Some(seq) = update.seq() else {
+            return Some(vec![update]);
+        };
+        let Some(last) = self.last else {
+            self.last = Some(seq);
+            return Some(vec![update]);
+        };
+        if seq <= last {
+            // Already applied
+            return Some(Vec::new());
+        }
+        self.pending.insert(seq, update);
+        let mut ready = Vec::new();
+        while let Some(update) = self.pending.remove(&(self.last.unwrap() + 1)) {
+            self.last = update.seq();
+            ready.push(update);
+        }
+        if self.pending.len() > MAX_PENDING {
+            return None;
+        }
+        Some(ready)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(seq: u64) -> ServerSignalUpdate {
+        ServerSignalUpdate {
+            seq: Some(seq),
+            ..ServerSignalUpdate::replace("count", Value::from(seq))
+        }
+    }
+
+    fn seqs(updates: Option<Vec<ServerSignalUpdate>>) -> Option<Vec<u64>> {
+        updates.map(|updates| updates.iter().filter_map(|update| update.seq()).collect())
+    }
+
+    fn after(last: u64) -> Sequence {
+        Sequence {
+            epoch: Some(1),
+            last: Some(last),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn applies_updates_in_order() {
+        let mut sequence = Sequence::default();
+        assert_eq!(seqs(sequence.push(update(4))), Some(vec![4]));
+        assert_eq!(seqs(sequence.push(update(5))), Some(vec![5]));
+        assert_eq!(seqs(sequence.push(update(6))), Some(vec![6]));
+    }
+
+    #[test]
+    fn holds_back_updates_until_the_missing_ones_arrive() {
+        let mut sequence = after(1);
+        assert_eq!(seqs(sequence.push(update(3))), Some(vec![]));
+        assert_eq!(seqs(sequence.push(update(4))), Some(vec![]));
+        assert_eq!(seqs(sequence.push(update(2))), Some(vec![2, 3, 4]));
+        assert!(sequence.pending.is_empty());
+    }
+
+    #[test]
+    fn skips_updates_applied_before() {
+        let mut sequence = after(2);
+        assert_eq!(seqs(sequence.push(update(2))), Some(vec![]));
+        assert_eq!(seqs(sequence.push(update(1))), Some(vec![]));
+        assert_eq!(seqs(sequence.push(update(3))), Some(vec![3]));
+        assert_eq!(seqs(sequence.push(update(3))), Some(vec![]));
+    }
+
+    #[test]
+    fn gives_up_when_too_many_are_held_back() {
+        let mut sequence = after(0);
+        for seq in 2..MAX_PENDING as u64 + 2 {
+            assert_eq!(seqs(sequence.push(update(seq))), Some(vec![]));
+        }
+        assert_eq!(sequence.push(update(MAX_PENDING as u64 + 2)), None);
+    }
+
+    #[test]
+    fn passes_unnumbered_updates_through() {
+        let mut sequence = after(1);
+        let unnumbered = ServerSignalUpdate::replace("count", Value::from(0));
+        assert_eq!(
+            sequence.push(unnumbered).map(|updates| updates.len()),
+            Some(1)
+        );
+        assert_eq!(sequence.last, Some(1));
+    }
+
+    #[test]
+    fn resumes_from_the_last_applied_update() {
+        let mut sequence = after(1);
+        sequence.push(update(3));
+        assert_eq!(sequence.resume(), Some((1, 1)));
+        assert!(sequence.pending.is_empty());
+        assert_eq!(Sequence::default().resume(), None);
+    }
+}
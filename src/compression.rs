@@ -0,0 +1,41 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::error::Error;
+use crate::messages::Messages;
+
+/// Serializes `message`, wrapping it in [`Messages::Compressed`] if the JSON is at least
+/// `threshold` bytes long.
+#[cfg(feature = "server")]
+pub fn encode(message: &Messages, threshold: usize) -> Result<String, Error> {
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    let json = serde_json::to_string(message)?;
+    if json.len() < threshold {
+        return Ok(json);
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(json.as_bytes())
+        .map_err(|_| Error::CompressionFailed)?;
+    let compressed = encoder.finish().map_err(|_| Error::CompressionFailed)?;
+    Ok(serde_json::to_string(&Messages::Compressed(
+        STANDARD.encode(compressed),
+    ))?)
+}
+
+/// Unpacks the payload of a [`Messages::Compressed`].
+#[cfg(any(not(feature = "server"), feature = "testing"))]
+pub fn decode(data: &str) -> Result<Messages, Error> {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    let compressed = STANDARD
+        .decode(data)
+        .map_err(|_| Error::CompressionFailed)?;
+    let mut json = String::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_string(&mut json)
+        .map_err(|_| Error::CompressionFailed)?;
+    Ok(serde_json::from_str(&json)?)
+}
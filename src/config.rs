@@ -0,0 +1,520 @@
+use std::time::Duration;
+
+use crate::auth::TokenProvider;
+#[cfg(feature = "server")]
+use crate::auth::Verifier;
+use crate::cipher::{Cipher, SharedCipher};
+use crate::messages::ClientId;
+
+/// Controls how often a connection is probed and when it is considered dead.
+///
+/// The same configuration type is used on both sides of the connection:
+///
+/// - On the server, a WebSocket `Ping` frame is sent every `interval`. If no frame
+///   (including the browser's automatic `Pong`) arrives within `timeout`, the connection
+///   is closed and all of its observers are dropped.
+/// - On the client, a [`HeartbeatMessage::Ping`](crate::messages::HeartbeatMessage::Ping)
+///   is sent every `interval`. If no message arrives from the server within `timeout`, the
+///   socket is closed and reopened, which re-establishes all signals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl HeartbeatConfig {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self { interval, timeout }
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+        }
+    }
+}
+
+/// Sends bursts of small messages in one [`Messages::Batch`](crate::messages::Messages::Batch)
+/// frame, which saves the overhead of a frame per message for chatty apps.
+///
+/// A message waits at most `delay` for further ones before the batch is sent. Batches hold at
+/// most `max_messages` messages of together `max_bytes`, a larger message is sent on its own.
+/// Used on both sides, see [`WsConfig::batch`] and `ConnectionConfig::batch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchConfig {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+    pub delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 32,
+            max_bytes: 64 * 1024,
+            delay: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Client-side options for [`provide_websocket_with_config`](crate::provide_websocket_with_config).
+#[derive(Clone, Debug)]
+pub struct WsConfig {
+    pub heartbeat: HeartbeatConfig,
+    /// Encrypts all messages, see [`cipher`](crate::cipher).
+    pub cipher: Option<SharedCipher>,
+    /// Maximum number of messages queued while the connection isn't open. Beyond that the oldest
+    /// one is dropped, see [`WebSocketHandle::on_message_dropped`](crate::WebSocketHandle).
+    pub queue_capacity: usize,
+    /// The identity sent to the server, generated and kept in `localStorage` if `None`.
+    pub client_id: Option<ClientId>,
+    /// What signals created without an explicit policy start with, has to match the policy of
+    /// the server registry.
+    pub initial_value: InitialValue,
+    /// Appended to the url passed to `provide_websocket`, e.g. `/api/realtime`. Also where
+    /// [`axum::router`](crate::axum) mounts the socket on the server.
+    pub path: String,
+    /// Appended to the url as query string, e.g. an auth token.
+    pub query_params: Vec<(String, String)>,
+    /// The WebSocket subprotocols requested from the server.
+    pub protocols: Vec<String>,
+    /// Fetches the token sent to the server, see [`auth`](crate::auth).
+    pub auth_token: Option<TokenProvider>,
+    /// How often the token is fetched again and sent to the server.
+    pub auth_refresh: Option<Duration>,
+    /// Batches messages to the server, `None` sends every message on its own.
+    pub batch: Option<BatchConfig>,
+    /// Measures the latency of updates, see [`latency`](crate::latency).
+    pub measure_latency: bool,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: HeartbeatConfig::default(),
+            cipher: None,
+            queue_capacity: 256,
+            client_id: None,
+            initial_value: InitialValue::default(),
+            path: String::new(),
+            query_params: Vec::new(),
+            protocols: Vec::new(),
+            auth_token: None,
+            auth_refresh: None,
+            batch: None,
+            measure_latency: false,
+        }
+    }
+}
+
+impl WsConfig {
+    pub fn heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Encrypts all messages with `cipher`, the server has to use the same one.
+    pub fn cipher(mut self, cipher: impl Cipher + 'static) -> Self {
+        self.cipher = Some(SharedCipher::new(cipher));
+        self
+    }
+
+    /// Identifies the client with `id` instead of a generated one, e.g. the id of the device or
+    /// of the logged in user.
+    pub fn client_id(mut self, id: impl Into<String>) -> Self {
+        self.client_id = Some(ClientId(id.into()));
+        self
+    }
+
+    /// The policy of signals created without one, see
+    /// [`ServerSignals::with_initial_value`](crate::server_signals::ServerSignals) on the server.
+    pub fn initial_value(mut self, initial_value: InitialValue) -> Self {
+        self.initial_value = initial_value;
+        self
+    }
+
+    /// Hosts the socket under `path`, see [`WsConfig::url`].
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::config::WsConfig;
+    /// # let token = String::new();
+    /// let config = WsConfig::default().path("/api/realtime").query_param("token", &token);
+    /// leptos_ws::provide_websocket_with_config("", config);
+    /// ```
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Adds a query parameter to the url, it is percent-encoded.
+    pub fn query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query_params.push((key.into(), value.into()));
+        self
+    }
+
+    /// Requests the WebSocket subprotocol `protocol`, the server has to accept it, see
+    /// [`ConnectionConfig::protocols`].
+    pub fn protocol(mut self, protocol: impl Into<String>) -> Self {
+        self.protocols.push(protocol.into());
+        self
+    }
+
+    /// Authenticates the connection with the token returned by `provider`, e.g. from a cookie
+    /// or fetched from the server. The connection opens once it resolved, see
+    /// [`auth`](crate::auth).
+    pub fn auth_token<F, Fut>(mut self, provider: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Option<String>> + 'static,
+    {
+        self.auth_token = Some(TokenProvider::new(provider));
+        self
+    }
+
+    /// Fetches the token again every `interval` and sends it to the server, e.g. before it
+    /// expires. [`WebSocketHandle::refresh_auth`](crate::WebSocketHandle::refresh_auth) does
+    /// the same on demand.
+    pub fn auth_refresh(mut self, interval: Duration) -> Self {
+        self.auth_refresh = Some(interval);
+        self
+    }
+
+    /// Sends bursts of messages to the server in batches, see [`BatchConfig`]. The server has
+    /// to be built with this version of leptos_ws or later.
+    pub fn batch(mut self, batch: BatchConfig) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    /// Asks the server to stamp every update with its send time, so the client can measure how
+    /// long updates take, see [`latency`](crate::latency). The server has to be built with this
+    /// version of leptos_ws or later.
+    pub fn measure_latency(mut self, measure: bool) -> Self {
+        self.measure_latency = measure;
+        self
+    }
+
+    /// The url connected to: `base` followed by the path and the query parameters.
+    ///
+    /// `base` may be empty or relative, e.g. `""` or `/`, then the socket is opened on the host
+    /// of the page.
+    pub fn url(&self, base: &str) -> String {
+        let mut url = base.to_string();
+        if !self.path.is_empty() {
+            let path = self.path.trim_start_matches('/');
+            if !url.ends_with('/') {
+                url.push('/');
+            }
+            url.push_str(path);
+        }
+        for (index, (key, value)) in self.query_params.iter().enumerate() {
+            url.push(match (index, url.contains('?')) {
+                (0, false) => '?',
+                _ => '&',
+            });
+            url.push_str(&percent_encode(key));
+            url.push('=');
+            url.push_str(&percent_encode(value));
+        }
+        url
+    }
+}
+
+/// Encodes everything but the unreserved characters of RFC 3986.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Which value a signal holds before the server sent its own, see
+/// `ServerSignal::new_with_initial`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InitialValue {
+    /// The value passed to the constructor.
+    UseClientDefault,
+    /// Like `UseClientDefault`, but `ServerSignal::ready` stays pending until the value of the
+    /// server arrived, so a `Suspense` shows its fallback instead of the default. Meant for CSR,
+    /// hydrated pages start correct with `UseSsrSerializedValue`.
+    AwaitServer,
+    /// The value the page was rendered with, serialized into the SSR payload so hydration starts
+    /// with it and the `Establish` round-trip only confirms it. Same as `UseClientDefault`
+    /// without SSR, e.g. in CSR builds.
+    #[default]
+    UseSsrSerializedValue,
+}
+
+/// Server-side options for a single WebSocket connection.
+#[cfg(feature = "server")]
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionConfig {
+    pub heartbeat: HeartbeatConfig,
+    pub rate_limit: RateLimitConfig,
+    pub outbound: OutboundConfig,
+    /// Messages to the client of at least this many bytes are compressed, `None` disables
+    /// compression.
+    #[cfg(feature = "compression")]
+    pub compression_threshold: Option<usize>,
+    pub affinity: Option<AffinityConfig>,
+    /// Extracts the identity of a client from the upgrade request.
+    #[cfg(feature = "axum")]
+    pub identify: Option<crate::axum::Identify>,
+    /// Extracts the tenant of a client from the upgrade request.
+    #[cfg(feature = "axum")]
+    pub tenant: Option<crate::axum::TenantKey>,
+    /// Encrypts all messages, see [`cipher`](crate::cipher).
+    pub cipher: Option<SharedCipher>,
+    /// Clients which don't send a `Hello` receive complete values instead of patches, see
+    /// [`protocol`](crate::protocol).
+    pub interop: bool,
+    /// The WebSocket subprotocols accepted, the first one requested by the client is selected.
+    pub protocols: Vec<String>,
+    /// Checks the auth token of every client, see [`auth`](crate::auth).
+    pub authenticate: Option<Verifier>,
+    /// Batches messages to clients which support it, `None` sends every message on its own.
+    pub batch: Option<BatchConfig>,
+}
+
+#[cfg(feature = "server")]
+impl ConnectionConfig {
+    pub fn heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// Compresses every message to the client of at least `threshold` bytes. The client has to
+    /// be built with the `compression` feature as well.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+
+    pub fn affinity(mut self, affinity: AffinityConfig) -> Self {
+        self.affinity = Some(affinity);
+        self
+    }
+
+    /// Encrypts all messages with `cipher` and drops unencrypted ones, the clients have to use
+    /// the same cipher.
+    pub fn cipher(mut self, cipher: impl Cipher + 'static) -> Self {
+        self.cipher = Some(SharedCipher::new(cipher));
+        self
+    }
+
+    /// Accepts clients which only speak the [`protocol`](crate::protocol) subset, e.g. plain
+    /// browser pages. Clients which don't send a `Hello` are then sent every update as the
+    /// complete value instead of a patch, so older Leptos clients have to send one.
+    pub fn interop(mut self, interop: bool) -> Self {
+        self.interop = interop;
+        self
+    }
+
+    /// Accepts the WebSocket subprotocols requested with [`WsConfig::protocol`]. Browsers close
+    /// connections for which the server doesn't select one of the requested protocols.
+    pub fn protocols(mut self, protocols: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Requires every client to authenticate with a token accepted by `verify`, which returns
+    /// the reason otherwise. Clients are checked on their `Hello` and every refresh of the token,
+    /// see [`auth`](crate::auth). Any message but a `Hello` or heartbeat from a client which didn't
+    /// authenticate yet closes the connection, and its latency reports are ignored.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::config::ConnectionConfig;
+    /// # fn decode_jwt(_token: &str) -> Result<(), std::fmt::Error> { Ok(()) }
+    /// let config = ConnectionConfig::default().authenticate(|token| {
+    ///     decode_jwt(token).map(|_| ()).map_err(|err| err.to_string())
+    /// });
+    /// ```
+    pub fn authenticate(
+        mut self,
+        verify: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.authenticate = Some(Verifier::new(verify));
+        self
+    }
+
+    /// Sends bursts of messages to the client in batches, see [`BatchConfig`]. Clients which
+    /// don't advertise [`FEATURE_BATCH`](crate::messages::FEATURE_BATCH) receive every message
+    /// on its own.
+    pub fn batch(mut self, batch: BatchConfig) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    /// Identifies the client of a connection from the request headers, e.g. by a session
+    /// cookie, to track and limit message rates per identity instead of per connection.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::config::ConnectionConfig;
+    /// let config = ConnectionConfig::default().identify(|headers| {
+    ///     headers.get("x-user-id")?.to_str().ok().map(str::to_string)
+    /// });
+    /// ```
+    #[cfg(feature = "axum")]
+    pub fn identify(
+        mut self,
+        identify: impl Fn(&axum::http::HeaderMap) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.identify = Some(crate::axum::Identify(std::sync::Arc::new(identify)));
+        self
+    }
+
+    /// Serves each connection from the registry of its tenant, see
+    /// [`ServerSignals::tenant`](crate::server_signals::ServerSignals::tenant). Connections
+    /// without a tenant are served from the registry passed to the handler.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::config::ConnectionConfig;
+    /// let config = ConnectionConfig::default().tenant(|headers| {
+    ///     headers.get("host")?.to_str().ok()?.split('.').next().map(str::to_string)
+    /// });
+    /// ```
+    #[cfg(feature = "axum")]
+    pub fn tenant(
+        mut self,
+        tenant: impl Fn(&axum::http::HeaderMap) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.tenant = Some(crate::axum::TenantKey(std::sync::Arc::new(tenant)));
+        self
+    }
+}
+
+/// Pins the connections of a client to one server node through a cookie, for load balancers
+/// with cookie based sticky sessions.
+///
+/// The cookie holds the `node` the client was connected to. If a reconnect still ends up on a
+/// different node, it is logged and available as
+/// [`Connection::previous_node`](crate::connection::Connection::previous_node). The client
+/// re-establishes all signals after every reconnect, so it resyncs with the values of the new
+/// node either way.
+#[cfg(feature = "server")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AffinityConfig {
+    pub cookie: String,
+    /// Identifies this server node, e.g. the hostname. Has to be a valid cookie value.
+    pub node: String,
+}
+
+#[cfg(feature = "server")]
+impl AffinityConfig {
+    /// Uses the cookie `leptos_ws_node`.
+    pub fn new(node: impl Into<String>) -> Self {
+        Self {
+            cookie: "leptos_ws_node".to_string(),
+            node: node.into(),
+        }
+    }
+
+    pub fn cookie(mut self, cookie: impl Into<String>) -> Self {
+        self.cookie = cookie.into();
+        self
+    }
+}
+
+/// Limits the size of the patches a signal broadcasts, to catch writes which replace far more of
+/// the value than intended, e.g. a whole document every second.
+#[cfg(feature = "server")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PatchBudget {
+    /// Patches of at least this many bytes are logged with the JSON paths they touch.
+    pub warn_bytes: Option<usize>,
+    /// Patches of at least this many bytes are logged and not broadcast, and the write is
+    /// reverted unless newer writes are already queued.
+    pub max_bytes: Option<usize>,
+}
+
+/// Limits what a single client may send, enforced in the server receive loop.
+///
+/// A message exceeding any limit is dropped and answered with
+/// [`Messages::RateLimited`](crate::messages::Messages::RateLimited). After `max_violations`
+/// dropped messages the connection is closed.
+#[cfg(feature = "server")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub messages_per_second: u32,
+    /// Maximum size of a single frame in bytes.
+    pub max_message_bytes: usize,
+    /// Maximum number of operations in a single JSON patch, or of values a merge patch changes.
+    pub max_patch_operations: usize,
+    pub max_violations: u32,
+    /// Limit for all connections of one identity together, e.g. a user with many tabs. Requires
+    /// `ConnectionConfig::identify`.
+    pub messages_per_second_per_identity: Option<u32>,
+}
+
+#[cfg(feature = "server")]
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 100,
+            max_message_bytes: 1024 * 1024,
+            max_patch_operations: 1000,
+            max_violations: 10,
+            messages_per_second_per_identity: None,
+        }
+    }
+}
+
+/// Limits the messages queued for a client which reads slower than they are sent, e.g. a
+/// stuck browser tab.
+#[cfg(feature = "server")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutboundConfig {
+    /// Maximum number of messages waiting to be written to the client.
+    pub capacity: usize,
+    /// What happens to a message which doesn't fit into the queue.
+    pub overflow: OverflowPolicy,
+}
+
+#[cfg(feature = "server")]
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            overflow: OverflowPolicy::KeepLatest,
+        }
+    }
+}
+
+/// See [`OutboundConfig::overflow`].
+#[cfg(feature = "server")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the oldest queued signal update, and the later ones of the same signal. The client
+    /// receives the complete value of that signal in their place, read when it is written.
+    DropOldest,
+    /// Replaces the queued updates of the signal with its complete value, read when it is
+    /// written to the client. Falls back to `DropOldest` if the queue is full of other signals.
+    KeepLatest,
+    /// Closes the connection, the client reconnects and establishes all signals again.
+    Disconnect,
+}
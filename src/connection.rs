@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use tokio::sync::watch;
+
+use crate::cipher::SharedCipher;
+use crate::config::{BatchConfig, ConnectionConfig, RateLimitConfig};
+use crate::messages::{
+    ClientCapabilities, ClientId, RateLimitReason, ServerSignalUpdate, FEATURE_ANNOUNCE,
+    FEATURE_BATCH, FEATURE_DENIED, FEATURE_MERGE_PATCH, FEATURE_PATCHES, FEATURE_PAUSE,
+    FEATURE_RESUME, FEATURE_SCHEMA, FEATURE_TIMESTAMPS,
+};
+
+/// Identifies a single WebSocket connection on the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A read-only view of a live connection, as registered in [`ServerSignals`](crate::server_signals::ServerSignals).
+#[derive(Clone, Debug)]
+pub struct Connection {
+    id: ConnectionId,
+    last_seen: watch::Receiver<Instant>,
+    capabilities: watch::Receiver<ClientCapabilities>,
+    previous_node: Option<String>,
+    identity: Option<String>,
+}
+
+impl Connection {
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// The last time any frame was received from this connection.
+    pub fn last_seen(&self) -> Instant {
+        *self.last_seen.borrow()
+    }
+
+    /// Returns a receiver which is notified every time a frame is received from this connection.
+    pub fn watch_last_seen(&self) -> watch::Receiver<Instant> {
+        self.last_seen.clone()
+    }
+
+    /// What the client advertised in its `Hello`, the defaults if it didn't send one (yet).
+    pub fn capabilities(&self) -> ClientCapabilities {
+        self.capabilities.borrow().clone()
+    }
+
+    /// The durable identity of the client from its `Hello`, which unlike [`Connection::id`]
+    /// stays the same when it reconnects. `None` for clients which don't send one.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.capabilities.borrow().client_id.clone()
+    }
+
+    /// The node the client was connected to before, according to its affinity cookie, see
+    /// [`AffinityConfig`](crate::config::AffinityConfig).
+    pub fn previous_node(&self) -> Option<&str> {
+        self.previous_node.as_deref()
+    }
+
+    /// Who opened the connection, as extracted by
+    /// [`ConnectionConfig::identify`](crate::config::ConnectionConfig).
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+}
+
+/// The connections an update is relayed to, see
+/// [`ServerSignal::update_where`](crate::ServerSignal).
+#[derive(Clone)]
+pub(crate) struct Audience(Arc<dyn Fn(&Connection) -> bool + Send + Sync>);
+
+impl Audience {
+    pub fn new(includes: impl Fn(&Connection) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(includes))
+    }
+
+    pub fn includes(&self, connection: &Connection) -> bool {
+        (self.0)(connection)
+    }
+}
+
+impl std::fmt::Debug for Audience {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Audience")
+    }
+}
+
+impl PartialEq for Audience {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Audience {}
+
+/// The writing half of a [`Connection`], owned by the socket loop.
+pub(crate) struct ConnectionHandle {
+    id: ConnectionId,
+    last_seen: watch::Sender<Instant>,
+    capabilities: watch::Sender<ClientCapabilities>,
+    identity: Option<String>,
+    authenticated: AtomicBool,
+}
+
+impl ConnectionHandle {
+    pub fn new(previous_node: Option<String>, identity: Option<String>) -> (Self, Connection) {
+        let id = ConnectionId::next();
+        let (send, recv) = watch::channel(Instant::now());
+        let (capabilities, capabilities_recv) = watch::channel(ClientCapabilities::default());
+        (
+            Self {
+                id,
+                last_seen: send,
+                capabilities,
+                identity: identity.clone(),
+                authenticated: AtomicBool::new(false),
+            },
+            Connection {
+                id,
+                last_seen: recv,
+                capabilities: capabilities_recv,
+                previous_node,
+                identity,
+            },
+        )
+    }
+
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    pub fn touch(&self) {
+        self.last_seen.send_replace(Instant::now());
+    }
+
+    pub fn last_seen(&self) -> Instant {
+        *self.last_seen.borrow()
+    }
+
+    pub fn capabilities(&self) -> ClientCapabilities {
+        self.capabilities.borrow().clone()
+    }
+
+    pub fn set_capabilities(&self, capabilities: ClientCapabilities) {
+        self.capabilities.send_replace(capabilities);
+    }
+
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// Whether the client sent an accepted auth token, see [`auth`](crate::auth).
+    pub fn authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_authenticated(&self, authenticated: bool) {
+        self.authenticated.store(authenticated, Ordering::Relaxed);
+    }
+}
+
+/// The optional wire features used for a connection, negotiated from the server's
+/// [`ConnectionConfig`] and the [`ClientCapabilities`] of the client.
+///
+/// Lets clients of different versions stay connected to the same server during a deploy: each of
+/// them only receives what it can handle.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionFeatures {
+    /// Messages larger than this are compressed.
+    #[cfg(feature = "compression")]
+    pub compression_threshold: Option<usize>,
+    /// Updates are sent as patches, otherwise as the complete new value.
+    pub patches: bool,
+    /// The client is told when a signal is paused.
+    pub pause: bool,
+    /// The client is told when it may not establish a signal.
+    pub denied: bool,
+    /// The client is told about announced signals.
+    pub announce: bool,
+    /// The client is told the value type of every signal it establishes.
+    pub schema: bool,
+    /// Merge patches are sent as they are, otherwise as the complete new value.
+    pub merge_patch: bool,
+    /// The client is told the version of every value, and may resume from it after a reconnect.
+    pub resume: bool,
+    /// Messages to the client are sent in batches.
+    pub batch: Option<BatchConfig>,
+    /// Updates carry the time they were sent, and pings are answered with the time.
+    pub timestamps: bool,
+    /// Messages are encrypted with the cipher of the server.
+    pub cipher: Option<SharedCipher>,
+}
+
+impl ConnectionFeatures {
+    pub fn negotiate(config: &ConnectionConfig, capabilities: &ClientCapabilities) -> Self {
+        Self {
+            #[cfg(feature = "compression")]
+            compression_threshold: config
+                .compression_threshold
+                .filter(|_| capabilities.compression),
+            // Protocol version 0 means no `Hello` was received, see [`FEATURE_PATCHES`]
+            patches: (capabilities.protocol_version == 0 && !config.interop)
+                || capabilities.supports(FEATURE_PATCHES),
+            pause: capabilities.supports(FEATURE_PAUSE),
+            denied: capabilities.supports(FEATURE_DENIED),
+            announce: capabilities.supports(FEATURE_ANNOUNCE),
+            schema: capabilities.supports(FEATURE_SCHEMA),
+            merge_patch: capabilities.supports(FEATURE_MERGE_PATCH),
+            resume: capabilities.supports(FEATURE_RESUME),
+            batch: config
+                .batch
+                .filter(|_| capabilities.supports(FEATURE_BATCH)),
+            timestamps: capabilities.supports(FEATURE_TIMESTAMPS),
+            cipher: config.cipher.clone(),
+        }
+    }
+}
+
+/// Message rates of all connections of one identity, see
+/// [`ServerSignals::identity_stats`](crate::server_signals::ServerSignals::identity_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IdentityStats {
+    pub connections: usize,
+    /// Messages received from the clients of the identity in the last full second.
+    pub received_per_second: u32,
+    /// Messages sent to the clients of the identity in the last full second.
+    pub sent_per_second: u32,
+}
+
+#[derive(Default)]
+struct IdentityRates {
+    window_start: Option<Instant>,
+    received: u32,
+    sent: u32,
+    stats: IdentityStats,
+}
+
+impl IdentityRates {
+    /// Starts a new window once a second has passed, keeping the counts of the last one.
+    fn roll(&mut self) {
+        let now = Instant::now();
+        match self.window_start {
+            Some(start) if now.duration_since(start).as_secs() < 1 => {}
+            _ => {
+                self.stats.received_per_second = self.received;
+                self.stats.sent_per_second = self.sent;
+                self.window_start = Some(now);
+                self.received = 0;
+                self.sent = 0;
+            }
+        }
+    }
+}
+
+type QuotaExceeded = Arc<dyn Fn(&str, &IdentityStats) + Send + Sync>;
+
+/// The message rates of all identities with an open connection.
+#[derive(Clone, Default)]
+pub(crate) struct Identities {
+    rates: Arc<Mutex<HashMap<String, Arc<Mutex<IdentityRates>>>>>,
+    on_quota_exceeded: Arc<RwLock<Option<QuotaExceeded>>>,
+}
+
+impl Identities {
+    /// Counts a new connection of `identity`, until the returned meter is dropped.
+    pub fn meter(&self, identity: String) -> IdentityMeter {
+        let mut identities = self.rates.lock().unwrap();
+        let rates = identities.entry(identity.clone()).or_default().clone();
+        // Counted while holding the map, so a closing connection can't remove the entry meanwhile
+        rates.lock().unwrap().stats.connections += 1;
+        drop(identities);
+        IdentityMeter {
+            identity,
+            rates,
+            identities: self.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> HashMap<String, IdentityStats> {
+        self.rates
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(identity, rates)| {
+                let mut rates = rates.lock().unwrap();
+                rates.roll();
+                (identity.clone(), rates.stats)
+            })
+            .collect()
+    }
+
+    pub fn on_quota_exceeded(&self, callback: QuotaExceeded) {
+        *self.on_quota_exceeded.write().unwrap() = Some(callback);
+    }
+}
+
+/// Counts the messages of one connection towards the rates of its identity.
+pub(crate) struct IdentityMeter {
+    identity: String,
+    rates: Arc<Mutex<IdentityRates>>,
+    identities: Identities,
+}
+
+impl IdentityMeter {
+    /// Counts a received message, returns an error if the identity sent more than `limit`
+    /// messages in the current second.
+    fn received(&self, limit: Option<u32>) -> Result<(), RateLimitReason> {
+        let mut rates = self.rates.lock().unwrap();
+        rates.roll();
+        rates.received += 1;
+        let received = rates.received;
+        let stats = IdentityStats {
+            received_per_second: received,
+            ..rates.stats
+        };
+        drop(rates);
+        match limit {
+            Some(limit) if received > limit => {
+                let callback = self.identities.on_quota_exceeded.read().unwrap().clone();
+                if let Some(callback) = callback {
+                    callback(&self.identity, &stats);
+                }
+                Err(RateLimitReason::TooManyMessages)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn sent(&self) {
+        let mut rates = self.rates.lock().unwrap();
+        rates.roll();
+        rates.sent += 1;
+    }
+}
+
+impl Drop for IdentityMeter {
+    fn drop(&mut self) {
+        let mut identities = self.identities.rates.lock().unwrap();
+        let mut rates = self.rates.lock().unwrap();
+        rates.stats.connections -= 1;
+        if rates.stats.connections == 0 {
+            drop(rates);
+            identities.remove(&self.identity);
+        }
+    }
+}
+
+/// Enforces a [`RateLimitConfig`] for a single connection, and the per identity limit for all
+/// connections of its identity.
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    meter: Option<Arc<IdentityMeter>>,
+    window_start: Instant,
+    in_window: u32,
+    violations: u32,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, meter: Option<IdentityMeter>) -> Self {
+        Self {
+            config,
+            meter: meter.map(Arc::new),
+            window_start: Instant::now(),
+            in_window: 0,
+            violations: 0,
+        }
+    }
+
+    /// Counts a received frame of `bytes` length.
+    pub fn check_message(&mut self, bytes: usize) -> Result<(), RateLimitReason> {
+        if bytes > self.config.max_message_bytes {
+            return Err(RateLimitReason::MessageTooLarge);
+        }
+        if self.window_start.elapsed().as_secs() >= 1 {
+            self.window_start = Instant::now();
+            self.in_window = 0;
+        }
+        self.in_window += 1;
+        if self.in_window > self.config.messages_per_second {
+            return Err(RateLimitReason::TooManyMessages);
+        }
+        match &self.meter {
+            Some(meter) => meter.received(self.config.messages_per_second_per_identity),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts messages sent to the client towards the rates of its identity.
+    pub fn meter(&self) -> Option<Arc<IdentityMeter>> {
+        self.meter.clone()
+    }
+
+    /// Checks the size of an update of a client, counting merge patches like the JSON patch
+    /// they replace.
+    pub fn check_patch(&self, update: &ServerSignalUpdate) -> Result<(), RateLimitReason> {
+        if update.operations() > self.config.max_patch_operations {
+            return Err(RateLimitReason::PatchTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Records a dropped message, returns `true` if the connection should be closed.
+    pub fn violation(&mut self) -> bool {
+        self.violations += 1;
+        self.violations >= self.config.max_violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter::new(config, None)
+    }
+
+    #[test]
+    fn messages_over_the_rate_are_dropped() {
+        let mut limiter = limiter(RateLimitConfig {
+            messages_per_second: 3,
+            ..RateLimitConfig::default()
+        });
+        for _ in 0..3 {
+            assert_eq!(limiter.check_message(10), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_message(10),
+            Err(RateLimitReason::TooManyMessages)
+        );
+    }
+
+    #[test]
+    fn large_messages_and_patches_are_dropped() {
+        let mut limiter = limiter(RateLimitConfig {
+            max_message_bytes: 100,
+            max_patch_operations: 1,
+            ..RateLimitConfig::default()
+        });
+        assert_eq!(limiter.check_message(100), Ok(()));
+        assert_eq!(
+            limiter.check_message(101),
+            Err(RateLimitReason::MessageTooLarge)
+        );
+        let update: ServerSignalUpdate = serde_json::from_value(serde_json::json!({
+            "name": "a",
+            "patch": [
+                { "op": "add", "path": "/a", "value": 1 },
+                { "op": "add", "path": "/b", "value": 2 },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(
+            limiter.check_patch(&update),
+            Err(RateLimitReason::PatchTooLarge)
+        );
+    }
+
+    #[test]
+    fn merge_patches_count_the_values_they_change() {
+        let limiter = limiter(RateLimitConfig {
+            max_patch_operations: 2,
+            ..RateLimitConfig::default()
+        });
+        let merge = |merge| {
+            serde_json::from_value::<ServerSignalUpdate>(serde_json::json!({
+                "name": "a",
+                "patch": [],
+                "merge": merge,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            limiter.check_patch(&merge(serde_json::json!({ "a": 1, "b": { "c": null } }))),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check_patch(&merge(serde_json::json!([1, 2, 3]))),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check_patch(&merge(
+                serde_json::json!({ "a": 1, "b": { "c": 2, "d": 3 } })
+            )),
+            Err(RateLimitReason::PatchTooLarge)
+        );
+    }
+
+    #[test]
+    fn connections_of_an_identity_share_its_limit() {
+        let identities = Identities::default();
+        let exceeded = Arc::new(Mutex::new(Vec::new()));
+        identities.on_quota_exceeded(Arc::new({
+            let exceeded = exceeded.clone();
+            move |identity: &str, _: &IdentityStats| {
+                exceeded.lock().unwrap().push(identity.to_string())
+            }
+        }));
+        let config = RateLimitConfig {
+            messages_per_second_per_identity: Some(3),
+            ..RateLimitConfig::default()
+        };
+        let mut a = RateLimiter::new(config, Some(identities.meter("ada".to_string())));
+        let mut b = RateLimiter::new(config, Some(identities.meter("ada".to_string())));
+        let mut other = RateLimiter::new(config, Some(identities.meter("grace".to_string())));
+        assert_eq!(a.check_message(10), Ok(()));
+        assert_eq!(a.check_message(10), Ok(()));
+        assert_eq!(b.check_message(10), Ok(()));
+        assert_eq!(b.check_message(10), Err(RateLimitReason::TooManyMessages));
+        assert_eq!(other.check_message(10), Ok(()));
+        assert_eq!(*exceeded.lock().unwrap(), ["ada"]);
+    }
+
+    #[test]
+    fn identities_are_removed_with_their_last_connection() {
+        let identities = Identities::default();
+        let a = RateLimiter::new(
+            RateLimitConfig::default(),
+            Some(identities.meter("ada".to_string())),
+        );
+        let b = RateLimiter::new(
+            RateLimitConfig::default(),
+            Some(identities.meter("ada".to_string())),
+        );
+        assert_eq!(identities.stats()["ada"].connections, 2);
+
+        drop(a);
+        assert_eq!(identities.stats()["ada"].connections, 1);
+        drop(b);
+        assert!(identities.stats().is_empty());
+    }
+
+    #[test]
+    fn connections_are_closed_after_too_many_violations() {
+        let mut limiter = limiter(RateLimitConfig {
+            max_violations: 3,
+            ..RateLimitConfig::default()
+        });
+        assert!(!limiter.violation());
+        assert!(!limiter.violation());
+        assert!(limiter.violation());
+    }
+}
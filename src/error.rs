@@ -1,3 +1,9 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, RwLock};
+
+use json_patch::{Patch, PatchOperation};
+use serde_json::Value;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,7 +14,162 @@ pub enum Error {
     AddingSignalFailed,
     #[error("Could not update Signal")]
     UpdateSignalFailed,
+    #[error("Backend failed: {0}")]
+    BackendFailed(String),
+    #[error("Signal store failed: {0}")]
+    StoreFailed(String),
+    #[error("Could not compress or decompress message")]
+    CompressionFailed,
+    #[error("Could not encrypt or decrypt message")]
+    EncryptionFailed,
+    #[error("Unknown signal {0}")]
+    UnknownSignal(String),
+    #[error("Dependency cycle: {0}")]
+    DependencyCycle(String),
+    #[error("{0}")]
+    PatchFailed(Box<PatchFailure>),
+    #[error("Signal {name} is a {found}, not a {expected}")]
+    TypeMismatch {
+        name: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Auth token rejected: {0}")]
+    AuthRejected(String),
+    #[error("Signal {name} holds a {local} here, but a {remote} on the other side")]
+    SchemaMismatch {
+        name: String,
+        local: String,
+        remote: String,
+    },
 
     #[error(transparent)]
     SerializationFailed(#[from] serde_json::Error),
-}
\ No newline at end of file
+}
+
+/// Why a patch could not be applied to a signal.
+///
+/// The value of the signal is left unchanged, so it no longer matches the other side until it is
+/// established again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchFailure {
+    pub signal: String,
+    /// The index of the failed operation in the patch.
+    pub operation: usize,
+    /// The kind of the failed operation, e.g. `replace`.
+    pub op: &'static str,
+    /// The JSON pointer the failed operation targets.
+    pub path: String,
+    pub reason: String,
+    /// The value at `path` before the patch was applied, `None` if there was none.
+    pub current: Option<Value>,
+    /// The value a `test` operation expected at `path`.
+    pub expected: Option<Value>,
+}
+
+impl PatchFailure {
+    pub(crate) fn new(
+        signal: &str,
+        value: &Value,
+        patch: &Patch,
+        err: json_patch::PatchError,
+    ) -> Self {
+        let operation = patch.0.get(err.operation);
+        let (op, expected) = match operation {
+            Some(PatchOperation::Add(_)) => ("add", None),
+            Some(PatchOperation::Remove(_)) => ("remove", None),
+            Some(PatchOperation::Replace(_)) => ("replace", None),
+            Some(PatchOperation::Move(_)) => ("move", None),
+            Some(PatchOperation::Copy(_)) => ("copy", None),
+            Some(PatchOperation::Test(test)) => ("test", Some(test.value.clone())),
+            None => ("unknown", None),
+        };
+        let path = err.path.to_string();
+        Self {
+            signal: signal.to_string(),
+            operation: err.operation,
+            op,
+            current: value.pointer(&path).cloned(),
+            path,
+            reason: err.kind.to_string(),
+            expected,
+        }
+    }
+}
+
+impl Display for PatchFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not apply {} at {} (operation {}) to {}: {}",
+            self.op, self.path, self.operation, self.signal, self.reason
+        )
+    }
+}
+
+/// Downcasts a signal from a registry, failing if it was registered with a different type.
+pub(crate) fn downcast<T: Clone + 'static>(
+    name: &str,
+    signal: &dyn Any,
+    found: &'static str,
+) -> Result<T, Error> {
+    signal
+        .downcast_ref::<T>()
+        .cloned()
+        .ok_or_else(|| Error::TypeMismatch {
+            name: name.to_string(),
+            expected: std::any::type_name::<T>(),
+            found,
+        })
+}
+
+/// An error inside the socket loops or the background tasks of a signal, passed to the
+/// `on_error` hooks of [`ServerSignals`](crate::server_signals::ServerSignals) and
+/// [`ClientSignals`](crate::extension), e.g. to report it to an error tracker.
+#[derive(Debug)]
+pub struct ErrorEvent {
+    /// The signal the error occurred for, if it can be attributed to one.
+    pub signal: Option<String>,
+    pub error: Error,
+}
+
+impl Display for ErrorEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.signal {
+            Some(signal) => write!(f, "{signal}: {}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+type ErrorCallback = Arc<dyn Fn(&ErrorEvent) + Send + Sync>;
+
+/// The `on_error` hooks of a registry.
+#[derive(Clone, Default)]
+pub(crate) struct ErrorHooks(Arc<RwLock<Vec<ErrorCallback>>>);
+
+impl std::fmt::Debug for ErrorHooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ErrorHooks")
+    }
+}
+
+impl ErrorHooks {
+    pub fn add(&self, callback: impl Fn(&ErrorEvent) + Send + Sync + 'static) {
+        self.0.write().unwrap().push(Arc::new(callback));
+    }
+
+    /// Logs the error and passes it to every hook.
+    pub fn report(&self, signal: Option<&str>, error: Error) {
+        let event = ErrorEvent {
+            signal: signal.map(str::to_string),
+            error,
+        };
+        leptos::logging::error!("{event}");
+        // Cloned so hooks can register further hooks without deadlocking
+        let callbacks = self.0.read().unwrap().clone();
+        for callback in callbacks {
+            callback(&event);
+        }
+    }
+}
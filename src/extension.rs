@@ -0,0 +1,40 @@
+//! Building blocks for custom signal kinds.
+//!
+//! A signal kind is a pair of types, one per side, which share a name and exchange
+//! [`ServerSignalUpdate`]s:
+//!
+//! - On the server, implement [`ServerSignalTrait`] and register the signal with
+//!   [`ServerSignals::create_signal`], or with [`ServerSignals::get_or_create_signal`] if
+//!   several tasks may create it at once. Every connection establishing the name receives the
+//!   current [`json`](ServerSignalTrait::json) and then every update sent to the channel returned
+//!   by [`add_observer`](ServerSignalTrait::add_observer).
+//! - On the client, implement [`ClientSignalTrait`] and register the signal with
+//!   [`ClientSignals::create_signal`], which sends the establish message. The signal is
+//!   established again after every reconnect for as long as
+//!   [`is_subscribed`](ClientSignalTrait::is_subscribed) returns `true`.
+//!
+//! Both registries are provided as context by [`provide_websocket`](crate::provide_websocket)
+//! on the client and by the application on the server, and are looked up with `use_context`.
+//!
+//! ```rust,no_run
+//! # use leptos::prelude::*;
+//! # #[cfg(feature = "ssr")]
+//! # type MyCrdt = leptos_ws::ServerSignal<String>;
+//! #[cfg(feature = "ssr")]
+//! fn register(signal: MyCrdt) -> Result<(), leptos_ws::error::Error> {
+//!     let mut signals = use_context::<leptos_ws::extension::ServerSignals>().unwrap();
+//!     futures::executor::block_on(signals.create_signal("doc".to_string(), signal))
+//! }
+//! ```
+
+pub use crate::messages::ServerSignalUpdate;
+
+#[cfg(feature = "server")]
+pub use crate::server_signal::ServerSignalTrait;
+#[cfg(feature = "server")]
+pub use crate::server_signals::ServerSignals;
+
+#[cfg(not(feature = "server"))]
+pub use crate::client_signal::ClientSignalTrait;
+#[cfg(not(feature = "server"))]
+pub use crate::client_signals::ClientSignals;
@@ -0,0 +1,261 @@
+use std::panic::Location;
+
+use leptos::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{ClientInputSignal, ServerSignal};
+
+#[cfg(feature = "server")]
+pub use server::FsmSignal;
+
+#[cfg(not(feature = "server"))]
+pub use client::FsmSignal;
+
+/// A transition requested by a client. The id changes with every request, so requesting the same
+/// event twice is sent twice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TransitionRequest<E> {
+    id: u64,
+    event: E,
+}
+
+#[cfg(feature = "server")]
+mod server {
+    use std::sync::Arc;
+
+    use super::*;
+
+    type Transition<S, E> = dyn Fn(&S, &E) -> Option<S> + Send + Sync;
+
+    /// A state machine, e.g. the lobby, ready and in-game phases of a match.
+    ///
+    /// Clients can't set the state, they request a transition with an event. The server looks
+    /// it up in the transition table, a function returning the next state or `None` if the event
+    /// isn't allowed in the current state, and sends the new state to every client. Refused
+    /// requests are rolled back on the client, see [`FsmSignal::on_rejected`].
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::FsmSignal;
+    /// # use serde::{Deserialize, Serialize};
+    /// # #[derive(Clone, Serialize, Deserialize)]
+    /// # enum Phase { Lobby, Ready, InGame }
+    /// # #[derive(Clone, Serialize, Deserialize)]
+    /// # enum Event { Ready, Start, Finish }
+    /// let phase = FsmSignal::new("phase".to_string(), Phase::Lobby, |phase, event| {
+    ///     match (phase, event) {
+    ///         (Phase::Lobby, Event::Ready) => Some(Phase::Ready),
+    ///         (Phase::Ready, Event::Start) => Some(Phase::InGame),
+    ///         (Phase::InGame, Event::Finish) => Some(Phase::Lobby),
+    ///         _ => None,
+    ///     }
+    /// })
+    /// .unwrap();
+    /// ```
+    #[derive(Clone)]
+    pub struct FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + DeserializeOwned,
+        E: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<TransitionRequest<E>>>,
+        state: ServerSignal<S>,
+        transition: Arc<Transition<S, E>>,
+    }
+
+    impl<S, E> FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        pub fn new(
+            name: String,
+            initial: S,
+            transition: impl Fn(&S, &E) -> Option<S> + Send + Sync + 'static,
+        ) -> Result<Self, Error> {
+            let state = ServerSignal::new(name.clone(), initial)?;
+            let transition: Arc<Transition<S, E>> = Arc::new(transition);
+            let (input, created) = ClientInputSignal::create(name, None::<TransitionRequest<E>>)?;
+            let signal = Self {
+                input,
+                state,
+                transition,
+            };
+            // The validator and the callback are only registered by the call which created the
+            // input, concurrent calls get the same input
+            if !created {
+                return Ok(signal);
+            }
+            let input = signal.input.clone().with_validator({
+                let state = signal.state.clone();
+                let transition = signal.transition.clone();
+                move |request| match request {
+                    Some(request) => state
+                        .with_untracked(|state| transition(state, &request.event))
+                        .map(|_| ())
+                        .ok_or_else(|| "Transition not allowed".to_string()),
+                    None => Ok(()),
+                }
+            });
+            let fsm = signal.clone();
+            input.on_client_update(move |_, connection| {
+                if let Some(Some(request)) = fsm.input.value_for(connection) {
+                    fsm.apply(&request.event);
+                }
+            });
+            Ok(signal)
+        }
+
+        /// Applies the transition of `event` right away if the current state allows it.
+        pub fn request_transition(&self, event: E) {
+            self.apply(&event);
+        }
+
+        /// Does nothing, transitions of the server are never rolled back.
+        pub fn on_rejected(&self, _callback: impl Fn(&str) + Send + Sync + 'static) {}
+
+        /// Whether the current state allows `event`.
+        pub fn can_transition(&self, event: &E) -> bool {
+            self.state
+                .with_untracked(|state| (self.transition)(state, event))
+                .is_some()
+        }
+
+        /// Checked again when it is applied, the state may have changed since the request was
+        /// validated.
+        fn apply(&self, event: &E) {
+            self.state.update(|state| {
+                if let Some(next) = (self.transition)(state, event) {
+                    *state = next;
+                }
+            });
+        }
+    }
+
+    impl<S, E> DefinedAt for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.state.defined_at()
+        }
+    }
+
+    impl<S, E> Track for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.state.track()
+        }
+    }
+
+    impl<S, E> ReadUntracked for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<S> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.state.try_read_untracked()
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use super::*;
+
+    /// A state machine, e.g. the lobby, ready and in-game phases of a match.
+    ///
+    /// The state is only changed by the server. This client requests a transition with
+    /// [`FsmSignal::request_transition`], the server applies it if its transition table allows
+    /// it in the current state and sends the new state to every client.
+    #[derive(Clone)]
+    pub struct FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + DeserializeOwned,
+        E: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<TransitionRequest<E>>>,
+        state: ServerSignal<S>,
+    }
+
+    impl<S, E> FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        /// The transition table only runs on the server.
+        pub fn new(
+            name: String,
+            initial: S,
+            transition: impl Fn(&S, &E) -> Option<S> + Send + Sync + 'static,
+        ) -> Result<Self, Error> {
+            let _ = transition;
+            let input = ClientInputSignal::new(name.clone(), None)?;
+            let state = ServerSignal::new(name, initial)?;
+            Ok(Self { input, state })
+        }
+
+        /// Asks the server to apply `event`. The state changes once the server sent the new one.
+        pub fn request_transition(&self, event: E) {
+            let id = self
+                .input
+                .with_untracked(|request| request.as_ref().map_or(0, |request| request.id + 1));
+            self.input.set(Some(TransitionRequest { id, event }));
+        }
+
+        /// Registers a callback which is called with the reason when the server refused a
+        /// transition, e.g. because another client changed the state first.
+        ///
+        /// Replaces any previously registered callback.
+        pub fn on_rejected(&self, callback: impl Fn(&str) + Send + Sync + 'static) {
+            self.input
+                .on_rejected(move |rejection| callback(&rejection.reason));
+        }
+
+        /// Always `false`, only the server knows the transition table. Request the transition
+        /// and handle [`FsmSignal::on_rejected`] instead.
+        pub fn can_transition(&self, _event: &E) -> bool {
+            false
+        }
+    }
+
+    impl<S, E> DefinedAt for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.state.defined_at()
+        }
+    }
+
+    impl<S, E> Track for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.state.track()
+        }
+    }
+
+    impl<S, E> ReadUntracked for FsmSignal<S, E>
+    where
+        S: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+        E: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<S> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.state.try_read_untracked()
+        }
+    }
+}
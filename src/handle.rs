@@ -0,0 +1,282 @@
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use leptos::prelude::*;
+
+use crate::hooks::WsErrorContext;
+use crate::messages::Messages;
+
+/// The state of the WebSocket connection, see [`WebSocketHandle::connection_state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The first connection attempt is in progress.
+    Connecting,
+    Open,
+    /// The connection was open before and is being reestablished.
+    Reconnecting,
+    Closed,
+}
+
+type Callback = Arc<dyn Fn() + Send + Sync>;
+type ErrorCallback = Arc<dyn Fn(&str) + Send + Sync>;
+type DroppedCallback = Arc<dyn Fn(&Messages) + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct Callbacks {
+    open: RwLock<Vec<Callback>>,
+    close: RwLock<Vec<Callback>>,
+    error: RwLock<Vec<ErrorCallback>>,
+    dropped: RwLock<Vec<DroppedCallback>>,
+}
+
+#[cfg_attr(feature = "server", allow(dead_code))]
+impl Callbacks {
+    pub fn opened(&self) {
+        Self::call(&self.open, |callback| callback());
+    }
+
+    pub fn closed(&self) {
+        Self::call(&self.close, |callback| callback());
+    }
+
+    pub fn failed(&self, error: &str) {
+        Self::call(&self.error, |callback| callback(error));
+    }
+
+    pub fn dropped(&self, message: &Messages) {
+        Self::call(&self.dropped, |callback| callback(message));
+    }
+
+    fn call<C: Clone>(callbacks: &RwLock<Vec<C>>, call: impl Fn(C)) {
+        // Cloned so callbacks can register further callbacks without deadlocking
+        let callbacks = callbacks.read().map(|c| c.clone()).unwrap_or_default();
+        for callback in callbacks {
+            call(callback);
+        }
+    }
+}
+
+/// Returned by [`provide_websocket`](crate::provide_websocket) to observe the connection.
+///
+/// ```rust,no_run
+/// # fn refetch_everything() {}
+/// if let Some(ws) = leptos_ws::provide_websocket("ws://localhost:3000/ws") {
+///     let state = ws.connection_state();
+///     ws.on_open(move || refetch_everything());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct WebSocketHandle {
+    state: Signal<ConnectionState>,
+    callbacks: Arc<Callbacks>,
+    #[cfg(not(feature = "server"))]
+    signals: crate::client_signals::ClientSignals,
+    errors: WsErrorContext,
+}
+
+impl WebSocketHandle {
+    #[cfg(not(feature = "server"))]
+    pub(crate) fn new(
+        state: Signal<ConnectionState>,
+        callbacks: Arc<Callbacks>,
+        signals: crate::client_signals::ClientSignals,
+    ) -> Self {
+        let errors = WsErrorContext::default();
+        signals.on_error({
+            let errors = errors.clone();
+            move |event| errors.push(crate::hooks::WsError::from(event))
+        });
+        if let Ok(mut callbacks) = callbacks.error.write() {
+            let errors = errors.clone();
+            callbacks.push(Arc::new(move |message: &str| {
+                errors.push(crate::hooks::WsError {
+                    signal: None,
+                    message: message.to_string(),
+                })
+            }));
+        }
+        Self {
+            state,
+            callbacks,
+            signals,
+            errors,
+        }
+    }
+
+    /// Makes this the connection of the current component and its children: signals created
+    /// there are synced over it.
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::ServerSignal;
+    /// # #[derive(Clone, serde::Serialize, serde::Deserialize)]
+    /// # struct Player;
+    /// #[component]
+    /// fn Game() -> impl IntoView {
+    ///     if let Some(ws) = leptos_ws::use_websocket_at("/ws/game") {
+    ///         ws.provide();
+    ///     }
+    ///     let players = ServerSignal::new("players".to_string(), Vec::<Player>::new()).unwrap();
+    ///     // ...
+    /// }
+    /// ```
+    pub fn provide(&self) {
+        #[cfg(not(feature = "server"))]
+        if let Ok(ws) = self.signals.ws() {
+            provide_context(self.clone());
+            provide_context(self.signals.clone());
+            provide_context(self.errors.clone());
+            provide_context(ws);
+        }
+    }
+
+    /// The identity this client sends to the server, which stays the same across reconnects and
+    /// page loads. Always `None` on the server.
+    pub fn client_id(&self) -> Option<crate::messages::ClientId> {
+        #[cfg(not(feature = "server"))]
+        if let Some(id) = self.signals.client_id() {
+            return Some(id);
+        }
+        None
+    }
+
+    pub fn connection_state(&self) -> Signal<ConnectionState> {
+        self.state
+    }
+
+    /// The errors of this connection and its signals, see
+    /// [`use_ws_errors`](crate::hooks::use_ws_errors).
+    pub fn errors(&self) -> WsErrorContext {
+        self.errors.clone()
+    }
+
+    /// Called every time the connection opens, including reconnects.
+    pub fn on_open(&self, callback: impl Fn() + Send + Sync + 'static) {
+        if let Ok(mut callbacks) = self.callbacks.open.write() {
+            callbacks.push(Arc::new(callback));
+        }
+    }
+
+    pub fn on_close(&self, callback: impl Fn() + Send + Sync + 'static) {
+        if let Ok(mut callbacks) = self.callbacks.close.write() {
+            callbacks.push(Arc::new(callback));
+        }
+    }
+
+    /// Called when a patch from the server could not be applied to a signal, e.g. to log it. The
+    /// signal fetches the complete value from the server right after.
+    pub fn on_patch_failed(
+        &self,
+        callback: impl Fn(&crate::error::PatchFailure) + Send + Sync + 'static,
+    ) {
+        #[cfg(not(feature = "server"))]
+        self.signals.on_patch_failed(callback);
+        #[cfg(feature = "server")]
+        let _ = callback;
+    }
+
+    /// Called for every error handling the messages of the server, with the signal it occurred
+    /// for, e.g. to report it to an error tracker. See
+    /// [`ClientSignals::on_error`](crate::extension::ClientSignals).
+    pub fn on_signal_error(
+        &self,
+        callback: impl Fn(&crate::error::ErrorEvent) + Send + Sync + 'static,
+    ) {
+        #[cfg(not(feature = "server"))]
+        self.signals.on_error(callback);
+        #[cfg(feature = "server")]
+        let _ = callback;
+    }
+
+    /// Called with a description of the error, e.g. when a message could not be decoded.
+    pub fn on_error(&self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        if let Ok(mut callbacks) = self.callbacks.error.write() {
+            callbacks.push(Arc::new(callback));
+        }
+    }
+
+    /// Called with every message dropped because more than
+    /// [`WsConfig::queue_capacity`](crate::config::WsConfig) messages were queued while the
+    /// connection wasn't open.
+    ///
+    /// Signals are established again on every reconnect either way, so only dropped updates of
+    /// a [`ClientInputSignal`](crate::ClientInputSignal) are lost, until its next write.
+    pub fn on_message_dropped(&self, callback: impl Fn(&Messages) + Send + Sync + 'static) {
+        if let Ok(mut callbacks) = self.callbacks.dropped.write() {
+            callbacks.push(Arc::new(callback));
+        }
+    }
+
+    /// Sends `token` to the server in place of the current one, e.g. after logging in, without
+    /// reconnecting. See [`auth`](crate::auth).
+    pub fn set_auth_token(&self, token: impl Into<String>) {
+        #[cfg(not(feature = "server"))]
+        self.signals.set_auth_token(Some(token.into()));
+        #[cfg(feature = "server")]
+        let _ = token;
+    }
+
+    /// Fetches the token from [`WsConfig::auth_token`](crate::config::WsConfig) again and sends
+    /// it to the server if it changed.
+    pub fn refresh_auth(&self) -> impl Future<Output = ()> + 'static {
+        #[cfg(not(feature = "server"))]
+        let signals = self.signals.clone();
+        async move {
+            #[cfg(not(feature = "server"))]
+            signals.refresh_auth().await;
+        }
+    }
+
+    /// The number of messages waiting for the connection to open.
+    pub fn queued(&self) -> Signal<usize> {
+        #[cfg(not(feature = "server"))]
+        if let Ok(ws) = self.signals.ws() {
+            return ws.queued.into();
+        }
+        Signal::derive(|| 0)
+    }
+
+    /// The latencies of the updates received so far, see [`latency`](crate::latency). Empty
+    /// unless [`WsConfig::measure_latency`](crate::config::WsConfig) is enabled, and on the
+    /// server.
+    pub fn latency_histogram(&self) -> crate::latency::LatencyHistogram {
+        #[cfg(not(feature = "server"))]
+        if let Some(clock) = self.signals.ws().ok().and_then(|ws| ws.clock) {
+            return clock.histogram();
+        }
+        Default::default()
+    }
+
+    /// Resolves once every queued message was handed to the open connection, e.g. to wait
+    /// before producing more writes during an outage instead of having them dropped.
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # #[cfg(feature = "client")]
+    /// # async fn upload(ws: leptos_ws::WebSocketHandle, input: leptos_ws::ClientInputSignal<Vec<u32>>, rows: Vec<u32>) {
+    /// for row in rows {
+    ///     ws.flush().await;
+    ///     input.update(|rows| rows.push(row));
+    /// }
+    /// # }
+    /// ```
+    pub fn flush(&self) -> impl Future<Output = ()> + 'static {
+        #[cfg(not(feature = "server"))]
+        let queued = self.signals.ws().ok().map(|ws| ws.queued);
+        async move {
+            #[cfg(not(feature = "server"))]
+            if let Some(queued) = queued {
+                let flushed = ArcAsyncDerived::new(move || {
+                    let empty = queued.get() == 0;
+                    async move {
+                        if !empty {
+                            // Dropped once `queued` changes and the derived signal reruns
+                            std::future::pending::<()>().await;
+                        }
+                    }
+                });
+                flushed.clone().await;
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Hook-style helpers returning plain Leptos signals.
+
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ServerSignal;
+
+/// The state of a signal's connection to the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyncStatus {
+    /// No value was received from the server yet, the signal holds its initial value.
+    Connecting,
+    /// The signal receives updates from the server.
+    Live,
+    /// The connection is lost, the signal holds the last value received.
+    Stale,
+    /// The server stopped broadcasting updates of the signal, see
+    /// [`ServerSignals::disable`](crate::server_signals::ServerSignals). It holds the last value
+    /// received until updates resume.
+    Paused,
+    /// The server refused to send the signal to this client, see
+    /// [`ServerSignal::new_with_acl`](crate::ServerSignal). It keeps its initial value.
+    Denied,
+}
+
+/// The last change of a signal's value, see `ServerSignal::update_summary`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UpdateSummary {
+    /// When the change was applied, in milliseconds since the Unix epoch.
+    pub at: f64,
+    /// The number of JSON patch operations, `0` if the complete value was sent, e.g. after a
+    /// reconnect.
+    pub operations: usize,
+    pub origin: UpdateOrigin,
+}
+
+/// Where a change described by an [`UpdateSummary`] was made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UpdateOrigin {
+    /// Received from the server, or a client input rolled back by it.
+    Server,
+    /// Written by this client, e.g. to a [`ClientInputSignal`](crate::ClientInputSignal).
+    ThisClient,
+}
+
+impl UpdateSummary {
+    #[cfg(not(feature = "server"))]
+    pub(crate) fn now(operations: usize, origin: UpdateOrigin) -> Self {
+        Self {
+            at: js_sys::Date::now(),
+            operations,
+            origin,
+        }
+    }
+}
+
+/// Creates a [`ServerSignal`] starting at `T::default()` and returns its value and sync status.
+///
+/// ```rust,no_run
+/// # use leptos::prelude::*;
+/// # use leptos_ws::hooks::{use_server_signal, SyncStatus};
+/// # fn view() -> impl IntoView {
+/// let (count, status) = use_server_signal::<i32>("count");
+/// view! { <p class:stale=move || status.get() == SyncStatus::Stale>{count}</p> }
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Panics if no websocket was provided with [`provide_websocket`](crate::provide_websocket) on
+/// the client, or if no `ServerSignals` are in context on the server.
+#[track_caller]
+pub fn use_server_signal<T>(name: &str) -> (Signal<T>, Signal<SyncStatus>)
+where
+    T: Default + Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    use_server_signal_with(name, T::default())
+}
+
+/// Like [`use_server_signal`], but starting at `initial`.
+#[track_caller]
+pub fn use_server_signal_with<T>(name: &str, initial: T) -> (Signal<T>, Signal<SyncStatus>)
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    let signal = ServerSignal::new(name.to_string(), initial)
+        .unwrap_or_else(|err| panic!("Could not create server signal {name}: {err}"));
+    let status = sync_status(&signal);
+    (Signal::derive(move || signal.get()), status)
+}
+
+#[cfg(feature = "server")]
+fn sync_status<T>(_signal: &ServerSignal<T>) -> Signal<SyncStatus>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    Signal::derive(|| SyncStatus::Live)
+}
+
+#[cfg(not(feature = "server"))]
+fn sync_status<T>(signal: &ServerSignal<T>) -> Signal<SyncStatus>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    use leptos_use::core::ConnectionReadyState;
+
+    let synced = signal.synced();
+    let paused = signal.paused();
+    let denied = signal.denied();
+    let ready_state = signal.ready_state();
+    Signal::derive(move || {
+        if denied.get() {
+            return SyncStatus::Denied;
+        }
+        let open = ready_state.get() == ConnectionReadyState::Open;
+        match (synced.get(), open, paused.get()) {
+            (false, _, _) => SyncStatus::Connecting,
+            (true, false, _) => SyncStatus::Stale,
+            (true, true, true) => SyncStatus::Paused,
+            (true, true, false) => SyncStatus::Live,
+        }
+    })
+}
+
+/// A connection or sync error of the websocket, see [`use_ws_errors`].
+///
+/// Implements [`std::error::Error`], so it can be returned into an `ErrorBoundary` like the
+/// [`Error`](crate::error::Error) of the signal constructors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WsError {
+    /// The signal the error occurred for, `None` for errors of the connection itself.
+    pub signal: Option<String>,
+    pub message: String,
+}
+
+impl WsError {
+    /// Whether the connection failed, instead of a single signal.
+    pub fn is_connection_error(&self) -> bool {
+        self.signal.is_none()
+    }
+}
+
+impl std::fmt::Display for WsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.signal {
+            Some(signal) => write!(f, "{signal}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for WsError {}
+
+impl From<&crate::error::ErrorEvent> for WsError {
+    fn from(event: &crate::error::ErrorEvent) -> Self {
+        Self {
+            signal: event.signal.clone(),
+            message: event.error.to_string(),
+        }
+    }
+}
+
+/// The errors of a websocket, provided as context with it, see [`use_ws_errors`].
+///
+/// Keeps the last [`WsErrorContext::CAPACITY`] errors until they are cleared.
+#[derive(Clone, Debug, Default)]
+pub struct WsErrorContext {
+    errors: ArcRwSignal<Vec<WsError>>,
+}
+
+impl WsErrorContext {
+    pub const CAPACITY: usize = 32;
+
+    pub fn errors(&self) -> Signal<Vec<WsError>> {
+        self.errors.clone().into()
+    }
+
+    /// Removes every error, e.g. when the user dismissed them.
+    pub fn clear(&self) {
+        self.errors.update(Vec::clear);
+    }
+
+    #[cfg_attr(feature = "server", allow(dead_code))]
+    pub(crate) fn push(&self, error: WsError) {
+        self.errors.update(|errors| {
+            errors.push(error);
+            let excess = errors.len().saturating_sub(Self::CAPACITY);
+            errors.drain(..excess);
+        });
+    }
+}
+
+/// The connection and sync errors of the websocket provided by
+/// [`provide_websocket`](crate::provide_websocket), oldest first. Always empty on the server.
+///
+/// ```rust,no_run
+/// # use leptos::prelude::*;
+/// # use leptos_ws::hooks::use_ws_errors;
+/// # fn view() -> impl IntoView {
+/// let errors = use_ws_errors();
+/// view! {
+///     <Show when=move || !errors.get().is_empty()>
+///         <ul class="errors">
+///             <For each=move || errors.get() key=|error| error.to_string() let:error>
+///                 <li>{error.to_string()}</li>
+///             </For>
+///         </ul>
+///     </Show>
+/// }
+/// # }
+/// ```
+pub fn use_ws_errors() -> Signal<Vec<WsError>> {
+    match use_context::<WsErrorContext>() {
+        Some(context) => context.errors(),
+        None => Signal::stored(Vec::new()),
+    }
+}
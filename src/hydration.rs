@@ -0,0 +1,29 @@
+//! Carries signal values from the SSR render to the hydrating client, see
+//! [`InitialValue::UseSsrSerializedValue`](crate::config::InitialValue).
+//!
+//! Both sides reserve one slot of the shared context per signal constructor call, so the slots
+//! line up with those of resources created by the same components.
+
+use leptos::prelude::*;
+
+/// Reserves the next slot of the SSR render and fills it with `json`, if given.
+#[cfg(feature = "server")]
+pub(crate) fn embed(json: Option<String>) {
+    if let Some(context) = Owner::current_shared_context() {
+        let id = context.next_id();
+        if let Some(json) = json {
+            context.write_async(id, Box::pin(async move { json }));
+        }
+    }
+}
+
+/// Reserves the next slot while hydrating and returns what the server stored in it.
+#[cfg(not(feature = "server"))]
+pub(crate) fn take() -> Option<String> {
+    let context = Owner::current_shared_context()?;
+    if !context.during_hydration() {
+        return None;
+    }
+    let id = context.next_id();
+    context.read_data(&id)
+}
@@ -0,0 +1,233 @@
+//! End-to-end latency of updates, from the moment the server broadcast them until the client
+//! applied them, see [`WsConfig::measure_latency`](crate::config::WsConfig::measure_latency).
+//!
+//! The server stamps every update with its clock. The client estimates the offset between both
+//! clocks from its heartbeats, the server answers them with its time instead of a plain `Pong`,
+//! so the clocks don't have to agree.
+//!
+//! ```rust,no_run
+//! # use leptos_ws::config::WsConfig;
+//! # let count = leptos_ws::ServerSignal::new("count".to_string(), 0).unwrap();
+//! # #[cfg(feature = "server")]
+//! # let server_signals = leptos_ws::server_signals::ServerSignals::new();
+//! let config = WsConfig::default().measure_latency(true);
+//! // On the client, after every update
+//! let latency = count.latency();
+//! // On the server, aggregated over all clients
+//! # #[cfg(feature = "server")]
+//! let histogram = server_signals.latency_histogram();
+//! ```
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// The upper bounds of the buckets of a [`LatencyHistogram`] in milliseconds. Slower updates
+/// fall into a last bucket without an upper bound.
+pub const BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// The most updates a client may report with a single heartbeat, larger reports are dropped.
+#[cfg(feature = "server")]
+pub(crate) const MAX_REPORTED: u64 = 100_000;
+
+/// How many updates took how long, in the buckets of [`BUCKETS_MS`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// One count per bucket, plus one for the slower updates. Empty until the first one was
+    /// recorded.
+    counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let millis = latency.as_millis() as u64;
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| millis <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts.resize(BUCKETS_MS.len() + 1, 0);
+        self.counts[bucket] = self.counts[bucket].saturating_add(1);
+    }
+
+    /// Adds the counts of `other`, e.g. reported by another client.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        // Reports with more buckets than known are from a newer version and skipped
+        if other.counts.len() > BUCKETS_MS.len() + 1 {
+            return;
+        }
+        self.counts.resize(BUCKETS_MS.len() + 1, 0);
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count = count.saturating_add(*other);
+        }
+    }
+
+    /// The number of updates recorded.
+    pub fn count(&self) -> u64 {
+        self.counts
+            .iter()
+            .fold(0, |count, bucket| count.saturating_add(*bucket))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// The upper bound of every bucket with the number of updates in it, `None` for the last
+    /// one.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        BUCKETS_MS
+            .iter()
+            .map(|&bound| Some(Duration::from_millis(bound)))
+            .chain([None])
+            .zip(self.counts.iter().copied().chain(std::iter::repeat(0)))
+    }
+
+    /// The upper bound of the bucket the update at `percentile` (between 0 and 1) falls into.
+    /// `None` if nothing was recorded or it falls into the last bucket.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((count as f64 * percentile).ceil() as u64).max(1);
+        let mut seen = 0u64;
+        for (bound, bucket) in self.buckets() {
+            seen = seen.saturating_add(bucket);
+            if seen >= rank {
+                return bound;
+            }
+        }
+        None
+    }
+}
+
+/// Milliseconds since the Unix epoch, what updates are stamped with.
+#[cfg(feature = "server")]
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(not(feature = "server"))]
+pub(crate) use client::Clock;
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::LatencyHistogram;
+
+    /// The number of heartbeats the clock offset is estimated from.
+    const SAMPLES: usize = 8;
+
+    /// A clock offset measured with a heartbeat.
+    #[derive(Clone, Copy)]
+    struct Sample {
+        round_trip: f64,
+        /// The time of the server minus the one of the client, in milliseconds.
+        offset: f64,
+    }
+
+    /// Estimates the offset to the clock of the server and records the latency of updates.
+    #[derive(Default)]
+    pub(crate) struct Clock {
+        /// The heartbeat waiting for the time of the server, with when it was sent.
+        pending: Mutex<Option<(u64, f64)>>,
+        samples: Mutex<VecDeque<Sample>>,
+        histogram: Mutex<LatencyHistogram>,
+        /// Recorded since the last report to the server.
+        unreported: Mutex<LatencyHistogram>,
+    }
+
+    impl Clock {
+        pub fn ping_sent(&self, id: u64) {
+            *self.pending.lock().unwrap() = Some((id, js_sys::Date::now()));
+        }
+
+        /// Adds a sample from the time of the server when it answered heartbeat `id`.
+        pub fn time_received(&self, id: u64, server_time: u64) {
+            let now = js_sys::Date::now();
+            let Some((_, sent)) = self
+                .pending
+                .lock()
+                .unwrap()
+                .take()
+                .filter(|(pending, _)| *pending == id)
+            else {
+                return;
+            };
+            let round_trip = now - sent;
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() == SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(Sample {
+                round_trip,
+                // The server read its clock about halfway through the round trip
+                offset: server_time as f64 - (sent + round_trip / 2.0),
+            });
+        }
+
+        /// The estimated offset, from the sample with the shortest round trip, which is
+        /// the least distorted by queueing. `None` until the first heartbeat was answered.
+        fn offset(&self) -> Option<f64> {
+            self.samples
+                .lock()
+                .unwrap()
+                .iter()
+                .min_by(|a, b| a.round_trip.total_cmp(&b.round_trip))
+                .map(|sample| sample.offset)
+        }
+
+        /// The latency of an update stamped with `sent_at` by the server, recorded in the
+        /// histogram. `None` until the offset is known.
+        pub fn measure(&self, sent_at: u64) -> Option<Duration> {
+            let offset = self.offset()?;
+            let received = js_sys::Date::now() + offset;
+            let latency = Duration::from_secs_f64((received - sent_at as f64).max(0.0) / 1000.0);
+            self.histogram.lock().unwrap().record(latency);
+            self.unreported.lock().unwrap().record(latency);
+            Some(latency)
+        }
+
+        pub fn histogram(&self) -> LatencyHistogram {
+            self.histogram.lock().unwrap().clone()
+        }
+
+        /// The latencies recorded since the last call, `None` if there are none.
+        pub fn take_unreported(&self) -> Option<LatencyHistogram> {
+            let unreported = std::mem::take(&mut *self.unreported.lock().unwrap());
+            (!unreported.is_empty()).then_some(unreported)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merging_saturates_instead_of_overflowing() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_millis(1));
+        let report = LatencyHistogram {
+            counts: vec![u64::MAX; BUCKETS_MS.len() + 1],
+        };
+        histogram.merge(&report);
+        assert_eq!(histogram.count(), u64::MAX);
+        assert_eq!(histogram.percentile(1.0), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn reports_with_unknown_buckets_are_skipped() {
+        let mut histogram = LatencyHistogram::default();
+        let report = LatencyHistogram {
+            counts: vec![1; BUCKETS_MS.len() + 2],
+        };
+        histogram.merge(&report);
+        assert!(histogram.is_empty());
+    }
+}
@@ -1,39 +1,131 @@
 #![doc = include_str!("../README.md")]
 #![feature(unboxed_closures)]
-#[cfg(not(feature = "ssr"))]
+
+#[cfg(all(feature = "client", feature = "server", not(docsrs)))]
+compile_error!(
+    "leptos_ws: the `client` and `server` features are mutually exclusive. \
+     Enable `leptos_ws/client` in your hydrate/csr feature and `leptos_ws/server` in your ssr feature."
+);
+
+#[cfg(not(any(feature = "client", feature = "server")))]
+compile_error!(
+    "leptos_ws: no side selected. \
+     Enable `leptos_ws/client` in your hydrate/csr feature and `leptos_ws/server` in your ssr feature."
+);
+
+#[cfg(not(feature = "server"))]
+use crate::cipher::SharedCipher;
+#[cfg(not(feature = "server"))]
 use crate::client_signal::ClientSignal;
+use crate::config::WsConfig;
+#[cfg(not(feature = "server"))]
+use crate::config::{BatchConfig, HeartbeatConfig};
+#[cfg(not(feature = "server"))]
 use crate::messages::ServerSignalMessage;
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
+use crate::messages::{ClientInputMessage, HeartbeatMessage};
+#[cfg(not(feature = "server"))]
 use client_signals::ClientSignals;
+#[cfg(not(feature = "server"))]
 use codee::string::JsonSerdeCodec;
+#[cfg(not(feature = "server"))]
+use error::ErrorHooks;
+#[cfg(not(feature = "server"))]
+use handle::Callbacks;
 use leptos::prelude::*;
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
 use leptos_use::core::ConnectionReadyState;
-use leptos_use::ReconnectLimit;
-#[cfg(not(feature = "ssr"))]
-use leptos_use::{use_websocket_with_options, UseWebSocketOptions, UseWebSocketReturn};
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
+use leptos_use::{
+    use_websocket_with_options, UseWebSocketError, UseWebSocketOptions, UseWebSocketReturn,
+};
+#[cfg(not(feature = "server"))]
 use messages::Messages;
-#[cfg(not(feature = "ssr"))]
-use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "server"))]
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
+mod aggregate;
+pub mod auth;
+pub mod cipher;
+#[cfg(feature = "compression")]
+mod compression;
+pub mod config;
 pub mod error;
+pub mod extension;
+mod fsm;
+mod handle;
+pub mod hooks;
+mod hydration;
+pub mod latency;
+#[cfg(not(feature = "server"))]
+mod local_storage;
+mod log_tail;
+mod message_log;
 pub mod messages;
-#[cfg(feature = "ssr")]
+mod presence;
+pub mod protocol;
+mod scope;
+#[cfg(feature = "server")]
 mod server_signal;
 
-#[cfg(feature = "ssr")]
+#[cfg(feature = "server")]
 pub mod server_signals;
 
-#[cfg(not(feature = "ssr"))]
+#[cfg(feature = "server")]
+pub mod backend;
+
+#[cfg(feature = "server")]
+pub mod persistence;
+
+#[cfg(feature = "server")]
+pub mod connection;
+
+#[cfg(feature = "server")]
+mod routes;
+
+#[cfg(feature = "server")]
+pub mod server_input_signal;
+
+#[cfg(feature = "server")]
+pub mod tasks;
+
+#[cfg(feature = "server")]
+pub mod transport;
+
+#[cfg(not(feature = "server"))]
+mod client_input_signal;
+
+#[cfg(not(feature = "server"))]
 mod client_signal;
 
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
+mod stream;
+
+#[cfg(not(feature = "server"))]
 mod client_signals;
 
-#[cfg(all(feature = "axum", feature = "ssr"))]
+#[cfg(feature = "axum")]
 pub mod axum;
 
+#[cfg(feature = "nats")]
+pub mod nats;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+#[cfg(feature = "tungstenite")]
+pub mod tungstenite;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
 /// A type alias for a signal that synchronizes with the server.
 ///
 /// `ServerSignal<T>` represents a reactive value that can be updated from the server
@@ -51,28 +143,30 @@ pub mod axum;
 ///   These bounds ensure proper serialization, thread safety, and efficient handling of the signal's value.
 /// # Features
 ///
-/// This type alias is conditionally defined based on the "ssr" feature flag:
+/// This type alias is conditionally defined based on the "server" and "client" feature flags:
 ///
-/// - When the "ssr" feature is enabled (server-side rendering):
+/// - When the "server" feature is enabled (server-side rendering):
 ///   `ServerSignal<T>` is an alias for `server_signal::ServerSignal<T>`, which is the
 ///   server-side implementation capable of sending updates to connected clients.
 ///
-/// - When the "ssr" feature is not enabled (client-side):
+/// - When the "client" feature is enabled (client-side):
 ///   `ServerSignal<T>` is an alias for `ClientSignal<T>`, which is the client-side
 ///   implementation that receives updates from the server.
 ///
 /// # Usage
 ///
 /// On the server:
-/// ```rust,ignore
+/// ```rust,no_run
+/// # use leptos_ws::ServerSignal;
 /// #[cfg(feature = "ssr")]
 /// fn create_server_signal() -> ServerSignal<i32> {
-///     ServerSignal::new("counter".to_string(), 0)
+///     ServerSignal::new("counter".to_string(), 0).unwrap()
 /// }
 /// ```
 ///
 /// On the client:
-/// ```rust,ignore
+/// ```rust,no_run
+/// # use leptos_ws::ServerSignal;
 /// #[cfg(not(feature = "ssr"))]
 /// fn use_server_signal() {
 ///     let counter = ServerSignal::<i32>::new("counter".to_string(), 0);
@@ -84,87 +178,429 @@ pub mod axum;
 ///
 /// When using `ServerSignal`, ensure that you've set up the WebSocket connection
 /// using the `provide_websocket` function in your application's root component.
-#[cfg(feature = "ssr")]
+#[cfg(feature = "server")]
 pub type ServerSignal<T> = server_signal::ServerSignal<T>;
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
 pub type ServerSignal<T> = ClientSignal<T>;
 
-#[cfg(not(feature = "ssr"))]
+/// A signal written by the client and read by the server.
+///
+/// - When the "server" feature is enabled, this is an alias for
+///   `server_input_signal::ServerInputSignal<T>`, which exposes the latest value of any client
+///   as well as the values of all connected clients.
+/// - When the "client" feature is enabled, writes are diffed and sent to the server as JSON patches.
+///
+/// No value is ever sent from the server to the client, so there is no initial download.
+#[cfg(feature = "server")]
+pub type ClientInputSignal<T> = server_input_signal::ServerInputSignal<T>;
+#[cfg(not(feature = "server"))]
+pub use client_input_signal::ClientInputSignal;
+
+pub use aggregate::AggregateSignal;
+pub use fsm::FsmSignal;
+pub use handle::{ConnectionState, WebSocketHandle};
+pub use log_tail::LogTailSignal;
+pub use message_log::MessageLog;
+pub use presence::{PresenceEntries, PresenceMap};
+pub use scope::ScopedSignals;
+#[cfg(feature = "server")]
+pub use server_signal::ServerSignalHandle;
+
+#[cfg(not(feature = "server"))]
 #[derive(Clone)]
 struct ServerSignalWebSocket {
     send: Arc<dyn Fn(&Messages) + Send + Sync + 'static>,
     ready_state: Signal<ConnectionReadyState>,
-    delayed_msgs: Arc<Mutex<Vec<Messages>>>,
+    delayed_msgs: Arc<Mutex<VecDeque<Messages>>>,
+    /// The number of messages in `delayed_msgs`.
+    queued: ArcRwSignal<usize>,
+    queue_capacity: usize,
+    callbacks: Arc<Callbacks>,
+    last_seen: ArcRwSignal<Option<f64>>,
+    batch: Option<BatchConfig>,
+    /// Messages waiting to be sent in the next batch, with their size.
+    batched: Arc<Mutex<(Vec<Messages>, usize)>>,
+    /// Set if [`WsConfig::measure_latency`] is enabled.
+    clock: Option<Arc<latency::Clock>>,
+}
+#[cfg(not(feature = "server"))]
+impl Debug for ServerSignalWebSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerSignalWebSocket")
+            .field("ready_state", &self.ready_state)
+            .finish_non_exhaustive()
+    }
 }
-#[cfg(not(feature = "ssr"))]
+#[cfg(not(feature = "server"))]
 impl ServerSignalWebSocket {
+    pub fn is_open(&self) -> bool {
+        self.ready_state.get_untracked() == ConnectionReadyState::Open
+    }
+
     pub fn send(&self, msg: &Messages) -> Result<(), serde_json::Error> {
         if self.ready_state.get() != ConnectionReadyState::Open {
-            self.delayed_msgs
+            let mut delayed_msgs = self
+                .delayed_msgs
                 .lock()
-                .expect("Failed to lock delayed_msgs")
-                .push(msg.clone());
+                .expect("Failed to lock delayed_msgs");
+            let dropped = if delayed_msgs.len() >= self.queue_capacity.max(1) {
+                delayed_msgs.pop_front()
+            } else {
+                None
+            };
+            delayed_msgs.push_back(msg.clone());
+            let queued = delayed_msgs.len();
+            drop(delayed_msgs);
+            self.queued.set(queued);
+            if let Some(dropped) = dropped {
+                leptos::logging::warn!("Dropped a message queued while the connection is closed");
+                self.callbacks.dropped(&dropped);
+            }
+        } else if let Some(batch) = self.batch {
+            let bytes = serde_json::to_string(msg)?.len();
+            let mut batched = self.batched.lock().expect("Failed to lock batched");
+            if !batched.0.is_empty() && batched.1 + bytes > batch.max_bytes {
+                drop(batched);
+                self.flush_batch();
+                batched = self.batched.lock().expect("Failed to lock batched");
+            }
+            batched.0.push(msg.clone());
+            batched.1 += bytes;
+            let (first, full) = (batched.0.len() == 1, batched.0.len() >= batch.max_messages);
+            drop(batched);
+            if full {
+                self.flush_batch();
+            } else if first {
+                let ws = self.clone();
+                set_timeout(move || ws.flush_batch(), batch.delay);
+            }
         } else {
-            (self.send)(&msg);
+            (self.send)(msg);
         }
         Ok(())
     }
-    pub fn new(url: &str) -> Self {
+
+    /// Sends the messages collected for the current batch, see [`WsConfig::batch`].
+    fn flush_batch(&self) {
+        let mut messages = {
+            let mut batched = self.batched.lock().expect("Failed to lock batched");
+            batched.1 = 0;
+            std::mem::take(&mut batched.0)
+        };
+        match messages.len() {
+            0 => {}
+            1 => (self.send)(&messages.pop().unwrap()),
+            _ => (self.send)(&Messages::Batch(messages)),
+        }
+    }
+    /// Opens a connection with its own registry, without providing it as context.
+    pub fn connect(url: &str, config: WsConfig) -> WebSocketHandle {
         let delayed_msgs = Arc::default();
         let state_signals = ClientSignals::new();
+        state_signals.set_client_id(
+            config
+                .client_id
+                .clone()
+                .unwrap_or_else(local_storage::client_id),
+        );
+        state_signals.set_initial_value(config.initial_value);
+        if let Some(provider) = config.auth_token.clone() {
+            state_signals.set_token_provider(provider);
+        }
         let initial_connection = create_rw_signal(true);
+        let last_seen = ArcRwSignal::new(None);
+        let callbacks = Arc::new(Callbacks::default());
         // Create WebSocket with custom message handler
         let UseWebSocketReturn {
             ready_state,
             send,
             open,
+            close,
             ..
         } = use_websocket_with_options::<Messages, Messages, JsonSerdeCodec>(
-            url,
+            &config.url(url),
             UseWebSocketOptions::default()
-                .on_message(Self::handle_message(state_signals.clone()))
+                .protocols((!config.protocols.is_empty()).then(|| config.protocols.clone()))
+                .on_message(Self::handle_message(
+                    state_signals.clone(),
+                    last_seen.clone(),
+                    config.cipher.clone(),
+                ))
                 .on_open({
                     let signals = state_signals.clone();
+                    let last_seen = last_seen.clone();
+                    let callbacks = callbacks.clone();
                     move |_| {
+                        last_seen.set(Some(js_sys::Date::now()));
+                        signals.hello().ok();
                         // Only reconnect if this is not the initial connection
                         if !initial_connection.get() {
                             signals.reconnect().ok();
+                        } else {
+                            signals.establish_inputs().ok();
                         }
                         initial_connection.set(false);
+                        callbacks.opened();
+                    }
+                })
+                .on_close({
+                    let callbacks = callbacks.clone();
+                    move |_| callbacks.closed()
+                })
+                .on_error({
+                    let callbacks = callbacks.clone();
+                    move |err| match err {
+                        UseWebSocketError::Event(_) => callbacks.failed("WebSocket error"),
+                        UseWebSocketError::Codec(_) => {
+                            callbacks.failed("Could not encode or decode message")
+                        }
                     }
                 })
                 .immediate(false),
         );
 
+        let state = Signal::derive(move || match ready_state.get() {
+            ConnectionReadyState::Connecting if initial_connection.get() => {
+                ConnectionState::Connecting
+            }
+            ConnectionReadyState::Connecting => ConnectionState::Reconnecting,
+            ConnectionReadyState::Open => ConnectionState::Open,
+            ConnectionReadyState::Closing | ConnectionReadyState::Closed => ConnectionState::Closed,
+        });
         let ws_client = Self {
             ready_state: ready_state.clone(),
-            send: Arc::new(send),
+            send: Self::sealed(send, config.cipher.clone(), state_signals.error_hooks()),
             delayed_msgs,
+            queued: ArcRwSignal::new(0),
+            queue_capacity: config.queue_capacity,
+            callbacks: callbacks.clone(),
+            last_seen,
+            batch: config.batch,
+            batched: Arc::default(),
+            clock: config.measure_latency.then(Arc::default),
         };
-        // Start Websocket
-        open();
-
-        // Provide ClientSignals for Child Components to work
-        provide_context(state_signals);
+        state_signals.bind(ws_client.clone());
+        // Start Websocket, once the auth token is known so it is sent with the first `Hello`
+        if config.auth_token.is_some() {
+            let signals = state_signals.clone();
+            let open = open.clone();
+            leptos::task::spawn_local(async move {
+                signals.refresh_auth().await;
+                open();
+            });
+        } else {
+            open();
+        }
 
         Self::setup_delayed_message_processor(&ws_client, ready_state);
+        Self::setup_heartbeat(&ws_client, config.heartbeat, open, close);
+        if let (Some(_), Some(interval)) = (&config.auth_token, config.auth_refresh) {
+            Self::setup_auth_refresh(&state_signals, interval);
+        }
 
-        ws_client
+        WebSocketHandle::new(state, callbacks, state_signals)
     }
 
-    fn handle_message(state_signals: ClientSignals) -> impl Fn(&Messages) {
-        move |msg: &Messages| match msg {
+    /// Encrypts every message before it is sent if a cipher is configured.
+    fn sealed(
+        send: impl Fn(&Messages) + Send + Sync + 'static,
+        cipher: Option<SharedCipher>,
+        errors: ErrorHooks,
+    ) -> Arc<dyn Fn(&Messages) + Send + Sync + 'static> {
+        match cipher {
+            Some(cipher) => Arc::new(move |msg: &Messages| {
+                match serde_json::to_string(msg)
+                    .map_err(error::Error::from)
+                    .and_then(|json| cipher.seal(&json))
+                {
+                    Ok(sealed) => send(&sealed),
+                    Err(err) => errors.report(None, err),
+                }
+            }),
+            None => Arc::new(send),
+        }
+    }
+
+    fn handle_message(
+        state_signals: ClientSignals,
+        last_seen: ArcRwSignal<Option<f64>>,
+        cipher: Option<SharedCipher>,
+    ) -> impl Fn(&Messages) {
+        move |msg: &Messages| {
+            last_seen.set(Some(js_sys::Date::now()));
+            match msg {
+                // The server encrypts each message of a batch on its own
+                Messages::Batch(messages) => {
+                    for msg in messages {
+                        Self::open_message(&state_signals, &cipher, msg);
+                    }
+                }
+                msg => Self::open_message(&state_signals, &cipher, msg),
+            }
+        }
+    }
+
+    fn open_message(state_signals: &ClientSignals, cipher: &Option<SharedCipher>, msg: &Messages) {
+        match (cipher, msg) {
+            (Some(cipher), Messages::Encrypted(data)) => match cipher.open(data) {
+                Ok(msg) => Self::dispatch_message(state_signals, &msg),
+                Err(err) => state_signals.report(None, err),
+            },
+            (Some(_), _) => {
+                leptos::logging::error!("Dropped an unencrypted message from the server");
+            }
+            (None, msg) => Self::dispatch_message(state_signals, msg),
+        }
+    }
+
+    fn dispatch_message(state_signals: &ClientSignals, msg: &Messages) {
+        match msg {
             Messages::ServerSignal(server_msg) => match server_msg {
-                ServerSignalMessage::Establish(_) => {
+                ServerSignalMessage::Establish(_)
+                | ServerSignalMessage::EstablishWithPayload(_)
+                | ServerSignalMessage::Resume(_)
+                | ServerSignalMessage::Unsubscribe(_) => {
                     // Usually client-to-server message, ignore if received
                 }
                 ServerSignalMessage::EstablishResponse((name, value)) => {
-                    state_signals.set_json(name, value.to_owned());
+                    if let Some(Err(err)) = state_signals.set_json(name, value.to_owned()) {
+                        state_signals.report(Some(name), err);
+                    }
                 }
                 ServerSignalMessage::Update(update) => {
-                    state_signals.update(&update.name, update.to_owned());
+                    if let Some(Err(err)) = state_signals.update(&update.name, update.to_owned()) {
+                        state_signals.report(Some(&update.name), err);
+                    }
+                }
+                ServerSignalMessage::Version(version) => {
+                    state_signals.set_version(version);
+                }
+                ServerSignalMessage::Paused(name) => {
+                    state_signals.pause(name);
+                }
+                ServerSignalMessage::Denied(name) => {
+                    leptos::logging::warn!("The server refused to establish {name}");
+                    state_signals.deny(name);
+                }
+                ServerSignalMessage::Schema((name, schema)) => {
+                    if let Err(err) = state_signals.check_schema(name, schema) {
+                        state_signals.report(Some(name), err);
+                    }
+                }
+            },
+            Messages::Heartbeat(HeartbeatMessage::Time((id, server_time))) => {
+                if let Some(clock) = state_signals.ws().ok().and_then(|ws| ws.clock) {
+                    clock.time_received(*id, *server_time);
                 }
+            }
+            Messages::Heartbeat(_) => {
+                // Any message counts as a sign of life, nothing else to do
+            }
+            Messages::Hello(_) => {
+                // Client-to-server message, ignore if received
+            }
+            Messages::RateLimited(reason) => {
+                leptos::logging::warn!("Message dropped by the server: {reason:?}");
+            }
+            Messages::SignalAnnounced(announcement) => {
+                state_signals.announce(announcement);
+            }
+            Messages::AuthRejected(reason) => {
+                state_signals.report(None, error::Error::AuthRejected(reason.clone()));
+                // The server closes the connection, reconnect with a fresh token
+                let signals = state_signals.clone();
+                leptos::task::spawn_local(async move { signals.refresh_auth().await });
+            }
+            Messages::Authenticate(_) => {
+                // Client-to-server message, ignore if received
+            }
+            Messages::Batch(messages) => {
+                for msg in messages {
+                    Self::dispatch_message(state_signals, msg);
+                }
+            }
+            #[cfg(feature = "compression")]
+            Messages::Compressed(data) => match compression::decode(data) {
+                Ok(msg) => Self::dispatch_message(state_signals, &msg),
+                Err(err) => state_signals.report(None, err),
+            },
+            #[cfg(not(feature = "compression"))]
+            Messages::Compressed(_) => {
+                leptos::logging::error!(
+                    "Received a compressed message, enable the `compression` feature of leptos_ws"
+                );
+            }
+            Messages::Encrypted(_) => {
+                leptos::logging::error!(
+                    "Received an encrypted message, configure the cipher with `WsConfig::cipher`"
+                );
+            }
+            Messages::ClientInput(input_msg) => match input_msg {
+                ClientInputMessage::Ack((name, seq)) => {
+                    state_signals.acknowledge_input(name, *seq);
+                }
+                ClientInputMessage::Reject(rejection) => {
+                    state_signals.reject_input(rejection).ok();
+                }
+                ClientInputMessage::Establish(_) | ClientInputMessage::Update(_) => {
+                    // Client-to-server message, ignore if received
+                }
+            },
+        }
+    }
+
+    fn setup_heartbeat(
+        ws_client: &Self,
+        heartbeat: HeartbeatConfig,
+        open: impl Fn() + 'static,
+        close: impl Fn() + 'static,
+    ) {
+        let ws = ws_client.clone();
+        let next_ping = AtomicU64::new(0);
+        let handle = set_interval_with_handle(
+            move || {
+                if ws.ready_state.get_untracked() != ConnectionReadyState::Open {
+                    return;
+                }
+                let silent_for = ws
+                    .last_seen
+                    .get_untracked()
+                    .map(|last_seen| js_sys::Date::now() - last_seen)
+                    .unwrap_or_default();
+                if silent_for > heartbeat.timeout.as_millis() as f64 {
+                    // The server stopped answering, reconnecting re-establishes all signals
+                    ws.last_seen.set(None);
+                    close();
+                    open();
+                    return;
+                }
+                let id = next_ping.fetch_add(1, Ordering::Relaxed);
+                if let Some(clock) = &ws.clock {
+                    if let Some(latencies) = clock.take_unreported() {
+                        (ws.send)(&Messages::Heartbeat(HeartbeatMessage::Latency(latencies)));
+                    }
+                    clock.ping_sent(id);
+                }
+                (ws.send)(&Messages::Heartbeat(HeartbeatMessage::Ping(id)));
             },
+            heartbeat.interval,
+        );
+        if let Ok(handle) = handle {
+            on_cleanup(move || handle.clear());
+        }
+    }
+
+    fn setup_auth_refresh(state_signals: &ClientSignals, interval: std::time::Duration) {
+        let signals = state_signals.clone();
+        let handle = set_interval_with_handle(
+            move || {
+                let signals = signals.clone();
+                leptos::task::spawn_local(async move { signals.refresh_auth().await });
+            },
+            interval,
+        );
+        if let Ok(handle) = handle {
+            on_cleanup(move || handle.clear());
         }
     }
 
@@ -185,29 +621,54 @@ impl ServerSignalWebSocket {
             let mut delayed_msgs = ws.delayed_msgs.lock().expect("Failed to lock delayed_msgs");
             delayed_msgs.drain(..).collect::<Vec<_>>()
         };
+        ws.queued.set(0);
 
         for msg in messages {
             if let Err(err) = ws.send(&msg) {
-                eprintln!("Failed to send delayed message: {:?}", err);
+                leptos::logging::error!("Failed to send delayed message: {:?}", err);
             }
         }
     }
 }
 
-#[cfg(not(feature = "ssr"))]
-#[inline]
-fn provide_websocket_inner(url: &str) -> Option<()> {
-    use leptos::prelude::{provide_context, use_context};
+/// The connections opened with [`provide_websocket_at`], by URL.
+#[cfg(not(feature = "server"))]
+#[derive(Clone, Default)]
+struct WebSockets(Arc<Mutex<HashMap<String, WebSocketHandle>>>);
 
-    if let None = use_context::<ServerSignalWebSocket>() {
-        provide_context(ServerSignalWebSocket::new(url));
+#[cfg(not(feature = "server"))]
+#[inline]
+fn provide_websocket_inner(url: &str, config: WsConfig) -> Option<WebSocketHandle> {
+    if let Some(handle) = use_context::<WebSocketHandle>() {
+        return Some(handle);
     }
-    Some(())
+    let handle = websocket_at(url, config)?;
+    handle.provide();
+    Some(handle)
 }
 
-#[cfg(feature = "ssr")]
+#[cfg(feature = "server")]
 #[inline]
-fn provide_websocket_inner(_url: &str) -> Option<()> {
+fn provide_websocket_inner(_url: &str, _config: WsConfig) -> Option<WebSocketHandle> {
+    None
+}
+
+#[cfg(not(feature = "server"))]
+fn websocket_at(url: &str, config: WsConfig) -> Option<WebSocketHandle> {
+    let websockets = use_context::<WebSockets>().unwrap_or_else(|| {
+        let websockets = WebSockets::default();
+        provide_context(websockets.clone());
+        websockets
+    });
+    let mut websockets = websockets.0.lock().ok()?;
+    let handle = websockets
+        .entry(url.to_string())
+        .or_insert_with(|| ServerSignalWebSocket::connect(url, config));
+    Some(handle.clone())
+}
+
+#[cfg(feature = "server")]
+fn websocket_at(_url: &str, _config: WsConfig) -> Option<WebSocketHandle> {
     None
 }
 /// Establishes and provides a WebSocket connection for server signals.
@@ -222,18 +683,18 @@ fn provide_websocket_inner(_url: &str) -> Option<()> {
 ///
 /// # Returns
 ///
-/// Returns a `Result` which is:
-/// - `Some(())` if the connection is successfully established (client-side only).
+/// Returns:
+/// - `Some(WebSocketHandle)` on the client, to observe the connection state.
 /// - `None` if running in SSR mode.
 ///
 /// # Features
 ///
-/// - When the "ssr" feature is not enabled (client-side):
+/// - When the "client" feature is enabled (client-side):
 ///   - Creates a new WebSocket connection.
 ///   - Sets up message handling for server signals.
 ///   - Provides context for `ServerSignalWebSocket` and `ClientSignals`.
 ///
-/// - When the "ssr" feature is enabled (server-side):
+/// - When the "server" feature is enabled (server-side):
 ///   - Returns `None` without establishing a connection.
 ///
 /// # Examples
@@ -242,10 +703,10 @@ fn provide_websocket_inner(_url: &str) -> Option<()> {
 /// use leptos_ws::provide_websocket;
 ///
 /// fn setup_websocket() {
-///     if let Ok(Some(_)) = provide_websocket("ws://example.com/socket") {
-///         println!("WebSocket connection established");
+///     if let Some(ws) = provide_websocket("ws://example.com/socket") {
+///         let state = ws.connection_state();
 ///     } else {
-///         println!("Running in SSR mode or connection failed");
+///         println!("Running in SSR mode");
 ///     }
 /// }
 /// ```
@@ -254,6 +715,98 @@ fn provide_websocket_inner(_url: &str) -> Option<()> {
 ///
 /// This function should be called in the root component of your Leptos application
 /// to ensure the WebSocket connection is available throughout the app.
-pub fn provide_websocket(url: &str) -> Option<()> {
-    provide_websocket_inner(url)
+pub fn provide_websocket(url: &str) -> Option<WebSocketHandle> {
+    provide_websocket_inner(url, WsConfig::default())
+}
+
+/// Like [`provide_websocket`], but with a custom [`WsConfig`], e.g. to tune the heartbeat.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use leptos_ws::config::{HeartbeatConfig, WsConfig};
+///
+/// let config = WsConfig::default().heartbeat(HeartbeatConfig::new(
+///     Duration::from_secs(5),
+///     Duration::from_secs(15),
+/// ));
+/// leptos_ws::provide_websocket_with_config("ws://example.com/socket", config);
+/// ```
+pub fn provide_websocket_with_config(url: &str, config: WsConfig) -> Option<WebSocketHandle> {
+    provide_websocket_inner(url, config)
+}
+
+/// Opens an additional connection to `url`, e.g. a separate endpoint for bulk data next to a
+/// low-latency one.
+///
+/// Unlike [`provide_websocket`], the connection does not become the default for signals. Call
+/// [`WebSocketHandle::provide`] in the components whose signals should be synced over it. Calling
+/// this again with the same `url` returns the existing connection.
+///
+/// ```rust,no_run
+/// leptos_ws::provide_websocket("/ws");
+/// leptos_ws::provide_websocket_at("/ws/bulk");
+/// ```
+///
+/// Returns `None` during SSR.
+pub fn provide_websocket_at(url: &str) -> Option<WebSocketHandle> {
+    websocket_at(url, WsConfig::default())
+}
+
+/// Like [`provide_websocket_at`], but with a custom [`WsConfig`].
+pub fn provide_websocket_at_with_config(url: &str, config: WsConfig) -> Option<WebSocketHandle> {
+    websocket_at(url, config)
+}
+
+/// Returns the connection to `url` opened by [`provide_websocket`] or [`provide_websocket_at`] in
+/// this or a parent component.
+pub fn use_websocket_at(url: &str) -> Option<WebSocketHandle> {
+    #[cfg(not(feature = "server"))]
+    if let Some(websockets) = use_context::<WebSockets>() {
+        return websockets.0.lock().ok()?.get(url).cloned();
+    }
+    let _ = url;
+    None
+}
+
+/// Returns the signals announced by the server with
+/// [`ServerSignals::announce`](crate::server_signals::ServerSignals::announce), e.g. to render a
+/// chart for every one of them.
+///
+/// ```rust,no_run
+/// # use leptos::prelude::*;
+/// # use leptos_ws::use_announced_signals;
+/// # #[component]
+/// # fn Chart(name: String) -> impl IntoView {
+/// #     name
+/// # }
+/// # fn view() -> impl IntoView {
+/// let announced = use_announced_signals();
+/// view! {
+///     <For each=move || announced.get() key=|signal| signal.name.clone() let:signal>
+///         <Chart name=signal.name />
+///     </For>
+/// }
+/// # }
+/// ```
+///
+/// Always empty during SSR, the client learns about the signals once it connected.
+pub fn use_announced_signals() -> Signal<Vec<messages::SignalAnnouncement>> {
+    #[cfg(not(feature = "server"))]
+    if let Some(signals) = use_context::<ClientSignals>() {
+        return signals.announced().into();
+    }
+    Signal::derive(Vec::new)
+}
+
+/// Returns the time of the last message received from the server, in milliseconds since the Unix epoch.
+///
+/// The value is `None` while no connection is open and always `None` during SSR.
+pub fn use_last_seen() -> Signal<Option<f64>> {
+    #[cfg(not(feature = "server"))]
+    if let Some(ws) = use_context::<ServerSignalWebSocket>() {
+        return ws.last_seen.into();
+    }
+    Signal::derive(|| None)
 }
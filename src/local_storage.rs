@@ -0,0 +1,49 @@
+//! Caches the last known values of persistent signals in the browser's `localStorage`, see
+//! `ServerSignal::new_persistent`, and keeps the [`ClientId`] of the browser.
+
+use crate::messages::ClientId;
+
+/// Outside of the `leptos_ws:` prefix, so no signal name can collide with it.
+const CLIENT_ID_KEY: &str = "leptos_ws.client_id";
+
+/// Keys are prefixed, so the cache doesn't collide with other data of the app.
+fn key(name: &str) -> String {
+    format!("leptos_ws:{name}")
+}
+
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The cached JSON of the signal `name`, if any.
+pub(crate) fn load(name: &str) -> Option<String> {
+    storage()?.get_item(&key(name)).ok()?
+}
+
+/// Caches `json` as the value of the signal `name`. Failures, e.g. a full or disabled storage,
+/// only cost the cache and are ignored.
+pub(crate) fn store(name: &str, json: &str) {
+    if let Some(storage) = storage() {
+        let _ = storage.set_item(&key(name), json);
+    }
+}
+
+/// The client id kept in the storage, generating and storing one on the first call. Without a
+/// storage, e.g. in private windows of some browsers, the id lasts as long as the page.
+pub(crate) fn client_id() -> ClientId {
+    if let Some(id) = storage().and_then(|storage| storage.get_item(CLIENT_ID_KEY).ok()?) {
+        return ClientId(id);
+    }
+    let id = (0..4)
+        .map(|_| {
+            format!(
+                "{:08x}",
+                (js_sys::Math::random() * f64::from(u32::MAX)) as u32
+            )
+        })
+        .collect::<String>();
+    if let Some(storage) = storage() {
+        let _ = storage.set_item(CLIENT_ID_KEY, &id);
+    }
+    ClientId(id)
+}
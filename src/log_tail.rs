@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::panic::Location;
+
+use leptos::prelude::*;
+use serde_json::Value;
+
+#[cfg(feature = "server")]
+pub use server::LogTailSignal;
+
+#[cfg(not(feature = "server"))]
+pub use client::LogTailSignal;
+
+#[cfg(feature = "server")]
+mod server {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use tokio::sync::broadcast::Receiver;
+
+    use super::*;
+    use crate::backend::Backend;
+    use crate::error::Error;
+    use crate::messages::ServerSignalUpdate;
+    use crate::scope::scoped_name;
+    use crate::server_signal::{Observers, ServerSignalTrait};
+    use crate::server_signals::ServerSignals;
+    use crate::tasks::{TaskKind, TaskRegistry};
+
+    /// An append-only list of lines, e.g. the tail of a log file.
+    ///
+    /// The server keeps the last `capacity` lines, which a client receives when it establishes
+    /// the signal. After that only appended lines are sent, never the whole list.
+    #[derive(Clone)]
+    pub struct LogTailSignal {
+        name: String,
+        capacity: usize,
+        lines: ArcRwSignal<VecDeque<String>>,
+        observers: Arc<Observers>,
+        backend: Option<Arc<dyn Backend>>,
+        tasks: TaskRegistry,
+    }
+
+    #[async_trait]
+    impl ServerSignalTrait for LogTailSignal {
+        async fn add_observer(&self) -> Receiver<ServerSignalUpdate> {
+            self.observers.subscribe()
+        }
+
+        fn observer_count(&self) -> usize {
+            self.observers.receiver_count()
+        }
+
+        fn version(&self) -> Option<(u64, u64)> {
+            Some(self.observers.version())
+        }
+
+        fn updates_since(&self, epoch: u64, seq: u64) -> Option<Vec<ServerSignalUpdate>> {
+            self.observers.since(epoch, seq)
+        }
+
+        fn last_update(&self) -> Option<std::time::Instant> {
+            self.observers.last_sent()
+        }
+
+        async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
+            let lines = appended_lines(&patch)?;
+            self.observers.send_after(patch, || {
+                self.lines
+                    .update(|buffer| append(buffer, lines, self.capacity))
+            });
+            Ok(())
+        }
+
+        fn json(&self) -> Result<Value, Error> {
+            Ok(serde_json::to_value(&*self.lines.read_untracked())?)
+        }
+
+        async fn snapshot(&self, _payload: Value) -> Result<(Value, Option<(u64, u64)>), Error> {
+            let (json, version) = self.observers.snapshot(|| self.json());
+            Ok((json?, Some(version)))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn track(&self) {
+            self.lines.track()
+        }
+    }
+
+    impl LogTailSignal {
+        pub fn new(name: String, capacity: usize) -> Result<Self, Error> {
+            let name = scoped_name(name);
+            let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+            signals
+                .register_signal(
+                    name.clone(),
+                    LogTailSignal {
+                        name: name.clone(),
+                        capacity,
+                        lines: ArcRwSignal::new(VecDeque::with_capacity(capacity)),
+                        observers: Arc::new(Observers::new(32)),
+                        backend: signals.backend(),
+                        tasks: signals.tasks().clone(),
+                    },
+                )
+                .map(|(signal, _)| signal)
+        }
+
+        /// Appends a line and sends it to every client.
+        pub fn push(&self, line: impl Into<String>) {
+            let line = line.into();
+            let update = ServerSignalUpdate {
+                name: self.name.clone().into(),
+                patch: serde_json::from_value(serde_json::json!([
+                    { "op": "add", "path": "/-", "value": line }
+                ]))
+                .unwrap(),
+                merge: None,
+                seq: None,
+                sent_at: None,
+                audience: None,
+            };
+            if let Some(backend) = self.backend.clone() {
+                let update = update.clone();
+                self.tasks.spawn(TaskKind::Backend, None, async move {
+                    if let Err(err) = backend.publish(&update).await {
+                        leptos::logging::error!("Could not publish log line: {err}");
+                    }
+                });
+            }
+            self.observers.send_after(update, || {
+                self.lines
+                    .update(|buffer| append(buffer, vec![line], self.capacity))
+            });
+        }
+
+        /// Always empty, only clients receive lines.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = String> + Send + 'static {
+            futures::stream::empty()
+        }
+    }
+
+    impl DefinedAt for LogTailSignal {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.lines.defined_at()
+        }
+    }
+
+    impl Track for LogTailSignal {
+        #[track_caller]
+        fn track(&self) {
+            self.lines.track()
+        }
+    }
+
+    impl ReadUntracked for LogTailSignal {
+        type Value = <ArcRwSignal<VecDeque<String>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.lines.try_read_untracked()
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use std::any::Any;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::client_signal::ClientSignalTrait;
+    use crate::client_signals::ClientSignals;
+    use crate::error::Error;
+    use crate::messages::ServerSignalUpdate;
+    use crate::scope::scoped_name;
+    use crate::stream::Streams;
+
+    /// An append-only list of lines, e.g. the tail of a log file.
+    ///
+    /// Only appended lines are received from the server. The client keeps at most `capacity`
+    /// lines and drops the oldest ones beyond that.
+    ///
+    /// While [`following`](LogTailSignal::set_follow) is turned off, new lines are held back so
+    /// the list stays still, e.g. while the user scrolls through it. They are added once
+    /// following is turned on again.
+    #[derive(Clone, Debug)]
+    pub struct LogTailSignal {
+        capacity: usize,
+        lines: ArcRwSignal<VecDeque<String>>,
+        follow: ArcRwSignal<bool>,
+        held_back: Arc<Mutex<Vec<String>>>,
+        streams: Arc<Streams<String>>,
+    }
+
+    impl ClientSignalTrait for LogTailSignal {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
+            let lines = appended_lines(&patch)?;
+            for line in &lines {
+                self.streams.send_value(line);
+            }
+            if self.follow.get_untracked() {
+                self.lines
+                    .update(|buffer| append(buffer, lines, self.capacity));
+            } else {
+                self.held_back
+                    .lock()
+                    .map_err(|_| Error::UpdateSignalFailed)?
+                    .extend(lines);
+            }
+            Ok(())
+        }
+
+        fn json(&self) -> Result<Value, Error> {
+            Ok(serde_json::to_value(&*self.lines.read_untracked())?)
+        }
+
+        fn set_json(&self, new_value: Value) -> Result<(), Error> {
+            let lines: Vec<String> = serde_json::from_value(new_value)?;
+            self.held_back
+                .lock()
+                .map_err(|_| Error::UpdateSignalFailed)?
+                .clear();
+            self.lines.update(|buffer| {
+                buffer.clear();
+                append(buffer, lines, self.capacity);
+            });
+            Ok(())
+        }
+
+        fn track(&self) {
+            self.lines.track()
+        }
+
+        fn is_subscribed(&self) -> bool {
+            true
+        }
+    }
+
+    impl LogTailSignal {
+        pub fn new(name: String, capacity: usize) -> Result<Self, Error> {
+            let name = scoped_name(name);
+            let mut signals: ClientSignals =
+                use_context::<ClientSignals>().ok_or(Error::MissingServerSignals)?;
+            if let Some(signal) = signals.get_signal::<LogTailSignal>(&name) {
+                return signal;
+            }
+            let new_signal = Self {
+                capacity,
+                lines: ArcRwSignal::new(VecDeque::with_capacity(capacity)),
+                follow: ArcRwSignal::new(true),
+                held_back: Arc::default(),
+                streams: Arc::default(),
+            };
+            signals.get_or_create_signal(name, new_signal)
+        }
+
+        pub fn follow(&self) -> Signal<bool> {
+            self.follow.clone().into()
+        }
+
+        /// Turns following on or off, turning it on adds all lines received in the meantime.
+        pub fn set_follow(&self, follow: bool) {
+            if follow {
+                let held_back = self
+                    .held_back
+                    .lock()
+                    .map(|mut held_back| std::mem::take(&mut *held_back))
+                    .unwrap_or_default();
+                self.lines
+                    .update(|buffer| append(buffer, held_back, self.capacity));
+            }
+            self.follow.set(follow);
+        }
+
+        /// Every line appended from now on, whether following or not. Dropping the stream
+        /// unsubscribes it.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = String> + Send + 'static {
+            self.streams.values()
+        }
+    }
+
+    impl DefinedAt for LogTailSignal {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.lines.defined_at()
+        }
+    }
+
+    impl Track for LogTailSignal {
+        #[track_caller]
+        fn track(&self) {
+            self.lines.track()
+        }
+    }
+
+    impl ReadUntracked for LogTailSignal {
+        type Value = <ArcRwSignal<VecDeque<String>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.lines.try_read_untracked()
+        }
+    }
+}
+
+/// Extracts the lines of a patch made of `add /-` operations.
+fn appended_lines(
+    patch: &crate::messages::ServerSignalUpdate,
+) -> Result<Vec<String>, crate::error::Error> {
+    let mut appended = Value::Array(Vec::new());
+    patch.apply(&mut appended)?;
+    Ok(serde_json::from_value(appended)?)
+}
+
+fn append(buffer: &mut VecDeque<String>, lines: Vec<String>, capacity: usize) {
+    buffer.extend(lines);
+    while buffer.len() > capacity {
+        buffer.pop_front();
+    }
+}
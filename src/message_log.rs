@@ -0,0 +1,368 @@
+use std::panic::Location;
+
+use leptos::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "server")]
+pub use server::MessageLog;
+
+#[cfg(not(feature = "server"))]
+pub use client::MessageLog;
+
+#[cfg(feature = "server")]
+mod server {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use tokio::sync::broadcast::Receiver;
+
+    use super::*;
+    use crate::backend::Backend;
+    use crate::error::Error;
+    use crate::messages::ServerSignalUpdate;
+    use crate::scope::scoped_name;
+    use crate::server_signal::{Observers, ServerSignalTrait};
+    use crate::server_signals::ServerSignals;
+    use crate::tasks::{TaskKind, TaskRegistry};
+
+    /// The establish payload of a windowed client, see [`MessageLog::new_windowed`].
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct HistoryRequest {
+        last: Option<usize>,
+    }
+
+    impl HistoryRequest {
+        fn from_payload(payload: Value) -> Result<Self, Error> {
+            Ok(match payload {
+                Value::Null => HistoryRequest::default(),
+                payload => serde_json::from_value(payload)?,
+            })
+        }
+    }
+
+    /// An append-only log of entries, e.g. the messages of a chat or an audit trail.
+    ///
+    /// The server keeps the last `history` entries, which a client receives when it establishes
+    /// the signal. After that only appended entries are sent, never a diff of the whole log.
+    #[derive(Clone)]
+    pub struct MessageLog<T> {
+        name: String,
+        history: usize,
+        entries: ArcRwSignal<Vec<T>>,
+        observers: Arc<Observers>,
+        backend: Option<Arc<dyn Backend>>,
+        tasks: TaskRegistry,
+    }
+
+    #[async_trait]
+    impl<T> ServerSignalTrait for MessageLog<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        async fn add_observer(&self) -> Receiver<ServerSignalUpdate> {
+            self.observers.subscribe()
+        }
+
+        async fn establish(
+            &self,
+            payload: Value,
+        ) -> Result<(Value, Receiver<ServerSignalUpdate>), Error> {
+            let request = HistoryRequest::from_payload(payload)?;
+            // Subscribed first, so no entry appended in between is missed
+            let recv = self.observers.subscribe();
+            Ok((self.history_json(&request)?, recv))
+        }
+
+        async fn snapshot(&self, payload: Value) -> Result<(Value, Option<(u64, u64)>), Error> {
+            let request = HistoryRequest::from_payload(payload)?;
+            let (json, version) = self.observers.snapshot(|| self.history_json(&request));
+            Ok((json?, Some(version)))
+        }
+
+        fn observer_count(&self) -> usize {
+            self.observers.receiver_count()
+        }
+
+        fn version(&self) -> Option<(u64, u64)> {
+            Some(self.observers.version())
+        }
+
+        fn updates_since(&self, epoch: u64, seq: u64) -> Option<Vec<ServerSignalUpdate>> {
+            self.observers.since(epoch, seq)
+        }
+
+        fn last_update(&self) -> Option<std::time::Instant> {
+            self.observers.last_sent()
+        }
+
+        async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
+            let entries = appended_entries(&patch)?;
+            self.observers.send_after(patch, || {
+                self.entries
+                    .update(|log| append(log, entries, Some(self.history)))
+            });
+            Ok(())
+        }
+
+        fn json(&self) -> Result<Value, Error> {
+            Ok(serde_json::to_value(&*self.entries.read_untracked())?)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> Option<&'static str> {
+            Some(std::any::type_name::<T>())
+        }
+
+        fn track(&self) {
+            self.entries.track()
+        }
+    }
+
+    impl<T> MessageLog<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        pub fn new(name: String, history: usize) -> Result<Self, Error> {
+            let name = scoped_name(name);
+            let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+            signals
+                .register_signal(
+                    name.clone(),
+                    MessageLog {
+                        name: name.clone(),
+                        history,
+                        entries: ArcRwSignal::new(Vec::with_capacity(history)),
+                        observers: Arc::new(Observers::new(32)),
+                        backend: signals.backend(),
+                        tasks: signals.tasks().clone(),
+                    },
+                )
+                .map(|(signal, _)| signal)
+        }
+
+        /// The entries a client asked for with `request`.
+        fn history_json(&self, request: &HistoryRequest) -> Result<Value, Error> {
+            let entries = self.entries.read_untracked();
+            let skip = request
+                .last
+                .map_or(0, |last| entries.len().saturating_sub(last));
+            Ok(serde_json::to_value(&entries[skip..])?)
+        }
+
+        /// Like [`MessageLog::new`], the window only applies to clients.
+        pub fn new_windowed(name: String, history: usize, window: usize) -> Result<Self, Error> {
+            let _ = window;
+            Self::new(name, history)
+        }
+
+        /// Appends an entry and sends it to every client.
+        pub fn push(&self, entry: T) -> Result<(), Error> {
+            let update = ServerSignalUpdate {
+                name: self.name.clone().into(),
+                patch: serde_json::from_value(serde_json::json!([
+                    { "op": "add", "path": "/-", "value": serde_json::to_value(&entry)? }
+                ]))?,
+                merge: None,
+                seq: None,
+                sent_at: None,
+                audience: None,
+            };
+            if let Some(backend) = self.backend.clone() {
+                let update = update.clone();
+                self.tasks.spawn(TaskKind::Backend, None, async move {
+                    if let Err(err) = backend.publish(&update).await {
+                        leptos::logging::error!("Could not publish log entry: {err}");
+                    }
+                });
+            }
+            self.observers.send_after(update, || {
+                self.entries
+                    .update(|log| append(log, vec![entry], Some(self.history)))
+            });
+            Ok(())
+        }
+
+        /// Always empty, only clients receive entries.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = T> + Send + 'static {
+            futures::stream::empty()
+        }
+    }
+
+    impl<T: 'static> DefinedAt for MessageLog<T> {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.entries.defined_at()
+        }
+    }
+
+    impl<T: 'static> Track for MessageLog<T> {
+        #[track_caller]
+        fn track(&self) {
+            self.entries.track()
+        }
+    }
+
+    impl<T: 'static> ReadUntracked for MessageLog<T> {
+        type Value = <ArcRwSignal<Vec<T>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.entries.try_read_untracked()
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::client_signal::ClientSignalTrait;
+    use crate::client_signals::ClientSignals;
+    use crate::error::Error;
+    use crate::messages::ServerSignalUpdate;
+    use crate::scope::scoped_name;
+    use crate::stream::Streams;
+
+    /// An append-only log of entries, e.g. the messages of a chat or an audit trail.
+    ///
+    /// Only appended entries are received from the server. A windowed log keeps at most
+    /// `window` entries and drops the oldest ones beyond that, it also only receives the last
+    /// `window` entries of the history when it joins.
+    #[derive(Clone)]
+    pub struct MessageLog<T> {
+        window: Option<usize>,
+        entries: ArcRwSignal<Vec<T>>,
+        streams: Arc<Streams<T>>,
+    }
+
+    impl<T> ClientSignalTrait for MessageLog<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> Option<&'static str> {
+            Some(std::any::type_name::<T>())
+        }
+
+        fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
+            let entries: Vec<T> = appended_entries(&patch)?;
+            for entry in &entries {
+                self.streams.send_value(entry);
+            }
+            self.entries.update(|log| append(log, entries, self.window));
+            Ok(())
+        }
+
+        fn json(&self) -> Result<Value, Error> {
+            Ok(serde_json::to_value(&*self.entries.read_untracked())?)
+        }
+
+        fn set_json(&self, new_value: Value) -> Result<(), Error> {
+            let entries: Vec<T> = serde_json::from_value(new_value)?;
+            self.entries.update(|log| {
+                log.clear();
+                append(log, entries, self.window);
+            });
+            Ok(())
+        }
+
+        fn track(&self) {
+            self.entries.track()
+        }
+
+        fn is_subscribed(&self) -> bool {
+            true
+        }
+
+        fn establish_payload(&self) -> Option<Value> {
+            self.window
+                .map(|window| serde_json::json!({ "last": window }))
+        }
+    }
+
+    impl<T> MessageLog<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        /// A log which keeps every entry, starting with the history of the server.
+        pub fn new(name: String, history: usize) -> Result<Self, Error> {
+            let _ = history;
+            Self::new_with_window(name, None)
+        }
+
+        /// A log which keeps the last `window` entries.
+        pub fn new_windowed(name: String, history: usize, window: usize) -> Result<Self, Error> {
+            let _ = history;
+            Self::new_with_window(name, Some(window))
+        }
+
+        fn new_with_window(name: String, window: Option<usize>) -> Result<Self, Error> {
+            let name = scoped_name(name);
+            let mut signals: ClientSignals =
+                use_context::<ClientSignals>().ok_or(Error::MissingServerSignals)?;
+            if let Some(signal) = signals.get_signal::<MessageLog<T>>(&name) {
+                return signal;
+            }
+            let new_signal = Self {
+                window,
+                entries: ArcRwSignal::new(Vec::new()),
+                streams: Arc::default(),
+            };
+            signals.get_or_create_signal(name, new_signal)
+        }
+
+        /// Every entry appended from now on, without the history received when joining.
+        /// Dropping the stream unsubscribes it.
+        pub fn to_stream(&self) -> impl futures::Stream<Item = T> + Send + 'static {
+            self.streams.values()
+        }
+    }
+
+    impl<T: 'static> DefinedAt for MessageLog<T> {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.entries.defined_at()
+        }
+    }
+
+    impl<T: 'static> Track for MessageLog<T> {
+        #[track_caller]
+        fn track(&self) {
+            self.entries.track()
+        }
+    }
+
+    impl<T: 'static> ReadUntracked for MessageLog<T> {
+        type Value = <ArcRwSignal<Vec<T>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.entries.try_read_untracked()
+        }
+    }
+}
+
+/// Extracts the entries of a patch made of `add /-` operations.
+fn appended_entries<T: DeserializeOwned>(
+    patch: &crate::messages::ServerSignalUpdate,
+) -> Result<Vec<T>, crate::error::Error> {
+    let mut appended = Value::Array(Vec::new());
+    patch.apply(&mut appended)?;
+    Ok(serde_json::from_value(appended)?)
+}
+
+fn append<T>(log: &mut Vec<T>, entries: Vec<T>, capacity: Option<usize>) {
+    log.extend(entries);
+    if let Some(capacity) = capacity {
+        let excess = log.len().saturating_sub(capacity);
+        log.drain(..excess);
+    }
+}
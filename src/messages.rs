@@ -1,12 +1,40 @@
 use std::borrow::Cow;
 
-use json_patch::Patch;
+use json_patch::{Patch, PatchOperation, ReplaceOperation};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::latency::LatencyHistogram;
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Messages {
     ServerSignal(ServerSignalMessage),
+    Heartbeat(HeartbeatMessage),
+    ClientInput(ClientInputMessage),
+    /// Server to client, a message was dropped because it exceeded a rate limit.
+    RateLimited(RateLimitReason),
+    /// Client to server, sent first on every (re)connect.
+    Hello(ClientCapabilities),
+    /// Server to client, another message as deflated and base64 encoded JSON.
+    ///
+    /// Only sent if compression is enabled on the server, requires the `compression` feature on
+    /// the client.
+    Compressed(String),
+    /// Either direction, another message encrypted with the configured
+    /// [`Cipher`](crate::cipher::Cipher).
+    Encrypted(String),
+    /// Server to client, a signal clients may establish without knowing its name up front, see
+    /// [`ServerSignals::announce`](crate::server_signals::ServerSignals::announce).
+    SignalAnnounced(SignalAnnouncement),
+    /// Client to server, a refreshed auth token replacing the one of the `Hello`, see
+    /// [`auth`](crate::auth).
+    Authenticate(String),
+    /// Server to client, the auth token was rejected for this reason and the connection is
+    /// closed.
+    AuthRejected(String),
+    /// Either direction, several messages sent in one frame, see
+    /// [`BatchConfig`](crate::config::BatchConfig). Never nested.
+    Batch(Vec<Messages>),
     // Hier können weitere Nachrichtentypen hinzugefügt werden
     // ChatMessage(ChatMessage),
     // StateSync(StateSyncMessage),
@@ -16,14 +44,250 @@ pub enum Messages {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ServerSignalMessage {
     Establish(String),
+    /// Client to server, like `Establish` but with metadata for the signal kind, e.g. a filter.
+    EstablishWithPayload((String, Value)),
     EstablishResponse((String, Value)),
     Update(ServerSignalUpdate),
+    /// Client to server, stop sending updates for this signal until it is established again.
+    Unsubscribe(String),
+    /// Server to client, updates of this signal are paused until the next `EstablishResponse`,
+    /// see [`ServerSignals::disable`](crate::server_signals::ServerSignals).
+    Paused(String),
+    /// Server to client, the client may not establish this signal, see
+    /// [`ServerSignal::new_with_acl`](crate::ServerSignal).
+    Denied(String),
+    /// The value type of a signal, see [`Error::SchemaMismatch`](crate::error::Error). The server
+    /// sends it before the `EstablishResponse`, the client answers with its own if they differ.
+    Schema((String, String)),
+    /// Server to client, sent after the `EstablishResponse`: the value includes every update up
+    /// to this version.
+    Version(SignalVersion),
+    /// Client to server, like `EstablishWithPayload`, but the client already applied every update
+    /// up to this version. The server only sends the missing updates if it still has them, and
+    /// the complete value otherwise.
+    Resume((SignalVersion, Value)),
+}
+
+/// Identifies the updates a client applied, see [`ServerSignalMessage::Resume`].
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SignalVersion {
+    pub name: String,
+    /// Identifies the numbering of the updates, which starts over when the signal is created
+    /// again, e.g. when the server restarted.
+    pub epoch: u64,
+    /// The [`ServerSignalUpdate::seq`] of the last update included.
+    pub seq: u64,
+}
+
+/// Messages of a [`ClientInputSignal`](crate::ClientInputSignal).
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum ClientInputMessage {
+    /// Client to server, sent on every (re)connect with the complete current value of the client.
+    Establish((String, Value)),
+    /// Client to server, a patch tagged with a per-signal sequence number.
+    Update((u64, ServerSignalUpdate)),
+    /// Server to client, the update with this sequence number was applied.
+    Ack((String, u64)),
+    /// Server to client, the update was not applied and the client has to roll back.
+    Reject(InputRejection),
+}
+
+/// Sent when the server refuses an update of a [`ClientInputSignal`](crate::ClientInputSignal),
+/// or the value it was established with.
+///
+/// Until the client establishes the signal again, all further updates of it are ignored.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct InputRejection {
+    pub name: String,
+    /// The number of the refused update, `0` for an established value.
+    pub seq: u64,
+    pub reason: String,
+    /// The last value of this client the server accepted, or the initial value of the signal
+    /// if there is none.
+    pub value: Value,
+}
+
+/// The version of the wire protocol implemented by this crate.
+///
+/// Bumped whenever a message is added which older clients can't handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Advertised in [`ClientCapabilities::features`] by clients which apply JSON patches.
+///
+/// Clients which send a `Hello` without it receive the complete value with every update instead,
+/// e.g. minimal clients written in other languages. Clients which don't send a `Hello` at all
+/// predate the negotiation and receive patches, unless the server accepts
+/// [`protocol`](crate::protocol) interop clients.
+pub const FEATURE_PATCHES: &str = "patches";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which understand
+/// [`ServerSignalMessage::Paused`]. Other clients just stop receiving updates of disabled signals.
+pub const FEATURE_PAUSE: &str = "pause";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which understand
+/// [`ServerSignalMessage::Denied`]. Other clients just never receive a value of denied signals.
+pub const FEATURE_DENIED: &str = "denied";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which understand
+/// [`Messages::SignalAnnounced`]. Other clients are not told about announced signals.
+pub const FEATURE_ANNOUNCE: &str = "announce";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which understand
+/// [`ServerSignalMessage::Schema`]. Signals of other clients are not checked for mismatching types.
+pub const FEATURE_SCHEMA: &str = "schema";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which apply merge patches, see
+/// [`PatchStrategy::MergePatch`]. Other clients receive the complete value instead.
+pub const FEATURE_MERGE_PATCH: &str = "merge_patch";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which understand
+/// [`ServerSignalMessage::Version`]. Other clients receive the complete value after every
+/// reconnect.
+pub const FEATURE_RESUME: &str = "resume";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which measure the latency of
+/// updates, see [`latency`](crate::latency). They receive [`ServerSignalUpdate::sent_at`] and
+/// their pings are answered with [`HeartbeatMessage::Time`]. Only advertised if enabled.
+pub const FEATURE_TIMESTAMPS: &str = "timestamps";
+
+/// Advertised in [`ClientCapabilities::features`] by clients which unpack [`Messages::Batch`].
+/// Other clients receive every message in its own frame.
+pub const FEATURE_BATCH: &str = "batch";
+
+/// What a client supports, advertised in [`Messages::Hello`].
+///
+/// The server only uses optional wire features the client advertised. Clients which don't send a
+/// `Hello` are treated like [`ClientCapabilities::default`], i.e. as supporting none of them.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ClientCapabilities {
+    pub protocol_version: u32,
+    /// The client can unpack [`Messages::Compressed`].
+    pub compression: bool,
+    /// The largest message the client wants to receive, if limited.
+    pub max_message_bytes: Option<usize>,
+    /// Names of further optional features, for extensions.
+    pub features: Vec<String>,
+    /// The durable identity of the client, which stays the same across reconnects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
+    /// Authenticates the connection, see [`auth`](crate::auth). Removed by the server once it
+    /// was checked, so it isn't part of `Connection::capabilities()`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+}
+
+impl ClientCapabilities {
+    /// The capabilities of this build of the client.
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            compression: cfg!(feature = "compression"),
+            max_message_bytes: None,
+            client_id: None,
+            auth_token: None,
+            features: vec![
+                FEATURE_PATCHES.to_string(),
+                FEATURE_PAUSE.to_string(),
+                FEATURE_DENIED.to_string(),
+                FEATURE_ANNOUNCE.to_string(),
+                FEATURE_SCHEMA.to_string(),
+                FEATURE_MERGE_PATCH.to_string(),
+                FEATURE_RESUME.to_string(),
+                FEATURE_BATCH.to_string(),
+            ],
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+}
+
+/// Identifies a client across reconnects, unlike the connection id on the server which is new
+/// for every connection.
+///
+/// Generated by the client on its first connection and kept in `localStorage`, or assigned with
+/// [`WsConfig::client_id`](crate::config::WsConfig).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct ClientId(pub String);
+
+impl std::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A signal announced by the server, e.g. for a dashboard which shows every chart the server
+/// creates at runtime.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub struct SignalAnnouncement {
+    pub name: String,
+    /// What kind of value the signal holds, as chosen by the app, e.g. `"chart"`.
+    pub type_hint: String,
+}
+
+/// Why a message of a client was dropped.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum RateLimitReason {
+    TooManyMessages,
+    MessageTooLarge,
+    PatchTooLarge,
+}
+
+/// Application level keepalive, used by the client since browsers can't send WebSocket pings.
+///
+/// The number is echoed back unchanged in the matching `Pong`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum HeartbeatMessage {
+    Ping(u64),
+    Pong(u64),
+    /// Server to client instead of `Pong` for clients advertising [`FEATURE_TIMESTAMPS`]: the
+    /// number of the ping and the time of the server in milliseconds since the Unix epoch.
+    Time((u64, u64)),
+    /// Client to server, the latencies the client measured since its last report.
+    Latency(LatencyHistogram),
+}
+
+/// How the updates of a signal are encoded on the wire.
+///
+/// JSON patches are small for most changes, but `json_patch::diff` produces one operation per
+/// moved element for reordered vectors. Signals whose values are mostly objects with changing
+/// members can use merge patches instead, and signals which are rewritten entirely on every
+/// write can skip diffing altogether.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatchStrategy {
+    /// RFC 6902 JSON patches, replacing the whole value if the patch would be larger.
+    #[default]
+    JsonPatch,
+    /// RFC 7386 merge patches. Replaces the whole value if the new value contains `null` members,
+    /// which a merge patch can't express.
+    MergePatch,
+    /// Every update carries the complete new value.
+    Replace,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ServerSignalUpdate {
     pub(crate) name: Cow<'static, str>,
     pub(crate) patch: Patch,
+    /// An RFC 7386 merge patch applied instead of `patch`, see [`PatchStrategy::MergePatch`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) merge: Option<Value>,
+    /// Position among the updates of the signal broadcast by the server, starting at 1. `None`
+    /// for updates sent by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) seq: Option<u64>,
+    /// When the server broadcast the update, in milliseconds since the Unix epoch. Only sent to
+    /// clients advertising [`FEATURE_TIMESTAMPS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sent_at: Option<u64>,
+    /// The connections the update is relayed to, all of them if `None`, see
+    /// [`ServerSignal::update_where`](crate::ServerSignal). Never sent over the wire.
+    #[cfg(feature = "server")]
+    #[serde(skip)]
+    pub(crate) audience: Option<crate::connection::Audience>,
 }
 
 impl ServerSignalUpdate {
@@ -38,19 +302,280 @@ impl ServerSignalUpdate {
     {
         let left = serde_json::to_value(old)?;
         let right = serde_json::to_value(new)?;
-        let patch = json_patch::diff(&left, &right);
-        Ok(ServerSignalUpdate {
+        Ok(Self::new_from_json(name, &left, &right))
+    }
+
+    /// Creates an update replacing the whole value with `value`, as a single `replace` operation
+    /// on the root.
+    pub fn replace(name: impl Into<Cow<'static, str>>, value: Value) -> Self {
+        ServerSignalUpdate {
             name: name.into(),
-            patch,
+            patch: Patch(vec![PatchOperation::Replace(ReplaceOperation {
+                // The default pointer is the root of the document
+                path: Default::default(),
+                value,
+            })]),
+            merge: None,
+            seq: None,
+            sent_at: None,
+            #[cfg(feature = "server")]
+            audience: None,
+        }
+    }
+
+    /// Creates an update from two json values, encoded with `strategy`.
+    pub fn new_with_strategy(
+        name: impl Into<Cow<'static, str>>,
+        old: &Value,
+        new: &Value,
+        strategy: PatchStrategy,
+    ) -> Self {
+        match strategy {
+            PatchStrategy::JsonPatch => Self::new_from_json(name, old, new),
+            PatchStrategy::MergePatch => match merge_diff(old, new) {
+                Some(merge) => ServerSignalUpdate {
+                    name: name.into(),
+                    patch: Patch(Vec::new()),
+                    merge: Some(merge),
+                    seq: None,
+                    sent_at: None,
+                    #[cfg(feature = "server")]
+                    audience: None,
+                },
+                None => Self::replace(name, new.clone()),
+            },
+            PatchStrategy::Replace => Self::replace(name, new.clone()),
+        }
+    }
+
+    /// An update with the number of this one which changes nothing, relayed to the connections
+    /// outside of its audience so their numbering has no gap.
+    #[cfg(feature = "server")]
+    pub(crate) fn skipped(&self) -> Self {
+        ServerSignalUpdate {
+            name: self.name.clone(),
+            patch: Patch(Vec::new()),
+            merge: None,
+            seq: self.seq,
+            sent_at: self.sent_at,
+            audience: None,
+        }
+    }
+
+    /// Whether the update is a merge patch, see [`PatchStrategy::MergePatch`].
+    pub fn is_merge(&self) -> bool {
+        self.merge.is_some()
+    }
+
+    /// Whether the update replaces the whole value, see [`ServerSignalUpdate::replace`].
+    pub fn is_replace(&self) -> bool {
+        matches!(
+            self.patch.0.as_slice(),
+            [PatchOperation::Replace(replace)] if replace.path.is_root()
+        )
+    }
+
+    /// The name of the signal this update is for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn patch(&self) -> &Patch {
+        &self.patch
+    }
+
+    /// Position among the updates of the signal broadcast by the server, starting at 1. `None`
+    /// for updates sent by clients.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// When the server broadcast the update, in milliseconds since the Unix epoch, see
+    /// [`latency`](crate::latency).
+    pub fn sent_at(&self) -> Option<u64> {
+        self.sent_at
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    /// Stamps the update with the current time, see [`ServerSignalUpdate::sent_at`].
+    #[cfg(feature = "server")]
+    pub(crate) fn stamped(mut self) -> Self {
+        self.sent_at = Some(crate::latency::now_millis());
+        self
+    }
+
+    /// Applies the patch to `value`, leaving it unchanged if an operation fails.
+    ///
+    /// Fails with [`Error::PatchFailed`](crate::error::Error) describing the failed operation.
+    pub fn apply(&self, value: &mut Value) -> Result<(), crate::error::Error> {
+        if let Some(merge) = &self.merge {
+            json_patch::merge(value, merge);
+            return Ok(());
+        }
+        json_patch::patch(value, &self.patch).map_err(|err| {
+            crate::error::Error::PatchFailed(Box::new(crate::error::PatchFailure::new(
+                &self.name,
+                value,
+                &self.patch,
+                err,
+            )))
         })
     }
 
     /// Creates a new [`ServerSignalUpdate`] from two json values.
+    ///
+    /// If the patch would be larger than `new` itself, e.g. after most of the value was
+    /// rewritten, the update [replaces](ServerSignalUpdate::replace) the whole value instead.
     pub fn new_from_json(name: impl Into<Cow<'static, str>>, old: &Value, new: &Value) -> Self {
         let patch = json_patch::diff(old, new);
+        // A single operation is never much larger than the value it carries
+        if patch.0.len() > 1 && json_len(&patch) > json_len(new) {
+            return Self::replace(name, new.clone());
+        }
         ServerSignalUpdate {
             name: name.into(),
             patch,
+            merge: None,
+            seq: None,
+            sent_at: None,
+            #[cfg(feature = "server")]
+            audience: None,
+        }
+    }
+
+    /// The size of the patch serialized as JSON.
+    #[cfg(feature = "server")]
+    pub(crate) fn patch_len(&self) -> usize {
+        match &self.merge {
+            Some(merge) => json_len(merge),
+            None => json_len(&self.patch),
+        }
+    }
+
+    /// The number of operations of the patch, or the number of values a merge patch sets or
+    /// removes, each of which a JSON patch would need an operation for.
+    #[cfg(feature = "server")]
+    pub(crate) fn operations(&self) -> usize {
+        match &self.merge {
+            Some(merge) => merged_values(merge),
+            None => self.patch.0.len(),
+        }
+    }
+}
+
+/// The number of values `merge` sets or removes, nested objects are merged member by member.
+#[cfg(feature = "server")]
+fn merged_values(merge: &Value) -> usize {
+    match merge {
+        Value::Object(members) if !members.is_empty() => members.values().map(merged_values).sum(),
+        _ => 1,
+    }
+}
+
+/// The RFC 7386 merge patch turning `old` into `new`, `None` if `new` has `null` members, which
+/// a merge patch would remove instead.
+fn merge_diff(old: &Value, new: &Value) -> Option<Value> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        // Anything but two objects is merged by replacing it
+        return (!has_null_members(new)).then(|| new.clone());
+    };
+    let mut merge = serde_json::Map::new();
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        merge.insert(key.clone(), Value::Null);
+    }
+    for (key, value) in new {
+        match old.get(key) {
+            Some(previous) if previous == value => {}
+            Some(_) if value.is_null() => return None,
+            Some(previous) => {
+                merge.insert(key.clone(), merge_diff(previous, value)?);
+            }
+            None if has_null_members(value) || value.is_null() => return None,
+            None => {
+                merge.insert(key.clone(), value.clone());
+            }
         }
     }
+    Some(Value::Object(merge))
+}
+
+fn has_null_members(value: &Value) -> bool {
+    match value {
+        Value::Object(members) => members
+            .values()
+            .any(|member| member.is_null() || has_null_members(member)),
+        _ => false,
+    }
+}
+
+fn json_len(value: &impl Serialize) -> usize {
+    serde_json::to_string(value).map_or(0, |json| json.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Checks the merge patch from `old` to `new` against `json_patch::merge`.
+    fn merged(old: Value, new: &Value) -> Option<Value> {
+        let merge = merge_diff(&old, new)?;
+        let mut value = old;
+        json_patch::merge(&mut value, &merge);
+        assert_eq!(&value, new);
+        Some(merge)
+    }
+
+    #[test]
+    fn merges_only_the_changed_members() {
+        let old = json!({"name": "Ada", "address": {"city": "London", "zip": "N1"}});
+        let new = json!({"name": "Ada", "address": {"city": "Paris", "zip": "N1"}});
+        assert_eq!(
+            merged(old, &new),
+            Some(json!({"address": {"city": "Paris"}}))
+        );
+    }
+
+    #[test]
+    fn removes_missing_members_with_null() {
+        let old = json!({"name": "Ada", "nickname": "A"});
+        let new = json!({"name": "Ada"});
+        assert_eq!(merged(old, &new), Some(json!({"nickname": null})));
+    }
+
+    #[test]
+    fn replaces_arrays_and_other_types() {
+        assert_eq!(
+            merged(json!({"tags": [1, 2]}), &json!({"tags": [1, 2, 3]})),
+            Some(json!({"tags": [1, 2, 3]}))
+        );
+        assert_eq!(merged(json!({"a": 1}), &json!(7)), Some(json!(7)));
+        assert_eq!(merged(json!(7), &json!({"a": 1})), Some(json!({"a": 1})));
+    }
+
+    #[test]
+    fn rejects_null_members() {
+        assert_eq!(merge_diff(&json!({"a": 1}), &json!({"a": null})), None);
+        assert_eq!(merge_diff(&json!({}), &json!({"a": {"b": null}})), None);
+        assert_eq!(merge_diff(&json!(1), &json!({"a": null})), None);
+    }
+
+    #[test]
+    fn replaces_values_with_null_members() {
+        let update = ServerSignalUpdate::new_with_strategy(
+            "user",
+            &json!({"a": 1}),
+            &json!({"a": null}),
+            PatchStrategy::MergePatch,
+        );
+        assert!(update.merge.is_none());
+        let mut value = json!({"a": 1});
+        update.apply(&mut value).unwrap();
+        assert_eq!(value, json!({"a": null}));
+    }
 }
@@ -0,0 +1,97 @@
+use async_nats::Client;
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::Backend;
+use crate::error::Error;
+use crate::messages::ServerSignalUpdate;
+
+/// A [`Backend`] publishing every signal on its own NATS subject, `<prefix>.<signal name>`.
+///
+/// Characters which are not allowed in a subject token are replaced with `_`, the signal name
+/// itself is sent in the payload.
+///
+/// ```rust,no_run
+/// # use leptos_ws::{nats::NatsBackend, server_signals::ServerSignals};
+/// # async fn connect() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = async_nats::connect("nats://localhost:4222").await?;
+/// let server_signals = ServerSignals::with_backend(NatsBackend::new(client)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct NatsBackend {
+    client: Client,
+    prefix: String,
+    /// Identifies this node, updates published by it are skipped when received.
+    node: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    node: String,
+    update: ServerSignalUpdate,
+}
+
+impl NatsBackend {
+    pub fn new(client: Client) -> Self {
+        Self::with_prefix(client, "leptos_ws")
+    }
+
+    pub fn with_prefix(client: Client, prefix: impl Into<String>) -> Self {
+        Self {
+            node: client.new_inbox(),
+            client,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn subject(&self, name: &str) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}.{}", self.prefix, name)
+    }
+}
+
+#[async_trait]
+impl Backend for NatsBackend {
+    async fn publish(&self, update: &ServerSignalUpdate) -> Result<(), Error> {
+        let payload = serde_json::to_vec(&Envelope {
+            node: self.node.clone(),
+            update: update.clone(),
+        })?;
+        self.client
+            .publish(self.subject(&update.name), payload.into())
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, ServerSignalUpdate>, Error> {
+        let subscriber = self
+            .client
+            .subscribe(format!("{}.>", self.prefix))
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))?;
+        let node = self.node.clone();
+        Ok(subscriber
+            .filter_map(move |message| {
+                let envelope = serde_json::from_slice::<Envelope>(&message.payload).ok();
+                let node = node.clone();
+                async move {
+                    envelope
+                        .filter(|envelope| envelope.node != node)
+                        .map(|envelope| envelope.update)
+                }
+            })
+            .boxed())
+    }
+}
@@ -0,0 +1,110 @@
+//! Keeps the values of [`ServerSignal`](crate::ServerSignal)s across server restarts.
+//!
+//! Install a store with [`ServerSignals::with_store`](crate::server_signals::ServerSignals::with_store)
+//! before the first client connects. All stored values are loaded once at that point, and every
+//! signal created afterwards starts with its stored value instead of the value passed to `new`.
+//! Local writes are saved after the configured debounce, so a burst of updates results in a
+//! single write of the latest value.
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::error::Error;
+
+#[async_trait]
+pub trait SignalStore: Send + Sync {
+    /// Returns every stored signal, called once when the store is installed.
+    async fn load_all(&self) -> Result<Vec<(String, Value)>, Error>;
+    async fn save(&self, name: &str, value: &Value) -> Result<(), Error>;
+}
+
+/// A [`SignalStore`] keeping every signal as a JSON document in a sled tree.
+#[cfg(feature = "sled")]
+#[derive(Clone, Debug)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+#[cfg(feature = "sled")]
+#[async_trait]
+impl SignalStore for SledStore {
+    async fn load_all(&self) -> Result<Vec<(String, Value)>, Error> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (name, value) = entry.map_err(|err| Error::StoreFailed(err.to_string()))?;
+                Ok((
+                    String::from_utf8_lossy(&name).into_owned(),
+                    serde_json::from_slice(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn save(&self, name: &str, value: &Value) -> Result<(), Error> {
+        self.tree
+            .insert(name, serde_json::to_vec(value)?)
+            .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        self.tree
+            .flush_async()
+            .await
+            .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        Ok(())
+    }
+}
+
+/// A [`SignalStore`] keeping every signal as a row of the `leptos_ws_signals` table.
+#[cfg(feature = "sqlite")]
+#[derive(Clone, Debug)]
+pub struct SqliteStore {
+    pool: sqlx::SqlitePool,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Creates the `leptos_ws_signals` table if it doesn't exist yet.
+    pub async fn new(pool: sqlx::SqlitePool) -> Result<Self, Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS leptos_ws_signals (name TEXT PRIMARY KEY, value TEXT NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        Ok(Self { pool })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl SignalStore for SqliteStore {
+    async fn load_all(&self) -> Result<Vec<(String, Value)>, Error> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT name, value FROM leptos_ws_signals")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        rows.into_iter()
+            .map(|(name, value)| Ok((name, serde_json::from_str(&value)?)))
+            .collect()
+    }
+
+    async fn save(&self, name: &str, value: &Value) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO leptos_ws_signals (name, value) VALUES (?, ?) \
+             ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(name)
+        .bind(serde_json::to_string(value)?)
+        .execute(&self.pool)
+        .await
+        .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+
+use crate::backend::Backend;
+use crate::error::Error;
+use crate::messages::ServerSignalUpdate;
+
+/// Postgres rejects notification payloads of 8000 bytes or more. Leaves room for the header.
+const MAX_CHUNK: usize = 7900;
+
+/// How long the chunks of an update are kept while waiting for the rest, e.g. when the node
+/// sending it lost its connection in between.
+const PARTIAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A [`Backend`] for deployments which already run Postgres, sending updates with
+/// `NOTIFY` on a single channel, `leptos_ws` by default.
+///
+/// Updates larger than a notification are split into chunks, which are sent in one transaction
+/// and put back together by the receiving nodes. Updates sent while the listening connection
+/// is reconnecting are lost, the clients of that node receive the complete value once the
+/// signal changes there again or they reconnect.
+///
+/// ```rust,no_run
+/// # use leptos_ws::{postgres::PostgresBackend, server_signals::ServerSignals};
+/// # use sqlx::PgPool;
+/// # async fn connect() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = PgPool::connect("postgres://localhost/app").await?;
+/// let server_signals = ServerSignals::with_backend(PostgresBackend::new(pool)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PostgresBackend {
+    pool: PgPool,
+    channel: String,
+    /// Identifies this node, updates published by it are skipped when received.
+    node: String,
+    next_id: Arc<AtomicU64>,
+}
+
+impl PostgresBackend {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_channel(pool, "leptos_ws")
+    }
+
+    pub fn with_channel(pool: PgPool, channel: impl Into<String>) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        Self {
+            pool,
+            channel: channel.into(),
+            node: format!("{}-{started}", std::process::id()),
+            next_id: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for PostgresBackend {
+    async fn publish(&self, update: &ServerSignalUpdate) -> Result<(), Error> {
+        let payload = serde_json::to_string(update)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // Notifications are delivered on commit, so the chunks arrive together or not at all
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))?;
+        for notification in notifications(&self.node, id, &payload) {
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(&self.channel)
+                .bind(notification)
+                .execute(&mut *transaction)
+                .await
+                .map_err(|err| Error::BackendFailed(err.to_string()))?;
+        }
+        transaction
+            .commit()
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, ServerSignalUpdate>, Error> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))?;
+        listener
+            .listen(&self.channel)
+            .await
+            .map_err(|err| Error::BackendFailed(err.to_string()))?;
+        let mut chunks = Chunks::new(self.node.clone());
+        Ok(listener
+            .into_stream()
+            .filter_map(move |notification| {
+                let update = notification
+                    .ok()
+                    .and_then(|notification| chunks.add(notification.payload()));
+                async move { update }
+            })
+            .boxed())
+    }
+}
+
+/// The notifications carrying `payload`, each a chunk of it behind a header line, see
+/// [`Chunks`].
+fn notifications(node: &str, id: u64, payload: &str) -> Vec<String> {
+    let chunks = split_payload(payload);
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("{node} {id} {index} {}\n{chunk}", chunks.len()))
+        .collect()
+}
+
+/// Splits `payload` into chunks which fit into a notification, on character boundaries.
+fn split_payload(payload: &str) -> Vec<&str> {
+    let mut chunks = Vec::with_capacity(payload.len() / MAX_CHUNK + 1);
+    let mut rest = payload;
+    while rest.len() > MAX_CHUNK {
+        let mut end = MAX_CHUNK;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks.push(rest);
+    chunks
+}
+
+/// Puts the chunks received from other nodes back together. Every chunk starts with a header
+/// line, `<node> <id> <index> <count>`.
+struct Chunks {
+    node: String,
+    /// The chunks received so far of each update, by node and id.
+    partial: HashMap<(String, u64), Partial>,
+}
+
+struct Partial {
+    chunks: Vec<Option<String>>,
+    missing: usize,
+    started: Instant,
+}
+
+impl Chunks {
+    fn new(node: String) -> Self {
+        Self {
+            node,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Returns the update once its last chunk was added. Updates of this node and malformed
+    /// notifications are skipped.
+    fn add(&mut self, notification: &str) -> Option<ServerSignalUpdate> {
+        let (header, chunk) = notification.split_once('\n')?;
+        let mut header = header.split(' ');
+        let node = header.next()?;
+        if node == self.node {
+            return None;
+        }
+        let id = header.next()?.parse::<u64>().ok()?;
+        let index = header.next()?.parse::<usize>().ok()?;
+        let count = header.next()?.parse::<usize>().ok()?;
+        if index >= count {
+            return None;
+        }
+        if count == 1 {
+            return decode(chunk);
+        }
+        self.partial
+            .retain(|_, partial| partial.started.elapsed() < PARTIAL_TIMEOUT);
+        let key = (node.to_string(), id);
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| Partial {
+            chunks: vec![None; count],
+            missing: count,
+            started: Instant::now(),
+        });
+        let slot = partial.chunks.get_mut(index)?;
+        if slot.is_none() {
+            *slot = Some(chunk.to_string());
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        let partial = self.partial.remove(&key)?;
+        decode(&partial.chunks.into_iter().flatten().collect::<String>())
+    }
+}
+
+fn decode(payload: &str) -> Option<ServerSignalUpdate> {
+    serde_json::from_str(payload).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn update(size: usize) -> ServerSignalUpdate {
+        ServerSignalUpdate::replace("doc", json!("x".repeat(size)))
+    }
+
+    fn payload(update: &ServerSignalUpdate) -> String {
+        serde_json::to_string(update).unwrap()
+    }
+
+    #[test]
+    fn payloads_are_split_on_character_boundaries() {
+        let payload = "€".repeat(MAX_CHUNK);
+        let chunks = split_payload(&payload);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= MAX_CHUNK));
+        assert_eq!(chunks.concat(), payload);
+
+        assert_eq!(split_payload("{}"), ["{}"]);
+    }
+
+    #[test]
+    fn chunks_are_put_back_together_in_any_order() {
+        let update = update(MAX_CHUNK * 2);
+        let mut notifications = notifications("other", 7, &payload(&update));
+        assert_eq!(notifications.len(), 3);
+        notifications.reverse();
+        // Repeated chunks don't count twice
+        notifications.insert(1, notifications[0].clone());
+
+        let mut chunks = Chunks::new("this".to_string());
+        let last = notifications.pop().unwrap();
+        for notification in &notifications {
+            assert!(chunks.add(notification).is_none());
+        }
+        let received = chunks.add(&last).unwrap();
+        assert_eq!(payload(&received), payload(&update));
+        assert!(chunks.partial.is_empty());
+    }
+
+    #[test]
+    fn own_and_malformed_notifications_are_skipped() {
+        let mut chunks = Chunks::new("this".to_string());
+        let small = payload(&update(10));
+        assert!(chunks.add(&notifications("this", 0, &small)[0]).is_none());
+        assert!(chunks.add(&format!("other 0 1 1\n{small}")).is_none());
+        assert!(chunks.add(&format!("other x 0 1\n{small}")).is_none());
+        assert!(chunks.add(&small).is_none());
+        assert!(chunks.add(&notifications("other", 0, &small)[0]).is_some());
+    }
+
+    #[test]
+    fn incomplete_updates_expire() {
+        let mut chunks = Chunks::new("this".to_string());
+        let large = notifications("other", 0, &payload(&update(MAX_CHUNK)));
+        chunks.add(&large[0]);
+        let partial = chunks.partial.values_mut().next().unwrap();
+        partial.started -= PARTIAL_TIMEOUT;
+
+        chunks.add(&notifications("other", 1, &payload(&update(MAX_CHUNK)))[0]);
+        assert_eq!(chunks.partial.len(), 1);
+        assert!(chunks.add(&large[1]).is_none());
+    }
+}
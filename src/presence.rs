@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::panic::Location;
+
+use leptos::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+use crate::{ClientInputSignal, ServerSignal};
+
+#[cfg(feature = "server")]
+pub use server::PresenceMap;
+
+#[cfg(not(feature = "server"))]
+pub use client::PresenceMap;
+
+/// The entries of a [`PresenceMap`], keyed by the [`ClientId`](crate::messages::ClientId) of
+/// their client, or by connection id for clients which don't send one.
+pub type PresenceEntries<T> = BTreeMap<String, T>;
+
+#[cfg(feature = "server")]
+mod server {
+    use super::*;
+    use crate::connection::{Connection, ConnectionId};
+    use crate::server_signals::{ConnectionMap, ServerSignals};
+
+    /// A map with one entry per connected client, e.g. shared cursors, typing indicators or who
+    /// is viewing a page.
+    ///
+    /// Every client writes only its own entry, everyone reads the whole map keyed by client id.
+    /// Entries are removed when their client disconnects. The map is kept up to date by a task
+    /// of the registry like [`ServerSignal::derive`], also after the request which created it
+    /// was rendered.
+    #[derive(Clone)]
+    pub struct PresenceMap<T>
+    where
+        T: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<T>>,
+        entries: ServerSignal<PresenceEntries<T>>,
+    }
+
+    impl<T> PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        pub fn new(name: String) -> Result<Self, Error> {
+            let connections = ServerSignals::current()
+                .ok_or(Error::MissingServerSignals)?
+                .connection_map();
+            let input = ClientInputSignal::new(name.clone(), None)?;
+            let values = input.values();
+            let entries = ServerSignal::derive(name, move || {
+                values.with(|values| {
+                    values
+                        .iter()
+                        .filter_map(|(connection, entry)| {
+                            Some((key(&connections, *connection), entry.clone()?))
+                        })
+                        .collect()
+                })
+            })?;
+            Ok(Self { input, entries })
+        }
+
+        /// The entry of a single client, if it is connected and has one.
+        pub fn entry_for(&self, connection: ConnectionId) -> Option<T> {
+            self.input.value_for(connection).flatten()
+        }
+
+        /// Does nothing, only clients own an entry.
+        pub fn set(&self, _entry: T) {}
+
+        /// Does nothing, only clients own an entry.
+        pub fn clear(&self) {}
+
+        /// Always `None`, only clients own an entry.
+        pub fn mine(&self) -> Option<T> {
+            None
+        }
+
+        /// Always empty, only clients receive the entries.
+        pub fn to_stream(
+            &self,
+        ) -> impl futures::Stream<Item = PresenceEntries<T>> + Send + 'static {
+            self.entries.to_stream()
+        }
+    }
+
+    /// The key of the entry of a connection, its [`ClientId`](crate::messages::ClientId) so the
+    /// entry keeps its key when the client reconnects, or the connection id for clients which
+    /// don't send one.
+    fn key(connections: &ConnectionMap, connection: ConnectionId) -> String {
+        connections
+            .read()
+            .unwrap()
+            .get(&connection)
+            .and_then(Connection::client_id)
+            .map_or_else(|| connection.to_string(), |id| id.0)
+    }
+
+    impl<T> DefinedAt for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.entries.defined_at()
+        }
+    }
+
+    impl<T> Track for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.entries.track()
+        }
+    }
+
+    impl<T> ReadUntracked for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<PresenceEntries<T>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.entries.try_read_untracked()
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+mod client {
+    use super::*;
+
+    /// A map with one entry per connected client, e.g. shared cursors, typing indicators or who
+    /// is viewing a page.
+    ///
+    /// This client writes only its own entry with [`PresenceMap::set`], and reads the entries of
+    /// all clients keyed by client id, including its own. Entries are removed when their client
+    /// disconnects.
+    #[derive(Clone)]
+    pub struct PresenceMap<T>
+    where
+        T: Clone + Send + Sync + DeserializeOwned,
+    {
+        input: ClientInputSignal<Option<T>>,
+        entries: ServerSignal<PresenceEntries<T>>,
+    }
+
+    impl<T> PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        pub fn new(name: String) -> Result<Self, Error> {
+            let input = ClientInputSignal::new(name.clone(), None)?;
+            let entries = ServerSignal::new(name, PresenceEntries::new())?;
+            Ok(Self { input, entries })
+        }
+
+        /// Sets the entry of this client.
+        pub fn set(&self, entry: T) {
+            self.input.set(Some(entry));
+        }
+
+        /// Removes the entry of this client, e.g. when the user stopped typing.
+        pub fn clear(&self) {
+            self.input.set(None);
+        }
+
+        /// The entry of this client as last set, without waiting for the server.
+        pub fn mine(&self) -> Option<T> {
+            self.input.get_untracked()
+        }
+
+        /// The entries of all clients every time they change from now on.
+        pub fn to_stream(
+            &self,
+        ) -> impl futures::Stream<Item = PresenceEntries<T>> + Send + 'static {
+            self.entries.to_stream()
+        }
+    }
+
+    impl<T> DefinedAt for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        fn defined_at(&self) -> Option<&'static Location<'static>> {
+            self.entries.defined_at()
+        }
+    }
+
+    impl<T> Track for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        #[track_caller]
+        fn track(&self) {
+            self.entries.track()
+        }
+    }
+
+    impl<T> ReadUntracked for PresenceMap<T>
+    where
+        T: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+    {
+        type Value = <ServerSignal<PresenceEntries<T>> as ReadUntracked>::Value;
+
+        fn try_read_untracked(&self) -> Option<Self::Value> {
+            self.entries.try_read_untracked()
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! The stable subset of the wire protocol for clients which don't use Leptos, e.g. a plain
+//! browser page or a script.
+//!
+//! Every message is a JSON text frame. Enums are tagged with the variant name as the only key of
+//! an object, tuples are arrays:
+//!
+//! ```text
+//! client: {"ServerSignal":{"Establish":"count"}}
+//! server: {"ServerSignal":{"EstablishResponse":["count",0]}}
+//! server: {"ServerSignal":{"EstablishResponse":["count",1]}}
+//! ```
+//!
+//! Such clients only have to send `Establish` (and `Unsubscribe`) and handle
+//! `EstablishResponse`. If the server enables
+//! [`ConnectionConfig::interop`](crate::config::ConnectionConfig), clients which don't send a
+//! `Hello` receive every update as an `EstablishResponse` with the complete value. Clients which
+//! want patches send a `Hello` with the `patches` feature and receive `Update`s carrying an
+//! [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON patch instead. When that is smaller,
+//! the patch is a single `replace` of the path `""`, i.e. the whole value. Clients which
+//! advertise the `batch` feature may receive several messages at once in a `Batch`, and may send
+//! them the same way. Clients which advertise the `timestamps` feature receive updates with their
+//! `sent_at` time and a `Time` instead of a `Pong`, see [`latency`](crate::latency).
+//!
+//! The messages in [`schema`] and [`typescript`] only change with a new
+//! [`PROTOCOL_VERSION`](crate::messages::PROTOCOL_VERSION), and then only by adding variants.
+
+use serde_json::{json, Value};
+
+/// JSON Schema of the messages an interop client sends (`ClientMessage`) and receives
+/// (`ServerMessage`).
+pub fn schema() -> Value {
+    let tagged = |tag: &str, value: Value| {
+        json!({
+            "type": "object",
+            "properties": { tag: value },
+            "required": [tag],
+            "additionalProperties": false
+        })
+    };
+    let signal = |tag: &str, value: Value| tagged("ServerSignal", tagged(tag, value));
+    let name = json!({ "type": "string" });
+    let named = |value: Value| {
+        json!({
+            "type": "array",
+            "prefixItems": [{ "type": "string" }, value],
+            "items": false,
+            "minItems": 2
+        })
+    };
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": "https://docs.rs/leptos_ws/protocol.json",
+        "title": "leptos_ws interop protocol",
+        "$defs": {
+            "ClientMessage": {
+                "oneOf": [
+                    signal("Establish", name.clone()),
+                    signal("Unsubscribe", name.clone()),
+                    tagged("Hello", json!({ "$ref": "#/$defs/ClientCapabilities" })),
+                    tagged("Authenticate", json!({ "type": "string" })),
+                    tagged("Heartbeat", tagged("Ping", json!({ "type": "integer", "minimum": 0 }))),
+                    tagged("Heartbeat", tagged("Latency", json!({
+                        "type": "object",
+                        "properties": {
+                            "counts": { "type": "array", "items": { "type": "integer", "minimum": 0 } }
+                        },
+                        "required": ["counts"]
+                    }))),
+                    tagged("Batch", json!({
+                        "type": "array",
+                        "items": { "$ref": "#/$defs/ClientMessage" }
+                    })),
+                ]
+            },
+            "ServerMessage": {
+                "oneOf": [
+                    signal("EstablishResponse", named(json!({}))),
+                    signal("Update", json!({ "$ref": "#/$defs/ServerSignalUpdate" })),
+                    signal("Paused", name.clone()),
+                    signal("Denied", name.clone()),
+                    tagged("Heartbeat", tagged("Pong", json!({ "type": "integer", "minimum": 0 }))),
+                    tagged("Heartbeat", tagged("Time", json!({
+                        "type": "array",
+                        "prefixItems": [
+                            { "type": "integer", "minimum": 0 },
+                            { "type": "integer", "minimum": 0 }
+                        ],
+                        "items": false,
+                        "minItems": 2
+                    }))),
+                    tagged("RateLimited", json!({
+                        "enum": ["TooManyMessages", "MessageTooLarge", "PatchTooLarge"]
+                    })),
+                    tagged("AuthRejected", json!({ "type": "string" })),
+                    tagged("Batch", json!({
+                        "type": "array",
+                        "items": { "$ref": "#/$defs/ServerMessage" }
+                    })),
+                ]
+            },
+            "ClientCapabilities": {
+                "type": "object",
+                "properties": {
+                    "protocol_version": { "type": "integer", "minimum": 0 },
+                    "compression": { "type": "boolean" },
+                    "max_message_bytes": { "type": ["integer", "null"], "minimum": 0 },
+                    "features": { "type": "array", "items": { "type": "string" } },
+                    "client_id": { "type": "string" },
+                    "auth_token": { "type": "string" }
+                }
+            },
+            "ServerSignalUpdate": {
+                "type": "object",
+                "properties": {
+                    "name": name,
+                    "patch": {
+                        "type": "array",
+                        "items": { "$ref": "#/$defs/PatchOperation" }
+                    },
+                    "seq": { "type": "integer", "minimum": 1 },
+                    "sent_at": { "type": "integer", "minimum": 0 }
+                },
+                "required": ["name", "patch"]
+            },
+            "PatchOperation": {
+                "type": "object",
+                "properties": {
+                    "op": {
+                        "enum": ["add", "remove", "replace", "move", "copy", "test"]
+                    },
+                    "path": { "type": "string" },
+                    "from": { "type": "string" },
+                    "value": {}
+                },
+                "required": ["op", "path"]
+            }
+        }
+    })
+}
+
+/// TypeScript declarations of the messages in [`schema`].
+pub fn typescript() -> &'static str {
+    TYPESCRIPT
+}
+
+const TYPESCRIPT: &str = r#"// leptos_ws interop protocol
+export type ClientMessage =
+  | { ServerSignal: { Establish: string } }
+  | { ServerSignal: { Unsubscribe: string } }
+  | { Hello: ClientCapabilities }
+  | { Authenticate: string }
+  | { Heartbeat: { Ping: number } }
+  | { Heartbeat: { Latency: { counts: number[] } } }
+  | { Batch: ClientMessage[] };
+
+export type ServerMessage =
+  | { ServerSignal: { EstablishResponse: [string, unknown] } }
+  | { ServerSignal: { Update: ServerSignalUpdate } }
+  | { ServerSignal: { Paused: string } }
+  | { ServerSignal: { Denied: string } }
+  | { Heartbeat: { Pong: number } }
+  | { Heartbeat: { Time: [number, number] } }
+  | { RateLimited: "TooManyMessages" | "MessageTooLarge" | "PatchTooLarge" }
+  | { AuthRejected: string }
+  | { Batch: ServerMessage[] };
+
+export interface ClientCapabilities {
+  protocol_version?: number;
+  compression?: boolean;
+  max_message_bytes?: number | null;
+  features?: string[];
+  client_id?: string;
+  auth_token?: string;
+}
+
+export interface ServerSignalUpdate {
+  name: string;
+  patch: PatchOperation[];
+  seq?: number;
+  sent_at?: number;
+}
+
+export interface PatchOperation {
+  op: "add" | "remove" | "replace" | "move" | "copy" | "test";
+  path: string;
+  from?: string;
+  value?: unknown;
+}
+"#;
@@ -0,0 +1,176 @@
+//! Fans the updates of every signal out to the connections which established it.
+//!
+//! Each signal with at least one subscribed connection has a single task forwarding its updates,
+//! and each connection receives the updates of all its signals on a single channel, so the
+//! number of tasks grows with signals plus connections instead of their product.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+use crate::connection::{ConnectionFeatures, ConnectionId};
+use crate::messages::ServerSignalUpdate;
+use crate::tasks::{TaskKind, TaskRegistry};
+use crate::transport::Frame;
+
+/// What the relay task of a connection receives, in the order it has to be handled.
+pub(crate) enum Routed {
+    /// The connection is establishing the signal, its updates are held back until the value was
+    /// sent.
+    Subscribe(String),
+    /// The frames establishing the signal, encoded for the connection.
+    Established {
+        name: String,
+        features: ConnectionFeatures,
+        frames: Vec<Frame>,
+        /// The number of the last update the frames include, later ones are relayed.
+        included: Option<u64>,
+    },
+    Update(ServerSignalUpdate),
+    /// Updates of the signal were skipped because its route lagged behind, the connection has
+    /// to send the complete value.
+    Lagged(String),
+    Unsubscribe(String),
+}
+
+type Subscribers = Arc<Mutex<HashMap<ConnectionId, UnboundedSender<Routed>>>>;
+
+/// The connections which established a signal, and the task forwarding its updates to them.
+struct Route {
+    subscribers: Subscribers,
+    task: JoinHandle<()>,
+}
+
+/// The routes of all signals of a registry, see the [module docs](self).
+#[derive(Clone, Default)]
+pub(crate) struct Routes {
+    routes: Arc<Mutex<HashMap<String, Route>>>,
+}
+
+impl Routes {
+    /// Adds `connection` to the route of `name`, returns `false` if the signal has none yet.
+    pub fn join(
+        &self,
+        name: &str,
+        connection: ConnectionId,
+        sender: &UnboundedSender<Routed>,
+    ) -> bool {
+        match self.routes.lock().unwrap().get(name) {
+            Some(route) => {
+                route
+                    .subscribers
+                    .lock()
+                    .unwrap()
+                    .insert(connection, sender.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts the route of `name` forwarding from `receiver`, or joins the route another
+    /// connection started meanwhile.
+    pub fn start(
+        &self,
+        name: &str,
+        connection: ConnectionId,
+        sender: &UnboundedSender<Routed>,
+        receiver: Receiver<ServerSignalUpdate>,
+        tasks: &TaskRegistry,
+    ) {
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes.entry(name.to_string()).or_insert_with(|| {
+            let subscribers = Subscribers::default();
+            let task = tasks.spawn(
+                TaskKind::Route,
+                None,
+                forward(
+                    name.to_string(),
+                    receiver,
+                    subscribers.clone(),
+                    self.clone(),
+                ),
+            );
+            Route { subscribers, task }
+        });
+        route
+            .subscribers
+            .lock()
+            .unwrap()
+            .insert(connection, sender.clone());
+    }
+
+    /// Stops routing `name` to `connection`, and stops the route once no connection is left.
+    pub fn leave(&self, name: &str, connection: ConnectionId) {
+        let mut routes = self.routes.lock().unwrap();
+        let Some(route) = routes.get(name) else {
+            return;
+        };
+        let mut subscribers = route.subscribers.lock().unwrap();
+        subscribers.remove(&connection);
+        if subscribers.is_empty() {
+            drop(subscribers);
+            if let Some(route) = routes.remove(name) {
+                route.task.abort();
+            }
+        }
+    }
+
+    /// Stops routing every signal to `connection`, e.g. once it closed.
+    pub fn leave_all(&self, connection: ConnectionId) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|_, route| {
+            let mut subscribers = route.subscribers.lock().unwrap();
+            subscribers.remove(&connection);
+            if subscribers.is_empty() {
+                route.task.abort();
+            }
+            !subscribers.is_empty()
+        });
+    }
+
+    /// The number of connections the updates of `name` are routed to, `None` if it has no route.
+    pub fn subscribers(&self, name: &str) -> Option<usize> {
+        let routes = self.routes.lock().unwrap();
+        let route = routes.get(name)?;
+        let subscribers = route.subscribers.lock().unwrap().len();
+        Some(subscribers)
+    }
+}
+
+async fn forward(
+    name: String,
+    mut receiver: Receiver<ServerSignalUpdate>,
+    subscribers: Subscribers,
+    routes: Routes,
+) {
+    loop {
+        match receiver.recv().await {
+            Ok(update) => subscribers
+                .lock()
+                .unwrap()
+                .retain(|_, sender| sender.send(Routed::Update(update.clone())).is_ok()),
+            Err(RecvError::Lagged(skipped)) => {
+                leptos::logging::warn!(
+                    "Routing of {name} lagged behind, skipped {skipped} updates, resyncing"
+                );
+                subscribers
+                    .lock()
+                    .unwrap()
+                    .retain(|_, sender| sender.send(Routed::Lagged(name.clone())).is_ok());
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    // The signal is gone, unless another one of the same name started its own route meanwhile
+    let mut routes = routes.routes.lock().unwrap();
+    if routes
+        .get(&name)
+        .is_some_and(|route| Arc::ptr_eq(&route.subscribers, &subscribers))
+    {
+        routes.remove(&name);
+    }
+}
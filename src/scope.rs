@@ -0,0 +1,55 @@
+use leptos::prelude::*;
+
+/// Prefixes the names of all signals created in a component and its children, so unrelated
+/// features can use the same short names without colliding.
+///
+/// Scopes nest: the prefix of an inner scope is appended to the one of the enclosing scope. The
+/// component has to create the scope on both the server and the client, so both sides agree on
+/// the names.
+///
+/// ```rust,no_run
+/// # use leptos::prelude::*;
+/// # use leptos_ws::{ScopedSignals, ServerSignal};
+/// #[component]
+/// fn Dashboard() -> impl IntoView {
+///     ScopedSignals::with_prefix("dashboard:");
+///     // Synced as "dashboard:count"
+///     let count = ServerSignal::new("count".to_string(), 0).unwrap();
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopedSignals {
+    prefix: String,
+}
+
+impl ScopedSignals {
+    /// Starts a scope for the current component, signals created after this use its prefix.
+    pub fn with_prefix(prefix: impl Into<String>) -> Self {
+        let prefix = match use_context::<ScopedSignals>() {
+            Some(outer) => outer.name(&prefix.into()),
+            None => prefix.into(),
+        };
+        let scope = Self { prefix };
+        provide_context(scope.clone());
+        scope
+    }
+
+    /// The complete prefix, including the ones of enclosing scopes.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The name a signal called `name` is synced as in this scope.
+    pub fn name(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+}
+
+/// The name a signal called `name` is synced as in the current scope.
+pub(crate) fn scoped_name(name: String) -> String {
+    match use_context::<ScopedSignals>() {
+        Some(scope) => scope.name(&name),
+        None => name,
+    }
+}
@@ -0,0 +1,428 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::panic::Location;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::connection::ConnectionId;
+use crate::error::Error;
+use crate::messages::{ClientInputMessage, InputRejection, ServerSignalUpdate};
+use crate::scope::scoped_name;
+use crate::server_signals::ServerSignals;
+use crate::tasks::{Lifetime, TaskKind, TaskRegistry};
+use crate::ServerSignal;
+use async_trait::async_trait;
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::select;
+use tokio::sync::RwLock;
+use tokio::time::MissedTickBehavior;
+
+/// The server half of a [`ClientInputSignal`](crate::ClientInputSignal).
+///
+/// Every connected client owns one value of the signal which only it can write. The server
+/// reads the most recently written value reactively through `get()`/`with()`, or all values
+/// keyed by connection through [`ServerInputSignal::values`].
+#[derive(Clone, Debug)]
+pub struct ServerInputSignal<T>
+where
+    T: Clone + Send + Sync + for<'de> Deserialize<'de>,
+{
+    name: String,
+    latest: ArcRwSignal<T>,
+    /// What a client establishing the signal with an invalid value is rolled back to, unless it
+    /// had a valid one before.
+    initial: Value,
+    values: ArcRwSignal<HashMap<ConnectionId, T>>,
+    json_values: Arc<RwLock<HashMap<ConnectionId, InputState>>>,
+    on_client_update: Arc<ClientUpdateCallbacks>,
+    validator: Arc<Validator<T>>,
+    tasks: TaskRegistry,
+    lifetime: Lifetime,
+}
+
+type ValidatorFn<T> = Arc<dyn Fn(&T) -> Result<(), String> + Send + Sync>;
+
+struct Validator<T>(std::sync::RwLock<Option<ValidatorFn<T>>>);
+
+impl<T> Default for Validator<T> {
+    fn default() -> Self {
+        Self(std::sync::RwLock::new(None))
+    }
+}
+
+impl<T> Debug for Validator<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+type ClientUpdateCallback = Arc<dyn Fn(&ServerSignalUpdate, ConnectionId) + Send + Sync>;
+
+#[derive(Default)]
+struct ClientUpdateCallbacks(std::sync::RwLock<Vec<ClientUpdateCallback>>);
+
+impl Debug for ClientUpdateCallbacks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let count = self
+            .0
+            .read()
+            .map(|callbacks| callbacks.len())
+            .unwrap_or_default();
+        write!(f, "{count} callbacks")
+    }
+}
+
+#[derive(Debug)]
+struct InputState {
+    json: Value,
+    /// Set after a rejection, all updates are ignored until the client establishes again.
+    resyncing: bool,
+}
+
+#[async_trait]
+pub trait ServerInputSignalTrait {
+    /// Sets the value of a client, returning the rejection for the client if it is invalid.
+    async fn establish(
+        &self,
+        connection: ConnectionId,
+        value: Value,
+    ) -> Result<Option<ClientInputMessage>, Error>;
+    /// Applies a patch of a client, returning the reply for the client if there is one.
+    async fn update_json(
+        &self,
+        connection: ConnectionId,
+        seq: u64,
+        patch: ServerSignalUpdate,
+    ) -> Option<ClientInputMessage>;
+    async fn remove_connection(&self, connection: ConnectionId);
+    fn as_any(&self) -> &dyn Any;
+    /// The name of the concrete type, reported when a signal is looked up with another type.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+#[async_trait]
+impl<T> ServerInputSignalTrait for ServerInputSignal<T>
+where
+    T: Clone + Send + Sync + for<'de> Deserialize<'de> + 'static + Serialize,
+{
+    async fn establish(
+        &self,
+        connection: ConnectionId,
+        value: Value,
+    ) -> Result<Option<ClientInputMessage>, Error> {
+        let new_value: T = serde_json::from_value(value.clone())?;
+        let mut states = self.json_values.write().await;
+        if let Err(reason) = self.validate(&new_value) {
+            // Updates are ignored until the client establishes the signal again
+            let value = match states.get_mut(&connection) {
+                Some(state) => {
+                    state.resyncing = true;
+                    state.json.clone()
+                }
+                None => self.initial.clone(),
+            };
+            return Ok(Some(ClientInputMessage::Reject(InputRejection {
+                name: self.name.clone(),
+                seq: 0,
+                reason,
+                value,
+            })));
+        }
+        states.insert(
+            connection,
+            InputState {
+                json: value,
+                resyncing: false,
+            },
+        );
+        drop(states);
+        self.apply(connection, new_value);
+        Ok(None)
+    }
+
+    async fn update_json(
+        &self,
+        connection: ConnectionId,
+        seq: u64,
+        patch: ServerSignalUpdate,
+    ) -> Option<ClientInputMessage> {
+        let mut states = self.json_values.write().await;
+        let state = states.get_mut(&connection)?;
+        if state.resyncing {
+            return None;
+        }
+        let mut patched = state.json.clone();
+        let result = patch
+            .apply(&mut patched)
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                serde_json::from_value::<T>(patched.clone()).map_err(|err| err.to_string())
+            })
+            .and_then(|new_value| {
+                self.validate(&new_value)?;
+                Ok(new_value)
+            });
+        match result {
+            Ok(new_value) => {
+                state.json = patched;
+                drop(states);
+                self.apply(connection, new_value);
+                let callbacks = self
+                    .on_client_update
+                    .0
+                    .read()
+                    .map(|callbacks| callbacks.clone())
+                    .unwrap_or_default();
+                for callback in callbacks {
+                    callback(&patch, connection);
+                }
+                Some(ClientInputMessage::Ack((self.name.clone(), seq)))
+            }
+            Err(reason) => {
+                state.resyncing = true;
+                Some(ClientInputMessage::Reject(InputRejection {
+                    name: self.name.clone(),
+                    seq,
+                    reason,
+                    value: state.json.clone(),
+                }))
+            }
+        }
+    }
+
+    async fn remove_connection(&self, connection: ConnectionId) {
+        if self.json_values.write().await.remove(&connection).is_some() {
+            self.values.update(|values| {
+                values.remove(&connection);
+            });
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> ServerInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    pub fn new(name: String, value: T) -> Result<Self, Error> {
+        Self::create(name, value).map(|(input, _)| input)
+    }
+
+    /// Like [`ServerInputSignal::new`], also returns whether the input was created instead of
+    /// returning the one registered before.
+    pub(crate) fn create(name: String, value: T) -> Result<(Self, bool), Error> {
+        let name = scoped_name(name);
+        let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+        let new_signal = ServerInputSignal {
+            name: name.clone(),
+            initial: serde_json::to_value(&value)?,
+            latest: ArcRwSignal::new(value),
+            values: ArcRwSignal::new(HashMap::new()),
+            json_values: Arc::default(),
+            on_client_update: Arc::default(),
+            validator: Arc::default(),
+            tasks: signals.tasks().clone(),
+            lifetime: Lifetime::default(),
+        };
+        signals.register_input(name, new_signal)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Always empty, the server holds one value per client, see
+    /// [`ServerInputSignal::on_client_update`] instead.
+    pub fn to_stream(&self) -> impl futures::Stream<Item = T> + Send + 'static {
+        futures::stream::empty()
+    }
+
+    /// Always empty, see [`ServerInputSignal::to_stream`].
+    pub fn patch_stream(
+        &self,
+    ) -> impl futures::Stream<Item = crate::messages::ServerSignalUpdate> + Send + 'static {
+        futures::stream::empty()
+    }
+
+    /// The current value of every connected client, keyed by connection.
+    ///
+    /// Clients are removed again once their connection closes.
+    pub fn values(&self) -> ArcReadSignal<HashMap<ConnectionId, T>> {
+        self.values.read_only()
+    }
+
+    /// The current value of a single client, if it is connected and has established the signal.
+    pub fn value_for(&self, connection: ConnectionId) -> Option<T> {
+        self.values
+            .with_untracked(|values| values.get(&connection).cloned())
+    }
+
+    /// Checks every value proposed by a client before it is applied.
+    ///
+    /// If the validator returns an error, the update is dropped and the client is rolled back to
+    /// its last valid value, receiving the error as
+    /// [`InputRejection::reason`](crate::messages::InputRejection). Replaces any previous validator.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ClientInputSignal;
+    /// let volume = ClientInputSignal::new("volume".to_string(), 50u8)
+    ///     .unwrap()
+    ///     .with_validator(|volume| match *volume <= 100 {
+    ///         true => Ok(()),
+    ///         false => Err("volume must be at most 100".to_string()),
+    ///     });
+    /// ```
+    pub fn with_validator(
+        self,
+        validator: impl Fn(&T) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        if let Ok(mut current) = self.validator.0.write() {
+            *current = Some(Arc::new(validator));
+        }
+        self
+    }
+
+    /// Same as returning `self`. Sends are only debounced by the client.
+    pub fn with_send_debounce(self, _interval: std::time::Duration) -> Self {
+        self
+    }
+
+    fn validate(&self, value: &T) -> Result<(), String> {
+        let validator = self.validator.0.read().ok().and_then(|v| v.clone());
+        match validator {
+            Some(validator) => validator(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Registers a callback which is called after every update of a client was applied, with the
+    /// patch and the connection it was received from.
+    ///
+    /// Rejected updates don't call it. Use `value_for(connection)` to read the new value.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ClientInputSignal;
+    /// let cursor = ClientInputSignal::new("cursor".to_string(), (0, 0)).unwrap();
+    /// let signal = cursor.clone();
+    /// cursor.on_client_update(move |_, connection| {
+    ///     leptos::logging::log!("{connection} moved to {:?}", signal.value_for(connection));
+    /// });
+    /// ```
+    pub fn on_client_update(
+        &self,
+        callback: impl Fn(&ServerSignalUpdate, ConnectionId) + Send + Sync + 'static,
+    ) {
+        if let Ok(mut callbacks) = self.on_client_update.0.write() {
+            callbacks.push(Arc::new(callback));
+        }
+    }
+
+    /// Like [`ServerInputSignal::on_client_update`], but spawns the returned future on the tokio
+    /// runtime, e.g. to write the update to a database.
+    pub fn on_client_update_async<F, Fut>(&self, callback: F)
+    where
+        F: Fn(ServerSignalUpdate, ConnectionId) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let tasks = self.tasks.clone();
+        self.on_client_update(move |update, connection| {
+            tasks.spawn(
+                TaskKind::Callback,
+                None,
+                callback(update.clone(), connection),
+            );
+        });
+    }
+
+    /// Publishes an aggregate of all client values as a [`ServerSignal`] named `name`, e.g. the
+    /// number of clients per room, so clients don't need every value just to render a count.
+    ///
+    /// The summary is recomputed once per `interval` and only sent to clients if it changed.
+    /// Clients read it like any other signal with `ServerSignal::new(name, default)`.
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use leptos_ws::ClientInputSignal;
+    /// let rooms = ClientInputSignal::new("room".to_string(), String::new()).unwrap();
+    /// let online = rooms
+    ///     .summary("online".to_string(), Duration::from_secs(1), |values| values.len())
+    ///     .unwrap();
+    /// ```
+    pub fn summary<S>(
+        &self,
+        name: String,
+        interval: Duration,
+        summarize: impl Fn(&HashMap<ConnectionId, T>) -> S + Send + Sync + 'static,
+    ) -> Result<ServerSignal<S>, Error>
+    where
+        S: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+    {
+        let values = self.values.clone();
+        let summary = ServerSignal::new(name, values.with_untracked(&summarize))?;
+        let signal = summary.clone();
+        let ended = self.lifetime.ended();
+        self.tasks.spawn(TaskKind::Recompute, None, async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let recompute = async {
+                loop {
+                    ticks.tick().await;
+                    let new_summary = values.with_untracked(&summarize);
+                    summary.update(|summary| *summary = new_summary);
+                }
+            };
+            // Until the input is dropped, e.g. along with its registry
+            select! {
+                _ = recompute => {}
+                _ = ended => {}
+            }
+        });
+        Ok(signal)
+    }
+
+    fn apply(&self, connection: ConnectionId, new_value: T) {
+        self.values.update(|values| {
+            values.insert(connection, new_value.clone());
+        });
+        self.latest.set(new_value);
+    }
+}
+
+impl<T> DefinedAt for ServerInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    fn defined_at(&self) -> Option<&'static Location<'static>> {
+        self.latest.defined_at()
+    }
+}
+
+impl<T> Track for ServerInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    #[track_caller]
+    fn track(&self) {
+        self.latest.track()
+    }
+}
+
+impl<T> ReadUntracked for ServerInputSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    type Value = <ArcRwSignal<T> as ReadUntracked>::Value;
+
+    fn try_read_untracked(&self) -> Option<Self::Value> {
+        self.latest.try_read_untracked()
+    }
+}
@@ -1,39 +1,140 @@
 use std::any::Any;
+use std::collections::VecDeque;
 use std::ops::{Deref, DerefMut};
 use std::panic::Location;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
-use crate::error::Error;
-use crate::messages::ServerSignalUpdate;
-use crate::server_signals::ServerSignals;
-use axum::async_trait;
-use futures::executor::block_on;
+use crate::backend::Backend;
+use crate::config::{InitialValue, PatchBudget};
+use crate::connection::{Audience, Connection};
+use crate::error::{Error, ErrorHooks};
+use crate::hooks::UpdateSummary;
+use crate::hydration;
+use crate::messages::{PatchStrategy, ServerSignalUpdate};
+use crate::scope::scoped_name;
+use crate::server_signals::{Persistence, ServerSignals};
+use crate::tasks::{Lifetime, TaskKind};
+use async_trait::async_trait;
 use guards::{Plain, ReadGuard};
 use leptos::prelude::*;
+use leptos::reactive::graph::{
+    AnySource, AnySubscriber, ReactiveNode, Source, Subscriber, ToAnySubscriber, WithObserver,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::broadcast::{channel, Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::select;
+use tokio::sync::broadcast::{channel, error::RecvError, Receiver, Sender};
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio::time::{sleep_until, Instant};
 
 /// A signal owned by the server which writes to the websocket when mutated.
+///
+/// Writing never blocks: the new value is queued and a background task computes the patch and
+/// broadcasts it to all observers, in the order the writes happened. The signal therefore has to
+/// be created inside a tokio runtime.
+///
+/// `set` and `update` only hold the lock of the reactive value while the closure runs and the
+/// result is serialized. Diffing happens in the background task on a snapshot, under a lock
+/// writers never wait for, so writing from a server function, an effect or another signal's
+/// update can't deadlock.
 #[derive(Clone, Debug)]
 pub struct ServerSignal<T>
 where
     T: Clone + Send + Sync + for<'de> Deserialize<'de>,
 {
     initial: T,
+    /// Whether SSR renders the current value instead of `initial`.
+    initial_value: InitialValue,
     name: String,
     value: ArcRwSignal<T>,
     json_value: Arc<RwLock<Value>>,
-    observers: Arc<Sender<ServerSignalUpdate>>,
+    observers: Arc<Observers>,
+    dispatch: mpsc::UnboundedSender<Queued>,
+    /// Changes made with [`ServerSignal::stage`] which aren't committed yet.
+    staged: Arc<Mutex<Option<T>>>,
+    patch_budget: Arc<Mutex<PatchBudget>>,
+    patch_strategy: Arc<Mutex<PatchStrategy>>,
+    projection: Projection<T>,
+    errors: ErrorHooks,
+    lifetime: Lifetime,
 }
+/// The server half of a signal kind, as stored in [`ServerSignals`].
+///
+/// See [`extension`](crate::extension) for how to implement custom signal kinds.
 #[async_trait]
 pub trait ServerSignalTrait {
+    /// Called for every connection establishing the signal. Every update sent on the returned
+    /// channel is forwarded to that client.
     async fn add_observer(&self) -> Receiver<ServerSignalUpdate>;
+    /// Applies a patch to the JSON representation and broadcasts it to all observers.
     async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error>;
-    async fn update_if_changed(&self) -> Result<(), Error>;
+    /// The complete current value, sent to a client when it establishes the signal.
     fn json(&self) -> Result<Value, Error>;
+    /// Sets the value to `json`, e.g. from [`ServerSignals::import_snapshot`], and broadcasts the
+    /// change to all observers.
+    async fn import_json(&self, name: String, json: Value) -> Result<(), Error> {
+        let update = ServerSignalUpdate::new_from_json(name, &self.json()?, &json);
+        if update.patch.0.is_empty() {
+            return Ok(());
+        }
+        self.update_json(update).await
+    }
+    /// Called for every establish message of a client, returns the value sent back to the client
+    /// and the channel of updates forwarded to it.
+    ///
+    /// `payload` is what the client signal returned from
+    /// [`ClientSignalTrait::establish_payload`](crate::extension), or `Value::Null`. Signal kinds
+    /// which accept a payload deserialize it into their own type here.
+    async fn establish(
+        &self,
+        payload: Value,
+    ) -> Result<(Value, Receiver<ServerSignalUpdate>), Error> {
+        let _ = payload;
+        Ok((self.json()?, self.add_observer().await))
+    }
+    /// The value sent to a client establishing the signal with `payload`, with the version of
+    /// the last update it includes. The client doesn't receive the updates up to it again.
+    ///
+    /// The default reads the version before calling [`ServerSignalTrait::establish`]. Signal
+    /// kinds which number their updates read both at once instead, so the value never includes
+    /// an update the version doesn't.
+    async fn snapshot(&self, payload: Value) -> Result<(Value, Option<(u64, u64)>), Error> {
+        let version = self.version();
+        let (value, _) = self.establish(payload).await?;
+        Ok((value, version))
+    }
+    /// The number of receivers of updates, i.e. established connections plus other subscribers.
+    /// Signal kinds which don't keep track of their observers return 0.
+    fn observer_count(&self) -> usize {
+        0
+    }
+    /// When the last update was broadcast, `None` if there was none yet or the signal kind
+    /// doesn't keep track of it.
+    fn last_update(&self) -> Option<std::time::Instant> {
+        None
+    }
     fn as_any(&self) -> &dyn Any;
+    /// The name of the concrete type, reported when a signal is looked up with another type.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+    /// The name of the value type, compared with the one of the client signal to detect
+    /// signals declared with different types on both sides. `None` skips the check.
+    fn schema(&self) -> Option<&'static str> {
+        None
+    }
+    /// The epoch and sequence number of the last update broadcast, sent to clients so they can
+    /// resume after a reconnect. Signal kinds which don't keep a log of their updates return
+    /// `None`, their clients always receive the complete value.
+    fn version(&self) -> Option<(u64, u64)> {
+        None
+    }
+    /// The updates broadcast after `seq`, if they are numbered in `epoch` and still kept.
+    fn updates_since(&self, epoch: u64, seq: u64) -> Option<Vec<ServerSignalUpdate>> {
+        let _ = (epoch, seq);
+        None
+    }
     fn track(&self);
 }
 
@@ -46,41 +147,75 @@ where
         self.subscribe()
     }
 
+    fn observer_count(&self) -> usize {
+        self.observers.receiver_count()
+    }
+
+    fn last_update(&self) -> Option<std::time::Instant> {
+        self.observers.last_sent()
+    }
+
     async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
         let mut writer = self.json_value.write().await;
-        if json_patch::patch(writer.deref_mut(), &patch.patch).is_ok() {
-            //*self.value.write() = serde_json::from_value(writer.clone())?;
-            let _ = self.observers.send(patch);
-            Ok(())
-        } else {
-            Err(Error::UpdateSignalFailed)
+        patch.apply(writer.deref_mut())?;
+        // Written directly, the update must not be dispatched again. A projection can't be
+        // turned back into the value, which then keeps what this node wrote last.
+        if self.projection.complete {
+            self.value.set(serde_json::from_value(writer.clone())?);
         }
+        self.observers.send(patch);
+        Ok(())
     }
 
-    async fn update_if_changed(&self) -> Result<(), Error> {
-        let json = self.json_value.read().await.clone();
-        let new_json = serde_json::to_value(self.value.get())?;
-        let mut res = Err(Error::UpdateSignalFailed);
-        if json != new_json {
-            res = self
-                .update_json(ServerSignalUpdate::new_from_json(
-                    self.name.clone(),
-                    &json,
-                    &new_json,
-                ))
-                .await;
-        }
-        res
+    fn json(&self) -> Result<Value, Error> {
+        Ok(self.value.with(|value| self.projection.json(value))?)
     }
 
-    fn json(&self) -> Result<Value, Error> {
-        Ok(serde_json::to_value(self.value.get())?)
+    async fn snapshot(&self, _payload: Value) -> Result<(Value, Option<(u64, u64)>), Error> {
+        // The last broadcast value, the current one may include writes which are still queued.
+        // Updates are numbered while it is locked for writing, so the version matches it
+        let json = self.json_value.read().await;
+        Ok((json.clone(), Some(self.observers.version())))
+    }
+
+    async fn import_json(&self, _name: String, json: Value) -> Result<(), Error> {
+        // A projection can't be turned back into the value
+        if !self.projection.complete {
+            return Ok(());
+        }
+        let imported: T = serde_json::from_value(json)?;
+        // Queued like any write, so it is diffed against what clients last received and isn't
+        // overwritten by an older write still queued
+        write_value(
+            &self.value,
+            &self.dispatch,
+            &self.projection,
+            &self.name,
+            &self.errors,
+            |value| {
+                *value = imported;
+                (true, ())
+            },
+        );
+        Ok(())
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
 
+    fn schema(&self) -> Option<&'static str> {
+        self.projection.schema
+    }
+
+    fn version(&self) -> Option<(u64, u64)> {
+        Some(self.observers.version())
+    }
+
+    fn updates_since(&self, epoch: u64, seq: u64) -> Option<Vec<ServerSignalUpdate>> {
+        self.observers.since(epoch, seq)
+    }
+
     #[track_caller]
     fn track(&self) {
         self.value.track()
@@ -92,28 +227,639 @@ where
     T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
 {
     pub fn new(name: String, value: T) -> Result<Self, Error> {
-        let mut signals = use_context::<ServerSignals>().ok_or(Error::MissingServerSignals)?;
-        if block_on(signals.contains(&name)) {
-            return Ok(block_on(signals.get_signal::<ServerSignal<T>>(name)).unwrap());
+        Self::new_with_throttle(
+            name,
+            value,
+            None,
+            default_initial_value(),
+            Projection::identity(),
+        )
+    }
+
+    /// Like [`ServerSignal::new`], with a policy for what the client shows before it received
+    /// the value of the server.
+    ///
+    /// With [`InitialValue::UseSsrSerializedValue`], pages are rendered with the current value
+    /// and it is embedded into the SSR payload for hydration.
+    pub fn new_with_initial(
+        name: String,
+        value: T,
+        initial_value: InitialValue,
+    ) -> Result<Self, Error> {
+        Self::new_with_throttle(name, value, None, initial_value, Projection::identity())
+    }
+
+    /// Like [`ServerSignal::new`], but only connections for which `acl` returns `true` may
+    /// establish the signal, see [`ServerSignals::set_acl`].
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::{config::ConnectionConfig, ServerSignal};
+    /// # #[cfg(feature = "axum")]
+    /// # fn role_from_session(_headers: &axum::http::HeaderMap) -> Option<String> { None }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let report = vec![0u64];
+    /// # #[cfg(feature = "axum")]
+    /// let config = ConnectionConfig::default().identify(|headers| role_from_session(headers));
+    /// let report = ServerSignal::new_with_acl("salary_report".to_string(), report, |role| {
+    ///     role == Some("admin")
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_acl(
+        name: String,
+        value: T,
+        acl: impl Fn(Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+        signals.set_acl(scoped_name(name.clone()), acl);
+        Self::new(name, value)
+    }
+
+    /// Like [`ServerSignal::new`], but broadcasts at most one update per `interval`, with the
+    /// latest value written in the meantime. Intermediate values are never sent.
+    ///
+    /// For sources which change far more often than clients need to see, e.g. mouse positions,
+    /// progress bars or sensor readings. Reads on the server always see the latest value.
+    pub fn throttled(name: String, value: T, interval: Duration) -> Result<Self, Error> {
+        Self::new_with_throttle(
+            name,
+            value,
+            Some(interval),
+            default_initial_value(),
+            Projection::identity(),
+        )
+    }
+
+    /// Like [`ServerSignal::new`], but clients only see what `project` returns, e.g. a view of
+    /// the value without internal ids or secrets. The projection is what gets diffed, so the
+    /// rest never appears in the value a client establishes, in patches or in the SSR payload.
+    ///
+    /// Clients declare the signal with the type of the view. Projected signals aren't persisted
+    /// and can't be read back from updates of other server nodes, each node keeps the complete
+    /// value it wrote itself.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ServerSignal;
+    /// # use serde::{Deserialize, Serialize};
+    /// # #[derive(Clone, Serialize, Deserialize)]
+    /// # struct User { name: String, avatar: String, password_hash: String }
+    /// # #[derive(Clone, Default, Serialize, Deserialize)]
+    /// # struct PublicUser { name: String, avatar: String }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let user = User { name: String::new(), avatar: String::new(), password_hash: String::new() };
+    /// let user = ServerSignal::new_projected("user".to_string(), user, |user: &User| PublicUser {
+    ///     name: user.name.clone(),
+    ///     avatar: user.avatar.clone(),
+    /// })?;
+    /// // On the client
+    /// # #[cfg(feature = "client")]
+    /// let user = ServerSignal::new("user".to_string(), PublicUser::default())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_projected<V>(
+        name: String,
+        value: T,
+        project: impl Fn(&T) -> V + Send + Sync + 'static,
+    ) -> Result<Self, Error>
+    where
+        V: Serialize + 'static,
+    {
+        Self::new_with_throttle(
+            name,
+            value,
+            None,
+            default_initial_value(),
+            Projection::new(project),
+        )
+    }
+
+    /// Like [`ServerSignal::new_projected`], with a view which is the value without the fields
+    /// named in `skip`. They are removed from the serialized value before it is diffed.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ServerSignal;
+    /// # use serde::{Deserialize, Serialize};
+    /// # #[derive(Clone, Default, Serialize, Deserialize)]
+    /// # struct Account { name: String, internal_id: u64, api_key: String }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let account = Account::default();
+    /// let account = ServerSignal::new_skipping(
+    ///     "account".to_string(),
+    ///     account,
+    ///     &["internal_id", "api_key"],
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_skipping(name: String, value: T, skip: &[&str]) -> Result<Self, Error> {
+        Self::new_with_throttle(
+            name,
+            value,
+            None,
+            default_initial_value(),
+            Projection::skipping(skip),
+        )
+    }
+
+    fn new_with_throttle(
+        name: String,
+        value: T,
+        throttle: Option<Duration>,
+        initial_value: InitialValue,
+        projection: Projection<T>,
+    ) -> Result<Self, Error> {
+        Self::create(name, value, throttle, initial_value, projection).map(|(signal, _)| signal)
+    }
+
+    /// Like [`ServerSignal::new_with_throttle`], also returns whether the signal was created
+    /// instead of returning the one registered before.
+    fn create(
+        name: String,
+        value: T,
+        throttle: Option<Duration>,
+        initial_value: InitialValue,
+        projection: Projection<T>,
+    ) -> Result<(Self, bool), Error> {
+        let (signal, created) = Self::register(name, value, throttle, initial_value, projection)?;
+        let json = match initial_value {
+            InitialValue::UseSsrSerializedValue => Some(serde_json::to_string(
+                &signal.projection.json(&signal.rendered_value())?,
+            )?),
+            _ => None,
+        };
+        hydration::embed(json);
+        Ok((signal, created))
+    }
+
+    fn register(
+        name: String,
+        value: T,
+        throttle: Option<Duration>,
+        initial_value: InitialValue,
+        projection: Projection<T>,
+    ) -> Result<(Self, bool), Error> {
+        let name = scoped_name(name);
+        let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+        // Nothing is restored, projected or spawned for a signal which exists already
+        if let Some(signal) = signals.lookup_signal::<Self>(&name) {
+            return signal.map(|signal| (signal, false));
         }
-        let (send, _) = channel(32);
+        let initial = value.clone();
+        // What is stored is the projection, the value can only be restored from itself
+        let restored = signals.restored(&name).filter(|_| projection.complete);
+        let (value, restore_error) = match restored.map(serde_json::from_value) {
+            Some(Ok(stored)) => (stored, None),
+            Some(Err(err)) => (value, Some(err)),
+            None => (value, None),
+        };
+        let (dispatch, queue) = mpsc::unbounded_channel();
+        let json = projection.json(&value)?;
+        let persistence = signals.persistence().filter(|_| projection.complete);
+        let (persist, persisted) = match persistence {
+            Some(persistence) => {
+                let (persist, updates) = watch::channel(json.clone());
+                (Some(persist), Some((persistence, updates)))
+            }
+            None => (None, None),
+        };
+        let json_value = Arc::new(RwLock::new(json));
+        let observers = Arc::new(Observers::new(32));
+        let value = ArcRwSignal::new(value);
+        let patch_budget = Arc::new(Mutex::new(signals.patch_budget()));
+        let patch_strategy = Arc::new(Mutex::new(PatchStrategy::default()));
+        let guard = PatchGuard {
+            budget: patch_budget.clone(),
+            strategy: patch_strategy.clone(),
+            revert: Box::new({
+                let value = value.clone();
+                let complete = projection.complete;
+                move |json: &Value| {
+                    if !complete {
+                        return;
+                    }
+                    if let Ok(previous) = serde_json::from_value::<T>(json.clone()) {
+                        *value.write() = previous;
+                    }
+                }
+            }),
+        };
+        let broadcast = Broadcast {
+            json_value: json_value.clone(),
+            observers: observers.clone(),
+            backend: signals.backend(),
+            persist,
+            guard,
+            errors: signals.error_hooks(),
+        };
         let new_signal = ServerSignal {
-            initial: value.clone(),
+            initial,
+            initial_value,
             name: name.clone(),
-            value: ArcRwSignal::new(value.clone()),
-            json_value: Arc::new(RwLock::new(serde_json::to_value(value)?)),
-            observers: Arc::new(send),
+            value,
+            json_value,
+            observers,
+            dispatch,
+            staged: Arc::default(),
+            patch_budget,
+            patch_strategy,
+            projection,
+            errors: signals.error_hooks(),
+            lifetime: Lifetime::default(),
         };
-        let signal = new_signal.clone();
-        block_on(signals.create_signal(name, new_signal)).unwrap();
+        let (signal, created) = signals.register_signal(name.clone(), new_signal)?;
+        if !created {
+            // Registered by another task in the meantime, this one is dropped before its tasks
+            // were spawned
+            return Ok((signal, false));
+        }
+        // Only spawned once the signal is registered, so they run exactly once per signal, and
+        // the hooks run without holding the registry
+        if let Some(err) = restore_error {
+            // Reported, but not fatal: the signal starts with `value` instead
+            signals.report(Some(&name), err.into());
+        }
+        if let Some((persistence, updates)) = persisted {
+            signals.tasks().spawn(
+                TaskKind::Persist,
+                None,
+                persist_updates(name.clone(), persistence, updates, signals.error_hooks()),
+            );
+        }
+        signals.tasks().spawn(
+            TaskKind::Dispatch,
+            None,
+            dispatch_updates(name, broadcast, WriteQueue::new(queue, throttle)),
+        );
+        Ok((signal, true))
+    }
+
+    /// Creates a signal which is recomputed with `compute` whenever one of the signals named in
+    /// `sources` changes, and broadcast like any other signal.
+    ///
+    /// `compute` reads the sources itself, usually with `get_untracked()`. Changes arriving while
+    /// it runs are coalesced into one more recomputation. Fails with [`Error::UnknownSignal`] if a
+    /// source doesn't exist yet and with [`Error::DependencyCycle`] if a source is itself derived
+    /// from this signal.
+    ///
+    /// Like `name`, the sources are looked up in the current [`ScopedSignals`](crate::ScopedSignals).
+    /// If the signal exists already, e.g. because a component calls this on every request, it is
+    /// returned and `compute` is dropped.
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::ServerSignal;
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// let price = ServerSignal::new("price".to_string(), 10)?;
+    /// let amount = ServerSignal::new("amount".to_string(), 3)?;
+    /// let total = ServerSignal::derived(
+    ///     "total".to_string(),
+    ///     &["price", "amount"],
+    ///     move || price.get_untracked() * amount.get_untracked(),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn derived(
+        name: String,
+        sources: &[&str],
+        compute: impl Fn() -> T + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+        let scoped = scoped_name(name.clone());
+        // Created before, e.g. while rendering an earlier request, with its recompute tasks
+        if let Some(signal) = signals.lookup_signal::<Self>(&scoped) {
+            return signal;
+        }
+        let sources = sources
+            .iter()
+            .map(|source| scoped_name(source.to_string()))
+            .collect::<Vec<_>>();
+        let mut observed = Vec::with_capacity(sources.len());
+        for source in &sources {
+            let source = signals
+                .signal(source)
+                .ok_or_else(|| Error::UnknownSignal(source.clone()))?;
+            observed.push(source);
+        }
+        signals.add_dependencies(&scoped, &sources)?;
+        let (signal, created) = Self::create(
+            name,
+            compute(),
+            None,
+            default_initial_value(),
+            Projection::identity(),
+        )?;
+        // Another task created it since it was looked up, and spawned the recompute tasks
+        if !created {
+            return Ok(signal);
+        }
+        // Holding a change is enough, changes arriving during a recomputation are coalesced
+        let (changed, changes) = mpsc::channel::<()>(1);
+        for source in observed {
+            let changed = changed.clone();
+            let ended = signal.lifetime.ended();
+            signals
+                .tasks()
+                .spawn(TaskKind::Recompute, None, async move {
+                    let mut receiver = source.add_observer().await;
+                    drop(source);
+                    // Changes made before subscribing weren't received, recomputes once for them
+                    let _ = changed.try_send(());
+                    let forward = async {
+                        while let Ok(_) | Err(RecvError::Lagged(_)) = receiver.recv().await {
+                            let _ = changed.try_send(());
+                        }
+                    };
+                    // Until the source or the derived signal is dropped
+                    select! {
+                        _ = forward => {}
+                        _ = ended => {}
+                    }
+                });
+        }
+        drop(changed);
+        signal.spawn_recompute(&signals, changes, compute)?;
         Ok(signal)
     }
 
+    /// Creates a signal holding the result of `compute`, which is recomputed whenever a reactive
+    /// value it reads changes, and broadcast like any other signal. Only the result is sent to
+    /// clients, not the data it is computed from.
+    ///
+    /// Unlike [`ServerSignal::derived`], the dependencies are tracked like in an effect, so they
+    /// can be any reactive values on the server, e.g. other server signals read with `get()`.
+    /// It is recomputed on a task of the registry rather than by the current reactive owner, so
+    /// it keeps recomputing after the request which created it was rendered.
+    ///
+    /// If the signal exists already it is returned and `compute` is dropped.
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::ServerSignal;
+    /// # fn summarize(samples: &[f64]) -> f64 { samples.iter().sum() }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// let samples = ServerSignal::new("samples".to_string(), Vec::<f64>::new())?;
+    /// let stats = ServerSignal::derive("stats".to_string(), move || summarize(&samples.get()))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn derive(
+        name: String,
+        compute: impl Fn() -> T + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let signals = ServerSignals::current().ok_or(Error::MissingServerSignals)?;
+        let (changed, changes) = mpsc::channel(1);
+        let dependencies = Dependencies::new(changed);
+        let (signal, created) = Self::create(
+            name,
+            dependencies.track(&compute),
+            None,
+            default_initial_value(),
+            Projection::identity(),
+        )?;
+        if created {
+            signal.spawn_recompute(&signals, changes, move || dependencies.track(&compute))?;
+        }
+        Ok(signal)
+    }
+
+    /// Sets the signal to the result of `compute` whenever a change is received, until the
+    /// signal is dropped along with its registry or no more changes can be received.
+    fn spawn_recompute(
+        &self,
+        signals: &ServerSignals,
+        mut changes: mpsc::Receiver<()>,
+        compute: impl Fn() -> T + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        // Only held weakly, the task must not keep the signal alive itself
+        let registered = signals
+            .signal(&self.name)
+            .as_ref()
+            .map(Arc::downgrade)
+            .ok_or_else(|| Error::UnknownSignal(self.name.clone()))?;
+        let ended = self.lifetime.ended();
+        signals
+            .tasks()
+            .spawn(TaskKind::Recompute, None, async move {
+                let recompute = async {
+                    while changes.recv().await.is_some() {
+                        let Some(registered) = registered.upgrade() else {
+                            break;
+                        };
+                        let new_value = compute();
+                        if let Some(derived) = registered.as_any().downcast_ref::<Self>() {
+                            derived.update(|value| *value = new_value);
+                        }
+                    }
+                };
+                select! {
+                    _ = recompute => {}
+                    _ = ended => {}
+                }
+            });
+        Ok(())
+    }
+
+    /// Same as [`ServerSignal::new`]. Laziness only affects when the client establishes the signal.
+    pub fn new_lazy(name: String, value: T) -> Result<Self, Error> {
+        Self::new(name, value)
+    }
+
+    /// Same as [`ServerSignal::new`]. Values are only cached by the client.
+    pub fn new_persistent(name: String, value: T) -> Result<Self, Error> {
+        Self::new(name, value)
+    }
+
+    /// Does nothing, only clients subscribe to signals.
+    pub fn unsubscribe(&self) {}
+
+    /// Does nothing, only clients subscribe to signals.
+    pub fn resubscribe(&self) {}
+
+    /// Always `true`, only clients subscribe to signals.
+    pub fn is_subscribed(&self) -> bool {
+        true
+    }
+
+    /// Always `None`, updates are only summarized on the client.
+    pub fn update_summary(&self) -> Signal<Option<UpdateSummary>> {
+        Signal::derive(|| None)
+    }
+
+    /// Always zero, latency is only measured on the client.
+    pub fn latency(&self) -> Signal<Duration> {
+        Signal::derive(|| Duration::ZERO)
+    }
+
     pub fn subscribe(&self) -> Receiver<ServerSignalUpdate> {
         self.observers.subscribe()
     }
+
+    /// Always empty, only clients receive values. [`ServerSignal::subscribe`] receives the updates
+    /// on the server.
+    pub fn to_stream(&self) -> impl futures::Stream<Item = T> + Send + 'static {
+        futures::stream::empty()
+    }
+
+    /// Always empty, only clients receive values. [`ServerSignal::subscribe`] receives the updates
+    /// on the server.
+    pub fn patch_stream(&self) -> impl futures::Stream<Item = ServerSignalUpdate> + Send + 'static {
+        futures::stream::empty()
+    }
+
+    /// Changes a staged copy of the value which neither clients nor reads on the server see until
+    /// [`ServerSignal::commit`] is called, e.g. to import data in several steps without ever
+    /// showing a half imported state.
+    ///
+    /// The first call copies the current value, later calls keep changing the copy.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ServerSignal;
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let items = ServerSignal::new("items".to_string(), Vec::<u32>::new())?;
+    /// # let batches = vec![vec![1, 2], vec![3]];
+    /// for batch in batches {
+    ///     items.stage(|items| items.extend(batch));
+    /// }
+    /// items.commit(); // clients receive a single patch
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stage(&self, fun: impl FnOnce(&mut T)) {
+        let mut staged = self.staged.lock().unwrap();
+        let staged = staged.get_or_insert_with(|| self.value.get_untracked());
+        fun(staged);
+    }
+
+    /// Replaces the value with the staged copy and broadcasts the difference as one update.
+    ///
+    /// Writes to the signal since the first [`ServerSignal::stage`] are overwritten. Does nothing
+    /// if nothing is staged.
+    pub fn commit(&self) {
+        let staged = self.staged.lock().unwrap().take();
+        if let Some(staged) = staged {
+            self.update(|value| *value = staged);
+        }
+    }
+
+    /// Sends the change `fun` makes to a copy of the value only to the connections for which
+    /// `audience` returns `true`, e.g. clients with a feature flag. The value itself and what
+    /// other connections see stay unchanged.
+    ///
+    /// The audience receives its view as a complete value and applies later updates on top of
+    /// it, until it receives the shared value again, e.g. after a reconnect. The change isn't
+    /// published to other server nodes, and clients which can't apply patches never see it.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ServerSignal;
+    /// # use serde::{Deserialize, Serialize};
+    /// # use std::collections::HashSet;
+    /// # #[derive(Clone, Serialize, Deserialize)]
+    /// # struct MenuItem(String);
+    /// # impl MenuItem {
+    /// #     fn new(label: &str) -> Self { Self(label.to_string()) }
+    /// # }
+    /// # #[derive(Clone, Default, Serialize, Deserialize)]
+    /// # struct Menu { items: Vec<MenuItem> }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let menu = ServerSignal::new("menu".to_string(), Menu::default())?;
+    /// # let beta_users = HashSet::<String>::new();
+    /// menu.update_where(
+    ///     move |connection| connection.identity().is_some_and(|user| beta_users.contains(user)),
+    ///     |menu| menu.items.push(MenuItem::new("Reports (beta)")),
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update_where(
+        &self,
+        audience: impl Fn(&Connection) -> bool + Send + Sync + 'static,
+        fun: impl FnOnce(&mut T),
+    ) {
+        let mut view = self.value.get_untracked();
+        fun(&mut view);
+        match self.projection.json(&view) {
+            Ok(view) => {
+                let _ = self
+                    .dispatch
+                    .send(Queued::Audience(view, Audience::new(audience)));
+            }
+            Err(err) => self.errors.report(Some(&self.name), err.into()),
+        }
+    }
+
+    /// Discards the staged copy.
+    pub fn abort(&self) {
+        self.staged.lock().unwrap().take();
+    }
+
+    /// Overrides the [`PatchBudget`] of the registry for this signal.
+    pub fn patch_budget(self, budget: PatchBudget) -> Self {
+        *self.patch_budget.lock().unwrap() = budget;
+        self
+    }
+
+    /// Selects how updates of this signal are encoded on the wire, JSON patches by default.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::{messages::PatchStrategy, ServerSignal};
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let players = vec![("alice".to_string(), 3)];
+    /// let ranking = ServerSignal::new("ranking".to_string(), players)?
+    ///     .patch_strategy(PatchStrategy::Replace);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn patch_strategy(self, strategy: PatchStrategy) -> Self {
+        *self.patch_strategy.lock().unwrap() = strategy;
+        self
+    }
+
+    /// The value as an async derived signal, for use in a `Suspense` on the client, see
+    /// [`InitialValue::AwaitServer`]. On the server it is ready right away.
+    pub fn ready(&self) -> ArcAsyncDerived<T> {
+        let signal = self.clone();
+        ArcAsyncDerived::new(move || {
+            let value = signal.get();
+            async move { value }
+        })
+    }
+
+    /// Resolves right away with the value the page is rendered with. On the client it waits for
+    /// the first value of the server.
+    pub fn await_established(&self) -> impl std::future::Future<Output = T> {
+        std::future::ready(self.rendered_value())
+    }
+
+    /// What a page is rendered with: the value the client starts with, unless the current value
+    /// is embedded for hydration.
+    fn rendered_value(&self) -> T {
+        if self.initial_value != InitialValue::UseSsrSerializedValue && self.check_is_hydrating() {
+            return self.initial.clone();
+        }
+        self.value.get_untracked()
+    }
+
+    /// Whether there are changes which aren't committed yet.
+    pub fn is_staged(&self) -> bool {
+        self.staged.lock().unwrap().is_some()
+    }
+
+    /// A handle to write the signal from a spawned task or anywhere else outside of the
+    /// reactive system, see [`ServerSignalHandle`].
+    pub fn handle(&self) -> ServerSignalHandle<T> {
+        ServerSignalHandle {
+            name: self.name.clone(),
+            value: self.value.clone(),
+            dispatch: self.dispatch.clone(),
+            projection: self.projection.clone(),
+            errors: self.errors.clone(),
+        }
+    }
     fn check_is_hydrating(&self) -> bool {
-        #[cfg(not(feature = "ssr"))]
+        #[cfg(not(feature = "server"))]
         return false;
         let owner = match Owner::current() {
             Some(owner) => owner,
@@ -123,7 +869,7 @@ where
             Some(shared_context) => shared_context,
             None => return false,
         };
-        #[cfg(feature = "ssr")]
+        #[cfg(feature = "server")]
         if shared_context.get_is_hydrating() || shared_context.during_hydration() == false {
             return true;
         }
@@ -138,17 +884,566 @@ where
     type Value = T;
 
     fn try_maybe_update<U>(&self, fun: impl FnOnce(&mut Self::Value) -> (bool, U)) -> Option<U> {
-        let mut lock = self.value.try_write()?;
-        let (did_update, val) = fun(&mut *lock);
-        if !did_update {
-            lock.untrack();
-        } else {
+        write_value(
+            &self.value,
+            &self.dispatch,
+            &self.projection,
+            &self.name,
+            &self.errors,
+            fun,
+        )
+    }
+}
+
+/// Writes `value` and queues the new value for broadcasting, for [`ServerSignal`] and
+/// [`ServerSignalHandle`].
+fn write_value<T, U>(
+    value: &ArcRwSignal<T>,
+    dispatch: &mpsc::UnboundedSender<Queued>,
+    projection: &Projection<T>,
+    name: &str,
+    errors: &ErrorHooks,
+    fun: impl FnOnce(&mut T) -> (bool, U),
+) -> Option<U>
+where
+    T: Send + Sync + 'static,
+{
+    let mut lock = value.try_write()?;
+    let (did_update, val) = fun(&mut *lock);
+    if !did_update {
+        lock.untrack();
+        return Some(val);
+    }
+    let new_json = projection.json(&lock);
+    drop(lock);
+    match new_json {
+        Ok(new_json) => {
+            // Only fails once the dispatch task is gone, i.e. the runtime shut down
+            let _ = dispatch.send(Queued::Value(new_json));
+        }
+        Err(err) => errors.report(Some(name), err.into()),
+    }
+    Some(val)
+}
+
+/// Writes a [`ServerSignal`] from anywhere on the server, e.g. a spawned task, a cron job or the
+/// consumer of a message queue, see [`ServerSignal::handle`].
+///
+/// The handle needs neither a reactive owner nor the context of a request. Writes are broadcast
+/// like the ones of the signal, and effects on the server which read the signal rerun.
+///
+/// ```rust,no_run
+/// # use leptos_ws::ServerSignal;
+/// # async fn run_step(_step: u32) {}
+/// # fn main() -> Result<(), leptos_ws::error::Error> {
+/// let progress = ServerSignal::new("progress".to_string(), 0)?;
+/// let handle = progress.handle();
+/// tokio::spawn(async move {
+///     for step in 1..=100 {
+///         run_step(step).await;
+///         handle.set(step);
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServerSignalHandle<T> {
+    name: String,
+    value: ArcRwSignal<T>,
+    dispatch: mpsc::UnboundedSender<Queued>,
+    projection: Projection<T>,
+    errors: ErrorHooks,
+}
+
+impl<T> ServerSignalHandle<T>
+where
+    T: Clone + Serialize + Send + Sync + 'static,
+{
+    /// The scoped name of the signal.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A copy of the current value.
+    pub fn get(&self) -> T {
+        self.value.get_untracked()
+    }
+
+    pub fn with<U>(&self, fun: impl FnOnce(&T) -> U) -> U {
+        self.value.with_untracked(fun)
+    }
+
+    pub fn set(&self, value: T) {
+        self.update(|current| *current = value);
+    }
+
+    /// Changes the value in place and broadcasts the difference.
+    pub fn update(&self, fun: impl FnOnce(&mut T)) {
+        write_value(
+            &self.value,
+            &self.dispatch,
+            &self.projection,
+            &self.name,
+            &self.errors,
+            |value| {
+                fun(value);
+                (true, ())
+            },
+        );
+    }
+}
+
+type Project<T> = dyn Fn(&T) -> Result<Value, serde_json::Error> + Send + Sync;
+
+/// Turns the value of a signal into the JSON clients see, see [`ServerSignal::new_projected`].
+struct Projection<T> {
+    project: Arc<Project<T>>,
+    /// The type clients declare the signal with, `None` if it isn't known.
+    schema: Option<&'static str>,
+    /// Whether the JSON is the complete value, which can be deserialized again.
+    complete: bool,
+}
+
+impl<T> Projection<T> {
+    fn identity() -> Self
+    where
+        T: Serialize + 'static,
+    {
+        Self {
+            project: Arc::new(|value| serde_json::to_value(value)),
+            schema: Some(std::any::type_name::<T>()),
+            complete: true,
+        }
+    }
+
+    fn new<V: Serialize + 'static>(project: impl Fn(&T) -> V + Send + Sync + 'static) -> Self {
+        Self {
+            project: Arc::new(move |value| serde_json::to_value(project(value))),
+            schema: Some(std::any::type_name::<V>()),
+            complete: false,
+        }
+    }
+
+    fn skipping(skip: &[&str]) -> Self
+    where
+        T: Serialize + 'static,
+    {
+        let skip = skip
+            .iter()
+            .map(|field| field.to_string())
+            .collect::<Vec<_>>();
+        Self {
+            project: Arc::new(move |value| {
+                let mut json = serde_json::to_value(value)?;
+                if let Value::Object(fields) = &mut json {
+                    for field in &skip {
+                        fields.remove(field);
+                    }
+                }
+                Ok(json)
+            }),
+            schema: None,
+            complete: false,
+        }
+    }
+
+    fn json(&self, value: &T) -> Result<Value, serde_json::Error> {
+        (self.project)(value)
+    }
+}
+
+impl<T> Clone for Projection<T> {
+    fn clone(&self) -> Self {
+        Self {
+            project: self.project.clone(),
+            schema: self.schema,
+            complete: self.complete,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Projection<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Projection")
+            .field("schema", &self.schema)
+            .field("complete", &self.complete)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The policy of the registry in context, see [`ServerSignals::with_initial_value`].
+fn default_initial_value() -> InitialValue {
+    ServerSignals::current().map_or_else(InitialValue::default, |signals| signals.initial_value())
+}
+
+/// Where [`dispatch_updates`] sends the writes of a signal.
+struct Broadcast {
+    /// The last broadcast value, which writes are diffed against.
+    json_value: Arc<RwLock<Value>>,
+    observers: Arc<Observers>,
+    backend: Option<Arc<dyn Backend>>,
+    persist: Option<watch::Sender<Value>>,
+    guard: PatchGuard,
+    errors: ErrorHooks,
+}
+
+/// Diffs every queued value against the last broadcast one and sends the patch to all observers
+/// and the backend, if there is one.
+///
+/// Ends once every handle of the signal has been dropped.
+async fn dispatch_updates(name: String, broadcast: Broadcast, mut queue: WriteQueue) {
+    let Broadcast {
+        json_value,
+        observers,
+        backend,
+        persist,
+        guard,
+        errors,
+    } = broadcast;
+    while let Some(write) = queue.next().await {
+        let new_json = match write {
+            Queued::Value(new_json) => new_json,
+            Queued::Audience(view, audience) => {
+                // The view may already include writes which are queued, so it is sent completely
+                // instead of as a patch against the last broadcast value
+                let mut update = ServerSignalUpdate::replace(name.clone(), view);
+                if guard.check(&update) {
+                    update.audience = Some(audience);
+                    // Numbered while the value is locked, see `ServerSignalTrait::snapshot`
+                    let json = json_value.write().await;
+                    observers.send(update);
+                    drop(json);
+                }
+                continue;
+            }
+        };
+        let mut json = json_value.write().await;
+        if *json == new_json {
+            continue;
+        }
+        let strategy = *guard.strategy.lock().unwrap();
+        let update =
+            ServerSignalUpdate::new_with_strategy(name.clone(), &json, &new_json, strategy);
+        if !guard.check(&update) {
+            if queue.is_empty() {
+                (guard.revert)(&json);
+            }
+            continue;
+        }
+        if let Some(persist) = &persist {
+            persist.send_replace(new_json.clone());
+        }
+        // Numbered while the value is locked, see `ServerSignalTrait::snapshot`
+        *json = new_json;
+        observers.send(update.clone());
+        drop(json);
+        if let Some(backend) = &backend {
+            if let Err(err) = backend.publish(&update).await {
+                errors.report(Some(&name), err);
+            }
+        }
+    }
+}
+
+/// The reactive values read by the `compute` of [`ServerSignal::derive`], tracked like the
+/// sources of an effect, but without a reactive owner which could stop it.
+struct Dependencies(Arc<DependencyNode>);
+
+struct DependencyNode {
+    sources: Mutex<Vec<AnySource>>,
+    /// Notifies the recompute task, holding one change is enough.
+    changed: mpsc::Sender<()>,
+}
+
+impl Dependencies {
+    fn new(changed: mpsc::Sender<()>) -> Self {
+        Self(Arc::new(DependencyNode {
+            sources: Mutex::default(),
+            changed,
+        }))
+    }
+
+    /// Runs `compute`, tracking only what it reads this time.
+    fn track<R>(&self, compute: impl FnOnce() -> R) -> R {
+        let subscriber = self.to_any_subscriber();
+        subscriber.clear_sources(&subscriber);
+        subscriber.with_observer(compute)
+    }
+}
+
+impl ToAnySubscriber for Dependencies {
+    fn to_any_subscriber(&self) -> AnySubscriber {
+        AnySubscriber(
+            Arc::as_ptr(&self.0) as usize,
+            Arc::downgrade(&self.0) as Weak<dyn Subscriber + Send + Sync>,
+        )
+    }
+}
+
+impl ReactiveNode for DependencyNode {
+    fn mark_dirty(&self) {
+        let _ = self.changed.try_send(());
+    }
+
+    // Memos only ask to be checked, recomputing is simpler than asking them whether they changed
+    fn mark_check(&self) {
+        let _ = self.changed.try_send(());
+    }
+
+    fn mark_subscribers_check(&self) {}
+
+    fn update_if_necessary(&self) -> bool {
+        true
+    }
+}
+
+impl Subscriber for DependencyNode {
+    fn add_source(&self, source: AnySource) {
+        let mut sources = self.sources.lock().unwrap();
+        if !sources.contains(&source) {
+            sources.push(source);
         }
-        drop(lock);
-        block_on(async move {
-            let _ = self.update_if_changed().await;
-        });
-        Some(val)
+    }
+
+    fn clear_sources(&self, subscriber: &AnySubscriber) {
+        let sources = std::mem::take(&mut *self.sources.lock().unwrap());
+        for source in sources {
+            source.remove_subscriber(subscriber);
+        }
+    }
+}
+
+/// The number of updates kept per signal for clients resuming after a reconnect. Clients which
+/// missed more receive the complete value.
+const RESUME_LOG: usize = 64;
+
+/// The channel broadcasting the updates of a signal, which numbers them in the order they are
+/// sent so clients can detect missing or reordered updates.
+#[derive(Debug)]
+pub(crate) struct Observers {
+    sender: Sender<ServerSignalUpdate>,
+    /// Distinguishes the numbering of this channel from the one of a signal of the same name
+    /// created earlier, e.g. before the server restarted.
+    epoch: u64,
+    seq: Mutex<u64>,
+    /// The last [`RESUME_LOG`] updates sent, guarded by `seq`.
+    log: Mutex<VecDeque<ServerSignalUpdate>>,
+    last_sent: Mutex<Option<std::time::Instant>>,
+}
+
+impl Observers {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = channel(capacity);
+        Self {
+            sender,
+            epoch: next_epoch(),
+            seq: Mutex::new(0),
+            log: Mutex::default(),
+            last_sent: Mutex::default(),
+        }
+    }
+
+    /// Sends `update` with the next sequence number and the current time, replacing the ones it
+    /// was received with from another node.
+    pub(crate) fn send(&self, update: ServerSignalUpdate) {
+        self.send_after(update, || {});
+    }
+
+    /// Like [`Observers::send`], applying the update with `apply` first while holding the
+    /// numbering, so a value read with [`Observers::snapshot`] includes both or neither.
+    pub(crate) fn send_after(&self, update: ServerSignalUpdate, apply: impl FnOnce()) {
+        // Held while sending, so updates are broadcast in the order of their numbers
+        let mut seq = self.seq.lock().unwrap();
+        apply();
+        *seq += 1;
+        let update = update.with_seq(*seq).stamped();
+        let mut log = self.log.lock().unwrap();
+        if log.len() == RESUME_LOG {
+            log.pop_front();
+        }
+        log.push_back(update.clone());
+        drop(log);
+        let _ = self.sender.send(update);
+        *self.last_sent.lock().unwrap() = Some(std::time::Instant::now());
+    }
+
+    /// The epoch and the number of the last update sent.
+    pub(crate) fn version(&self) -> (u64, u64) {
+        (self.epoch, *self.seq.lock().unwrap())
+    }
+
+    /// Reads a value with `read`, together with the version of the last update it includes.
+    pub(crate) fn snapshot<R>(&self, read: impl FnOnce() -> R) -> (R, (u64, u64)) {
+        let seq = self.seq.lock().unwrap();
+        (read(), (self.epoch, *seq))
+    }
+
+    /// The updates sent after `seq`, `None` if they aren't all kept anymore.
+    pub(crate) fn since(&self, epoch: u64, seq: u64) -> Option<Vec<ServerSignalUpdate>> {
+        let current = self.seq.lock().unwrap();
+        if epoch != self.epoch || seq > *current {
+            return None;
+        }
+        let log = self.log.lock().unwrap();
+        let kept_from = *current - log.len() as u64;
+        if seq < kept_from {
+            return None;
+        }
+        Some(
+            log.iter()
+                .skip((seq - kept_from) as usize)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    pub(crate) fn last_sent(&self) -> Option<std::time::Instant> {
+        *self.last_sent.lock().unwrap()
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<ServerSignalUpdate> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn receiver_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// A number which differs for every [`Observers`], also across restarts of the server.
+fn next_epoch() -> u64 {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let started = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    started.wrapping_add(NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+}
+
+/// A write to a signal, see [`WriteQueue`].
+enum Queued {
+    Value(Value),
+    /// The value as seen by part of the connections, see [`ServerSignal::update_where`].
+    Audience(Value, Audience),
+}
+
+/// The values written to a signal, waiting to be broadcast.
+struct WriteQueue {
+    receiver: mpsc::UnboundedReceiver<Queued>,
+    /// A write to an audience received while sampling, which is never skipped.
+    held: Option<Queued>,
+    /// Broadcast at most one value per interval, see [`ServerSignal::throttled`].
+    throttle: Option<Duration>,
+    next_broadcast: Instant,
+}
+
+impl WriteQueue {
+    fn new(receiver: mpsc::UnboundedReceiver<Queued>, throttle: Option<Duration>) -> Self {
+        Self {
+            receiver,
+            held: None,
+            throttle,
+            next_broadcast: Instant::now(),
+        }
+    }
+
+    async fn next(&mut self) -> Option<Queued> {
+        let mut write = match self.held.take() {
+            Some(held) => held,
+            None => self.receiver.recv().await?,
+        };
+        if let (Some(interval), Queued::Value(_)) = (self.throttle, &write) {
+            // Sample the latest value written until the interval is over
+            sleep_until(self.next_broadcast).await;
+            while let Ok(newer) = self.receiver.try_recv() {
+                if let Queued::Audience(..) = newer {
+                    self.held = Some(newer);
+                    break;
+                }
+                write = newer;
+            }
+            self.next_broadcast = Instant::now() + interval;
+        }
+        Some(write)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.held.is_none() && self.receiver.is_empty()
+    }
+}
+
+/// Enforces the [`PatchBudget`] of a signal in [`dispatch_updates`].
+struct PatchGuard {
+    budget: Arc<Mutex<PatchBudget>>,
+    strategy: Arc<Mutex<PatchStrategy>>,
+    /// Resets the value to the given last broadcast value after a rejected write.
+    revert: Box<dyn Fn(&Value) + Send + Sync>,
+}
+
+impl PatchGuard {
+    /// Returns whether the update may be broadcast, logging it if it exceeds the budget.
+    fn check(&self, update: &ServerSignalUpdate) -> bool {
+        let budget = *self.budget.lock().unwrap();
+        let Some(threshold) = budget.warn_bytes.into_iter().chain(budget.max_bytes).min() else {
+            return true;
+        };
+        let bytes = update.patch_len();
+        if bytes < threshold {
+            return true;
+        }
+        let paths = match &update.merge {
+            Some(Value::Object(members)) => members
+                .keys()
+                .map(|key| format!("/{key}"))
+                .collect::<Vec<_>>(),
+            Some(_) => vec![String::new()],
+            None => update
+                .patch
+                .0
+                .iter()
+                .map(|operation| operation.path().to_string())
+                .collect::<Vec<_>>(),
+        }
+        .join(", ");
+        if budget.max_bytes.is_some_and(|max| bytes >= max) {
+            leptos::logging::error!(
+                "Dropped update of {}: patch of {bytes} bytes exceeds the budget, touching {paths}",
+                update.name
+            );
+            return false;
+        }
+        leptos::logging::warn!(
+            "Large update of {}: patch of {bytes} bytes, touching {paths}",
+            update.name
+        );
+        true
+    }
+}
+
+/// Saves the latest value of a signal at most once per debounce interval.
+async fn persist_updates(
+    name: String,
+    persistence: Persistence,
+    mut updates: watch::Receiver<Value>,
+    errors: ErrorHooks,
+) {
+    while updates.changed().await.is_ok() {
+        tokio::time::sleep(persistence.debounce).await;
+        let value = updates.borrow_and_update().clone();
+        if let Err(err) = persistence.store.save(&name, &value).await {
+            errors.report(Some(&name), err);
+        }
+    }
+}
+
+impl<T> IsDisposed for ServerSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    /// Makes `set` go through [`Update`] like `update`, instead of writing the inner signal
+    /// without broadcasting.
+    fn is_disposed(&self) -> bool {
+        self.value.is_disposed()
     }
 }
 
@@ -168,7 +1463,7 @@ where
     type Value = ReadGuard<T, Plain<T>>;
 
     fn try_read_untracked(&self) -> Option<Self::Value> {
-        if self.check_is_hydrating() {
+        if self.initial_value != InitialValue::UseSsrSerializedValue && self.check_is_hydrating() {
             let guard: ReadGuard<T, Plain<T>> = ReadGuard::new(
                 Plain::try_new(Arc::new(std::sync::RwLock::new(self.initial.clone()))).unwrap(),
             );
@@ -186,8 +1481,8 @@ where
     type Value = T;
 
     fn try_get(&self) -> Option<Self::Value> {
-        #[cfg(feature = "ssr")]
-        if self.check_is_hydrating() {
+        #[cfg(feature = "server")]
+        if self.initial_value != InitialValue::UseSsrSerializedValue && self.check_is_hydrating() {
             return Some(self.initial.clone());
         }
         self.value.try_get()
@@ -344,3 +1639,51 @@ where
 //         &self.value
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observers_with(count: u64) -> Observers {
+        let observers = Observers::new(32);
+        for value in 1..=count {
+            observers.send(ServerSignalUpdate::replace("count", Value::from(value)));
+        }
+        observers
+    }
+
+    fn seqs(updates: Option<Vec<ServerSignalUpdate>>) -> Option<Vec<u64>> {
+        updates.map(|updates| updates.iter().filter_map(|update| update.seq()).collect())
+    }
+
+    #[test]
+    fn numbers_updates_in_order() {
+        let observers = observers_with(3);
+        let (epoch, seq) = observers.version();
+        assert_eq!(seq, 3);
+        assert_eq!(seqs(observers.since(epoch, 0)), Some(vec![1, 2, 3]));
+        assert_eq!(seqs(observers.since(epoch, 2)), Some(vec![3]));
+        assert_eq!(seqs(observers.since(epoch, 3)), Some(vec![]));
+    }
+
+    #[test]
+    fn rejects_other_epochs_and_future_updates() {
+        let observers = observers_with(3);
+        let (epoch, _) = observers.version();
+        assert_eq!(seqs(observers.since(epoch.wrapping_add(1), 1)), None);
+        assert_eq!(seqs(observers.since(epoch, 4)), None);
+        assert_ne!(Observers::new(32).version().0, epoch);
+    }
+
+    #[test]
+    fn forgets_updates_beyond_the_log() {
+        let count = RESUME_LOG as u64 + 10;
+        let observers = observers_with(count);
+        let (epoch, _) = observers.version();
+        assert_eq!(seqs(observers.since(epoch, 9)), None);
+        let kept = seqs(observers.since(epoch, 10)).unwrap();
+        assert_eq!(kept.len(), RESUME_LOG);
+        assert_eq!(kept.first(), Some(&11));
+        assert_eq!(seqs(observers.since(epoch, count - 1)), Some(vec![count]));
+    }
+}
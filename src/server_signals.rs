@@ -1,92 +1,1026 @@
-use crate::{error::Error, messages::ServerSignalUpdate, server_signal::ServerSignalTrait};
+use crate::{
+    backend::Backend,
+    config::{InitialValue, PatchBudget},
+    connection::{Connection, ConnectionId, Identities, IdentityMeter, IdentityStats},
+    error::{downcast, Error, ErrorEvent, ErrorHooks, PatchFailure},
+    latency::{LatencyHistogram, MAX_REPORTED},
+    messages::{ClientInputMessage, ServerSignalUpdate, SignalAnnouncement},
+    persistence::SignalStore,
+    routes::{Routed, Routes},
+    server_input_signal::ServerInputSignalTrait,
+    server_signal::ServerSignalTrait,
+    tasks::{TaskKind, TaskRegistry},
+};
+use futures::StreamExt;
 use leptos::prelude::*;
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{broadcast::Receiver, RwLock};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast::Receiver, mpsc::UnboundedSender, watch, RwLock};
+
+/// See [`ServerSignals::global`].
+static GLOBAL: OnceLock<ServerSignals> = OnceLock::new();
 
 #[derive(Clone)]
 pub struct ServerSignals {
-    signals: Arc<RwLock<HashMap<String, Arc<Box<dyn ServerSignalTrait + Send + Sync>>>>>,
+    /// Only locked for lookups and inserts, never across an await, so signals can be
+    /// registered from synchronous code without blocking the runtime.
+    signals: Arc<std::sync::RwLock<SignalMap>>,
+    inputs: Arc<std::sync::RwLock<InputMap>>,
+    /// Held by [`ServerSignals::import_snapshot`] while it sets the values, establishes wait
+    /// for it.
+    importing: Arc<RwLock<()>>,
+    /// Synchronous like `signals`, so connections can be looked up while computing a signal.
+    connections: ConnectionMap,
+    /// Which connections receive the updates of which signal.
+    routes: Routes,
+    backend: Option<Arc<dyn Backend>>,
+    store: Option<Persistence>,
+    identities: Identities,
+    /// The sources of every derived signal.
+    dependencies: Arc<std::sync::Mutex<HashMap<String, Vec<String>>>>,
+    patch_budget: PatchBudget,
+    initial_value: InitialValue,
+    /// The patterns passed to [`ServerSignals::disable`].
+    disabled: Arc<watch::Sender<Vec<String>>>,
+    acls: Arc<std::sync::RwLock<HashMap<String, Acl>>>,
+    on_patch_failed: Arc<std::sync::RwLock<Vec<PatchFailedCallback>>>,
+    on_error: ErrorHooks,
+    /// The signals passed to [`ServerSignals::announce`], in order.
+    announced: Arc<watch::Sender<Vec<SignalAnnouncement>>>,
+    /// The isolated registries of [`ServerSignals::tenant`].
+    tenants: Arc<std::sync::Mutex<HashMap<String, Tenant>>>,
+    tasks: TaskRegistry,
+    /// Owns what is created by [`ServerSignals::in_context`].
+    owner: Arc<OnceLock<Owner>>,
+    /// The latencies reported by clients, see [`ServerSignals::latency_histogram`].
+    latency: Arc<std::sync::Mutex<LatencyHistogram>>,
+}
+
+pub(crate) type ConnectionMap = Arc<std::sync::RwLock<HashMap<ConnectionId, Connection>>>;
+
+type SignalMap = HashMap<String, Arc<Box<dyn ServerSignalTrait + Send + Sync>>>;
+
+type InputMap = HashMap<String, Arc<Box<dyn ServerInputSignalTrait + Send + Sync>>>;
+
+type PatchFailedCallback = Arc<dyn Fn(&PatchFailure) + Send + Sync>;
+
+type Acl = Arc<dyn Fn(Option<&str>) -> bool + Send + Sync>;
+
+/// A registered signal, see [`ServerSignals::iter_signals`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignalInfo {
+    pub name: String,
+    /// The Rust type of the signal, e.g. `leptos_ws::server_signal::ServerSignal<i32>`.
+    pub type_name: &'static str,
+    /// Established connections plus other subscribers, see
+    /// [`ServerSignalTrait::observer_count`].
+    pub subscribers: usize,
+    /// When the last update was broadcast, `None` if it still has its initial value.
+    pub last_update: Option<Instant>,
+    /// The size of the current value serialized as JSON.
+    pub json_bytes: usize,
+}
+
+/// Totals of a [`ServerSignals`] registry, see [`ServerSignals::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SignalStats {
+    pub signals: usize,
+    pub inputs: usize,
+    pub connections: usize,
+    pub subscribers: usize,
+    pub json_bytes: usize,
+}
+
+/// The registry of a tenant and how many connections it serves, see
+/// [`ServerSignals::lease_tenant`].
+struct Tenant {
+    signals: ServerSignals,
+    #[cfg(feature = "axum")]
+    connections: usize,
+}
+
+/// Keeps the registry of a tenant alive while a connection of the tenant is open. The registry
+/// is evicted when the last lease of it is dropped.
+#[cfg(feature = "axum")]
+pub(crate) struct TenantLease {
+    tenants: Arc<std::sync::Mutex<HashMap<String, Tenant>>>,
+    key: String,
+    pub signals: ServerSignals,
+}
+
+#[cfg(feature = "axum")]
+impl Drop for TenantLease {
+    fn drop(&mut self) {
+        let evicted = match self.tenants.lock().unwrap().entry(self.key.clone()) {
+            // Replaced after ServerSignals::remove_tenant, the new one isn't leased by this
+            Entry::Occupied(tenant)
+                if !Arc::ptr_eq(&tenant.get().signals.signals, &self.signals.signals) =>
+            {
+                false
+            }
+            Entry::Occupied(mut tenant) => {
+                tenant.get_mut().connections -= 1;
+                if tenant.get().connections == 0 {
+                    tenant.remove();
+                    true
+                } else {
+                    false
+                }
+            }
+            Entry::Vacant(_) => false,
+        };
+        if evicted {
+            self.signals.dispose();
+        }
+    }
+}
+
+/// An installed [`SignalStore`] and the values it held when it was installed.
+#[derive(Clone)]
+pub(crate) struct Persistence {
+    pub store: Arc<dyn SignalStore>,
+    pub debounce: Duration,
+    restored: Arc<HashMap<String, Value>>,
 }
 
 impl ServerSignals {
     pub fn new() -> Self {
-        let signals = Arc::new(RwLock::new(HashMap::new()));
-        let me = Self { signals };
+        let connections = ConnectionMap::default();
+        let me = Self {
+            signals: Arc::default(),
+            inputs: Arc::default(),
+            importing: Arc::default(),
+            connections,
+            routes: Routes::default(),
+            backend: None,
+            store: None,
+            identities: Identities::default(),
+            dependencies: Arc::default(),
+            patch_budget: PatchBudget::default(),
+            initial_value: InitialValue::default(),
+            disabled: Arc::new(watch::channel(Vec::new()).0),
+            acls: Arc::default(),
+            on_patch_failed: Arc::default(),
+            on_error: ErrorHooks::default(),
+            announced: Arc::new(watch::channel(Vec::new()).0),
+            tenants: Arc::default(),
+            tasks: TaskRegistry::default(),
+            owner: Arc::default(),
+            latency: Arc::default(),
+        };
         me
     }
 
-    pub async fn create_signal<T: Clone + Send + Sync + 'static>(
-        &mut self,
-        name: String,
-        value: T,
-    ) -> Result<(), Error>
+    /// The registry of the process, for signals created outside of any request, e.g. by a
+    /// background job started in `main`. Created on first use, unless another registry was
+    /// made global with [`ServerSignals::set_global`] before.
+    ///
+    /// Signals created where no registry was provided as context use this one once it exists,
+    /// so provide the same registry to the app:
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use leptos::prelude::*;
+    /// # use leptos_ws::{server_signals::ServerSignals, ServerSignal};
+    /// # struct AppState {
+    /// #     server_signals: ServerSignals,
+    /// # }
+    /// let server_signals = ServerSignals::global();
+    /// tokio::spawn(async {
+    ///     let jobs = ServerSignals::global()
+    ///         .in_context(|| ServerSignal::new("jobs".to_string(), 0).unwrap());
+    ///     loop {
+    ///         jobs.update(|jobs| *jobs += 1);
+    ///         tokio::time::sleep(Duration::from_secs(1)).await;
+    ///     }
+    /// });
+    /// let state = AppState { server_signals };
+    /// ```
+    pub fn global() -> ServerSignals {
+        GLOBAL.get_or_init(ServerSignals::new).clone()
+    }
+
+    /// Makes this the registry returned by [`ServerSignals::global`], e.g. after configuring its
+    /// backend. Returns `false` if another registry is global already.
+    pub fn set_global(&self) -> bool {
+        GLOBAL.set(self.clone()).is_ok()
+    }
+
+    /// The registry provided as context, or the global one if there is any.
+    pub(crate) fn current() -> Option<ServerSignals> {
+        use_context::<ServerSignals>().or_else(|| GLOBAL.get().cloned())
+    }
+
+    /// Runs `f` with this registry provided as context, e.g. to create signals in a background
+    /// task. What `f` creates is owned by the registry, so derived signals keep recomputing
+    /// after `f` returned.
+    pub fn in_context<R>(&self, f: impl FnOnce() -> R) -> R {
+        let owner = self.owner.get_or_init(|| {
+            // Not a child of the current owner, which would dispose it along with itself
+            let current = Owner::current();
+            if let Some(current) = current.clone() {
+                current.unset();
+            }
+            let owner = Owner::new();
+            if let Some(current) = current {
+                current.set();
+            }
+            owner.with(|| provide_context(self.clone()));
+            owner
+        });
+        owner.with(f)
+    }
+
+    /// The registry of the tenant `key`, created on first use. Its signals, inputs and
+    /// connections are isolated from those of this registry and of every other tenant, so a
+    /// signal called `count` of one tenant is never sent to another one.
+    ///
+    /// Connections are routed to the registry of their tenant with
+    /// [`ConnectionConfig::tenant`](crate::config::ConnectionConfig), the SSR side has to provide
+    /// the same registry as context while rendering a request of the tenant:
+    ///
+    /// ```rust,no_run
+    /// # use leptos::prelude::*;
+    /// # let server_signals = leptos_ws::server_signals::ServerSignals::new();
+    /// # let parts = ();
+    /// # fn tenant_from_request(_parts: &()) -> String { String::new() }
+    /// let tenant_signals = server_signals.tenant(&tenant_from_request(&parts));
+    /// // The additional context, e.g. of `leptos_axum::render_route_with_context`
+    /// let additional_context = move || provide_context(tenant_signals.clone());
+    /// ```
+    ///
+    /// Tenant registries use the patch budget and initial value policy of this registry, but
+    /// neither its backend nor its store, since their signal names are only unique within the
+    /// tenant. Their tasks and the latencies their clients report are part of
+    /// [`ServerSignals::tasks`] and [`ServerSignals::latency_histogram`] of this registry, and
+    /// their errors are reported to its [`ServerSignals::on_error`] hooks.
+    ///
+    /// The registry of a tenant is evicted when the last connection of the tenant closes, so the
+    /// next render or connection of the tenant starts with an empty one.
+    pub fn tenant(&self, key: &str) -> ServerSignals {
+        self.tenants
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| self.new_tenant())
+            .signals
+            .clone()
+    }
+
+    /// Like [`ServerSignals::tenant`], for a connection of the tenant. Once the last connection
+    /// closed, i.e. the last lease is dropped, the registry of the tenant is evicted like with
+    /// [`ServerSignals::remove_tenant`].
+    #[cfg(feature = "axum")]
+    pub(crate) fn lease_tenant(&self, key: &str) -> TenantLease {
+        let mut tenants = self.tenants.lock().unwrap();
+        let tenant = tenants
+            .entry(key.to_string())
+            .or_insert_with(|| self.new_tenant());
+        tenant.connections += 1;
+        TenantLease {
+            tenants: self.tenants.clone(),
+            key: key.to_string(),
+            signals: tenant.signals.clone(),
+        }
+    }
+
+    fn new_tenant(&self) -> Tenant {
+        Tenant {
+            signals: Self {
+                tasks: self.tasks.clone(),
+                latency: self.latency.clone(),
+                on_error: self.on_error.clone(),
+                ..Self::new()
+                    .with_patch_budget(self.patch_budget)
+                    .with_initial_value(self.initial_value)
+            },
+            #[cfg(feature = "axum")]
+            connections: 0,
+        }
+    }
+
+    /// Forgets the registry of the tenant `key`, e.g. after the tenant was deleted. Signals of
+    /// the tenant which are still alive keep working, but new connections and renders of the
+    /// tenant start with an empty registry. The removed registry can't be used with
+    /// [`ServerSignals::in_context`] anymore.
+    pub fn remove_tenant(&self, key: &str) {
+        let removed = self.tenants.lock().unwrap().remove(key);
+        if let Some(tenant) = removed {
+            tenant.signals.dispose();
+        }
+    }
+
+    /// Takes back what [`ServerSignals::in_context`] provided, which refers to this registry and
+    /// would otherwise keep it alive forever.
+    fn dispose(&self) {
+        if let Some(owner) = self.owner.get() {
+            owner.with(take_context::<ServerSignals>);
+        }
+    }
+
+    /// The keys of all tenants with a registry.
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Creates a registry whose signals are kept in sync with other nodes through `backend`.
+    ///
+    /// Updates from other nodes are applied to the local signal of the same name, so every
+    /// node has to create its signals with the same initial value.
+    pub async fn with_backend(backend: impl Backend + 'static) -> Result<Self, Error> {
+        let mut me = Self::new();
+        let mut updates = backend.subscribe().await?;
+        me.backend = Some(Arc::new(backend));
+        let signals = me.clone();
+        me.tasks.spawn(TaskKind::Backend, None, async move {
+            while let Some(update) = updates.next().await {
+                let name = update.name.to_string();
+                if let Some(Err(err)) = signals.update(name.clone(), update).await {
+                    signals.report(Some(&name), err);
+                }
+            }
+        });
+        Ok(me)
+    }
+
+    /// Every task spawned for the signals and connections of this registry, see
+    /// [`tasks`](crate::tasks).
+    pub fn tasks(&self) -> &TaskRegistry {
+        &self.tasks
+    }
+
+    pub(crate) fn backend(&self) -> Option<Arc<dyn Backend>> {
+        self.backend.clone()
+    }
+
+    /// Persists all signals created from now on to `store`, writing at most once per `debounce`
+    /// per signal.
+    ///
+    /// The stored values are loaded right away, call this before any signal is created.
+    pub async fn with_store(
+        mut self,
+        store: impl SignalStore + 'static,
+        debounce: Duration,
+    ) -> Result<Self, Error> {
+        let restored = store.load_all().await?.into_iter().collect();
+        self.store = Some(Persistence {
+            store: Arc::new(store),
+            debounce,
+            restored: Arc::new(restored),
+        });
+        Ok(self)
+    }
+
+    /// The [`PatchBudget`] of all signals created from now on, unless overridden with
+    /// [`ServerSignal::patch_budget`](crate::ServerSignal).
+    pub fn with_patch_budget(mut self, budget: PatchBudget) -> Self {
+        self.patch_budget = budget;
+        self
+    }
+
+    pub(crate) fn patch_budget(&self) -> PatchBudget {
+        self.patch_budget
+    }
+
+    /// What pages render signals created from now on with, unless overridden with
+    /// [`ServerSignal::new_with_initial`](crate::ServerSignal). With the default
+    /// [`InitialValue::UseSsrSerializedValue`], SSR reflects writes made earlier in the same
+    /// request and the rendered value is embedded for hydration. The client has to use the same
+    /// policy, see [`WsConfig::initial_value`](crate::config::WsConfig).
+    pub fn with_initial_value(mut self, initial_value: InitialValue) -> Self {
+        self.initial_value = initial_value;
+        self
+    }
+
+    pub fn initial_value(&self) -> InitialValue {
+        self.initial_value
+    }
+
+    pub(crate) fn persistence(&self) -> Option<Persistence> {
+        self.store.clone()
+    }
+
+    /// The stored value of a signal, if a store is installed and held one.
+    pub(crate) fn restored(&self, name: &str) -> Option<Value> {
+        self.store.as_ref()?.restored.get(name).cloned()
+    }
+
+    pub async fn create_signal<T>(&mut self, name: String, value: T) -> Result<(), Error>
     where
-        T: ServerSignalTrait,
+        T: ServerSignalTrait + Clone + Send + Sync + 'static,
     {
-        if self
-            .signals
-            .write()
-            .await
-            .insert(name, Arc::new(Box::new(value)))
-            .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
-            .is_none()
-        {
-            Ok(())
-        } else {
-            Err(Error::AddingSignalFailed)
+        let mut signals = self.signals.write().unwrap();
+        if let Some(existing) = signals.get(&name) {
+            downcast::<T>(&name, existing.as_any(), existing.type_name())?;
+            return Err(Error::AddingSignalFailed);
         }
+        signals.insert(name, Arc::new(Box::new(value)));
+        Ok(())
     }
-    pub async fn get_signal<T: Clone + 'static>(&mut self, name: String) -> Option<T> {
+
+    /// Registers `value` as the signal `name` and returns it, or returns the signal already
+    /// registered under that name.
+    ///
+    /// Unlike [`ServerSignals::get_signal`] followed by [`ServerSignals::create_signal`], two
+    /// tasks creating the same signal at once both get the one registered first instead of an
+    /// error. Fails with [`Error::TypeMismatch`] if the registered signal isn't a `T`.
+    pub async fn get_or_create_signal<T>(&self, name: String, value: T) -> Result<T, Error>
+    where
+        T: ServerSignalTrait + Clone + Send + Sync + 'static,
+    {
+        self.register_signal(name, value).map(|(signal, _)| signal)
+    }
+
+    /// Like [`ServerSignals::get_or_create_signal`], without waiting, for the constructors of
+    /// signals. The returned flag tells whether `signal` was inserted. The registry is only held
+    /// while checking and inserting, so building `signal` and starting its tasks is up to the
+    /// caller.
+    pub(crate) fn register_signal<T>(&self, name: String, signal: T) -> Result<(T, bool), Error>
+    where
+        T: ServerSignalTrait + Clone + Send + Sync + 'static,
+    {
+        match self.signals.write().unwrap().entry(name) {
+            Entry::Occupied(existing) => downcast(
+                existing.key(),
+                existing.get().as_any(),
+                existing.get().type_name(),
+            )
+            .map(|signal| (signal, false)),
+            Entry::Vacant(slot) => {
+                slot.insert(Arc::new(Box::new(signal.clone())));
+                Ok((signal, true))
+            }
+        }
+    }
+
+    /// Returns the signal `name`, or [`Error::TypeMismatch`] if it isn't a `T`.
+    pub async fn get_signal<T: Clone + 'static>(
+        &mut self,
+        name: String,
+    ) -> Option<Result<T, Error>> {
+        self.lookup_signal(&name)
+    }
+
+    /// Like [`ServerSignals::get_signal`], without waiting.
+    pub(crate) fn lookup_signal<T: Clone + 'static>(&self, name: &str) -> Option<Result<T, Error>> {
         self.signals
-            .write()
-            .await
-            .get_mut(&name)
-            .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|value| downcast(name, value.as_any(), value.type_name()))
     }
+
+    /// The signal `name` of any kind.
+    pub(crate) fn signal(
+        &self,
+        name: &str,
+    ) -> Option<Arc<Box<dyn ServerSignalTrait + Send + Sync>>> {
+        self.signals.read().unwrap().get(name).cloned()
+    }
+
     pub async fn add_observer(&self, name: String) -> Option<Receiver<ServerSignalUpdate>> {
-        match self
+        Some(self.signal(&name)?.add_observer().await)
+    }
+
+    /// The number of receivers of updates of a signal, see [`ServerSignalTrait::observer_count`].
+    ///
+    /// Every connection which established the signal holds one until it unsubscribes or closes,
+    /// so this goes back down when clients leave.
+    pub async fn active_observer_count(&self, name: &str) -> Option<usize> {
+        let count = self.signal(name)?.observer_count();
+        Some(self.routed_observer_count(name, count))
+    }
+
+    /// Counts the connections a signal is routed to instead of the one receiver of the route.
+    fn routed_observer_count(&self, name: &str, count: usize) -> usize {
+        match self.routes.subscribers(name) {
+            Some(subscribers) => count.saturating_sub(1) + subscribers,
+            None => count,
+        }
+    }
+
+    /// Sends every update of the signal `name` to `sender` from now on, until
+    /// [`ServerSignals::unsubscribe`]. Returns `false` if there is no such signal.
+    pub(crate) async fn subscribe(
+        &self,
+        name: &str,
+        connection: ConnectionId,
+        sender: &UnboundedSender<Routed>,
+    ) -> bool {
+        if self.routes.join(name, connection, sender) {
+            return true;
+        }
+        match self.add_observer(name.to_string()).await {
+            Some(receiver) => {
+                self.routes
+                    .start(name, connection, sender, receiver, &self.tasks);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn unsubscribe(&self, name: &str, connection: ConnectionId) {
+        self.routes.leave(name, connection);
+    }
+
+    /// Describes every registered signal, e.g. for an admin page.
+    ///
+    /// Serializes the current value of every signal to measure it, so this is not meant to be
+    /// called on every request.
+    pub async fn iter_signals(&self) -> Vec<SignalInfo> {
+        let signals: Vec<_> = self
             .signals
             .read()
-            .await
-            .get(&name)
-            .map(|value| value.add_observer())
-        {
-            Some(fut) => Some(fut.await),
-            None => None,
+            .unwrap()
+            .iter()
+            .map(|(name, signal)| (name.clone(), signal.clone()))
+            .collect();
+        signals
+            .into_iter()
+            .map(|(name, signal)| SignalInfo {
+                type_name: signal.type_name(),
+                subscribers: self.routed_observer_count(&name, signal.observer_count()),
+                last_update: signal.last_update(),
+                json_bytes: signal
+                    .json()
+                    .ok()
+                    .and_then(|json| serde_json::to_vec(&json).ok())
+                    .map_or(0, |json| json.len()),
+                name,
+            })
+            .collect()
+    }
+
+    /// Totals over all signals and connections, see [`ServerSignals::iter_signals`].
+    pub async fn stats(&self) -> SignalStats {
+        let signals = self.iter_signals().await;
+        let inputs = self.inputs.read().unwrap().len();
+        SignalStats {
+            signals: signals.len(),
+            inputs,
+            connections: self.connections.read().unwrap().len(),
+            subscribers: signals.iter().map(|signal| signal.subscribers).sum(),
+            json_bytes: signals.iter().map(|signal| signal.json_bytes).sum(),
         }
     }
 
+    /// Establishes the signal for a client, see [`ServerSignalTrait::establish`].
+    pub async fn establish(
+        &self,
+        name: &str,
+        payload: Value,
+    ) -> Option<Result<(Value, Receiver<ServerSignalUpdate>), Error>> {
+        let _importing = self.importing.read().await;
+        let signal = self.signal(name)?;
+        Some(signal.establish(payload).await)
+    }
+
+    /// The value of the signal `name` for a client and the version it includes, see
+    /// [`ServerSignalTrait::snapshot`].
+    pub(crate) async fn snapshot(
+        &self,
+        name: &str,
+        payload: Value,
+    ) -> Option<Result<(Value, Option<(u64, u64)>), Error>> {
+        let _importing = self.importing.read().await;
+        let signal = self.signal(name)?;
+        Some(signal.snapshot(payload).await)
+    }
+
+    /// The value type of the signal `name`, see [`ServerSignalTrait::schema`].
+    pub(crate) async fn schema(&self, name: &str) -> Option<&'static str> {
+        self.signal(name)?.schema()
+    }
+
+    /// See [`ServerSignalTrait::updates_since`].
+    pub(crate) async fn updates_since(
+        &self,
+        name: &str,
+        epoch: u64,
+        seq: u64,
+    ) -> Option<Vec<ServerSignalUpdate>> {
+        self.signal(name)?.updates_since(epoch, seq)
+    }
+
+    /// Records that `name` is recomputed from `sources`, failing if that would create a cycle.
+    pub(crate) fn add_dependencies(&self, name: &str, sources: &[String]) -> Result<(), Error> {
+        let mut dependencies = self.dependencies.lock().unwrap();
+        for source in sources {
+            if let Some(mut path) = dependency_path(&dependencies, source, name) {
+                path.push(source.clone());
+                path.reverse();
+                path.insert(0, name.to_string());
+                return Err(Error::DependencyCycle(path.join(" -> ")));
+            }
+        }
+        dependencies.insert(name.to_string(), sources.to_vec());
+        Ok(())
+    }
+
     pub async fn json(&self, name: String) -> Option<Result<Value, Error>> {
-        match self
-            .signals
-            .read()
-            .await
-            .get(&name)
-            .map(|value| value.json())
-        {
-            Some(res) => Some(res),
-            None => None,
+        Some(self.signal(&name)?.json())
+    }
+    /// The values of all signals as one JSON object by name, e.g. to hand the state over to
+    /// another process during a blue/green deploy or to record a test fixture.
+    ///
+    /// All values are read while holding the registry, so no signal is created meanwhile.
+    pub async fn export_snapshot(&self) -> Result<Value, Error> {
+        let signals = self.signals.read().unwrap();
+        let mut snapshot = serde_json::Map::new();
+        for (name, signal) in signals.iter() {
+            snapshot.insert(name.clone(), signal.json()?);
         }
+        Ok(Value::Object(snapshot))
     }
+
+    /// Sets the signals to the values of a snapshot taken with
+    /// [`ServerSignals::export_snapshot`] and broadcasts the changes to their clients.
+    ///
+    /// Only signals which already exist are set, so create them before importing. Values in the
+    /// snapshot for other names are ignored, as are the ones of
+    /// [projected](crate::ServerSignal::new_projected) signals, whose snapshot only holds the
+    /// projection. Clients don't see a half imported state, since establishes wait until all
+    /// values are set.
+    pub async fn import_snapshot(&self, snapshot: Value) -> Result<(), Error> {
+        let snapshot: HashMap<String, Value> = serde_json::from_value(snapshot)?;
+        let _importing = self.importing.write().await;
+        for (name, value) in snapshot {
+            if let Some(signal) = self.signal(&name) {
+                signal.import_json(name, value).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a patch, e.g. from another node. If it doesn't apply, the `on_patch_failed`
+    /// callbacks are called.
     pub async fn update(
         &self,
         name: String,
         patch: ServerSignalUpdate,
     ) -> Option<Result<(), Error>> {
-        match self
-            .signals
+        let result = self.signal(&name)?.update_json(patch).await;
+        if let Err(Error::PatchFailed(failure)) = &result {
+            let callbacks = self.on_patch_failed.read().unwrap().clone();
+            for callback in callbacks {
+                callback(failure);
+            }
+        }
+        Some(result)
+    }
+
+    /// Registers a callback which is called when a patch could not be applied to a signal, e.g.
+    /// because another node diverged.
+    ///
+    /// Rejected updates of client inputs don't call it, the client is told with an
+    /// [`InputRejection`](crate::messages::InputRejection) instead.
+    pub fn on_patch_failed(&self, callback: impl Fn(&PatchFailure) + Send + Sync + 'static) {
+        self.on_patch_failed
             .write()
-            .await
-            .get_mut(&name)
-            .map(|value| value.update_json(patch))
-        {
-            Some(fut) => Some(fut.await),
+            .unwrap()
+            .push(Arc::new(callback));
+    }
+
+    /// Registers a callback which is called for every error the socket loops and the background
+    /// tasks of the signals run into, e.g. undecodable messages, establishes of unknown signals,
+    /// failed backend publishes or patches from other nodes which don't apply. Errors are still
+    /// logged as well.
+    ///
+    /// ```rust,no_run
+    /// # mod sentry {
+    /// #     pub enum Level { Error }
+    /// #     pub fn capture_message(_message: &str, _level: Level) {}
+    /// # }
+    /// # let signals = leptos_ws::server_signals::ServerSignals::new();
+    /// signals.on_error(|event| sentry::capture_message(&event.to_string(), sentry::Level::Error));
+    /// ```
+    pub fn on_error(&self, callback: impl Fn(&ErrorEvent) + Send + Sync + 'static) {
+        self.on_error.add(callback);
+    }
+
+    pub(crate) fn error_hooks(&self) -> ErrorHooks {
+        self.on_error.clone()
+    }
+
+    pub(crate) fn report(&self, signal: Option<&str>, error: Error) {
+        self.on_error.report(signal, error);
+    }
+
+    pub async fn contains(&self, name: &str) -> bool {
+        self.signals.read().unwrap().contains_key(name)
+    }
+
+    pub async fn create_input<T>(&mut self, name: String, value: T) -> Result<(), Error>
+    where
+        T: ServerInputSignalTrait + Clone + Send + Sync + 'static,
+    {
+        let mut inputs = self.inputs.write().unwrap();
+        if let Some(existing) = inputs.get(&name) {
+            downcast::<T>(&name, existing.as_any(), existing.type_name())?;
+            return Err(Error::AddingSignalFailed);
+        }
+        inputs.insert(name, Arc::new(Box::new(value)));
+        Ok(())
+    }
+
+    /// Like [`ServerSignals::get_or_create_signal`], for client input signals.
+    pub async fn get_or_create_input<T>(&self, name: String, value: T) -> Result<T, Error>
+    where
+        T: ServerInputSignalTrait + Clone + Send + Sync + 'static,
+    {
+        self.register_input(name, value).map(|(input, _)| input)
+    }
+
+    /// Like [`ServerSignals::get_or_create_input`], without waiting, for the constructors of
+    /// client input signals. The returned flag tells whether `value` was registered.
+    pub(crate) fn register_input<T>(&self, name: String, value: T) -> Result<(T, bool), Error>
+    where
+        T: ServerInputSignalTrait + Clone + Send + Sync + 'static,
+    {
+        match self.inputs.write().unwrap().entry(name) {
+            Entry::Occupied(existing) => downcast(
+                existing.key(),
+                existing.get().as_any(),
+                existing.get().type_name(),
+            )
+            .map(|input| (input, false)),
+            Entry::Vacant(slot) => {
+                slot.insert(Arc::new(Box::new(value.clone())));
+                Ok((value, true))
+            }
+        }
+    }
+
+    /// Returns the client input signal `name`, or [`Error::TypeMismatch`] if it isn't a `T`.
+    pub async fn get_input<T: Clone + 'static>(
+        &mut self,
+        name: String,
+    ) -> Option<Result<T, Error>> {
+        self.lookup_input(&name)
+    }
+
+    /// Like [`ServerSignals::get_input`], without waiting.
+    pub(crate) fn lookup_input<T: Clone + 'static>(&self, name: &str) -> Option<Result<T, Error>> {
+        self.inputs
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|value| downcast(name, value.as_any(), value.type_name()))
+    }
+
+    pub async fn contains_input(&self, name: &str) -> bool {
+        self.has_input(name)
+    }
+
+    /// Like [`ServerSignals::contains_input`], without waiting.
+    pub(crate) fn has_input(&self, name: &str) -> bool {
+        self.inputs.read().unwrap().contains_key(name)
+    }
+
+    pub async fn establish_input(
+        &self,
+        name: String,
+        connection: ConnectionId,
+        value: Value,
+    ) -> Option<Result<Option<ClientInputMessage>, Error>> {
+        let input = self.inputs.read().unwrap().get(&name).cloned();
+        match input {
+            Some(input) => Some(input.establish(connection, value).await),
             None => None,
         }
     }
 
-    pub async fn contains(&self, name: &str) -> bool {
-        self.signals.read().await.contains_key(name)
+    pub async fn update_input(
+        &self,
+        name: String,
+        connection: ConnectionId,
+        seq: u64,
+        patch: ServerSignalUpdate,
+    ) -> Option<ClientInputMessage> {
+        let input = self.inputs.read().unwrap().get(&name).cloned();
+        match input {
+            Some(input) => input.update_json(connection, seq, patch).await,
+            None => None,
+        }
+    }
+
+    /// Returns all currently open connections.
+    pub async fn connections(&self) -> Vec<Connection> {
+        self.connections.read().unwrap().values().cloned().collect()
+    }
+
+    pub async fn connection(&self, id: ConnectionId) -> Option<Connection> {
+        self.connections.read().unwrap().get(&id).cloned()
+    }
+
+    /// The open connections, to look them up from a signal of this registry without holding
+    /// the registry itself.
+    pub(crate) fn connection_map(&self) -> ConnectionMap {
+        self.connections.clone()
+    }
+
+    /// The message rates of every identity with an open connection, see
+    /// `ConnectionConfig::identify`.
+    pub fn identity_stats(&self) -> HashMap<String, IdentityStats> {
+        self.identities.stats()
+    }
+
+    /// The latencies of updates measured by all clients which enabled
+    /// [`WsConfig::measure_latency`](crate::config::WsConfig), see [`latency`](crate::latency).
+    pub fn latency_histogram(&self) -> LatencyHistogram {
+        self.latency.lock().unwrap().clone()
+    }
+
+    /// Adds the latencies a client reported with a heartbeat, dropping implausibly large reports.
+    pub(crate) fn record_latency(&self, histogram: &LatencyHistogram) {
+        if histogram.count() > MAX_REPORTED {
+            return;
+        }
+        self.latency.lock().unwrap().merge(histogram);
+    }
+
+    /// Registers a callback which is called whenever an identity exceeds
+    /// [`RateLimitConfig::messages_per_second_per_identity`](crate::config::RateLimitConfig), e.g.
+    /// to ban it.
+    pub fn on_quota_exceeded(
+        &self,
+        callback: impl Fn(&str, &IdentityStats) + Send + Sync + 'static,
+    ) {
+        self.identities.on_quota_exceeded(Arc::new(callback));
+    }
+
+    /// Restricts who may establish the signal `name`: `acl` is called with the identity of the
+    /// connection, see `ConnectionConfig::identify`, and the establish is denied unless it returns
+    /// `true`. Replaces any previous ACL of the signal.
+    ///
+    /// Connections without an identity are passed `None`. Only establishes are checked, so call
+    /// this before the signal is published, e.g. through
+    /// [`ServerSignal::new_with_acl`](crate::ServerSignal).
+    pub fn set_acl(
+        &self,
+        name: impl Into<String>,
+        acl: impl Fn(Option<&str>) -> bool + Send + Sync + 'static,
+    ) {
+        self.acls
+            .write()
+            .unwrap()
+            .insert(name.into(), Arc::new(acl));
+    }
+
+    /// Whether a connection with `identity` may establish the signal `name`, see
+    /// [`ServerSignals::set_acl`]. Signals without an ACL are public.
+    pub fn is_allowed(&self, name: &str, identity: Option<&str>) -> bool {
+        let acl = self.acls.read().unwrap().get(name).cloned();
+        acl.is_none_or(|acl| acl(identity))
+    }
+
+    /// Stops broadcasting updates of every signal matching `pattern`, either a signal name or a
+    /// prefix followed by `*`, e.g. `"metrics.*"`.
+    ///
+    /// The signals keep their value on the server, only clients stop receiving it: they see
+    /// [`SyncStatus::Paused`](crate::hooks::SyncStatus) until [`ServerSignals::enable`] is called
+    /// with the same pattern, which sends them the current value again.
+    ///
+    /// ```rust,no_run
+    /// # let signals = leptos_ws::server_signals::ServerSignals::new();
+    /// // Shed load during an incident
+    /// signals.disable("metrics.*");
+    /// // ...
+    /// signals.enable("metrics.*");
+    /// ```
+    pub fn disable(&self, pattern: impl Into<String>) {
+        let pattern = pattern.into();
+        self.disabled.send_if_modified(|patterns| {
+            let added = !patterns.contains(&pattern);
+            if added {
+                patterns.push(pattern);
+            }
+            added
+        });
+    }
+
+    /// Resumes broadcasting for a pattern passed to [`ServerSignals::disable`].
+    ///
+    /// Signals which still match another disabled pattern stay paused.
+    pub fn enable(&self, pattern: &str) {
+        self.disabled.send_if_modified(|patterns| {
+            let len = patterns.len();
+            patterns.retain(|disabled| disabled != pattern);
+            patterns.len() != len
+        });
+    }
+
+    /// Whether updates of the signal `name` are currently not broadcast, see
+    /// [`ServerSignals::disable`].
+    pub fn is_disabled(&self, name: &str) -> bool {
+        is_disabled(&self.disabled.borrow(), name)
+    }
+
+    /// Tells all current and future clients about the signal `name`, so they can establish it
+    /// without knowing its name up front, see
+    /// [`use_announced_signals`](crate::use_announced_signals). `type_hint` is up to the app,
+    /// e.g. which component renders the signal.
+    ///
+    /// ```rust,no_run
+    /// # use leptos_ws::ServerSignal;
+    /// # #[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+    /// # struct Chart { points: Vec<f64> }
+    /// # fn main() -> Result<(), leptos_ws::error::Error> {
+    /// # let signals = leptos_ws::server_signals::ServerSignals::new();
+    /// # let id = 1;
+    /// let name = format!("chart:{id}");
+    /// ServerSignal::new(name.clone(), Chart::default())?;
+    /// signals.announce(name, "chart");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Announcing a name again does nothing.
+    pub fn announce(&self, name: impl Into<String>, type_hint: impl Into<String>) {
+        let announcement = SignalAnnouncement {
+            name: name.into(),
+            type_hint: type_hint.into(),
+        };
+        self.announced.send_if_modified(|announced| {
+            let added = !announced
+                .iter()
+                .any(|existing| existing.name == announcement.name);
+            if added {
+                announced.push(announcement);
+            }
+            added
+        });
+    }
+
+    /// The signals passed to [`ServerSignals::announce`].
+    pub fn announcements(&self) -> Vec<SignalAnnouncement> {
+        self.announced.borrow().clone()
+    }
+
+    /// Notifies all connections when a signal is announced.
+    pub(crate) fn watch_announcements(&self) -> watch::Receiver<Vec<SignalAnnouncement>> {
+        self.announced.subscribe()
+    }
+
+    /// Notifies the relays of all connections when signals are disabled or enabled.
+    pub(crate) fn watch_disabled(&self) -> watch::Receiver<Vec<String>> {
+        self.disabled.subscribe()
+    }
+
+    pub(crate) fn meter(&self, identity: String) -> IdentityMeter {
+        self.identities.meter(identity)
+    }
+
+    pub(crate) async fn add_connection(&self, connection: Connection) {
+        self.connections
+            .write()
+            .unwrap()
+            .insert(connection.id(), connection);
+    }
+
+    pub(crate) async fn remove_connection(&self, id: ConnectionId) {
+        self.connections.write().unwrap().remove(&id);
+        self.routes.leave_all(id);
+        let inputs = self
+            .inputs
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for input in inputs {
+            input.remove_connection(id).await;
+        }
+    }
+}
+
+/// Whether `name` matches one of the patterns passed to [`ServerSignals::disable`].
+pub(crate) fn is_disabled(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => pattern == name,
+        })
+}
+
+/// The chain of dependencies leading from `from` to `to`, in reverse and without `from`.
+fn dependency_path(
+    dependencies: &HashMap<String, Vec<String>>,
+    from: &str,
+    to: &str,
+) -> Option<Vec<String>> {
+    if from == to {
+        return Some(Vec::new());
     }
+    dependencies.get(from)?.iter().find_map(|source| {
+        let mut path = dependency_path(dependencies, source, to)?;
+        path.push(source.clone());
+        Some(path)
+    })
 }
@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use crate::messages::ServerSignalUpdate;
+
+/// The open streams of a client signal, see `ServerSignal::to_stream`.
+///
+/// Senders whose stream was dropped are removed on the next send.
+pub(crate) struct Streams<T> {
+    values: Mutex<Vec<UnboundedSender<T>>>,
+    patches: Mutex<Vec<UnboundedSender<ServerSignalUpdate>>>,
+}
+
+impl<T> Default for Streams<T> {
+    fn default() -> Self {
+        Self {
+            values: Mutex::default(),
+            patches: Mutex::default(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Streams<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Streams").finish_non_exhaustive()
+    }
+}
+
+impl<T: Clone> Streams<T> {
+    pub fn values(&self) -> UnboundedReceiver<T> {
+        Self::open(&self.values)
+    }
+
+    pub fn patches(&self) -> UnboundedReceiver<ServerSignalUpdate> {
+        Self::open(&self.patches)
+    }
+
+    pub fn send_value(&self, value: &T) {
+        Self::send(&self.values, value);
+    }
+
+    pub fn send_patch(&self, patch: &ServerSignalUpdate) {
+        Self::send(&self.patches, patch);
+    }
+
+    fn open<V>(senders: &Mutex<Vec<UnboundedSender<V>>>) -> UnboundedReceiver<V> {
+        let (sender, receiver) = unbounded();
+        if let Ok(mut senders) = senders.lock() {
+            senders.push(sender);
+        }
+        receiver
+    }
+
+    fn send<V: Clone>(senders: &Mutex<Vec<UnboundedSender<V>>>, value: &V) {
+        if let Ok(mut senders) = senders.lock() {
+            senders.retain(|sender| sender.unbounded_send(value.clone()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Every task the crate spawns on the server, to check that none of them outlives what it
+//! belongs to, e.g. in long-running servers.
+//!
+//! ```rust,no_run
+//! # fn example(
+//! #     server_signals: leptos_ws::server_signals::ServerSignals,
+//! #     connection_id: leptos_ws::connection::ConnectionId,
+//! # ) {
+//! let tasks = server_signals.tasks();
+//! leptos::logging::log!("{} tasks, {:?}", tasks.len(), tasks.counts());
+//! // Closes the connection, its writer and relay task are aborted
+//! tasks.abort_connection(connection_id);
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::connection::ConnectionId;
+
+/// What a task spawned by the crate does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskKind {
+    /// Serves a connection accepted by the crate, e.g. with
+    /// [`tungstenite::serve`](crate::tungstenite).
+    Connection,
+    /// Writes the queued messages of a connection.
+    Writer,
+    /// Relays the updates of the signals a connection established.
+    Relay,
+    /// Forwards the updates of a signal to the relays of its connections.
+    Route,
+    /// Diffs and broadcasts the writes of a signal.
+    Dispatch,
+    /// Writes the values of a signal to the [`SignalStore`](crate::persistence::SignalStore).
+    Persist,
+    /// Recomputes a derived signal or a summary.
+    Recompute,
+    /// Receives updates from or publishes them to the [`Backend`](crate::backend::Backend).
+    Backend,
+    /// Runs a callback registered by the app, e.g. with `on_client_update_async`.
+    Callback,
+}
+
+/// The running tasks of a [`ServerSignals`](crate::server_signals::ServerSignals) registry,
+/// see [`ServerSignals::tasks`](crate::server_signals::ServerSignals::tasks).
+///
+/// Tasks are removed once they finish or are aborted.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    tasks: Arc<Mutex<Tasks>>,
+}
+
+#[derive(Default)]
+struct Tasks {
+    next_id: u64,
+    running: HashMap<u64, Task>,
+}
+
+struct Task {
+    kind: TaskKind,
+    connection: Option<ConnectionId>,
+    /// Set right after the task was spawned.
+    abort: Option<AbortHandle>,
+}
+
+impl std::fmt::Debug for TaskRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tasks", self.len())
+    }
+}
+
+/// Removes a task from the registry when it is dropped, i.e. when the task finished or was
+/// aborted.
+struct Registered {
+    tasks: Arc<Mutex<Tasks>>,
+    id: u64,
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        self.tasks.lock().unwrap().running.remove(&self.id);
+    }
+}
+
+impl TaskRegistry {
+    /// Spawns `future` on the tokio runtime, recorded until it finishes or is aborted.
+    pub(crate) fn spawn<F>(
+        &self,
+        kind: TaskKind,
+        connection: Option<ConnectionId>,
+        future: F,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = {
+            let mut tasks = self.tasks.lock().unwrap();
+            let id = tasks.next_id;
+            tasks.next_id += 1;
+            tasks.running.insert(
+                id,
+                Task {
+                    kind,
+                    connection,
+                    abort: None,
+                },
+            );
+            id
+        };
+        let registered = Registered {
+            tasks: self.tasks.clone(),
+            id,
+        };
+        let handle = tokio::spawn(async move {
+            let _registered = registered;
+            future.await
+        });
+        // The task may have finished already, then it stays removed
+        if let Some(task) = self.tasks.lock().unwrap().running.get_mut(&id) {
+            task.abort = Some(handle.abort_handle());
+        }
+        handle
+    }
+
+    /// The number of running tasks.
+    pub fn len(&self) -> usize {
+        self.tasks.lock().unwrap().running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of running tasks of each kind.
+    pub fn counts(&self) -> HashMap<TaskKind, usize> {
+        let mut counts = HashMap::new();
+        for task in self.tasks.lock().unwrap().running.values() {
+            *counts.entry(task.kind).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The number of running tasks of `connection`, its writer and relay while it is open.
+    pub fn count_for(&self, connection: ConnectionId) -> usize {
+        self.tasks
+            .lock()
+            .unwrap()
+            .running
+            .values()
+            .filter(|task| task.connection == Some(connection))
+            .count()
+    }
+
+    /// Aborts the tasks of `connection`, which closes it. Returns how many were aborted.
+    pub fn abort_connection(&self, connection: ConnectionId) -> usize {
+        self.abort(|task| task.connection == Some(connection))
+    }
+
+    /// Aborts every task, e.g. when shutting down. Signals stop broadcasting their updates and
+    /// all connections close.
+    pub fn abort_all(&self) -> usize {
+        self.abort(|_| true)
+    }
+
+    fn abort(&self, matches: impl Fn(&Task) -> bool) -> usize {
+        // Collected first, aborting may drop a task right away which locks the registry
+        let handles = self
+            .tasks
+            .lock()
+            .unwrap()
+            .running
+            .values()
+            .filter(|task| matches(task))
+            .filter_map(|task| task.abort.clone())
+            .collect::<Vec<_>>();
+        for handle in &handles {
+            handle.abort();
+        }
+        handles.len()
+    }
+}
+
+/// Shared by the clones of a signal, so its tasks can stop once the last clone was dropped,
+/// e.g. along with its registry, without keeping it alive themselves.
+#[derive(Clone, Debug)]
+pub(crate) struct Lifetime(Arc<watch::Sender<()>>);
+
+impl Default for Lifetime {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(()).0))
+    }
+}
+
+impl Lifetime {
+    /// Resolves once every clone was dropped.
+    pub(crate) fn ended(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.0.subscribe();
+        async move {
+            // Nothing is ever sent, this only fails once the sender is dropped
+            while receiver.changed().await.is_ok() {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::pending;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn removes_finished_tasks() {
+        let tasks = TaskRegistry::default();
+        let (finish, finished) = oneshot::channel::<()>();
+        let handle = tasks.spawn(TaskKind::Callback, None, async move {
+            let _ = finished.await;
+        });
+        assert_eq!(tasks.counts().get(&TaskKind::Callback), Some(&1));
+
+        finish.send(()).unwrap();
+        handle.await.unwrap();
+        assert!(tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn aborts_only_the_tasks_of_a_connection() {
+        let tasks = TaskRegistry::default();
+        let (closed, open) = (ConnectionId::next(), ConnectionId::next());
+        let writer = tasks.spawn(TaskKind::Writer, Some(closed), pending::<()>());
+        let relay = tasks.spawn(TaskKind::Relay, Some(closed), pending::<()>());
+        let kept = tasks.spawn(TaskKind::Writer, Some(open), pending::<()>());
+        assert_eq!(tasks.count_for(closed), 2);
+
+        assert_eq!(tasks.abort_connection(closed), 2);
+        assert!(writer.await.unwrap_err().is_cancelled());
+        assert!(relay.await.unwrap_err().is_cancelled());
+        assert_eq!(tasks.count_for(closed), 0);
+        assert_eq!(tasks.count_for(open), 1);
+
+        assert_eq!(tasks.abort_all(), 1);
+        assert!(kept.await.unwrap_err().is_cancelled());
+        assert!(tasks.is_empty());
+    }
+}
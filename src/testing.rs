@@ -0,0 +1,636 @@
+//! A harness for contract tests of signals against a real server.
+//!
+//! [`TestServer`] runs the axum handler on a random local port, or serves clients in memory
+//! without a network with [`TestServer::in_memory`]. [`TestClient`] speaks the wire protocol in
+//! place of a browser, so tests can assert that server and clients converge, including across
+//! reconnects.
+//!
+//! ```rust,no_run
+//! use leptos::prelude::*;
+//! use leptos_ws::server_signals::ServerSignals;
+//! use leptos_ws::testing::TestServer;
+//!
+//! #[tokio::test]
+//! async fn count_converges() {
+//!     let server = TestServer::start(ServerSignals::new()).await;
+//!     let count = server
+//!         .in_context(|| leptos_ws::ServerSignal::new("count".to_string(), 0))
+//!         .unwrap();
+//!     let mut client = server.client().await;
+//!     client.establish("count").await;
+//!     count.update(|count| *count = 1);
+//!     client.wait_for("count", 1).await;
+//! }
+//!
+//! #[tokio::test]
+//! async fn input_reaches_server() {
+//!     let server = TestServer::in_memory(ServerSignals::new());
+//!     let cursor = server
+//!         .in_context(|| leptos_ws::ServerInputSignal::new("cursor".to_string(), 0))
+//!         .unwrap();
+//!     let (mut first, mut second) = (server.client().await, server.client().await);
+//!     first.set_input("cursor", 1).await;
+//!     second.set_input("cursor", 2).await;
+//!     second.set_input("cursor", 3).await;
+//!     second.wait_for_ack("cursor").await;
+//!     assert_eq!(cursor.values().get_untracked().len(), 2);
+//! }
+//! ```
+//!
+//! The methods panic when the connection fails or a value doesn't converge in time, as they are
+//! meant to be called from tests.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddr,
+    pin::Pin,
+    time::Duration,
+};
+
+use axum::{routing::get, Router};
+use futures::{future::ready, stream, Sink, SinkExt, Stream, StreamExt};
+use leptos::prelude::{provide_context, Owner};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{error::SendError, unbounded_channel},
+    task::JoinHandle,
+    time::{timeout_at, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::{
+    axum::websocket_with_config,
+    config::ConnectionConfig,
+    messages::{
+        ClientCapabilities, ClientId, ClientInputMessage, InputRejection, Messages,
+        ServerSignalMessage, ServerSignalUpdate, SignalVersion,
+    },
+    server_signals::ServerSignals,
+    tasks::TaskKind,
+    transport::{serve, Frame},
+    tungstenite::to_frame,
+};
+
+/// Connects a client to `server_signals` in memory, returning the client's ends of the
+/// connection.
+///
+/// The server side runs on a spawned task like a socket of the axum handler, until the client
+/// drops its sink. `identity` stands in for what `ConnectionConfig::identify` would extract.
+pub fn connect_in_memory(
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+    identity: Option<String>,
+) -> (
+    impl Sink<Frame, Error = SendError<Frame>> + Send,
+    impl Stream<Item = Frame> + Send,
+) {
+    let (to_server, from_client) = unbounded_channel();
+    let (to_client, from_server) = unbounded_channel::<Frame>();
+    let server_stream = stream::unfold(from_client, |mut from_client| async move {
+        let frame = from_client.recv().await?;
+        Some((Ok::<_, Infallible>(frame), from_client))
+    });
+    let server_sink = futures::sink::unfold(to_client, |to_client, frame| async move {
+        to_client.send(frame)?;
+        Ok::<_, SendError<Frame>>(to_client)
+    });
+    let tasks = server_signals.tasks().clone();
+    tasks.spawn(
+        TaskKind::Connection,
+        None,
+        serve(
+            Box::pin(server_stream),
+            server_sink,
+            server_signals,
+            config,
+            None,
+            identity,
+        ),
+    );
+    let client_sink = futures::sink::unfold(to_server, |to_server, frame| async move {
+        to_server.send(frame)?;
+        Ok(to_server)
+    });
+    let client_stream = stream::unfold(from_server, |mut from_server| async move {
+        let frame = from_server.recv().await?;
+        Some((frame, from_server))
+    });
+    (client_sink, client_stream)
+}
+
+/// An axum server serving `leptos_ws` at `/ws` on a random local port, or a server serving its
+/// clients in memory.
+///
+/// The server is stopped when this is dropped.
+pub struct TestServer {
+    signals: ServerSignals,
+    owner: Owner,
+    transport: Transport,
+}
+
+enum Transport {
+    Tcp {
+        addr: SocketAddr,
+        task: JoinHandle<()>,
+    },
+    Memory(Box<ConnectionConfig>),
+}
+
+impl TestServer {
+    pub async fn start(signals: ServerSignals) -> Self {
+        Self::start_with_config(signals, ConnectionConfig::default()).await
+    }
+
+    pub async fn start_with_config(signals: ServerSignals, config: ConnectionConfig) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Could not bind test server");
+        let addr = listener.local_addr().expect("Test server has no address");
+        let app = Router::new().route("/ws", get(websocket_with_config(signals.clone(), config)));
+        let task = tokio::spawn(async move {
+            axum::serve(listener, app)
+                .await
+                .expect("Test server failed");
+        });
+        Self::with_transport(signals, Transport::Tcp { addr, task })
+    }
+
+    /// A server whose clients are connected through [`connect_in_memory`] instead of a socket.
+    pub fn in_memory(signals: ServerSignals) -> Self {
+        Self::in_memory_with_config(signals, ConnectionConfig::default())
+    }
+
+    pub fn in_memory_with_config(signals: ServerSignals, config: ConnectionConfig) -> Self {
+        Self::with_transport(signals, Transport::Memory(Box::new(config)))
+    }
+
+    fn with_transport(signals: ServerSignals, transport: Transport) -> Self {
+        let owner = Owner::new();
+        owner.with(|| provide_context(signals.clone()));
+        Self {
+            signals,
+            owner,
+            transport,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the server was started [in memory](TestServer::in_memory).
+    pub fn url(&self) -> String {
+        match &self.transport {
+            Transport::Tcp { addr, .. } => format!("ws://{addr}/ws"),
+            Transport::Memory(_) => panic!("An in-memory test server has no URL"),
+        }
+    }
+
+    pub fn signals(&self) -> &ServerSignals {
+        &self.signals
+    }
+
+    /// Runs `f` with the registry of this server provided as context, e.g. to create signals.
+    pub fn in_context<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.owner.with(f)
+    }
+
+    /// Connects a new client.
+    pub async fn client(&self) -> TestClient {
+        match &self.transport {
+            Transport::Tcp { .. } => TestClient::connect(self.url()).await,
+            Transport::Memory(config) => {
+                TestClient::new(Endpoint::Memory(Box::new((
+                    self.signals.clone(),
+                    (**config).clone(),
+                    None,
+                ))))
+                .await
+            }
+        }
+    }
+
+    /// Connects a new client with an identity, e.g. to test ACLs.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless the server was started [in memory](TestServer::in_memory), over a socket the
+    /// identity is extracted from the request by `ConnectionConfig::identify`.
+    pub async fn client_as(&self, identity: impl Into<String>) -> TestClient {
+        match &self.transport {
+            Transport::Tcp { .. } => panic!("Identities can only be set for in-memory clients"),
+            Transport::Memory(config) => {
+                TestClient::new(Endpoint::Memory(Box::new((
+                    self.signals.clone(),
+                    (**config).clone(),
+                    Some(identity.into()),
+                ))))
+                .await
+            }
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Transport::Tcp { task, .. } = &self.transport {
+            task.abort();
+        }
+    }
+}
+
+/// Where a [`TestClient`] connects to.
+enum Endpoint {
+    Url(String),
+    /// The registry, the config and the identity of the connection.
+    Memory(Box<(ServerSignals, ConnectionConfig, Option<String>)>),
+}
+
+type ClientSink = Pin<Box<dyn Sink<Frame, Error = String> + Send>>;
+type ClientStream = Pin<Box<dyn Stream<Item = Frame> + Send>>;
+
+/// A client speaking the wire protocol, standing in for a browser.
+///
+/// It keeps the JSON value of every established signal and of every client input it wrote,
+/// and re-establishes all of them on [`TestClient::reconnect`] like the real client does.
+pub struct TestClient {
+    endpoint: Endpoint,
+    sink: ClientSink,
+    stream: ClientStream,
+    timeout: Duration,
+    values: HashMap<String, Value>,
+    paused: HashSet<String>,
+    denied: HashSet<String>,
+    /// The epoch and sequence number of the last update included in every value, to resume
+    /// from after a reconnect.
+    versions: HashMap<String, (u64, u64)>,
+    /// The number of complete values received for every signal.
+    snapshots: HashMap<String, usize>,
+    /// The last value and sequence number written to every client input.
+    inputs: HashMap<String, (Value, u64)>,
+    acknowledged: HashMap<String, u64>,
+    rejections: Vec<InputRejection>,
+    client_id: Option<ClientId>,
+    auth_token: Option<String>,
+    auth_rejected: Option<String>,
+}
+
+impl TestClient {
+    pub async fn connect(url: impl Into<String>) -> Self {
+        Self::new(Endpoint::Url(url.into())).await
+    }
+
+    async fn new(endpoint: Endpoint) -> Self {
+        let (sink, stream) = Self::open(&endpoint, None).await;
+        Self {
+            endpoint,
+            sink,
+            stream,
+            timeout: Duration::from_secs(5),
+            values: HashMap::new(),
+            paused: HashSet::new(),
+            denied: HashSet::new(),
+            versions: HashMap::new(),
+            snapshots: HashMap::new(),
+            inputs: HashMap::new(),
+            acknowledged: HashMap::new(),
+            rejections: Vec::new(),
+            client_id: None,
+            auth_token: None,
+            auth_rejected: None,
+        }
+    }
+
+    /// Identifies the client with `id` from now on, like a real client with its
+    /// [`ClientId`], which it keeps across [`TestClient::reconnect`].
+    pub async fn set_client_id(&mut self, id: impl Into<String>) {
+        self.client_id = Some(ClientId(id.into()));
+        self.send(&hello(self.client_id.clone())).await;
+    }
+
+    /// Authenticates with `token`, again after every [`TestClient::reconnect`], see
+    /// [`ConnectionConfig::authenticate`](crate::config::ConnectionConfig).
+    pub async fn authenticate(&mut self, token: impl Into<String>) {
+        self.auth_token = Some(token.into());
+        if let Some(token) = self.auth_token.clone() {
+            self.send(&Messages::Authenticate(token)).await;
+        }
+    }
+
+    /// Receives messages until the server rejected the auth token, and returns its reason.
+    pub async fn wait_for_auth_rejected(&mut self) -> String {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(reason) = self.auth_rejected.take() {
+                return reason;
+            }
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!("The auth token was not rejected in time");
+            }
+        }
+    }
+
+    /// How long [`TestClient::wait_for`] and [`TestClient::wait_for_ack`] wait, 5 seconds by
+    /// default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Establishes a server signal, its values are available through [`TestClient::value`].
+    pub async fn establish(&mut self, name: &str) {
+        self.values.entry(name.to_string()).or_insert(Value::Null);
+        self.send(&Messages::ServerSignal(ServerSignalMessage::Establish(
+            name.to_string(),
+        )))
+        .await;
+    }
+
+    /// The last value received for a signal, `Value::Null` until the first one arrived.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// The number of complete values received for a signal, the establish response included.
+    /// A reconnect which resumed the signal only receives the updates it missed.
+    pub fn snapshots(&self, name: &str) -> usize {
+        self.snapshots.get(name).copied().unwrap_or_default()
+    }
+
+    /// Whether the server paused updates of a signal, see
+    /// [`ServerSignals::disable`](crate::server_signals::ServerSignals).
+    pub fn is_paused(&self, name: &str) -> bool {
+        self.paused.contains(name)
+    }
+
+    /// Receives messages until the server paused or resumed updates of a signal.
+    pub async fn wait_for_paused(&mut self, name: &str, paused: bool) {
+        let deadline = Instant::now() + self.timeout;
+        while self.is_paused(name) != paused {
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!("{name} was not paused = {paused} in time");
+            }
+        }
+    }
+
+    /// Receives messages until the server refused to establish a signal, see
+    /// [`ServerSignal::new_with_acl`](crate::ServerSignal).
+    pub async fn wait_for_denied(&mut self, name: &str) {
+        let deadline = Instant::now() + self.timeout;
+        while !self.denied.contains(name) {
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!("{name} was not denied in time");
+            }
+        }
+    }
+
+    /// Receives messages until the signal has the `expected` value.
+    pub async fn wait_for(&mut self, name: &str, expected: impl Serialize) {
+        let expected = serde_json::to_value(expected).expect("Could not serialize expected value");
+        let deadline = Instant::now() + self.timeout;
+        while self.values.get(name) != Some(&expected) {
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!(
+                    "{name} did not converge to {expected}, last value: {:?}",
+                    self.values.get(name)
+                );
+            }
+        }
+    }
+
+    /// Writes a client input, sending the complete value the first time and a patch afterwards.
+    pub async fn set_input(&mut self, name: &str, value: impl Serialize) {
+        let value = serde_json::to_value(value).expect("Could not serialize input");
+        let message = match self.inputs.get_mut(name) {
+            Some((old, seq)) => {
+                *seq += 1;
+                let update = ServerSignalUpdate::new_from_json(name.to_string(), old, &value);
+                *old = value;
+                ClientInputMessage::Update((*seq, update))
+            }
+            None => {
+                self.inputs.insert(name.to_string(), (value.clone(), 0));
+                ClientInputMessage::Establish((name.to_string(), value))
+            }
+        };
+        self.send(&Messages::ClientInput(message)).await;
+    }
+
+    /// Receives messages until the last update of a client input was acknowledged or rejected.
+    pub async fn wait_for_ack(&mut self, name: &str) {
+        let Some(&(_, seq)) = self.inputs.get(name) else {
+            panic!("{name} was never written");
+        };
+        if seq == 0 {
+            // Establishing isn't acknowledged
+            return;
+        }
+        let deadline = Instant::now() + self.timeout;
+        while self.acknowledged.get(name) < Some(&seq)
+            && !self
+                .rejections
+                .iter()
+                .any(|rejection| rejection.name == name && rejection.seq == seq)
+        {
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!("Update {seq} of {name} was neither acknowledged nor rejected");
+            }
+        }
+    }
+
+    /// Receives messages until the server rejected a value of a client input, returning the
+    /// rejection.
+    pub async fn wait_for_rejection(&mut self, name: &str) -> InputRejection {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let rejection = self
+                .rejections
+                .iter()
+                .find(|rejection| rejection.name == name);
+            if let Some(rejection) = rejection {
+                return rejection.clone();
+            }
+            if timeout_at(deadline, self.receive()).await.is_err() {
+                panic!("No value of {name} was rejected in time");
+            }
+        }
+    }
+
+    /// All updates the server rejected so far.
+    pub fn rejections(&self) -> &[InputRejection] {
+        &self.rejections
+    }
+
+    /// Closes the connection and opens a new one, re-establishing all signals and client inputs.
+    /// Signals resume from the last update received if the server told their version.
+    pub async fn reconnect(&mut self) {
+        let _ = self.sink.close().await;
+        (self.sink, self.stream) = Self::open(&self.endpoint, self.client_id.clone()).await;
+        if let Some(token) = self.auth_token.clone() {
+            self.send(&Messages::Authenticate(token)).await;
+        }
+        let signals = self.values.keys().cloned().collect::<Vec<_>>();
+        for name in signals {
+            let message = match self.versions.get(&name) {
+                Some(&(epoch, seq)) => {
+                    let version = SignalVersion { name, epoch, seq };
+                    ServerSignalMessage::Resume((version, Value::Null))
+                }
+                None => ServerSignalMessage::Establish(name),
+            };
+            self.send(&Messages::ServerSignal(message)).await;
+        }
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(name, (value, _))| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        for input in inputs {
+            self.send(&Messages::ClientInput(ClientInputMessage::Establish(input)))
+                .await;
+        }
+    }
+
+    async fn open(endpoint: &Endpoint, client_id: Option<ClientId>) -> (ClientSink, ClientStream) {
+        let (mut sink, stream): (ClientSink, ClientStream) = match endpoint {
+            Endpoint::Url(url) => {
+                let (socket, _) = connect_async(url)
+                    .await
+                    .expect("Could not connect to test server");
+                let (sink, stream) = socket.split();
+                (
+                    Box::pin(
+                        sink.with(|frame| ready(Ok(Message::from(frame))))
+                            .sink_map_err(|err: tokio_tungstenite::tungstenite::Error| {
+                                err.to_string()
+                            }),
+                    ),
+                    Box::pin(stream.filter_map(|message| ready(message.ok().and_then(to_frame)))),
+                )
+            }
+            Endpoint::Memory(memory) => {
+                let (signals, config, identity) = &**memory;
+                let (sink, stream) =
+                    connect_in_memory(signals.clone(), config.clone(), identity.clone());
+                (
+                    Box::pin(sink.sink_map_err(|err| err.to_string())),
+                    Box::pin(stream),
+                )
+            }
+        };
+        let hello = serde_json::to_string(&hello(client_id)).unwrap();
+        sink.send(Frame::Text(hello))
+            .await
+            .expect("Could not send hello");
+        (sink, stream)
+    }
+
+    /// Sends a message as it is, e.g. one a real client would only send in another state.
+    pub async fn send(&mut self, message: &Messages) {
+        let message = serde_json::to_string(message).unwrap();
+        self.sink
+            .send(Frame::Text(message))
+            .await
+            .expect("Could not send message to test server");
+    }
+
+    /// Receives and applies a single message.
+    async fn receive(&mut self) {
+        match self
+            .stream
+            .next()
+            .await
+            .expect("Test server closed the connection")
+        {
+            Frame::Text(text) => {
+                let messages =
+                    match serde_json::from_str(&text).expect("Invalid message from test server") {
+                        Messages::Batch(messages) => messages,
+                        message => vec![message],
+                    };
+                for message in messages {
+                    if let Some(reply) = self.apply(message) {
+                        self.send(&reply).await;
+                    }
+                }
+            }
+            // Answered like a browser, the server closes connections which miss heartbeats. The
+            // server may have closed the connection already, e.g. after rejecting the auth token,
+            // the messages sent before are received either way
+            Frame::Ping(payload) => {
+                let _ = self.sink.send(Frame::Pong(payload)).await;
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a message, returning the reply the real client would send.
+    fn apply(&mut self, message: Messages) -> Option<Messages> {
+        match message {
+            Messages::ServerSignal(ServerSignalMessage::EstablishResponse((name, value))) => {
+                self.paused.remove(&name);
+                // Unknown until the server sends it
+                self.versions.remove(&name);
+                *self.snapshots.entry(name.clone()).or_default() += 1;
+                self.values.insert(name, value);
+            }
+            Messages::ServerSignal(ServerSignalMessage::Version(version)) => {
+                self.versions
+                    .insert(version.name, (version.epoch, version.seq));
+            }
+            Messages::ServerSignal(ServerSignalMessage::Paused(name)) => {
+                self.paused.insert(name);
+            }
+            Messages::ServerSignal(ServerSignalMessage::Denied(name)) => {
+                self.denied.insert(name);
+            }
+            Messages::ServerSignal(ServerSignalMessage::Update(update)) => {
+                let version = self.versions.get_mut(update.name());
+                if let (Some((_, last)), Some(seq)) = (version, update.seq()) {
+                    // Already included, e.g. relayed while the missed updates were collected
+                    if seq <= *last {
+                        return None;
+                    }
+                    *last = seq;
+                }
+                if let Some(value) = self.values.get_mut(update.name()) {
+                    update.apply(value).expect("Could not apply update");
+                }
+            }
+            Messages::ClientInput(ClientInputMessage::Ack((name, seq))) => {
+                self.acknowledged.insert(name, seq);
+            }
+            Messages::ClientInput(ClientInputMessage::Reject(rejection)) => {
+                let name = rejection.name.clone();
+                let value = rejection.value.clone();
+                self.rejections.push(rejection);
+                // Roll back and establish again, the server ignores updates until then
+                let (input, _) = self.inputs.get_mut(&name)?;
+                *input = value.clone();
+                return Some(Messages::ClientInput(ClientInputMessage::Establish((
+                    name, value,
+                ))));
+            }
+            Messages::AuthRejected(reason) => {
+                self.auth_rejected = Some(reason);
+            }
+            #[cfg(feature = "compression")]
+            Messages::Compressed(data) => {
+                let message =
+                    crate::compression::decode(&data).expect("Invalid compressed message");
+                return self.apply(message);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// The `Hello` of the real client.
+fn hello(client_id: Option<ClientId>) -> Messages {
+    Messages::Hello(ClientCapabilities {
+        client_id,
+        ..ClientCapabilities::current()
+    })
+}
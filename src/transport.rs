@@ -0,0 +1,1326 @@
+//! The server side of a connection, independent of the web framework.
+//!
+//! [`serve`] runs the protocol over any stream and sink of [`Frame`]s. The [`axum`](crate::axum)
+//! handlers are built on it, other frameworks or in-memory connections (see
+//! [`testing`](crate::testing)) only need to convert their messages.
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "axum")]
+//! # async fn handle_socket(socket: axum::extract::ws::WebSocket, server_signals: leptos_ws::server_signals::ServerSignals) {
+//! # use axum::extract::ws::Message;
+//! # use futures::{SinkExt, StreamExt, TryStreamExt};
+//! # use leptos_ws::{config::ConnectionConfig, transport::Frame};
+//! let (sink, stream) = socket.split();
+//! leptos_ws::transport::serve(
+//!     stream.map_ok(Frame::from),
+//!     sink.with(|frame: Frame| async move { Ok::<_, axum::Error>(Message::from(frame)) }),
+//!     server_signals,
+//!     ConnectionConfig::default(),
+//!     None,
+//!     None,
+//! )
+//! .await;
+//! # }
+//! ```
+
+use crate::{
+    config::{BatchConfig, ConnectionConfig, OutboundConfig, OverflowPolicy},
+    connection::{Connection, ConnectionFeatures, ConnectionHandle, IdentityMeter, RateLimiter},
+    error::{Error, ErrorHooks},
+    latency::now_millis,
+    messages::{
+        ClientCapabilities, ClientInputMessage, HeartbeatMessage, Messages, RateLimitReason,
+        ServerSignalMessage, ServerSignalUpdate, SignalAnnouncement, SignalVersion,
+    },
+    routes::Routed,
+    server_signals::{is_disabled, ServerSignals},
+    tasks::TaskKind,
+};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use leptos::logging::error;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::pin,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        watch, Notify,
+    },
+    time::{interval, sleep},
+};
+
+/// A WebSocket frame, as sent and received by [`serve`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// The connection is closed or has to be closed.
+#[derive(Debug)]
+struct Closed;
+
+/// Messages waiting to be written to the client, bounded by [`OutboundConfig`].
+///
+/// Written by a separate task, so a client which reads slowly never blocks the relay or the
+/// receive loop.
+#[derive(Clone)]
+struct Outbox {
+    state: Arc<Mutex<OutboxState>>,
+    notify: Arc<Notify>,
+    config: OutboundConfig,
+    /// Where messages which can't be encoded are reported, see [`Outbox::encode`].
+    errors: ErrorHooks,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    queue: VecDeque<Outgoing>,
+    closed: bool,
+    /// Set once the client advertised [`FEATURE_BATCH`](crate::messages::FEATURE_BATCH).
+    batch: Option<BatchConfig>,
+    /// The number of the last update of each signal the client already received as part of a
+    /// complete value. The route may still relay them afterwards, they are skipped.
+    included: HashMap<String, u64>,
+}
+
+impl OutboxState {
+    /// Takes the next queued message, skipping updates the client already received.
+    fn pop(&mut self) -> Option<Outgoing> {
+        while let Some(next) = self.queue.pop_front() {
+            if let Outgoing::Update {
+                signal,
+                seq: Some(seq),
+                ..
+            } = &next
+            {
+                if self
+                    .included
+                    .get(signal)
+                    .is_some_and(|included| seq <= included)
+                {
+                    continue;
+                }
+            }
+            return Some(next);
+        }
+        None
+    }
+}
+
+enum Outgoing {
+    Other(Frame),
+    Update {
+        signal: String,
+        /// The number of the update, if the signal numbers them.
+        seq: Option<u64>,
+        frame: Frame,
+        /// For the snapshot replacing the update if it is dropped.
+        features: ConnectionFeatures,
+    },
+    /// The complete value of the signal, replacing updates dropped because the queue was full.
+    Snapshot {
+        signal: String,
+        features: ConnectionFeatures,
+    },
+}
+
+impl Outbox {
+    fn new(config: OutboundConfig, errors: ErrorHooks) -> Self {
+        Self {
+            state: Arc::default(),
+            notify: Arc::default(),
+            config,
+            errors,
+        }
+    }
+
+    /// Encodes a message for the client, see [`encode`]. If it can't be encoded, e.g. because
+    /// the cipher failed, the error is reported and the connection closed without writing the
+    /// messages still queued.
+    fn encode(&self, message: &Messages, features: &ConnectionFeatures) -> Result<Frame, Closed> {
+        encode(message, features).map(Frame::Text).map_err(|err| {
+            self.errors.report(None, err);
+            let mut state = self.state.lock().unwrap();
+            state.queue.clear();
+            state.closed = true;
+            drop(state);
+            self.notify.notify_one();
+            Closed
+        })
+    }
+
+    /// Joins queued messages into batches from now on, see [`BatchConfig`].
+    fn set_batch(&self, batch: Option<BatchConfig>) {
+        self.state.lock().unwrap().batch = batch;
+    }
+
+    /// Queues a frame which is never dropped, e.g. a reply to the client.
+    fn send(&self, frame: Frame) -> Result<(), Closed> {
+        self.push(Outgoing::Other(frame))
+    }
+
+    /// Records that the client received the updates of `signal` up to `seq` with its complete
+    /// value, or forgets about the signal if `seq` is `None`.
+    fn include(&self, signal: &str, seq: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        match seq {
+            Some(seq) => state.included.insert(signal.to_string(), seq),
+            None => state.included.remove(signal),
+        };
+    }
+
+    /// Replaces the queued updates of `signal` with its complete value, e.g. after updates were
+    /// skipped.
+    fn resync(&self, signal: &str, features: &ConnectionFeatures) -> Result<(), Closed> {
+        let mut state = self.state.lock().unwrap();
+        remove_updates(&mut state.queue, signal);
+        let covered = has_snapshot(&state.queue, signal);
+        drop(state);
+        if covered {
+            return Ok(());
+        }
+        self.push(Outgoing::Snapshot {
+            signal: signal.to_string(),
+            features: features.clone(),
+        })
+    }
+
+    /// Queues an update of `signal`, which may be dropped if the queue is full.
+    fn send_update(
+        &self,
+        signal: &str,
+        seq: Option<u64>,
+        frame: Frame,
+        features: &ConnectionFeatures,
+    ) -> Result<(), Closed> {
+        let mut state = self.state.lock().unwrap();
+        if has_snapshot(&state.queue, signal) {
+            // The complete value is read when it is written, so it includes this update
+            return if state.closed { Err(Closed) } else { Ok(()) };
+        }
+        let mut outgoing = Outgoing::Update {
+            signal: signal.to_string(),
+            seq,
+            frame,
+            features: features.clone(),
+        };
+        if state.queue.len() >= self.config.capacity
+            && self.config.overflow == OverflowPolicy::KeepLatest
+        {
+            remove_updates(&mut state.queue, signal);
+            outgoing = Outgoing::Snapshot {
+                signal: signal.to_string(),
+                features: features.clone(),
+            };
+        }
+        drop(state);
+        self.push(outgoing)
+    }
+
+    fn push(&self, outgoing: Outgoing) -> Result<(), Closed> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(Closed);
+        }
+        if state.queue.len() >= self.config.capacity {
+            if self.config.overflow == OverflowPolicy::Disconnect {
+                error!("Client does not keep up with its messages, disconnecting");
+                state.queue.clear();
+                state.closed = true;
+                drop(state);
+                self.notify.notify_one();
+                return Err(Closed);
+            }
+            let oldest = state
+                .queue
+                .iter()
+                .position(|queued| matches!(queued, Outgoing::Update { .. }));
+            if let Some(Outgoing::Update {
+                signal, features, ..
+            }) = oldest.and_then(|oldest| state.queue.remove(oldest))
+            {
+                // The client would hold back every later update of the signal until the dropped
+                // one arrives, so it receives the complete value in its place instead
+                remove_updates(&mut state.queue, &signal);
+                if !has_snapshot(&state.queue, &signal) {
+                    let position = oldest.unwrap_or_default().min(state.queue.len());
+                    state
+                        .queue
+                        .insert(position, Outgoing::Snapshot { signal, features });
+                }
+            }
+        }
+        if let Outgoing::Update { signal, .. } = &outgoing {
+            if has_snapshot(&state.queue, signal) {
+                // Included in the complete value, which is read when it is written
+                return Ok(());
+            }
+        }
+        state.queue.push_back(outgoing);
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Writes the queued messages until the outbox is closed or the sink fails.
+    async fn write<K: Sink<Frame>>(self, sink: K, server_signals: ServerSignals) {
+        let mut sink = pin!(sink);
+        'write: loop {
+            let (next, batch) = {
+                let mut state = self.state.lock().unwrap();
+                match state.pop() {
+                    None if state.closed => break,
+                    next => (next, state.batch),
+                }
+            };
+            let Some(next) = next else {
+                self.notify.notified().await;
+                continue;
+            };
+            let mut frames = self.frames(next, &server_signals).await;
+            if let Some(batch) = batch {
+                // Gives the rest of a burst the chance to be queued
+                if !batch.delay.is_zero() {
+                    sleep(batch.delay).await;
+                }
+                while frames.len() < batch.max_messages {
+                    let next = self.state.lock().unwrap().pop();
+                    let Some(next) = next else {
+                        break;
+                    };
+                    frames.extend(self.frames(next, &server_signals).await);
+                }
+                frames = batched(frames, &batch);
+            }
+            for frame in frames {
+                if sink.send(frame).await.is_err() {
+                    break 'write;
+                }
+            }
+        }
+        self.close();
+        let _ = sink.close().await;
+    }
+
+    /// The frames written for a queued message.
+    async fn frames(&self, outgoing: Outgoing, server_signals: &ServerSignals) -> Vec<Frame> {
+        match outgoing {
+            Outgoing::Other(frame) | Outgoing::Update { frame, .. } => vec![frame],
+            Outgoing::Snapshot { signal, features } => {
+                let (value, version) = match server_signals.snapshot(&signal, Value::Null).await {
+                    Some(Ok(snapshot)) => snapshot,
+                    Some(Err(err)) => {
+                        server_signals.report(Some(&signal), err);
+                        return Vec::new();
+                    }
+                    None => return Vec::new(),
+                };
+                // Updates relayed after the value was read may already be part of it
+                self.include(&signal, version.map(|(_, seq)| seq));
+                let mut messages = vec![ServerSignalMessage::EstablishResponse((
+                    signal.clone(),
+                    value,
+                ))];
+                if let Some((epoch, seq)) = version.filter(|_| features.resume) {
+                    messages.push(ServerSignalMessage::Version(SignalVersion {
+                        name: signal,
+                        epoch,
+                        seq,
+                    }));
+                }
+                messages
+                    .into_iter()
+                    .map(|message| self.encode(&Messages::ServerSignal(message), &features))
+                    .collect::<Result<_, _>>()
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+}
+
+/// Removes the queued updates of `signal`.
+fn remove_updates(queue: &mut VecDeque<Outgoing>, signal: &str) {
+    queue.retain(
+        |queued| !matches!(queued, Outgoing::Update { signal: queued, .. } if queued == signal),
+    );
+}
+
+/// Whether the complete value of `signal` is queued.
+fn has_snapshot(queue: &VecDeque<Outgoing>, signal: &str) -> bool {
+    queue.iter().any(
+        |queued| matches!(queued, Outgoing::Snapshot { signal: queued, .. } if queued == signal),
+    )
+}
+
+/// Joins consecutive text frames into [`Messages::Batch`] frames within the limits of `batch`.
+///
+/// The frames are already encoded, e.g. compressed or encrypted, so they are joined as they are
+/// and the client unpacks each one on its own.
+fn batched(frames: Vec<Frame>, batch: &BatchConfig) -> Vec<Frame> {
+    let mut batched = Vec::with_capacity(frames.len());
+    let mut pending = Vec::new();
+    let mut bytes = 0;
+    for frame in frames {
+        let full = match &frame {
+            Frame::Text(text) => {
+                pending.len() >= batch.max_messages || bytes + text.len() > batch.max_bytes
+            }
+            _ => true,
+        };
+        if full && !pending.is_empty() {
+            batched.push(batch_frame(std::mem::take(&mut pending)));
+            bytes = 0;
+        }
+        match frame {
+            Frame::Text(text) => {
+                bytes += text.len();
+                pending.push(text);
+            }
+            frame => batched.push(frame),
+        }
+    }
+    if !pending.is_empty() {
+        batched.push(batch_frame(pending));
+    }
+    batched
+}
+
+fn batch_frame(mut messages: Vec<String>) -> Frame {
+    match messages.len() {
+        1 => Frame::Text(messages.pop().unwrap()),
+        _ => Frame::Text(format!("{{\"Batch\":[{}]}}", messages.join(","))),
+    }
+}
+
+/// Runs a connection until the client closes it, the sink fails or the heartbeat times out.
+///
+/// `previous_node` is the node from the client's affinity cookie, see
+/// [`AffinityConfig`](crate::config::AffinityConfig), and `identity` is who opened the
+/// connection, used for the per-identity rate limits.
+pub async fn serve<S, K, E>(
+    mut stream: S,
+    sink: K,
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+    previous_node: Option<String>,
+    identity: Option<String>,
+) where
+    S: Stream<Item = Result<Frame, E>> + Unpin,
+    K: Sink<Frame> + Send + Sync + 'static,
+{
+    if let (Some(affinity), Some(previous_node)) = (&config.affinity, &previous_node) {
+        if *previous_node != affinity.node {
+            leptos::logging::warn!(
+                "Reconnect for node {previous_node} was routed to {}, the client resyncs",
+                affinity.node
+            );
+        }
+    }
+    let meter = identity
+        .clone()
+        .map(|identity| server_signals.meter(identity));
+    let (connection, info) = ConnectionHandle::new(previous_node, identity);
+    let tasks = server_signals.tasks();
+    let outbox = Outbox::new(config.outbound, server_signals.error_hooks());
+    let mut writer = tasks.spawn(
+        TaskKind::Writer,
+        Some(connection.id()),
+        outbox.clone().write(sink, server_signals.clone()),
+    );
+    server_signals.add_connection(info.clone()).await;
+    let mut limiter = RateLimiter::new(config.rate_limit, meter);
+    let (routed, routed_recv) = unbounded_channel();
+    let relay = tasks.spawn(
+        TaskKind::Relay,
+        Some(connection.id()),
+        relay(
+            info,
+            routed_recv,
+            server_signals.watch_disabled(),
+            outbox.clone(),
+            limiter.meter(),
+        ),
+    );
+    let mut heartbeat = interval(config.heartbeat.interval);
+    let mut announcements = server_signals.watch_announcements();
+    // Announcements are sent once the client said it understands them in its `Hello`
+    let mut announced = 0;
+    loop {
+        select! {
+            message = stream.next() => {
+                let Some(Ok(msg)) = message else {
+                    break;
+                };
+                connection.touch();
+                let context = ConnectionContext {
+                    server_signals: &server_signals,
+                    connection: &connection,
+                    outbox: &outbox,
+                    routed: &routed,
+                    config: &config,
+                };
+                if handle_message(msg, context, &mut limiter).await.is_err() {
+                    break;
+                }
+                if announce(&announcements.borrow(), &mut announced, &outbox, &connection, &config).is_err() {
+                    break;
+                }
+            }
+            Ok(()) = announcements.changed() => {
+                if announce(&announcements.borrow_and_update(), &mut announced, &outbox, &connection, &config).is_err() {
+                    break;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if connection.last_seen().elapsed() > config.heartbeat.timeout {
+                    break;
+                }
+                if outbox.send(Frame::Ping(Vec::new())).is_err() {
+                    break;
+                }
+            }
+            _ = &mut writer => break,
+        }
+    }
+    relay.abort();
+    server_signals.remove_connection(connection.id()).await;
+    outbox.close();
+}
+
+/// A signal established by the connection, see [`relay`].
+enum Subscription {
+    /// Updates received while the value is being sent, relayed right after it. If the route
+    /// lagged meanwhile, the complete value is sent again instead.
+    Pending {
+        held: Vec<ServerSignalUpdate>,
+        lagged: bool,
+    },
+    Active {
+        features: ConnectionFeatures,
+        paused: bool,
+    },
+}
+
+/// Relays the updates of every signal the connection established, as routed to it by
+/// [`ServerSignals::subscribe`]. One task per connection, however many signals it established.
+async fn relay(
+    connection: Connection,
+    mut routed: UnboundedReceiver<Routed>,
+    mut disabled: watch::Receiver<Vec<String>>,
+    outbox: Outbox,
+    meter: Option<Arc<IdentityMeter>>,
+) {
+    let mut subscriptions = HashMap::new();
+    loop {
+        let relayed = select! {
+            next = routed.recv() => {
+                let Some(next) = next else {
+                    break;
+                };
+                route(next, &connection, &mut subscriptions, &disabled, &outbox, &meter)
+            }
+            changed = disabled.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let disabled = disabled.borrow_and_update().clone();
+                pause(&disabled, &mut subscriptions, &outbox, &meter)
+            }
+        };
+        if relayed.is_err() {
+            break;
+        }
+    }
+}
+
+/// Handles one message routed to the connection.
+fn route(
+    routed: Routed,
+    connection: &Connection,
+    subscriptions: &mut HashMap<String, Subscription>,
+    disabled: &watch::Receiver<Vec<String>>,
+    outbox: &Outbox,
+    meter: &Option<Arc<IdentityMeter>>,
+) -> Result<(), Closed> {
+    match routed {
+        Routed::Subscribe(name) => {
+            subscriptions.insert(
+                name,
+                Subscription::Pending {
+                    held: Vec::new(),
+                    lagged: false,
+                },
+            );
+        }
+        Routed::Established {
+            name,
+            features,
+            frames,
+            included,
+        } => {
+            // Unsubscribed meanwhile
+            let Some(Subscription::Pending { held, lagged }) = subscriptions.remove(&name) else {
+                return Ok(());
+            };
+            // Held or still routed updates which are part of the value are skipped
+            outbox.include(&name, included);
+            for frame in frames {
+                outbox.send(frame)?;
+                if let Some(meter) = meter {
+                    meter.sent();
+                }
+            }
+            let paused = is_disabled(&disabled.borrow(), &name);
+            if lagged && !paused {
+                outbox.resync(&name, &features)?;
+            }
+            let held = held.into_iter().filter(|update| {
+                !lagged
+                    && !included
+                        .is_some_and(|included| update.seq.is_some_and(|seq| seq <= included))
+            });
+            for update in held.filter(|_| !paused) {
+                relay_update(update, connection, &features, outbox, meter)?;
+            }
+            subscriptions.insert(name, Subscription::Active { features, paused });
+        }
+        Routed::Update(update) => match subscriptions.get_mut(&*update.name) {
+            Some(Subscription::Pending { held, .. }) => held.push(update),
+            Some(Subscription::Active { paused: true, .. }) | None => {}
+            Some(Subscription::Active { features, .. }) => {
+                relay_update(update, connection, features, outbox, meter)?
+            }
+        },
+        Routed::Lagged(name) => match subscriptions.get_mut(&name) {
+            Some(Subscription::Pending { held, lagged }) => {
+                held.clear();
+                *lagged = true;
+            }
+            // Paused signals are sent completely once they resume
+            Some(Subscription::Active { paused: true, .. }) | None => {}
+            Some(Subscription::Active { features, .. }) => outbox.resync(&name, features)?,
+        },
+        Routed::Unsubscribe(name) => {
+            subscriptions.remove(&name);
+            outbox.include(&name, None);
+        }
+    }
+    Ok(())
+}
+
+/// Sends an update of a signal to the client, without the change if it isn't part of its
+/// audience.
+fn relay_update(
+    mut update: ServerSignalUpdate,
+    connection: &Connection,
+    features: &ConnectionFeatures,
+    outbox: &Outbox,
+    meter: &Option<Arc<IdentityMeter>>,
+) -> Result<(), Closed> {
+    let name = update.name.to_string();
+    if let Some(audience) = &update.audience {
+        // The complete value wouldn't include the change, so clients without patches skip it
+        if !features.patches {
+            return Ok(());
+        }
+        if !audience.includes(connection) {
+            update = update.skipped();
+        }
+    }
+    if !features.patches || (update.is_merge() && !features.merge_patch) {
+        // Fall back to the complete value for clients which can't apply the patch
+        return outbox.resync(&name, features);
+    }
+    let seq = update.seq;
+    let message = ServerSignalMessage::Update(timestamped(update, features));
+    send_relayed(&name, seq, message, features, outbox, meter)
+}
+
+/// Keeps the send time of an update only for clients which measure latency, the others would
+/// ignore it.
+fn timestamped(
+    mut update: ServerSignalUpdate,
+    features: &ConnectionFeatures,
+) -> ServerSignalUpdate {
+    if !features.timestamps {
+        update.sent_at = None;
+    }
+    update
+}
+
+/// Tells the client about signals which were paused or resumed by [`ServerSignals::disable`].
+fn pause(
+    disabled: &[String],
+    subscriptions: &mut HashMap<String, Subscription>,
+    outbox: &Outbox,
+    meter: &Option<Arc<IdentityMeter>>,
+) -> Result<(), Closed> {
+    for (name, subscription) in subscriptions.iter_mut() {
+        let Subscription::Active { features, paused } = subscription else {
+            continue;
+        };
+        let now_paused = is_disabled(disabled, name);
+        if now_paused == *paused {
+            continue;
+        }
+        *paused = now_paused;
+        if !now_paused {
+            // Updates were dropped while paused, resume with the complete value
+            outbox.resync(name, features)?;
+        } else if features.pause {
+            let message = ServerSignalMessage::Paused(name.clone());
+            send_relayed(name, None, message, features, outbox, meter)?;
+        }
+    }
+    Ok(())
+}
+
+fn send_relayed(
+    name: &str,
+    seq: Option<u64>,
+    message: ServerSignalMessage,
+    features: &ConnectionFeatures,
+    outbox: &Outbox,
+    meter: &Option<Arc<IdentityMeter>>,
+) -> Result<(), Closed> {
+    let frame = outbox.encode(&Messages::ServerSignal(message), features)?;
+    outbox.send_update(name, seq, frame, features)?;
+    if let Some(meter) = meter {
+        meter.sent();
+    }
+    Ok(())
+}
+
+/// What the messages of a connection are handled with, see [`handle_message`].
+#[derive(Clone, Copy)]
+struct ConnectionContext<'a> {
+    server_signals: &'a ServerSignals,
+    connection: &'a ConnectionHandle,
+    outbox: &'a Outbox,
+    /// Subscribes the relay of the connection to signals.
+    routed: &'a UnboundedSender<Routed>,
+    config: &'a ConnectionConfig,
+}
+
+/// Handles a single frame received from the client.
+///
+/// Returns an error if the connection should be closed.
+async fn handle_message(
+    msg: Frame,
+    context: ConnectionContext<'_>,
+    limiter: &mut RateLimiter,
+) -> Result<(), Closed> {
+    let ConnectionContext {
+        server_signals,
+        connection,
+        outbox,
+        config,
+        ..
+    } = context;
+    let features = ConnectionFeatures::negotiate(config, &connection.capabilities());
+    let bytes = match &msg {
+        Frame::Text(text) => text.len(),
+        Frame::Binary(data) => data.len(),
+        _ => 0,
+    };
+    if let Err(reason) = limiter.check_message(bytes) {
+        return rate_limited(reason, outbox, limiter, &features).await;
+    }
+    match msg {
+        Frame::Text(text) => match decode(&text, &features) {
+            Ok(messages) => {
+                for (index, message) in messages.into_iter().enumerate() {
+                    // Every message of a batch counts towards the rate limit
+                    if index > 0 {
+                        if let Err(reason) = limiter.check_message(0) {
+                            return rate_limited(reason, outbox, limiter, &features).await;
+                        }
+                    }
+                    handle_decoded(message, context, limiter, &features).await?;
+                }
+            }
+            Err(err) => server_signals.report(None, err),
+        },
+        Frame::Binary(_) => error!("Unexpected binary message from client"),
+        Frame::Ping(payload) => outbox.send(Frame::Pong(payload))?,
+        Frame::Pong(_) => {}
+        Frame::Close => return Err(Closed),
+    }
+    Ok(())
+}
+
+async fn handle_decoded(
+    message: Messages,
+    context: ConnectionContext<'_>,
+    limiter: &mut RateLimiter,
+    features: &ConnectionFeatures,
+) -> Result<(), Closed> {
+    let ConnectionContext {
+        server_signals,
+        connection,
+        outbox,
+        config,
+        ..
+    } = context;
+    match authorize(message, connection, outbox, config, features)? {
+        Messages::ServerSignal(server_msg) => match server_msg {
+            ServerSignalMessage::Establish(name) => {
+                establish(name, Value::Null, None, context).await?;
+            }
+            ServerSignalMessage::EstablishWithPayload((name, payload)) => {
+                establish(name, payload, None, context).await?;
+            }
+            ServerSignalMessage::Resume((version, payload)) => {
+                establish(version.name.clone(), payload, Some(version), context).await?;
+            }
+            ServerSignalMessage::Unsubscribe(name) => {
+                unsubscribe(name, context)?;
+            }
+            ServerSignalMessage::Schema((name, remote)) => {
+                // Only sent by the client if the types differ, it ignores the signal
+                unsubscribe(name.clone(), context)?;
+                let local = server_signals.schema(&name).await.unwrap_or_default();
+                server_signals.report(
+                    Some(&name),
+                    Error::SchemaMismatch {
+                        name: name.clone(),
+                        local: local.to_string(),
+                        remote,
+                    },
+                );
+            }
+            _ => error!("Unexpected server signal message from client"),
+        },
+        Messages::Heartbeat(HeartbeatMessage::Ping(id)) => {
+            let pong = match features.timestamps {
+                true => HeartbeatMessage::Time((id, now_millis())),
+                false => HeartbeatMessage::Pong(id),
+            };
+            outbox.send(outbox.encode(&Messages::Heartbeat(pong), features)?)?;
+        }
+        Messages::Heartbeat(HeartbeatMessage::Latency(histogram)) => {
+            // Heartbeats pass `authorize`, reports are only counted once the client may send
+            // anything else
+            let authorized = config.authenticate.is_none() || connection.authenticated();
+            if features.timestamps && authorized {
+                server_signals.record_latency(&histogram);
+            }
+        }
+        Messages::Heartbeat(HeartbeatMessage::Pong(_) | HeartbeatMessage::Time(_)) => {}
+        Messages::Hello(capabilities) => {
+            connection.set_capabilities(ClientCapabilities {
+                auth_token: None,
+                ..capabilities
+            });
+            let features = ConnectionFeatures::negotiate(config, &connection.capabilities());
+            outbox.set_batch(features.batch);
+        }
+        Messages::Authenticate(_) => {
+            // Checked by `authorize`
+        }
+        Messages::RateLimited(_)
+        | Messages::Compressed(_)
+        | Messages::Batch(_)
+        | Messages::Encrypted(_)
+        | Messages::SignalAnnounced(_)
+        | Messages::AuthRejected(_) => {
+            error!("Unexpected server message from client")
+        }
+        Messages::ClientInput(input_msg) => match input_msg {
+            ClientInputMessage::Establish((name, value)) => {
+                match server_signals
+                    .establish_input(name.clone(), connection.id(), value)
+                    .await
+                {
+                    Some(Ok(None)) => {}
+                    Some(Ok(Some(reply))) => {
+                        outbox.send(outbox.encode(&Messages::ClientInput(reply), features)?)?;
+                    }
+                    Some(Err(err)) => server_signals.report(Some(&name), err),
+                    None => server_signals.report(None, Error::UnknownSignal(name)),
+                }
+            }
+            ClientInputMessage::Update((seq, update)) => {
+                if let Err(reason) = limiter.check_patch(&update) {
+                    return rate_limited(reason, outbox, limiter, features).await;
+                }
+                let reply = server_signals
+                    .update_input(update.name.to_string(), connection.id(), seq, update)
+                    .await;
+                if let Some(reply) = reply {
+                    outbox.send(outbox.encode(&Messages::ClientInput(reply), features)?)?;
+                }
+            }
+            _ => error!("Unexpected client input message from client"),
+        },
+    }
+    Ok(())
+}
+
+/// Checks the auth token of `message` if the server requires one, see
+/// [`ConnectionConfig::authenticate`]. Rejects every other message but a `Hello` without token
+/// until the client authenticated, and returns the message without its token.
+fn authorize(
+    message: Messages,
+    connection: &ConnectionHandle,
+    outbox: &Outbox,
+    config: &ConnectionConfig,
+    features: &ConnectionFeatures,
+) -> Result<Messages, Closed> {
+    let Some(verifier) = &config.authenticate else {
+        return Ok(message);
+    };
+    let (verified, message) = match message {
+        Messages::Hello(mut capabilities) => match capabilities.auth_token.take() {
+            Some(token) => (verifier.verify(&token), Messages::Hello(capabilities)),
+            // The token may follow in an `Authenticate`
+            None => return Ok(Messages::Hello(capabilities)),
+        },
+        Messages::Authenticate(token) => {
+            let verified = verifier.verify(&token);
+            (verified, Messages::Authenticate(token))
+        }
+        message @ Messages::Heartbeat(_) => return Ok(message),
+        message if connection.authenticated() => return Ok(message),
+        message => (Err("Not authenticated".to_string()), message),
+    };
+    connection.set_authenticated(verified.is_ok());
+    match verified {
+        Ok(()) => Ok(message),
+        Err(reason) => {
+            outbox.send(outbox.encode(&Messages::AuthRejected(reason), features)?)?;
+            Err(Closed)
+        }
+    }
+}
+
+/// Sends the announcements after the first `announced` ones, if the client understands them.
+fn announce(
+    announcements: &[SignalAnnouncement],
+    announced: &mut usize,
+    outbox: &Outbox,
+    connection: &ConnectionHandle,
+    config: &ConnectionConfig,
+) -> Result<(), Closed> {
+    if *announced == announcements.len() {
+        return Ok(());
+    }
+    let features = ConnectionFeatures::negotiate(config, &connection.capabilities());
+    if !features.announce {
+        return Ok(());
+    }
+    for announcement in &announcements[*announced..] {
+        let message = Messages::SignalAnnounced(announcement.clone());
+        outbox.send(outbox.encode(&message, &features)?)?;
+    }
+    *announced = announcements.len();
+    Ok(())
+}
+
+/// Sends the current value of a signal to the client and relays all further updates.
+///
+/// A client resuming from `resume` only receives the updates it missed, if they are still kept.
+async fn establish(
+    name: String,
+    payload: Value,
+    resume: Option<SignalVersion>,
+    context: ConnectionContext<'_>,
+) -> Result<(), Closed> {
+    let ConnectionContext {
+        server_signals,
+        connection,
+        outbox,
+        routed,
+        config,
+    } = context;
+    let features = ConnectionFeatures::negotiate(config, &connection.capabilities());
+    if !server_signals.is_allowed(&name, connection.identity()) {
+        leptos::logging::warn!("Connection {} may not establish {name}", connection.id());
+        if features.denied {
+            let message = Messages::ServerSignal(ServerSignalMessage::Denied(name));
+            outbox.send(outbox.encode(&message, &features)?)?;
+        }
+        return Ok(());
+    }
+    // Routed before the value is read, so no update in between is missed
+    routed
+        .send(Routed::Subscribe(name.clone()))
+        .map_err(|_| Closed)?;
+    if !server_signals
+        .subscribe(&name, connection.id(), routed)
+        .await
+    {
+        server_signals.report(None, Error::UnknownSignal(name.clone()));
+        return unsubscribe(name, context);
+    }
+    let paused = is_disabled(&server_signals.watch_disabled().borrow(), &name);
+    if let Some(resume) = resume.filter(|_| features.resume && !paused) {
+        let missed = server_signals
+            .updates_since(&name, resume.epoch, resume.seq)
+            .await
+            .filter(|missed| {
+                // Changes for an audience are only relayed as they are sent
+                features.patches
+                    && (features.merge_patch || !missed.iter().any(|update| update.is_merge()))
+                    && !missed.iter().any(|update| update.audience.is_some())
+            });
+        if let Some(missed) = missed {
+            let included = missed
+                .last()
+                .and_then(|update| update.seq)
+                .or(Some(resume.seq));
+            let frames = missed
+                .into_iter()
+                .map(|update| {
+                    let update = timestamped(update, &features);
+                    let message = Messages::ServerSignal(ServerSignalMessage::Update(update));
+                    outbox.encode(&message, &features)
+                })
+                .collect::<Result<_, _>>()?;
+            return routed
+                .send(Routed::Established {
+                    name,
+                    features,
+                    frames,
+                    included,
+                })
+                .map_err(|_| Closed);
+        }
+    }
+    let (value, version) = match server_signals.snapshot(&name, payload).await {
+        Some(Ok(snapshot)) => snapshot,
+        Some(Err(err)) => {
+            server_signals.report(Some(&name), err);
+            return unsubscribe(name, context);
+        }
+        None => {
+            server_signals.report(None, Error::UnknownSignal(name.clone()));
+            return unsubscribe(name, context);
+        }
+    };
+    let mut messages = Vec::new();
+    if features.schema {
+        if let Some(schema) = server_signals.schema(&name).await {
+            messages.push(ServerSignalMessage::Schema((
+                name.clone(),
+                schema.to_string(),
+            )));
+        }
+    }
+    messages.push(ServerSignalMessage::EstablishResponse((
+        name.clone(),
+        value,
+    )));
+    if let Some((epoch, seq)) = version.filter(|_| features.resume) {
+        messages.push(ServerSignalMessage::Version(SignalVersion {
+            name: name.clone(),
+            epoch,
+            seq,
+        }));
+    }
+    if features.pause && paused {
+        messages.push(ServerSignalMessage::Paused(name.clone()));
+    }
+    let frames = messages
+        .into_iter()
+        .map(|message| outbox.encode(&Messages::ServerSignal(message), &features))
+        .collect::<Result<_, _>>()?;
+    routed
+        .send(Routed::Established {
+            name,
+            features,
+            frames,
+            included: version.map(|(_, seq)| seq),
+        })
+        .map_err(|_| Closed)
+}
+
+/// Stops relaying the updates of a signal to the client.
+fn unsubscribe(name: String, context: ConnectionContext<'_>) -> Result<(), Closed> {
+    context
+        .server_signals
+        .unsubscribe(&name, context.connection.id());
+    context
+        .routed
+        .send(Routed::Unsubscribe(name))
+        .map_err(|_| Closed)
+}
+
+/// Tells the client a message was dropped, returns an error once the connection has to be closed.
+async fn rate_limited(
+    reason: RateLimitReason,
+    outbox: &Outbox,
+    limiter: &mut RateLimiter,
+    features: &ConnectionFeatures,
+) -> Result<(), Closed> {
+    if limiter.violation() {
+        return Err(Closed);
+    }
+    outbox.send(outbox.encode(&Messages::RateLimited(reason), features)?)
+}
+
+/// Serializes a message to the client, compressing and encrypting it if configured for the
+/// connection.
+fn encode(message: &Messages, features: &ConnectionFeatures) -> Result<String, Error> {
+    #[cfg(feature = "compression")]
+    let json = match features.compression_threshold {
+        Some(threshold) => crate::compression::encode(message, threshold)?,
+        None => serde_json::to_string(message)?,
+    };
+    #[cfg(not(feature = "compression"))]
+    let json = serde_json::to_string(message)?;
+    match &features.cipher {
+        Some(cipher) => Ok(serde_json::to_string(&cipher.seal(&json)?)?),
+        None => Ok(json),
+    }
+}
+
+/// Parses a message from the client, unpacking a batch into its messages. The batch is either
+/// encrypted as a whole or each of its messages is.
+fn decode(text: &str, features: &ConnectionFeatures) -> Result<Vec<Messages>, Error> {
+    match serde_json::from_str::<Messages>(text)? {
+        Messages::Batch(messages) => messages
+            .into_iter()
+            .map(|message| open(message, features))
+            .collect(),
+        message => match open(message, features)? {
+            Messages::Batch(messages) => Ok(messages),
+            message => Ok(vec![message]),
+        },
+    }
+}
+
+/// Each message has to be encrypted if a cipher is configured.
+fn open(message: Messages, features: &ConnectionFeatures) -> Result<Messages, Error> {
+    match (&features.cipher, message) {
+        (Some(cipher), Messages::Encrypted(data)) => cipher.open(&data),
+        (Some(_), _) => Err(Error::EncryptionFailed),
+        (None, message) => Ok(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::cipher::Cipher;
+    use crate::messages::ClientCapabilities;
+
+    /// Reverses the text, enough to tell encrypted messages apart.
+    struct Reversed;
+
+    impl Cipher for Reversed {
+        fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+            Ok(plaintext.chars().rev().collect())
+        }
+
+        fn decrypt(&self, ciphertext: &str) -> Result<String, Error> {
+            Ok(ciphertext.chars().rev().collect())
+        }
+    }
+
+    struct Broken;
+
+    impl Cipher for Broken {
+        fn encrypt(&self, _plaintext: &str) -> Result<String, Error> {
+            Err(Error::EncryptionFailed)
+        }
+
+        fn decrypt(&self, _ciphertext: &str) -> Result<String, Error> {
+            Err(Error::EncryptionFailed)
+        }
+    }
+
+    fn features(cipher: impl Cipher + 'static) -> ConnectionFeatures {
+        let config = ConnectionConfig::default().cipher(cipher);
+        ConnectionFeatures::negotiate(&config, &ClientCapabilities::current())
+    }
+
+    fn establish() -> Messages {
+        Messages::ServerSignal(ServerSignalMessage::Establish("count".to_string()))
+    }
+
+    #[test]
+    fn encrypted_messages_round_trip() {
+        let features = features(Reversed);
+        let text = encode(&establish(), &features).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&text).unwrap(),
+            Messages::Encrypted(_)
+        ));
+        assert!(matches!(
+            decode(&text, &features).unwrap().as_slice(),
+            [Messages::ServerSignal(ServerSignalMessage::Establish(name))] if name == "count"
+        ));
+    }
+
+    #[test]
+    fn unencrypted_messages_are_refused() {
+        let text = serde_json::to_string(&establish()).unwrap();
+        assert!(matches!(
+            decode(&text, &features(Reversed)),
+            Err(Error::EncryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn messages_which_cant_be_encrypted_close_the_connection() {
+        let errors = ErrorHooks::default();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        errors.add({
+            let reported = reported.clone();
+            move |event| reported.lock().unwrap().push(event.to_string())
+        });
+        let outbox = Outbox::new(OutboundConfig::default(), errors);
+        outbox.send(Frame::Ping(Vec::new())).unwrap();
+
+        assert!(outbox.encode(&establish(), &features(Broken)).is_err());
+        assert_eq!(reported.lock().unwrap().len(), 1);
+        assert!(outbox.state.lock().unwrap().queue.is_empty());
+        assert!(outbox.send(Frame::Ping(Vec::new())).is_err());
+    }
+
+    fn texts(count: usize) -> Vec<Frame> {
+        (0..count)
+            .map(|_| Frame::Text(serde_json::to_string(&establish()).unwrap()))
+            .collect()
+    }
+
+    /// The number of messages in each frame, 0 for frames which aren't text.
+    fn sizes(frames: &[Frame]) -> Vec<usize> {
+        let features = ConnectionFeatures::negotiate(
+            &ConnectionConfig::default(),
+            &ClientCapabilities::current(),
+        );
+        frames
+            .iter()
+            .map(|frame| match frame {
+                Frame::Text(text) => decode(text, &features).unwrap().len(),
+                _ => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bursts_are_sent_in_batches() {
+        let batch = BatchConfig {
+            max_messages: 2,
+            ..BatchConfig::default()
+        };
+        assert_eq!(sizes(&batched(texts(5), &batch)), [2, 2, 1]);
+    }
+
+    #[test]
+    fn large_messages_and_control_frames_end_a_batch() {
+        let size = serde_json::to_string(&establish()).unwrap().len();
+        let batch = BatchConfig {
+            max_bytes: size * 2,
+            ..BatchConfig::default()
+        };
+        let mut frames = texts(3);
+        frames.insert(1, Frame::Ping(Vec::new()));
+        frames.push(Frame::Text(format!(
+            "{{\"AuthRejected\":\"{}\"}}",
+            "x".repeat(size * 2)
+        )));
+        assert_eq!(sizes(&batched(frames, &batch)), [1, 0, 2, 1]);
+    }
+
+    fn outbox(overflow: OverflowPolicy) -> Outbox {
+        let config = OutboundConfig {
+            capacity: 2,
+            overflow,
+        };
+        Outbox::new(config, ErrorHooks::default())
+    }
+
+    fn update(outbox: &Outbox, signal: &str, seq: u64) -> Result<(), Closed> {
+        let features = ConnectionFeatures::negotiate(
+            &ConnectionConfig::default(),
+            &ClientCapabilities::current(),
+        );
+        let frame = Frame::Text(format!("{signal}{seq}"));
+        outbox.send_update(signal, Some(seq), frame, &features)
+    }
+
+    /// The queued messages, `a1` for the first update of `a` and `a` for its complete value.
+    fn queued(outbox: &Outbox) -> Vec<String> {
+        let state = outbox.state.lock().unwrap();
+        state
+            .queue
+            .iter()
+            .map(|queued| match queued {
+                Outgoing::Update {
+                    frame: Frame::Text(text),
+                    ..
+                } => text.clone(),
+                Outgoing::Snapshot { signal, .. } => signal.clone(),
+                _ => "other".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dropped_updates_are_replaced_by_the_complete_value() {
+        let outbox = outbox(OverflowPolicy::DropOldest);
+        update(&outbox, "a", 1).unwrap();
+        update(&outbox, "b", 1).unwrap();
+        update(&outbox, "a", 2).unwrap();
+        assert_eq!(queued(&outbox), ["a", "b1"]);
+
+        // Part of the complete value, which is read when it is written
+        update(&outbox, "a", 3).unwrap();
+        assert_eq!(queued(&outbox), ["a", "b1"]);
+
+        update(&outbox, "b", 2).unwrap();
+        assert_eq!(queued(&outbox), ["a", "b"]);
+    }
+
+    #[test]
+    fn overflowing_signals_keep_their_latest_value() {
+        let outbox = outbox(OverflowPolicy::KeepLatest);
+        update(&outbox, "a", 1).unwrap();
+        update(&outbox, "b", 1).unwrap();
+        update(&outbox, "a", 2).unwrap();
+        assert_eq!(queued(&outbox), ["b1", "a"]);
+
+        update(&outbox, "a", 3).unwrap();
+        assert_eq!(queued(&outbox), ["b1", "a"]);
+    }
+
+    #[test]
+    fn overflowing_clients_are_disconnected() {
+        let outbox = outbox(OverflowPolicy::Disconnect);
+        update(&outbox, "a", 1).unwrap();
+        update(&outbox, "b", 1).unwrap();
+
+        assert!(update(&outbox, "a", 2).is_err());
+        assert!(queued(&outbox).is_empty());
+        assert!(outbox.send(Frame::Ping(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn updates_included_in_a_complete_value_are_skipped() {
+        let outbox = outbox(OverflowPolicy::DropOldest);
+        update(&outbox, "a", 2).unwrap();
+        update(&outbox, "a", 3).unwrap();
+        outbox.include("a", Some(2));
+
+        let next = outbox.state.lock().unwrap().pop();
+        assert!(matches!(next, Some(Outgoing::Update { seq: Some(3), .. })));
+    }
+}
@@ -0,0 +1,101 @@
+//! A standalone server on tokio-tungstenite, for hosting the signals outside the Leptos app
+//! server, e.g. as a separate realtime service.
+//!
+//! ```rust,no_run
+//! # async fn serve(server_signals: leptos_ws::server_signals::ServerSignals) -> std::io::Result<()> {
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await?;
+//! leptos_ws::tungstenite::serve(listener, server_signals).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Affinity cookies, `ConnectionConfig::identify` and `ConnectionConfig::tenant` are only
+//! supported by the [`axum`](crate::axum) handlers, which see the upgrade request.
+
+use std::io;
+
+use futures::{future::ready, SinkExt, StreamExt, TryStreamExt};
+use leptos::logging::error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_tungstenite::{accept_async, tungstenite};
+use tungstenite::Message;
+
+use crate::{
+    config::ConnectionConfig,
+    server_signals::ServerSignals,
+    tasks::TaskKind,
+    transport::{self, Frame},
+};
+
+/// Accepts WebSocket connections on `listener` until accepting fails.
+pub async fn serve(listener: TcpListener, server_signals: ServerSignals) -> io::Result<()> {
+    serve_with_config(listener, server_signals, ConnectionConfig::default()).await
+}
+
+/// Like [`serve`], but with a custom [`ConnectionConfig`] for every connection.
+pub async fn serve_with_config(
+    listener: TcpListener,
+    server_signals: ServerSignals,
+    config: ConnectionConfig,
+) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        server_signals.tasks().spawn(
+            TaskKind::Connection,
+            None,
+            handle_stream(stream, server_signals.clone(), config.clone()),
+        );
+    }
+}
+
+/// Runs the WebSocket handshake on an accepted stream and serves the connection until it
+/// closes, e.g. for TLS streams accepted with tokio-rustls.
+pub async fn handle_stream<S>(stream: S, server_signals: ServerSignals, config: ConnectionConfig)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let socket = match accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("WebSocket handshake failed: {err}");
+            return;
+        }
+    };
+    let (sink, stream) = socket.split();
+    transport::serve(
+        stream.try_filter_map(|message| ready(Ok(to_frame(message)))),
+        sink.with(|frame: Frame| ready(Ok::<_, tungstenite::Error>(Message::from(frame)))),
+        server_signals,
+        config,
+        None,
+        None,
+    )
+    .await;
+}
+
+impl From<Frame> for Message {
+    fn from(frame: Frame) -> Self {
+        match frame {
+            Frame::Text(text) => Message::Text(text),
+            Frame::Binary(data) => Message::Binary(data),
+            Frame::Ping(payload) => Message::Ping(payload),
+            Frame::Pong(payload) => Message::Pong(payload),
+            Frame::Close => Message::Close(None),
+        }
+    }
+}
+
+/// Raw frames are only seen when writing, so they are never received.
+pub(crate) fn to_frame(message: Message) -> Option<Frame> {
+    match message {
+        Message::Text(text) => Some(Frame::Text(text)),
+        Message::Binary(data) => Some(Frame::Binary(data)),
+        Message::Ping(payload) => Some(Frame::Ping(payload)),
+        Message::Pong(payload) => Some(Frame::Pong(payload)),
+        Message::Close(_) => Some(Frame::Close),
+        Message::Frame(_) => None,
+    }
+}
@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use leptos::prelude::*;
+use leptos_ws::config::{BatchConfig, ConnectionConfig};
+use leptos_ws::error::Error;
+use leptos_ws::latency::LatencyHistogram;
+use leptos_ws::messages::{ClientCapabilities, HeartbeatMessage, Messages, FEATURE_TIMESTAMPS};
+use leptos_ws::persistence::SignalStore;
+use leptos_ws::server_signals::ServerSignals;
+use leptos_ws::tasks::{TaskKind, TaskRegistry};
+use leptos_ws::testing::TestServer;
+use leptos_ws::{
+    AggregateSignal, ClientInputSignal, FsmSignal, PresenceEntries, PresenceMap, ServerSignal,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Barrier;
+use tokio::time::{sleep, Instant};
+
+#[tokio::test]
+async fn clients_converge_on_a_server_write() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let count = server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+    let mut a = server.client().await;
+    let mut b = server.client().await;
+    a.establish("count").await;
+    b.establish("count").await;
+    a.wait_for("count", 0).await;
+    b.wait_for("count", 0).await;
+
+    count.update(|count| *count += 1);
+    a.wait_for("count", 1).await;
+    b.wait_for("count", 1).await;
+}
+
+#[tokio::test]
+async fn clients_converge_over_a_socket() {
+    let server = TestServer::start(ServerSignals::new()).await;
+    let items = server.in_context(|| ServerSignal::new("items".to_string(), vec![1, 2]).unwrap());
+    let mut a = server.client().await;
+    let mut b = server.client().await;
+    a.establish("items").await;
+    b.establish("items").await;
+
+    items.update(|items| items.push(3));
+    a.wait_for("items", [1, 2, 3]).await;
+    b.wait_for("items", [1, 2, 3]).await;
+}
+
+#[tokio::test]
+async fn clients_converge_on_batched_bursts() {
+    let config = ConnectionConfig::default().batch(BatchConfig::default());
+    let server = TestServer::start_with_config(ServerSignals::new(), config).await;
+    let items = server.in_context(|| ServerSignal::new("items".to_string(), Vec::new()).unwrap());
+    let mut client = server.client().await;
+    client.establish("items").await;
+    client.wait_for("items", Vec::<i32>::new()).await;
+
+    for item in 0..100 {
+        items.update(|items| items.push(item));
+    }
+    client.wait_for("items", (0..100).collect::<Vec<_>>()).await;
+}
+
+#[tokio::test]
+async fn reconnecting_clients_resume_with_the_missed_updates() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let count = server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+    let mut client = server.client().await;
+    client.establish("count").await;
+    client.wait_for("count", 0).await;
+    count.update(|count| *count += 1);
+    client.wait_for("count", 1).await;
+
+    // Sent to the closed connection, the client receives it after resuming
+    count.update(|count| *count += 1);
+    client.reconnect().await;
+    client.wait_for("count", 2).await;
+    count.update(|count| *count += 1);
+    client.wait_for("count", 3).await;
+    assert_eq!(client.snapshots("count"), 1);
+}
+
+#[tokio::test]
+async fn clients_establishing_during_a_write_receive_it_once() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let items = server.in_context(|| {
+        ServerSignal::throttled("items".to_string(), vec![1], Duration::from_millis(100)).unwrap()
+    });
+    let mut a = server.client().await;
+    a.establish("items").await;
+    a.wait_for("items", [1]).await;
+
+    // The first write is sent right away, the second is held back by the throttle while the
+    // next client establishes
+    items.update(|items| items.push(2));
+    a.wait_for("items", [1, 2]).await;
+    items.update(|items| items.push(3));
+    let mut b = server.client().await;
+    b.establish("items").await;
+    b.wait_for("items", [1, 2, 3]).await;
+    // Once the held write was sent, b would apply it a second time
+    a.wait_for("items", [1, 2, 3]).await;
+
+    items.update(|items| items.push(4));
+    a.wait_for("items", [1, 2, 3, 4]).await;
+    b.wait_for("items", [1, 2, 3, 4]).await;
+}
+
+#[tokio::test]
+async fn audiences_apply_later_writes_on_top_of_their_view() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let menu =
+        server.in_context(|| ServerSignal::new("menu".to_string(), json!({"home": true})).unwrap());
+    let mut beta = server.client_as("beta").await;
+    let mut other = server.client().await;
+    beta.establish("menu").await;
+    other.establish("menu").await;
+    beta.wait_for("menu", json!({"home": true})).await;
+    other.wait_for("menu", json!({"home": true})).await;
+
+    menu.update_where(
+        |connection| connection.identity() == Some("beta"),
+        |menu| menu["reports"] = json!(true),
+    );
+    menu.update(|menu| menu["settings"] = json!(true));
+    beta.wait_for(
+        "menu",
+        json!({"home": true, "reports": true, "settings": true}),
+    )
+    .await;
+    other
+        .wait_for("menu", json!({"home": true, "settings": true}))
+        .await;
+    assert_eq!(
+        menu.get_untracked(),
+        json!({"home": true, "settings": true})
+    );
+    assert_eq!(beta.snapshots("menu"), 1);
+    assert_eq!(other.snapshots("menu"), 1);
+}
+
+#[tokio::test]
+async fn invalid_established_inputs_are_rejected() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let volume = server.in_context(|| {
+        ClientInputSignal::new("volume".to_string(), 50u8)
+            .unwrap()
+            .with_validator(|volume| match *volume <= 100 {
+                true => Ok(()),
+                false => Err("volume must be at most 100".to_string()),
+            })
+    });
+    let mut client = server.client().await;
+    client.set_input("volume", 150).await;
+    let rejection = client.wait_for_rejection("volume").await;
+    assert_eq!(rejection.value, json!(50));
+    assert_eq!(rejection.reason, "volume must be at most 100");
+
+    // The client established the initial value again after the rejection
+    client.set_input("volume", 70).await;
+    client.wait_for_ack("volume").await;
+    let values = volume.values().get_untracked();
+    assert_eq!(values.into_values().collect::<Vec<_>>(), [70]);
+}
+
+#[tokio::test]
+async fn imports_win_over_writes_queued_before() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let user = server
+        .in_context(|| ServerSignal::new("user".to_string(), json!({"name": "Ada"})).unwrap());
+    let mut client = server.client().await;
+    client.establish("user").await;
+    client.wait_for("user", json!({"name": "Ada"})).await;
+
+    user.set(json!({"name": "Ada", "role": "admin"}));
+    let snapshot = json!({"user": {"name": "Grace"}});
+    server.signals().import_snapshot(snapshot).await.unwrap();
+    client.wait_for("user", json!({"name": "Grace"})).await;
+    assert_eq!(user.get_untracked(), json!({"name": "Grace"}));
+}
+
+#[tokio::test]
+async fn clients_need_a_valid_token() {
+    let server = TestServer::in_memory_with_config(ServerSignals::new(), authenticated());
+    server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+
+    let mut anonymous = server.client().await;
+    anonymous.establish("count").await;
+    assert_eq!(
+        anonymous.wait_for_auth_rejected().await,
+        "Not authenticated"
+    );
+
+    let mut forged = server.client().await;
+    forged.authenticate("forged").await;
+    assert_eq!(forged.wait_for_auth_rejected().await, "Unknown token");
+
+    let mut client = server.client().await;
+    client.authenticate("secret").await;
+    client.establish("count").await;
+    client.wait_for("count", 0).await;
+}
+
+#[tokio::test]
+async fn latency_is_only_recorded_from_authenticated_clients() {
+    let server = TestServer::in_memory_with_config(ServerSignals::new(), authenticated());
+    server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+    let mut histogram = LatencyHistogram::default();
+    histogram.record(Duration::from_millis(20));
+    let report = Messages::Heartbeat(HeartbeatMessage::Latency(histogram));
+
+    // Without measuring latency
+    let mut client = server.client().await;
+    client.authenticate("secret").await;
+    client.send(&report).await;
+
+    let mut measuring = server.client().await;
+    let mut capabilities = ClientCapabilities::current();
+    capabilities.features.push(FEATURE_TIMESTAMPS.to_string());
+    measuring.send(&Messages::Hello(capabilities)).await;
+    measuring.send(&report).await;
+    measuring.authenticate("secret").await;
+    measuring.send(&report).await;
+
+    // Messages are handled in order, so both reports were handled once the value arrived
+    for client in [&mut client, &mut measuring] {
+        client.establish("count").await;
+        client.wait_for("count", 0).await;
+    }
+    assert_eq!(server.signals().latency_histogram().count(), 1);
+}
+
+#[tokio::test]
+async fn acls_deny_other_identities() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let salaries = server.in_context(|| {
+        ServerSignal::new_with_acl("salaries".to_string(), json!({"ada": 100}), |identity| {
+            identity == Some("admin")
+        })
+        .unwrap()
+    });
+    let mut admin = server.client_as("admin").await;
+    let mut user = server.client_as("user").await;
+    let mut anonymous = server.client().await;
+    admin.establish("salaries").await;
+    admin.wait_for("salaries", json!({"ada": 100})).await;
+    for client in [&mut user, &mut anonymous] {
+        client.establish("salaries").await;
+        client.wait_for_denied("salaries").await;
+    }
+
+    salaries.set(json!({"ada": 120}));
+    admin.wait_for("salaries", json!({"ada": 120})).await;
+    // Still the placeholder from before the value arrived
+    assert_eq!(user.value("salaries"), Some(&json!(null)));
+    assert_eq!(anonymous.value("salaries"), Some(&json!(null)));
+}
+
+#[tokio::test]
+async fn presence_has_an_entry_per_connected_client() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let cursors =
+        server.in_context(|| PresenceMap::<(i32, i32)>::new("cursors".to_string()).unwrap());
+    let mut a = server.client().await;
+    let mut b = server.client().await;
+    let mut c = server.client().await;
+    a.set_client_id("a").await;
+    b.set_client_id("b").await;
+    a.establish("cursors").await;
+    a.wait_for("cursors", json!({})).await;
+
+    a.set_input("cursors", Some((1, 2))).await;
+    b.set_input("cursors", Some((3, 4))).await;
+    // Clients without an entry are left out
+    c.set_input("cursors", None::<(i32, i32)>).await;
+    a.wait_for("cursors", json!({"a": [1, 2], "b": [3, 4]}))
+        .await;
+
+    b.set_input("cursors", Some((5, 6))).await;
+    a.wait_for("cursors", json!({"a": [1, 2], "b": [5, 6]}))
+        .await;
+
+    drop(b);
+    a.wait_for("cursors", json!({"a": [1, 2]})).await;
+    assert_eq!(
+        cursors.get_untracked(),
+        PresenceEntries::from([("a".to_string(), (1, 2))])
+    );
+}
+
+#[tokio::test]
+async fn aggregates_fold_the_contributions_of_connected_clients() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let total = server.in_context(|| {
+        AggregateSignal::new("total".to_string(), 0, |total, value: &i32| total + value).unwrap()
+    });
+    let mut a = server.client().await;
+    let mut b = server.client().await;
+    let mut c = server.client().await;
+    a.establish("total").await;
+    a.set_input("total", Some(1)).await;
+    b.set_input("total", Some(2)).await;
+    c.set_input("total", Some(3)).await;
+    a.wait_for("total", 6).await;
+
+    c.set_input("total", Some(4)).await;
+    a.wait_for("total", 7).await;
+
+    drop(c);
+    a.wait_for("total", 3).await;
+    b.set_input("total", None::<i32>).await;
+    a.wait_for("total", 1).await;
+    assert_eq!(total.get_untracked(), 1);
+}
+
+/// Counts up by the steps clients request, refusing steps which aren't positive.
+fn counter() -> FsmSignal<i32, i32> {
+    FsmSignal::new("counter".to_string(), 0, |count, step| {
+        (*step > 0).then_some(count + step)
+    })
+    .unwrap()
+}
+
+#[tokio::test]
+async fn state_machines_apply_allowed_transitions() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let counter = server.in_context(counter);
+    let mut client = server.client().await;
+    client.establish("counter").await;
+    client.set_input("counter", None::<()>).await;
+
+    client
+        .set_input("counter", json!({"id": 0, "event": 2}))
+        .await;
+    client.wait_for_ack("counter").await;
+    client.wait_for("counter", 2).await;
+
+    client
+        .set_input("counter", json!({"id": 1, "event": 0}))
+        .await;
+    assert_eq!(client.wait_for_rejection("counter").await.seq, 2);
+    assert_eq!(counter.get_untracked(), 2);
+}
+
+/// A count which is slow to serialize, so concurrent creations of a signal holding it overlap.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct SlowCount(i32);
+
+impl Serialize for SlowCount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        std::thread::sleep(Duration::from_millis(5));
+        self.0.serialize(serializer)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrently_created_state_machines_apply_a_transition_once() {
+    let server = Arc::new(TestServer::in_memory(ServerSignals::new()));
+    let barrier = Arc::new(Barrier::new(8));
+    let creations = (0..8).map(|_| {
+        let server = server.clone();
+        let barrier = barrier.clone();
+        tokio::spawn(async move {
+            barrier.wait().await;
+            server.in_context(|| {
+                FsmSignal::new("counter".to_string(), SlowCount(0), |count, step: &i32| {
+                    Some(SlowCount(count.0 + step))
+                })
+                .unwrap()
+            })
+        })
+    });
+    let counters = futures::future::try_join_all(creations).await.unwrap();
+
+    let mut client = server.client().await;
+    client.establish("counter").await;
+    client.set_input("counter", None::<()>).await;
+    client
+        .set_input("counter", json!({"id": 0, "event": 1}))
+        .await;
+    client.wait_for_ack("counter").await;
+    // Every callback registered would have applied it once more
+    assert_eq!(counters[0].get_untracked(), SlowCount(1));
+}
+
+/// Accepts the token `secret`.
+fn authenticated() -> ConnectionConfig {
+    ConnectionConfig::default().authenticate(|token| match token {
+        "secret" => Ok(()),
+        _ => Err("Unknown token".to_string()),
+    })
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_registrations_share_one_signal() {
+    let server = Arc::new(TestServer::in_memory(ServerSignals::new()));
+    let barrier = Arc::new(Barrier::new(8));
+    let registrations = (0..8).map(|_| {
+        let server = server.clone();
+        let barrier = barrier.clone();
+        tokio::spawn(async move {
+            barrier.wait().await;
+            server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap())
+        })
+    });
+    let signals = futures::future::try_join_all(registrations).await.unwrap();
+
+    signals[0].set(7);
+    assert!(signals.iter().all(|signal| signal.get_untracked() == 7));
+    let counts = server.signals().tasks().counts();
+    assert_eq!(counts.get(&TaskKind::Dispatch), Some(&1));
+}
+
+struct Stored(Vec<(String, serde_json::Value)>);
+
+#[async_trait::async_trait]
+impl SignalStore for Stored {
+    async fn load_all(&self) -> Result<Vec<(String, serde_json::Value)>, Error> {
+        Ok(self.0.clone())
+    }
+
+    async fn save(&self, _name: &str, _value: &serde_json::Value) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn error_hooks_can_create_signals() {
+    let stored = Stored(vec![("count".to_string(), json!("not a number"))]);
+    let signals = ServerSignals::new()
+        .with_store(stored, Duration::from_millis(10))
+        .await
+        .unwrap();
+    let registry = signals.clone();
+    signals.on_error(move |_| {
+        registry.in_context(|| ServerSignal::new("errors".to_string(), 1).unwrap());
+    });
+    let server = Arc::new(TestServer::in_memory(signals.clone()));
+
+    // On its own thread, a hook running while the registry is held would never return
+    let (done, created) = tokio::sync::oneshot::channel();
+    let runtime = tokio::runtime::Handle::current();
+    let creating = server.clone();
+    std::thread::spawn(move || {
+        let _runtime = runtime.enter();
+        let count = creating.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+        let _ = done.send(count);
+    });
+    let count = tokio::time::timeout(Duration::from_secs(5), created)
+        .await
+        .expect("creating the signal deadlocked")
+        .unwrap();
+    assert_eq!(count.get_untracked(), 0);
+    assert!(signals.contains("errors").await);
+}
+
+#[tokio::test]
+async fn connections_leave_no_tasks_behind() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    server.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+    let tasks = server.signals().tasks().clone();
+    let cycle = || async {
+        let mut client = server.client().await;
+        client.establish("count").await;
+        client.wait_for("count", 0).await;
+    };
+    // Tasks of the signal, e.g. its route, are spawned by the first establish and stay
+    cycle().await;
+    let baseline = settled(&tasks, None).await;
+
+    for _ in 0..10_000 {
+        cycle().await;
+    }
+    settled(&tasks, Some(&baseline)).await;
+    assert!(server.signals().connections().await.is_empty());
+}
+
+#[tokio::test]
+async fn derived_signals_stop_recomputing_along_with_their_registry() {
+    let signals = ServerSignals::new();
+    let tasks = signals.tasks().clone();
+    let owner = Owner::new();
+    let price = owner.with(|| {
+        provide_context(signals.clone());
+        let price = ServerSignal::new("price".to_string(), 10).unwrap();
+        let source = price.clone();
+        ServerSignal::derived("total".to_string(), &["price"], move || {
+            source.get_untracked() * 2
+        })
+        .unwrap();
+        price
+    });
+    assert_eq!(tasks.counts().get(&TaskKind::Recompute), Some(&2));
+
+    // The source outlives the registry, the derived signal doesn't
+    drop(owner);
+    drop(signals);
+    price.set(11);
+    recomputing(&tasks, 0).await;
+}
+
+#[tokio::test]
+async fn derived_values_outlive_the_owner_which_created_them() {
+    let server = TestServer::in_memory(ServerSignals::new());
+    let samples =
+        server.in_context(|| ServerSignal::new("samples".to_string(), vec![1, 2]).unwrap());
+    // Like the owner of a request, which is disposed once it was rendered
+    let owner = Owner::new();
+    owner.with(|| {
+        provide_context(server.signals().clone());
+        let samples = samples.clone();
+        ServerSignal::derive("sum".to_string(), move || samples.get().iter().sum::<i32>()).unwrap();
+    });
+    drop(owner);
+    let mut client = server.client().await;
+    client.establish("sum").await;
+    client.wait_for("sum", 3).await;
+
+    samples.update(|samples| samples.push(3));
+    client.wait_for("sum", 6).await;
+}
+
+#[tokio::test]
+async fn summaries_stop_along_with_their_input() {
+    let signals = ServerSignals::new();
+    let tasks = signals.tasks().clone();
+    let owner = Owner::new();
+    owner.with(|| {
+        provide_context(signals.clone());
+        ClientInputSignal::new("room".to_string(), String::new())
+            .unwrap()
+            .summary("online".to_string(), Duration::from_millis(10), |values| {
+                values.len()
+            })
+            .unwrap();
+    });
+    assert_eq!(tasks.counts().get(&TaskKind::Recompute), Some(&1));
+
+    drop(owner);
+    drop(signals);
+    recomputing(&tasks, 0).await;
+}
+
+#[tokio::test]
+async fn tenants_are_evicted_after_their_last_connection() {
+    let config = ConnectionConfig::default().tenant(|_| Some("acme".to_string()));
+    let server = TestServer::start_with_config(ServerSignals::new(), config).await;
+    let tenant = server.signals().tenant("acme");
+    tenant.in_context(|| ServerSignal::new("count".to_string(), 0).unwrap());
+    drop(tenant);
+
+    let mut first = server.client().await;
+    let mut second = server.client().await;
+    for client in [&mut first, &mut second] {
+        client.establish("count").await;
+        client.wait_for("count", 0).await;
+    }
+    drop(first);
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(server.signals().tenants(), vec!["acme".to_string()]);
+
+    drop(second);
+    // Nothing of the tenant is left running, not even the dispatch of its signal
+    settled(server.signals().tasks(), Some(&HashMap::new())).await;
+    assert!(server.signals().tenants().is_empty());
+}
+
+/// Waits until `expected` recompute tasks are running.
+async fn recomputing(tasks: &TaskRegistry, expected: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while tasks
+        .counts()
+        .get(&TaskKind::Recompute)
+        .copied()
+        .unwrap_or_default()
+        != expected
+    {
+        assert!(
+            Instant::now() < deadline,
+            "Tasks left behind: {:?}",
+            tasks.counts()
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// The task counts once no connection has any left, waiting until they equal `expected`.
+async fn settled(
+    tasks: &TaskRegistry,
+    expected: Option<&HashMap<TaskKind, usize>>,
+) -> HashMap<TaskKind, usize> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let counts = tasks.counts();
+        let closed = [TaskKind::Connection, TaskKind::Writer, TaskKind::Relay]
+            .iter()
+            .all(|kind| !counts.contains_key(kind));
+        if closed && expected.is_none_or(|expected| *expected == counts) {
+            return counts;
+        }
+        assert!(Instant::now() < deadline, "Tasks left behind: {counts:?}");
+        sleep(Duration::from_millis(10)).await;
+    }
+}